@@ -8,11 +8,21 @@ _run_complete() {
     prev="${COMP_WORDS[COMP_CWORD-1]}"
 
     # Basic options
-    opts="--list --generate-completion --install-completion --version --help -l -h"
+    opts="--list --generate-completion --install-completion --auto-container --no-remote-cache --jobs --force --keep-going --output --events --events-file --all --status --profile --dry-run --parallel --system --which-shell --format --yes --watch --which-runfile --from-makefile --no-color --split --paginate --timings --verbose --plain --resume --explain --no-global --allow-danger --version --help -l -h -j -k -n -y -v"
 
     # If we're completing after --generate-completion or --install-completion, suggest shells
     if [[ "${prev}" == "--generate-completion" ]] || [[ "${prev}" == "--install-completion" ]]; then
-        COMPREPLY=( $(compgen -W "bash zsh fish" -- "${cur}") )
+        COMPREPLY=( $(compgen -W "bash zsh fish powershell" -- "${cur}") )
+        return 0
+    fi
+
+    if [[ "${prev}" == "--output" ]]; then
+        COMPREPLY=( $(compgen -W "interleave group" -- "${cur}") )
+        return 0
+    fi
+
+    if [[ "${prev}" == "--format" ]]; then
+        COMPREPLY=( $(compgen -W "text json" -- "${cur}") )
         return 0
     fi
 
@@ -72,13 +82,16 @@ _run_complete() {
         local namespace="$prev"
 
         if command -v run &> /dev/null; then
-            local all_funcs=$(run --list 2>/dev/null | sed -n 's/^  //p')
+            local all_funcs=$(run --list 2>/dev/null | sed -n 's/^  \([^ ]*\).*/\1/p')
+            # Namespace separators configured by the Runfile (":" by default)
+            local separators=$(run --list-separators 2>/dev/null)
+            separators="${separators:-:}"
             local subcommands=""
 
             while IFS= read -r func; do
-                if [[ $func == ${namespace}:* ]]; then
-                    # Extract part after colon
-                    local subcmd="${func#*:}"
+                if [[ $func == "${namespace}"[${separators}]* ]]; then
+                    # Extract part after the separator
+                    local subcmd="${func:${#namespace}+1}"
                     subcommands="${subcommands}${subcmd} "
                 fi
             done <<< "$all_funcs"
@@ -89,6 +102,13 @@ _run_complete() {
         fi
     fi
 
+    # Values the function was called with before, with completion.history
+    if [[ ${COMP_CWORD} -ge 2 ]] && command -v run &> /dev/null; then
+        local IFS=$'\n'
+        local values=$(run --complete-history -- "${COMP_WORDS[@]:1:COMP_CWORD-1}" 2>/dev/null)
+        COMPREPLY+=( $(compgen -W "${values}" -- "${cur}") )
+    fi
+
     return 0
 }
 
@@ -18,13 +18,14 @@ function __run_get_top_level
 
     # Get all functions and extract top-level names
     set -l all_funcs (run --list 2>/dev/null | string match -r '^  \S+' | string trim)
+    set -l separators (__run_get_separators)
     set -l top_level
     set -l seen
 
     for func in $all_funcs
-        if string match -q '*:*' $func
-            # Extract prefix before colon
-            set -l prefix (string split -m 1 ':' $func)[1]
+        if string match -qr "[$separators]" -- $func
+            # Extract prefix before the separator
+            set -l prefix (string replace -r "[$separators].*" '' -- $func)
             if not contains $prefix $seen
                 set -a top_level $prefix
                 set -a seen $prefix
@@ -43,22 +44,63 @@ end
 function __run_get_subcommands
     set -l namespace $argv[1]
     set -l all_funcs (run --list 2>/dev/null | string match -r '^  \S+' | string trim)
+    set -l separators (__run_get_separators)
+    set -l pattern "^"(string escape --style=regex -- $namespace)"[$separators]"
 
     for func in $all_funcs
-        if string match -q "$namespace:*" $func
-            # Extract part after colon
-            string split -m 1 ':' $func | tail -n 1
+        if string match -qr -- $pattern $func
+            # Extract part after the separator
+            string sub -s (math (string length -- $namespace) + 2) -- $func
         end
     end
 end
 
+# Helper function to get the namespace separators configured by the Runfile
+function __run_get_separators
+    set -l separators (run --list-separators 2>/dev/null)
+    if test -z "$separators"
+        set separators ':'
+    end
+    echo $separators
+end
+
 # Completions for run command
 complete -c run -f
 
 # Options (always available)
 complete -c run -s l -l list -d 'List all available functions from the Runfile'
-complete -c run -l generate-completion -d 'Generate shell completion script' -xa 'bash zsh fish'
-complete -c run -l install-completion -d 'Install shell completion' -xa 'bash zsh fish'
+complete -c run -l generate-completion -d 'Generate shell completion script' -xa 'bash zsh fish powershell'
+complete -c run -l install-completion -d 'Install shell completion' -xa 'bash zsh fish powershell'
+complete -c run -l auto-container -d 'Run tasks in their container when tools are missing'
+complete -c run -l no-remote-cache -d 'Don't use the remote cache for this run'
+complete -c run -s j -l jobs -d 'Run up to N prerequisite functions at once'
+complete -c run -l force -d 'Re-run functions even if they are up to date'
+complete -c run -s k -l keep-going -d 'Keep running independent functions when one fails'
+complete -c run -l output -d 'How to show parallel output' -xa 'interleave group'
+complete -c run -l events -d 'Emit machine-readable events' -xa 'ndjson'
+complete -c run -l events-file -d 'Write events to this file' -r
+complete -c run -l all -d 'With --list, include workspace member functions'
+complete -c run -l status -d 'Show a status line with the running function and elapsed time'
+complete -c run -l profile -d 'Apply the variables of a Runfile profile'
+complete -c run -s n -l dry-run -d 'Print the commands that would run without running them'
+complete -c run -l parallel -d 'Run the named functions concurrently'
+complete -c run -l system -d 'With --install-completion, install for every user'
+complete -c run -l which-shell -d 'Print the default shell and why it was chosen'
+complete -c run -l format -d 'With --list, print as text or json' -xa 'text json'
+complete -c run -s y -l yes -d 'Run @confirm functions without asking'
+complete -c run -l watch -d 'Run the function again when matching files change'
+complete -c run -l which-runfile -d 'Print every path searched for a Runfile and which one is used'
+complete -c run -l from-makefile -d 'Call the rules of the Makefile in the current directory'
+complete -c run -l no-color -d 'Don't color output'
+complete -c run -l split -d 'Run each function in its own multiplexer pane'
+complete -c run -l paginate -d 'Page output longer than the terminal'
+complete -c run -l timings -d 'Report how long each function took'
+complete -c run -s v -l verbose -d 'Print each command before running it'
+complete -c run -l plain -d 'Plain line-by-line output for screen readers'
+complete -c run -l resume -d 'Continue the last failed or interrupted chain'
+complete -c run -l explain -d 'Show a function's definition and resolved commands'
+complete -c run -l no-global -d 'Don't read ~/.runfile along with the Runfile'
+complete -c run -l allow-danger -d 'Run @danger and @prod functions without typing their name'
 complete -c run -l version -d 'Print version information'
 complete -c run -s h -l help -d 'Print help information'
 
@@ -67,3 +109,6 @@ complete -c run -n "__fish_is_first_arg" -a "(__run_get_top_level)"
 
 # Subcommand completions (for the second argument if first was a namespace)
 complete -c run -n "not __fish_is_first_arg; and __fish_is_nth_token 2" -a "(__run_get_subcommands (commandline -opc)[2])"
+
+# Values the function was called with before, with completion.history
+complete -c run -n "not __fish_is_first_arg" -a "(run --complete-history -- (commandline -opc)[2..-1] 2>/dev/null)"
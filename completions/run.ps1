@@ -0,0 +1,106 @@
+# PowerShell completion script for run command
+
+Register-ArgumentCompleter -Native -CommandName run -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    $options = @(
+        @('--list', 'List all available functions'),
+        @('-l', 'List all available functions'),
+        @('--generate-completion', 'Generate shell completion script'),
+        @('--install-completion', 'Install shell completion'),
+        @('--auto-container', 'Run tasks in their container when tools are missing'),
+        @('--no-remote-cache', 'Don''t use the remote cache for this run'),
+        @('--jobs', 'Run up to N prerequisite functions at once'),
+        @('-j', 'Run up to N prerequisite functions at once'),
+        @('--force', 'Re-run functions even if they are up to date'),
+        @('--keep-going', 'Keep running independent functions when one fails'),
+        @('-k', 'Keep running independent functions when one fails'),
+        @('--output', 'How to show parallel output'),
+        @('--events', 'Emit machine-readable events (ndjson)'),
+        @('--events-file', 'Write events to this file'),
+        @('--all', 'With --list, include workspace member functions'),
+        @('--status', 'Show a status line with the running function and elapsed time'),
+        @('--profile', 'Apply the variables of a Runfile profile'),
+        @('--dry-run', 'Print the commands that would run without running them'),
+        @('-n', 'Print the commands that would run without running them'),
+        @('--parallel', 'Run the named functions concurrently'),
+        @('--system', 'With --install-completion, install for every user'),
+        @('--which-shell', 'Print the default shell and why it was chosen'),
+        @('--format', 'With --list, print as text or json'),
+        @('--yes', 'Run @confirm functions without asking'),
+        @('-y', 'Run @confirm functions without asking'),
+        @('--watch', 'Run the function again when matching files change'),
+        @('--which-runfile', 'Print every path searched for a Runfile and which one is used'),
+        @('--from-makefile', 'Call the rules of the Makefile in the current directory'),
+        @('--no-color', 'Don''t color output'),
+        @('--split', 'Run each function in its own multiplexer pane'),
+        @('--paginate', 'Page output longer than the terminal'),
+        @('--timings', 'Report how long each function took'),
+        @('--verbose', 'Print each command before running it'),
+        @('-v', 'Print each command before running it'),
+        @('--plain', 'Plain line-by-line output for screen readers'),
+        @('--resume', 'Continue the last failed or interrupted chain'),
+        @('--explain', 'Show a function''s definition and resolved commands'),
+        @('--no-global', 'Don''t read ~/.runfile along with the Runfile'),
+        @('--allow-danger', 'Run @danger and @prod functions without typing their name'),
+        @('--version', 'Print version'),
+        @('--help', 'Show help'),
+        @('-h', 'Show help')
+    )
+
+    # The words before the one being completed, `run` first
+    $words = @($commandAst.CommandElements |
+        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |
+        ForEach-Object { $_.ToString() })
+    $previous = $words[-1]
+
+    function Complete-Values($values, $type) {
+        $values | Where-Object { $_ -like "$wordToComplete*" } | ForEach-Object {
+            [System.Management.Automation.CompletionResult]::new($_, $_, $type, $_)
+        }
+    }
+
+    # Values of flags that take one
+    switch ($previous) {
+        { $_ -in '--generate-completion', '--install-completion' } {
+            return Complete-Values @('bash', 'zsh', 'fish', 'powershell') 'ParameterValue'
+        }
+        '--output' { return Complete-Values @('interleave', 'group') 'ParameterValue' }
+        '--format' { return Complete-Values @('text', 'json') 'ParameterValue' }
+    }
+    if ($previous -like '-*' -and $words.Count -gt 1) {
+        return
+    }
+
+    # Functions from the Runfile, and its namespace separators (":" by default)
+    $functions = @(run --list 2>$null | ForEach-Object {
+        if ($_ -match '^  (\S+)') { $Matches[1] }
+    })
+    $separators = "$(run --list-separators 2>$null)".Trim()
+    if (-not $separators) {
+        $separators = ':'
+    }
+    $separator = "[$([regex]::Escape($separators))]"
+
+    if ($words.Count -eq 1) {
+        # First argument: flags, functions and namespaces
+        $options | Where-Object { $_[0] -like "$wordToComplete*" } | ForEach-Object {
+            [System.Management.Automation.CompletionResult]::new($_[0], $_[0], 'ParameterName', $_[1])
+        }
+        $topLevel = $functions | ForEach-Object { ($_ -split $separator)[0] } | Select-Object -Unique
+        Complete-Values $topLevel 'Command'
+    } elseif ($words.Count -eq 2) {
+        # Second argument: the commands of the namespace named first
+        $prefix = "^$([regex]::Escape($previous))$separator"
+        $subcommands = $functions | Where-Object { $_ -match $prefix } | ForEach-Object {
+            $_.Substring($previous.Length + 1)
+        }
+        Complete-Values $subcommands 'Command'
+    }
+
+    # Values the function was called with before, with completion.history
+    if ($words.Count -ge 2) {
+        $history = @(run --complete-history -- $words[1..($words.Count - 1)] 2>$null)
+        Complete-Values $history 'ParameterValue'
+    }
+}
@@ -0,0 +1,26 @@
+# Bash hook for run: eval "$(run hook bash)" in ~/.bashrc
+#
+# Defines a function for each function of the Runfile in or above the current
+# directory, calling `run <name>`, and redefines them when the directory
+# changes. Names the shell already knows are left alone.
+
+_run_hook() {
+    [[ "$PWD" == "${_run_hook_dir-}" ]] && return
+    _run_hook_dir=$PWD
+    local name
+    for name in "${_run_hook_functions[@]}"; do
+        unset -f "$name"
+    done
+    _run_hook_functions=()
+    while IFS= read -r name; do
+        type -t "$name" > /dev/null && continue
+        eval "$name() { command run $name \"\$@\"; }"
+        _run_hook_functions+=("$name")
+    done < <(command run --hook-functions 2> /dev/null)
+}
+
+_run_hook_functions=()
+if [[ ";${PROMPT_COMMAND[*]:-};" != *";_run_hook;"* ]]; then
+    PROMPT_COMMAND="_run_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+_run_hook
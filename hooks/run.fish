@@ -0,0 +1,21 @@
+# Fish hook for run: run hook fish | source in ~/.config/fish/config.fish
+#
+# Defines a function for each function of the Runfile in or above the current
+# directory, calling `run <name>`, and redefines them when the directory
+# changes. Names the shell already knows are left alone.
+
+function __run_hook --on-variable PWD
+    for name in $__run_hook_functions
+        functions --erase $name
+    end
+    set -g __run_hook_functions
+    for name in (command run --hook-functions 2> /dev/null)
+        type -q $name; and continue
+        function $name --inherit-variable name
+            command run $name $argv
+        end
+        set -g -a __run_hook_functions $name
+    end
+end
+
+__run_hook
@@ -0,0 +1,179 @@
+//! Declared function outputs (`@outputs`): verification after a run, hash
+//! manifests under `.run/artifacts`, and listing or cleaning them later.
+//!
+//! Manifests use the `sha256sum` format, one `<hash>  <path>` line per file,
+//! so they can also be checked with `sha256sum -c`.
+
+use crate::glob;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory, relative to the working directory, holding one manifest per function.
+pub const ARTIFACTS_DIR: &str = ".run/artifacts";
+
+/// A recorded output file and its SHA-256 hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// State of a recorded artifact compared with the file on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArtifactState {
+    Unchanged,
+    Modified,
+    Missing,
+}
+
+/// Expand output patterns to the files they match, failing with the list of
+/// patterns that matched nothing.
+pub fn collect(patterns: &[String], base: &Path) -> Result<Vec<PathBuf>, Vec<String>> {
+    let mut files = Vec::new();
+    let mut unmatched = Vec::new();
+    for pattern in patterns {
+        let matched = glob::expand(pattern, base);
+        if matched.is_empty() {
+            unmatched.push(pattern.clone());
+        }
+        for file in matched {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    if unmatched.is_empty() {
+        Ok(files)
+    } else {
+        Err(unmatched)
+    }
+}
+
+/// Hash `files` and write them as the manifest for `function`.
+pub fn record(function: &str, files: &[PathBuf], base: &Path) -> io::Result<Vec<Artifact>> {
+    let mut artifacts = Vec::with_capacity(files.len());
+    for path in files {
+        artifacts.push(Artifact {
+            hash: hash_file(&base.join(path))?,
+            path: path.clone(),
+        });
+    }
+
+    let manifest = manifest_path(function, base);
+    if let Some(parent) = manifest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content: String = artifacts
+        .iter()
+        .map(|artifact| format!("{}  {}\n", artifact.hash, display_path(&artifact.path)))
+        .collect();
+    fs::write(manifest, content)?;
+    Ok(artifacts)
+}
+
+/// The artifacts last recorded for `function`, if any.
+pub fn load(function: &str, base: &Path) -> Option<Vec<Artifact>> {
+    let content = fs::read_to_string(manifest_path(function, base)).ok()?;
+    Some(
+        content
+            .lines()
+            .filter_map(|line| line.split_once("  "))
+            .map(|(hash, path)| Artifact {
+                path: PathBuf::from(path),
+                hash: hash.to_string(),
+            })
+            .collect(),
+    )
+}
+
+/// Names of all functions with a recorded manifest, sorted.
+pub fn recorded_functions(base: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(base.join(ARTIFACTS_DIR)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.strip_suffix(".sha256").map(decode_name)
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Compare a recorded artifact with the file currently on disk.
+pub fn state(artifact: &Artifact, base: &Path) -> ArtifactState {
+    match hash_file(&base.join(&artifact.path)) {
+        Ok(hash) if hash == artifact.hash => ArtifactState::Unchanged,
+        Ok(_) => ArtifactState::Modified,
+        Err(_) => ArtifactState::Missing,
+    }
+}
+
+/// Delete the recorded artifacts of `function` and its manifest, returning
+/// the paths that were removed. Directories left empty are removed too.
+pub fn clean(function: &str, base: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for artifact in load(function, base).unwrap_or_default() {
+        let path = base.join(&artifact.path);
+        if path.is_file() {
+            fs::remove_file(&path)?;
+            remove_empty_parents(&artifact.path, base);
+            removed.push(artifact.path);
+        }
+    }
+    match fs::remove_file(manifest_path(function, base)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    Ok(removed)
+}
+
+/// Hex-encoded SHA-256 of a file's contents.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Lowercase hex encoding of `bytes`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A path with `/` separators, as written in manifests.
+pub fn display_path(path: &Path) -> String {
+    path.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn manifest_path(function: &str, base: &Path) -> PathBuf {
+    base.join(ARTIFACTS_DIR)
+        .join(format!("{}.sha256", encode_name(function)))
+}
+
+/// Function names may contain `:`, which isn't allowed in Windows file names.
+pub fn encode_name(function: &str) -> String {
+    function.replace(':', "--")
+}
+
+fn decode_name(file_stem: &str) -> String {
+    file_stem.replace("--", ":")
+}
+
+/// Remove the now-empty directories leading to a deleted file, innermost first.
+fn remove_empty_parents(path: &Path, base: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current.as_os_str().is_empty() || fs::remove_dir(base.join(current)).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
@@ -1,5 +1,7 @@
-// Abstract Syntax Tree definitions
+//! The syntax tree of a Runfile, as [`parse_script`](crate::parser::parse_script)
+//! returns it.
 
+/// A parsed Runfile: its statements in order.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
@@ -14,21 +16,352 @@ pub enum Statement {
     SimpleFunctionDef {
         name: String,
         command_template: String,
+        attributes: Vec<Attribute>,
     },
     BlockFunctionDef {
         name: String,
         commands: Vec<String>,
+        attributes: Vec<Attribute>,
     },
     FunctionCall {
         name: String,
-        args: Vec<String>,
+        args: Vec<Expression>,
     },
     Command {
         command: String,
     },
+    Setting {
+        key: String,
+        value: String,
+    },
+    /// `template name(params) { ... }`: functions to generate per instantiation.
+    TemplateDef {
+        name: String,
+        params: Vec<String>,
+        body: String,
+    },
+    /// `env: one_of(dev, staging, prod) = dev`: a variable that may only
+    /// hold these values, with an optional initial value.
+    EnumVar {
+        name: String,
+        allowed: Vec<String>,
+        value: Option<Expression>,
+    },
+    /// `secretvar NAME = enc:...`: a variable encrypted with age, decrypted
+    /// when a command refers to it.
+    SecretVar {
+        name: String,
+        value: String,
+    },
+    /// `name = capture(function)`: a variable holding the output of another
+    /// function, run when a command first refers to it.
+    CaptureVar {
+        name: String,
+        function: String,
+    },
+    /// `out, errors, code = run_capture(function)`: variables holding the
+    /// stdout, stderr and exit code of one run of another function, run when
+    /// a command first refers to one of them. A failure doesn't stop `run`.
+    RunCapture {
+        stdout: Option<String>,
+        stderr: Option<String>,
+        code: Option<String>,
+        function: String,
+    },
+    /// `profile name { KEY=value ... }`: variables applied with `--profile`.
+    ProfileDef {
+        name: String,
+        variables: Vec<(String, String)>,
+    },
+    /// `env { KEY=value ... }` outside functions: variables set on every
+    /// command.
+    EnvBlock {
+        variables: Vec<(String, String)>,
+    },
+    /// `instantiate name(args) ...`: generate the functions of each template.
+    Instantiate {
+        calls: Vec<(String, Vec<Expression>)>,
+    },
+    /// `ifdef NAME { ... } else { ... }` (`ifndef` when negated): the branch
+    /// to load, picked by whether the environment variable is set.
+    EnvGuard {
+        variable: String,
+        negated: bool,
+        then_branch: Vec<Statement>,
+        else_branch: Vec<Statement>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
+    /// Literal text, used as-is.
+    String(String),
+    /// Text in which `$name` / `${name}` references are substituted when evaluated.
+    Interpolated(String),
+    /// `[web, api, worker]`: a list, of the values of its items.
+    List(Vec<Expression>),
+}
+
+/// The value of a variable: text, or the items of a list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
     String(String),
+    List(Vec<String>),
+}
+
+impl Value {
+    /// The items of the value: those of a list, or the text as the only one.
+    pub fn items(&self) -> &[String] {
+        match self {
+            Value::String(text) => std::slice::from_ref(text),
+            Value::List(items) => items,
+        }
+    }
+}
+
+/// A list is written as its items separated by spaces, as a command sees it.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.items().join(" "))
+    }
+}
+
+/// Annotation attached to a function definition, written as `@name args`
+/// on the line(s) preceding it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attribute {
+    /// `@skip-if <condition>`: skip the function when the condition holds.
+    SkipIf(Condition),
+    /// `@only-if <condition>`: run the function only when the condition holds.
+    OnlyIf(Condition),
+    /// `requires tool...` (or `@requires`): tools that must be on PATH,
+    /// optionally with a version constraint such as `node >= 18`.
+    Requires(Vec<ToolRequirement>),
+    /// `@container image`: image to run the function in when required tools
+    /// are missing locally.
+    Container(String),
+    /// `@shell pwsh`: run the function's commands with this shell instead of
+    /// `RUN_SHELL` or the platform default.
+    Shell(String),
+    /// `@outputs dist/** build/app`: files the function is expected to
+    /// produce, as paths or glob patterns relative to the working directory.
+    Outputs(Vec<String>),
+    /// `@inputs src/** Cargo.toml`: files whose contents, together with the
+    /// function body and arguments, decide whether cached outputs are reusable.
+    Inputs(Vec<String>),
+    /// `@always`: never skip the function as up to date or restore it from
+    /// the cache; it still refreshes the stored hashes.
+    Always,
+    /// `needs(a, b)` clause: functions that must complete before this one.
+    Needs(Vec<String>),
+    /// `deploy(env=$DEPLOY_ENV)`: names for the positional arguments, bound
+    /// as `$env` in the body, with defaults for those not passed.
+    Params(Vec<Parameter>),
+    /// `@notify-on failure`: post to the `notify.webhook` when the function
+    /// finishes with this outcome.
+    NotifyOn(NotifyOn),
+    /// `env { KEY=value ... }` at the start of a block: variables set on the
+    /// function's commands.
+    Env(Vec<(String, String)>),
+    /// `@root-relative`: run in the directory containing the Runfile.
+    RootRelative,
+    /// `@cwd ./frontend`: run in this directory, relative to the Runfile's.
+    Cwd(String),
+    /// `@once`: run at most once per invocation, replaying its output after.
+    Once,
+    /// `@confirm("Really deploy to prod?")`: ask on the terminal before
+    /// running the function, with a default question when none is given.
+    Confirm(Option<String>),
+    /// `@danger` or `@prod`: the function is destructive or touches
+    /// production, so it only runs with `--allow-danger`, `RUN_ALLOW_DANGER`
+    /// set or its name typed to confirm. Holds the annotation's name.
+    Danger(String),
+    /// `@keep-temp`: keep the `$(mktemp)` and `$(mktemp_dir)` paths created
+    /// during the invocation instead of removing them at its end.
+    KeepTemp,
+    /// `@allow-exit 1 3`: these non-zero exit codes don't count as failures.
+    AllowExit(Vec<i32>),
+    /// `@map-exit 2=warn`: report these exit codes as warnings instead.
+    MapExit(Vec<(i32, ExitAction)>),
+    /// `## Build the project` or `# desc: Build the project` above the
+    /// definition: shown next to the function in `run --list`.
+    Description(String),
+    /// `@matrix target=[x86_64,aarch64] profile=[debug,release]`: run the
+    /// function once for each combination of values.
+    Matrix(Vec<MatrixAxis>),
+    /// `@supervise restart=on-failure max=5`: run the function again when it
+    /// ends, as for a dev server that crashes.
+    Supervise(Supervision),
+    /// `@umask 077`: permission bits the function's commands leave off the
+    /// files and directories they create.
+    Umask(u32),
+    /// `@fix-perms +x dist/bin/*`: change the permissions of these files,
+    /// as paths or glob patterns relative to the working directory, once the
+    /// function succeeds.
+    FixPerms(FileMode, Vec<String>),
+}
+
+/// A named positional argument in a function's signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    /// Value when the argument isn't passed; without one it is required.
+    pub default: Option<ParamDefault>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamDefault {
+    /// `$NAME`: the value of this environment variable, when it is set.
+    Env(String),
+    /// A literal value, quoted or not.
+    Value(String),
+}
+
+impl std::fmt::Display for ParamDefault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamDefault::Env(name) => write!(f, "${}", name),
+            ParamDefault::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// One dimension of a `@matrix`, bound to `$name` in each variant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixAxis {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+/// When and how often `@supervise` runs a function again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Supervision {
+    /// Also restart the function when it succeeds (`restart=always`),
+    /// rather than only when it fails (`restart=on-failure`, the default).
+    pub always: bool,
+    /// Restarts allowed before its failure stands; unlimited without one.
+    pub max: Option<u32>,
+    /// Wait before the first restart, doubled for each one after it.
+    pub backoff: std::time::Duration,
+}
+
+/// Permissions for `@fix-perms`, written as for chmod.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileMode {
+    /// `755`: these permissions exactly.
+    Octal(u32),
+    /// `u+x,go-w`: changes to the permissions a file has.
+    Symbolic(Vec<ModeChange>),
+}
+
+/// One clause of a symbolic mode, such as `go-w`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeChange {
+    /// The bits of the classes changed, out of `0o700` for the user, `0o070`
+    /// for the group and `0o007` for others; all of them without any.
+    pub who: u32,
+    pub op: ModeOp,
+    /// The permissions, `rwx` as `0o7`, for each class changed.
+    pub perms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeOp {
+    /// `+`
+    Add,
+    /// `-`
+    Remove,
+    /// `=`
+    Set,
+}
+
+/// Which outcomes of a function trigger a webhook notification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotifyOn {
+    Success,
+    Failure,
+    Always,
+}
+
+/// How `@map-exit` treats an exit code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitAction {
+    /// Succeed, but report a warning in the summary.
+    Warn,
+    /// Succeed silently, as with `@allow-exit`.
+    Ok,
+}
+
+/// A tool a function needs, e.g. `docker` or `terraform ~> 1.6`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolRequirement {
+    pub tool: String,
+    pub constraint: Option<VersionConstraint>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionConstraint {
+    pub op: VersionOp,
+    pub version: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionOp {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// `~>`: at least this version, below the next release of its
+    /// second-to-last component (`~> 1.6` allows 1.6 up to 2.0).
+    Pessimistic,
+}
+
+/// Precondition evaluated right before a function runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// `exists("path")`: the file or directory exists.
+    Exists(String),
+    /// `command_exists("tool")`: the executable is on PATH.
+    CommandExists(String),
+    /// `env("NAME")`: the environment variable is set.
+    Env(String),
+    /// `!condition`
+    Not(Box<Condition>),
+}
+
+impl std::fmt::Display for ToolRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tool)?;
+        if let Some(constraint) = &self.constraint {
+            write!(f, " {}", constraint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.op {
+            VersionOp::Eq => "=",
+            VersionOp::Gt => ">",
+            VersionOp::Ge => ">=",
+            VersionOp::Lt => "<",
+            VersionOp::Le => "<=",
+            VersionOp::Pessimistic => "~>",
+        };
+        let version: Vec<String> = self.version.iter().map(u64::to_string).collect();
+        write!(f, "{} {}", op, version.join("."))
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Exists(path) => write!(f, "exists(\"{}\")", path),
+            Condition::CommandExists(tool) => write!(f, "command_exists(\"{}\")", tool),
+            Condition::Env(name) => write!(f, "env(\"{}\")", name),
+            Condition::Not(inner) => write!(f, "!{}", inner),
+        }
+    }
 }
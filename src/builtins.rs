@@ -0,0 +1,717 @@
+//! Commands run natively instead of through the shell, so they behave the
+//! same on every platform: `sleep 2s`, `wait_for tcp://localhost:5432
+//! timeout=30s`, `kill_port 3000`, `is_running postgres`,
+//! `diff expected.txt actual.txt` and `verify_checksum app.tar.gz <sha256>`.
+//!
+//! A builtin command only applies when it makes up the whole command, save
+//! for a final `> file` or `>> file` redirecting its output; as part of a
+//! pipeline or `&&` chain the shell's own command of that name is used.
+//! Builtin substitutions (`$(free_port)`, `$(is_running postgres)`,
+//! `$(mktemp)`, `$(mktemp_dir)`, `$(date -7d "%Y-%m-%d")`, `$(duration 5400)`,
+//! `$(seconds 1h30m)` and `$(sha256 app.tar.gz)`) are replaced wherever they
+//! appear. Paths are relative to the directory the command runs in.
+//! Temporary files and directories are removed at the end of the invocation
+//! that created them, unless a function run by it is `@keep-temp`.
+
+use crate::{artifacts, dates, diff};
+use std::borrow::Cow;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long `wait_for` waits when no `timeout=` is given.
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause between `wait_for` checks.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The temporary paths `$(mktemp)` and `$(mktemp_dir)` created for one
+/// invocation, or while the Runfile loaded, shared by the commands that may
+/// use them. The paths are removed when the last of them is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct TempPaths(Arc<Mutex<Created>>);
+
+#[derive(Debug, Default)]
+struct Created {
+    paths: Vec<PathBuf>,
+    /// Keep the paths rather than remove them (`@keep-temp`).
+    keep: bool,
+}
+
+impl TempPaths {
+    /// Keep the paths rather than remove them (`@keep-temp`).
+    pub fn keep(&self) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).keep = true;
+    }
+
+    fn add(&self, path: PathBuf) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .paths
+            .push(path);
+    }
+}
+
+impl Drop for Created {
+    fn drop(&mut self) {
+        if self.keep {
+            for path in &self.paths {
+                eprintln!("Keeping {}", path.display());
+            }
+            return;
+        }
+        for path in &self.paths {
+            let _ = remove(path);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Builtin {
+    /// `sleep 2s`
+    Sleep(Duration),
+    /// `wait_for <target> [timeout=30s]`
+    WaitFor { target: String, timeout: Duration },
+    /// `kill_port 3000`
+    KillPort(u16),
+    /// `is_running postgres`
+    IsRunning(String),
+    /// `diff old.txt new.txt`
+    Diff(String, String),
+    /// `verify_checksum app.tar.gz <sha256>`, or a checksum file such as
+    /// `SHA256SUMS` in place of the hash
+    VerifyChecksum { path: String, expected: String },
+}
+
+/// Where a builtin's output goes instead of stdout: `> file` or `>> file`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub path: String,
+    pub append: bool,
+}
+
+impl Redirect {
+    /// Open the target file, relative to `cwd`.
+    pub fn open(&self, cwd: &Path) -> std::io::Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(cwd.join(&self.path))
+    }
+}
+
+/// Recognise a builtin command, with any redirection of its output.
+pub fn parse(command: &str) -> Option<(Builtin, Option<Redirect>)> {
+    let mut words: Vec<&str> = command.split_whitespace().collect();
+    let redirect = split_redirect(&mut words);
+    parse_words(&words).map(|builtin| (builtin, redirect))
+}
+
+/// Remove a trailing `> file`, `>> file`, `>file` or `>>file` from `words`.
+fn split_redirect(words: &mut Vec<&str>) -> Option<Redirect> {
+    let (operator, path, consumed) = match words.as_slice() {
+        [.., operator @ (">" | ">>"), path] => (*operator, *path, 2),
+        [.., last] => match last.strip_prefix(">>") {
+            Some(path) => (">>", path, 1),
+            None => (">", last.strip_prefix('>')?, 1),
+        },
+        [] => return None,
+    };
+    if path.is_empty() || path.starts_with(['>', '&']) {
+        return None;
+    }
+    let redirect = Redirect {
+        path: unquote(path),
+        append: operator == ">>",
+    };
+    words.truncate(words.len() - consumed);
+    Some(redirect)
+}
+
+fn parse_words(words: &[&str]) -> Option<Builtin> {
+    match words {
+        ["sleep", duration] => parse_duration(duration).map(Builtin::Sleep),
+        ["wait_for", target, options @ ..] => {
+            let mut timeout = DEFAULT_WAIT_TIMEOUT;
+            for option in options {
+                timeout = parse_duration(option.strip_prefix("timeout=")?)?;
+            }
+            Some(Builtin::WaitFor {
+                target: unquote(target),
+                timeout,
+            })
+        }
+        ["kill_port", port] => port.parse().ok().map(Builtin::KillPort),
+        ["is_running", name] => Some(Builtin::IsRunning(unquote(name))),
+        ["diff", old, new] if !old.starts_with('-') && !new.starts_with('-') => {
+            Some(Builtin::Diff(unquote(old), unquote(new)))
+        }
+        ["verify_checksum", path, expected] => Some(Builtin::VerifyChecksum {
+            path: unquote(path),
+            expected: unquote(expected),
+        }),
+        _ => None,
+    }
+}
+
+/// Replace builtin substitutions in `text` with their results, with paths
+/// relative to `cwd` and temporary paths added to `temp`; any other `$(...)`
+/// is left for the shell.
+pub fn expand<'a>(text: &'a str, cwd: &Path, temp: &TempPaths) -> std::io::Result<Cow<'a, str>> {
+    if !text.contains("$(") {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("$(") {
+        let Some(length) = rest[start..].find(')') else {
+            break;
+        };
+        let inner = &rest[start + 2..start + length];
+        let words = split_words(inner);
+        let value = match words.as_slice() {
+            ["free_port"] => Some(free_port()?.to_string()),
+            ["is_running", name] => Some(is_running(&unquote(name)).to_string()),
+            ["mktemp"] => Some(create_temp(false, temp)?.display().to_string()),
+            ["mktemp_dir"] => Some(create_temp(true, temp)?.display().to_string()),
+            ["date", options @ ..] => date(options),
+            ["duration", duration] => parse_duration(duration).map(format_duration),
+            ["seconds", duration] => parse_duration(duration).map(|d| d.as_secs().to_string()),
+            ["sha256", path] => Some(sha256(&cwd.join(unquote(path)))?),
+            _ => None,
+        };
+        result.push_str(&rest[..start]);
+        match value {
+            Some(value) => result.push_str(&value),
+            None => result.push_str(&rest[start..start + length + 1]),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    result.push_str(rest);
+    Ok(Cow::Owned(result))
+}
+
+/// Run a builtin command in `cwd`, writing any output to `out`, colored if
+/// `color`.
+pub fn run(
+    builtin: &Builtin,
+    cwd: &Path,
+    out: &mut dyn Write,
+    color: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match builtin {
+        Builtin::Sleep(duration) => {
+            std::thread::sleep(*duration);
+            Ok(())
+        }
+        Builtin::WaitFor { target, timeout } => wait_for(target, *timeout),
+        Builtin::KillPort(port) => {
+            let killed = kill_port(*port)?;
+            if killed == 0 {
+                writeln!(out, "Nothing is listening on port {}", port)?;
+            } else {
+                writeln!(
+                    out,
+                    "Stopped {} process(es) listening on port {}",
+                    killed, port
+                )?;
+            }
+            Ok(())
+        }
+        Builtin::IsRunning(name) => {
+            if is_running(name) {
+                Ok(())
+            } else {
+                Err(format!("'{}' is not running", name).into())
+            }
+        }
+        Builtin::Diff(old, new) => {
+            let read = |path: &str| {
+                std::fs::read(cwd.join(path))
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                    .map_err(|e| format!("diff: {}: {}", path, e))
+            };
+            match diff::unified(old, &read(old)?, new, &read(new)?, color) {
+                None => Ok(()),
+                Some(output) => {
+                    out.write_all(output.as_bytes())?;
+                    Err(format!("{} and {} differ", old, new).into())
+                }
+            }
+        }
+        Builtin::VerifyChecksum { path, expected } => {
+            let actual = sha256(&cwd.join(path))?;
+            let expected = expected_checksum(path, expected, cwd)?;
+            if !actual.eq_ignore_ascii_case(&expected) {
+                return Err(format!(
+                    "Checksum mismatch for {}:\n  expected {}\n  actual   {}",
+                    path, expected, actual
+                )
+                .into());
+            }
+            writeln!(out, "{}: OK", path)?;
+            Ok(())
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of the file at `path`, with the path in any error.
+fn sha256(path: &Path) -> std::io::Result<String> {
+    artifacts::hash_file(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("sha256: {}: {}", path.display(), e)))
+}
+
+/// The hash `verify_checksum` expects for `path`: `expected` itself, with an
+/// optional `sha256:` prefix, or read from the checksum file it names, either
+/// a single hash or `sha256sum` lines, `<hash>  <name>`.
+fn expected_checksum(path: &str, expected: &str, cwd: &Path) -> Result<String, String> {
+    let is_hash = |text: &str| text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit());
+    let hash = expected.strip_prefix("sha256:").unwrap_or(expected);
+    if is_hash(hash) {
+        return Ok(hash.to_string());
+    }
+    let sums = std::fs::read_to_string(cwd.join(expected)).map_err(|e| {
+        format!(
+            "verify_checksum: '{}' is neither a SHA-256 hash nor a readable checksum file ({})",
+            expected, e
+        )
+    })?;
+    let name = Path::new(path).file_name().unwrap_or_default();
+    let lines: Vec<&str> = sums
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if let [line] = lines.as_slice()
+        && is_hash(line)
+    {
+        return Ok(line.to_string());
+    }
+    lines
+        .iter()
+        .find_map(|line| {
+            let (hash, file) = line.split_once(char::is_whitespace)?;
+            // `*name` marks a file hashed in binary mode
+            let file = file.trim_start().trim_start_matches('*');
+            (is_hash(hash) && Path::new(file).file_name() == Some(name)).then(|| hash.to_string())
+        })
+        .ok_or_else(|| format!("verify_checksum: no checksum for {} in {}", path, expected))
+}
+
+fn unquote(word: &str) -> String {
+    word.trim_matches(['"', '\'']).to_string()
+}
+
+/// Parse a duration such as `500ms`, `2s`, `1.5m`, `1h`, `1d` or `2w`, or
+/// a sum of them such as `1h30m`; a bare number is in seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+    if text.is_empty() {
+        return None;
+    }
+    let mut seconds = 0.0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let (number, after) = rest.split_at(split);
+        let unit = after
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit);
+        let number: f64 = number.parse().ok()?;
+        seconds += match unit {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            "d" => number * 86_400.0,
+            "w" => number * 604_800.0,
+            _ => return None,
+        };
+        rest = after;
+    }
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Format a duration the way `parse_duration` reads it, such as `1h30m` or
+/// `2d4h`, leaving out units that are zero.
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    let mut result = String::new();
+    for (unit, length) in [
+        ("w", 604_800),
+        ("d", 86_400),
+        ("h", 3600),
+        ("m", 60),
+        ("s", 1),
+    ] {
+        if seconds >= length {
+            result.push_str(&format!("{}{}", seconds / length, unit));
+            seconds %= length;
+        }
+    }
+    let millis = duration.subsec_millis();
+    if millis > 0 {
+        result.push_str(&format!("{}ms", millis));
+    }
+    if result.is_empty() {
+        result.push_str("0s");
+    }
+    result
+}
+
+/// `$(date [utc] [+1d] [-2h] ["%Y-%m-%d"])`: now, shifted by the offsets,
+/// in the format given or `%Y-%m-%d`, local time unless `utc`. Anything else,
+/// such as `$(date +%s)` or a plain `$(date)`, is left for the shell's `date`.
+fn date(options: &[&str]) -> Option<String> {
+    if options.is_empty() {
+        return None;
+    }
+    let mut utc = false;
+    let mut offset: i64 = 0;
+    let mut format = None;
+    for option in options {
+        if *option == "utc" {
+            utc = true;
+        } else if let Some(quoted) = option
+            .strip_prefix('"')
+            .and_then(|o| o.strip_suffix('"'))
+            .or_else(|| option.strip_prefix('\'').and_then(|o| o.strip_suffix('\'')))
+            .filter(|f| !f.starts_with('+'))
+        {
+            format = Some(quoted);
+        } else {
+            let (sign, duration) = match option.split_at_checked(1)? {
+                ("+", duration) => (1, duration),
+                ("-", duration) => (-1, duration),
+                _ => return None,
+            };
+            offset += sign * parse_duration(duration)?.as_secs() as i64;
+        }
+    }
+    let format = format.unwrap_or(dates::DEFAULT_FORMAT);
+    Some(dates::DateTime::now_plus(offset, utc).format(format))
+}
+
+/// Split on whitespace outside double and single quotes, keeping the quotes.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                start.get_or_insert(index);
+            }
+            (None, _) if c.is_whitespace() => {
+                if let Some(begin) = start.take() {
+                    words.push(&text[begin..index]);
+                }
+            }
+            (None, _) => {
+                start.get_or_insert(index);
+            }
+        }
+    }
+    if let Some(begin) = start {
+        words.push(&text[begin..]);
+    }
+    words
+}
+
+/// Wait until `target` is available: a TCP port accepts connections
+/// (`tcp://host:port`), a URL responds successfully (`http://`, `https://`)
+/// or a file exists (`file://path`).
+fn wait_for(target: &str, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let check: Box<dyn Fn() -> bool> = if let Some(address) = target.strip_prefix("tcp://") {
+        let address = address.trim_end_matches('/').to_string();
+        Box::new(move || tcp_ready(&address))
+    } else if target.starts_with("http://") || target.starts_with("https://") {
+        let url = target.to_string();
+        Box::new(move || http_ready(&url))
+    } else if let Some(path) = target.strip_prefix("file://") {
+        let path = path.to_string();
+        Box::new(move || std::path::Path::new(&path).exists())
+    } else {
+        return Err(format!(
+            "wait_for: unsupported target '{}' (expected tcp://, http://, https:// or file://)",
+            target
+        )
+        .into());
+    };
+
+    let started = Instant::now();
+    loop {
+        if check() {
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "Timed out after {}s waiting for {}",
+                timeout.as_secs_f64(),
+                target
+            )
+            .into());
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn tcp_ready(address: &str) -> bool {
+    let Ok(addresses) = address.to_socket_addrs() else {
+        return false;
+    };
+    addresses
+        .into_iter()
+        .any(|address| TcpStream::connect_timeout(&address, POLL_INTERVAL).is_ok())
+}
+
+fn http_ready(url: &str) -> bool {
+    Command::new("curl")
+        .args(["-fsS", "-o", "/dev/null", "--max-time", "5", url])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// A TCP port that is free on this machine right now.
+pub fn free_port() -> std::io::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// Terminate the processes listening on TCP `port`, returning how many
+/// there were.
+fn kill_port(port: u16) -> Result<usize, Box<dyn std::error::Error>> {
+    let pids = listening_pids(port)?;
+    for &pid in &pids {
+        terminate(pid)?;
+    }
+    Ok(pids.len())
+}
+
+/// Whether a process named `name` is running, ignoring any `.exe` suffix.
+fn is_running(name: &str) -> bool {
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+    process_names().iter().any(|process| {
+        let process = process.rsplit(['/', '\\']).next().unwrap_or(process);
+        process.strip_suffix(".exe").unwrap_or(process) == name
+    })
+}
+
+/// Read `/proc/net/tcp` and `/proc/net/tcp6` for the sockets listening on
+/// `port`, then find the processes holding them.
+#[cfg(target_os = "linux")]
+fn listening_pids(port: u16) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    const LISTEN: &str = "0A";
+    let mut inodes = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(contents) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (Some(local), Some(state), Some(inode)) =
+                (fields.get(1), fields.get(3), fields.get(9))
+            else {
+                continue;
+            };
+            let local_port = local
+                .rsplit(':')
+                .next()
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+            if local_port == Some(port) && *state == LISTEN {
+                inodes.push(format!("socket:[{}]", inode));
+            }
+        }
+    }
+    if inodes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pids = Vec::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds_socket = fds.flatten().any(|fd| {
+            std::fs::read_link(fd.path()).is_ok_and(|target| {
+                inodes
+                    .iter()
+                    .any(|inode| target.as_os_str() == inode.as_str())
+            })
+        });
+        if holds_socket {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn listening_pids(port: u16) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let output = Command::new("lsof")
+        .args(["-nP", "-t", &format!("-iTCP:{}", port), "-sTCP:LISTEN"])
+        .output()
+        .map_err(|e| format!("kill_port needs lsof: {}", e))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect())
+}
+
+#[cfg(windows)]
+fn listening_pids(port: u16) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let output = Command::new("netstat")
+        .args(["-ano", "-p", "TCP"])
+        .output()?;
+    let suffix = format!(":{}", port);
+    let mut pids: Vec<u32> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, local, _, "LISTENING", pid] if local.ends_with(&suffix) => pid.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect();
+    pids.sort_unstable();
+    pids.dedup();
+    Ok(pids)
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+    // SAFETY: kill has no memory-safety preconditions
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(format!(
+            "Could not stop process {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn terminate(pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("taskkill")
+        .args(["/F", "/PID", &pid.to_string()])
+        .stdout(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(format!("Could not stop process {}", pid).into());
+    }
+    Ok(())
+}
+
+/// The names of all running processes.
+#[cfg(target_os = "linux")]
+fn process_names() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+        })
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+        .map(|comm| comm.trim_end().to_string())
+        .collect()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_names() -> Vec<String> {
+    Command::new("ps")
+        .args(["-A", "-o", "comm="])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|line| line.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(windows)]
+fn process_names() -> Vec<String> {
+    Command::new("tasklist")
+        .args(["/FO", "CSV", "/NH"])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split(',').next())
+                .map(|name| name.trim_matches('"').to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Create an empty temporary file or directory, tracked in `temp`.
+fn create_temp(directory: bool, temp: &TempPaths) -> std::io::Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    loop {
+        let path = std::env::temp_dir().join(format!(
+            "run-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+            nanos
+        ));
+        // Only the user can read them, as with mktemp
+        let created = if directory {
+            let builder = &mut std::fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(builder, 0o700);
+            builder.create(&path)
+        } else {
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&path).map(drop)
+        };
+        match created {
+            Ok(()) => {
+                temp.add(path.clone());
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn remove(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
@@ -0,0 +1,282 @@
+//! Input-hash caching for functions that declare both `@inputs` and `@outputs`.
+//!
+//! A function's cache key hashes its name, body, arguments and the contents of
+//! its input files. When the key matches the one recorded for the last run
+//! and the recorded outputs are unchanged, the function is up to date. With a
+//! remote cache configured, outputs are also shared between machines: archives
+//! are uploaded under their key after a run and downloaded instead of running
+//! when another machine has already produced them.
+
+use crate::artifacts::{self, Artifact};
+use crate::glob;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Directory, relative to the working directory, holding cache keys.
+const CACHE_DIR: &str = ".run/cache";
+
+/// First line of an output archive.
+const ARCHIVE_HEADER: &str = "runcache 1";
+
+/// Compute the cache key for running `function` with `body` and `args`
+/// against the current contents of its `inputs`.
+pub fn input_key(
+    function: &str,
+    body: &str,
+    args: &[String],
+    inputs: &[String],
+    base: &Path,
+) -> io::Result<String> {
+    let mut files: Vec<PathBuf> = inputs
+        .iter()
+        .flat_map(|pattern| glob::expand(pattern, base))
+        .collect();
+    files.sort();
+    files.dedup();
+
+    let mut hasher = Sha256::new();
+    for part in [function, body]
+        .into_iter()
+        .chain(args.iter().map(String::as_str))
+    {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    for file in files {
+        hasher.update(artifacts::display_path(&file).as_bytes());
+        hasher.update([0]);
+        hasher.update(artifacts::hash_file(&base.join(&file))?.as_bytes());
+    }
+    Ok(artifacts::to_hex(&hasher.finalize()))
+}
+
+/// Whether `function` last ran with this key and its recorded outputs are
+/// still as it left them.
+pub fn is_up_to_date(function: &str, key: &str, base: &Path) -> bool {
+    let recorded = fs::read_to_string(key_path(function, base)).unwrap_or_default();
+    if recorded.trim() != key {
+        return false;
+    }
+    artifacts::load(function, base).is_some_and(|recorded| {
+        recorded
+            .iter()
+            .all(|artifact| artifacts::state(artifact, base) == artifacts::ArtifactState::Unchanged)
+    })
+}
+
+/// Remember the key `function` last ran with.
+pub fn save_key(function: &str, key: &str, base: &Path) -> io::Result<()> {
+    let path = key_path(function, base);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", key))
+}
+
+fn key_path(function: &str, base: &Path) -> PathBuf {
+    base.join(CACHE_DIR)
+        .join(format!("{}.key", artifacts::encode_name(function)))
+}
+
+/// Whether a remote cache may be written to, or only read from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    ReadWrite,
+    ReadOnly,
+}
+
+/// Where shared outputs are stored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    /// `file:///path`: a directory, e.g. on a shared drive.
+    Directory(PathBuf),
+    /// `http(s)://...`: objects fetched with GET and stored with PUT.
+    Http(String),
+    /// `s3://bucket/prefix`: objects copied with the `aws` CLI.
+    S3(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteCache {
+    pub backend: Backend,
+    pub mode: Mode,
+}
+
+impl RemoteCache {
+    /// Resolve the remote cache from the `cache.remote` and `cache.mode`
+    /// settings, overridden by `RUN_REMOTE_CACHE` and `RUN_CACHE_MODE`.
+    pub fn resolve(url: Option<&str>, mode: Option<&str>) -> Option<RemoteCache> {
+        let url = std::env::var("RUN_REMOTE_CACHE")
+            .ok()
+            .or_else(|| url.map(str::to_string))
+            .filter(|url| !url.is_empty())?;
+        let mode = std::env::var("RUN_CACHE_MODE")
+            .ok()
+            .or_else(|| mode.map(str::to_string));
+
+        let url = url.trim_end_matches('/');
+        let backend = if let Some(path) = url.strip_prefix("file://") {
+            Backend::Directory(PathBuf::from(path))
+        } else if url.starts_with("s3://") {
+            Backend::S3(url.to_string())
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            Backend::Http(url.to_string())
+        } else {
+            eprintln!("Warning: unsupported remote cache '{}' (ignored)", url);
+            return None;
+        };
+        let mode = match mode.as_deref() {
+            Some("read-only") | Some("readonly") => Mode::ReadOnly,
+            _ => Mode::ReadWrite,
+        };
+        Some(RemoteCache { backend, mode })
+    }
+
+    /// Download the archive stored under `key` to `dest`, returning whether
+    /// it was found.
+    pub fn fetch(&self, key: &str, dest: &Path) -> io::Result<bool> {
+        match &self.backend {
+            Backend::Directory(dir) => match fs::copy(dir.join(key), dest) {
+                Ok(_) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e),
+            },
+            Backend::Http(url) => {
+                let mut command = curl();
+                command.arg("-o").arg(dest).arg(format!("{}/{}", url, key));
+                let status = command.status()?;
+                // curl exits with 22 when the server answers with an HTTP error
+                match status.code() {
+                    Some(0) => Ok(true),
+                    Some(22) => Ok(false),
+                    _ => Err(io::Error::other(format!("curl failed with {}", status))),
+                }
+            }
+            Backend::S3(url) => {
+                let status = Command::new("aws")
+                    .args(["s3", "cp", "--only-show-errors"])
+                    .arg(format!("{}/{}", url, key))
+                    .arg(dest)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()?;
+                Ok(status.success())
+            }
+        }
+    }
+
+    /// Upload the archive at `src` under `key`.
+    pub fn store(&self, key: &str, src: &Path) -> io::Result<()> {
+        let status = match &self.backend {
+            Backend::Directory(dir) => {
+                fs::create_dir_all(dir)?;
+                // Copy then rename, so readers never see a partial archive
+                let partial = dir.join(format!(".{}.partial", key));
+                fs::copy(src, &partial)?;
+                return fs::rename(partial, dir.join(key));
+            }
+            Backend::Http(url) => curl()
+                .arg("-T")
+                .arg(src)
+                .arg(format!("{}/{}", url, key))
+                .status()?,
+            Backend::S3(url) => Command::new("aws")
+                .args(["s3", "cp", "--only-show-errors"])
+                .arg(src)
+                .arg(format!("{}/{}", url, key))
+                .status()?,
+        };
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("upload failed with {}", status)))
+        }
+    }
+}
+
+/// A quiet curl that fails on HTTP errors, authenticating with
+/// `RUN_CACHE_TOKEN` when it is set.
+fn curl() -> Command {
+    let mut command = Command::new("curl");
+    command.args(["-fsSL"]);
+    if let Ok(token) = std::env::var("RUN_CACHE_TOKEN") {
+        command
+            .arg("-H")
+            .arg(format!("Authorization: Bearer {}", token));
+    }
+    command
+}
+
+/// Write the recorded output files into a single archive at `dest`.
+///
+/// The format is a header line followed, for each file, by a
+/// `<length> <path>` line and the file's bytes.
+pub fn pack(files: &[Artifact], base: &Path, dest: &Path) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(dest)?);
+    writeln!(out, "{}", ARCHIVE_HEADER)?;
+    for artifact in files {
+        let content = fs::read(base.join(&artifact.path))?;
+        writeln!(
+            out,
+            "{} {}",
+            content.len(),
+            artifacts::display_path(&artifact.path)
+        )?;
+        out.write_all(&content)?;
+    }
+    out.flush()
+}
+
+/// Extract an archive written by [`pack`] into `base`, returning the paths
+/// it contained.
+pub fn unpack(archive: &Path, base: &Path) -> io::Result<Vec<PathBuf>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut reader = BufReader::new(fs::File::open(archive)?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != ARCHIVE_HEADER {
+        return Err(invalid("not a run cache archive"));
+    }
+
+    let mut paths = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(paths);
+        }
+        let (len, path) = line
+            .trim_end_matches('\n')
+            .split_once(' ')
+            .ok_or_else(|| invalid("malformed archive entry"))?;
+        let len: u64 = len
+            .parse()
+            .map_err(|_| invalid("malformed archive entry"))?;
+        let path = PathBuf::from(path);
+        // Never write outside the working directory
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(invalid("archive entry escapes the working directory"));
+        }
+
+        let mut content = Vec::new();
+        (&mut reader).take(len).read_to_end(&mut content)?;
+        if content.len() as u64 != len {
+            return Err(invalid("truncated archive"));
+        }
+        let target = base.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, content)?;
+        paths.push(path);
+    }
+}
+
+/// A scratch path for downloading or packing the archive for `key`.
+pub fn scratch_path(key: &str, base: &Path) -> io::Result<PathBuf> {
+    let dir = base.join(CACHE_DIR).join("tmp");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.{}", key, std::process::id())))
+}
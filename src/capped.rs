@@ -0,0 +1,142 @@
+//! Output kept in memory or in logs, capped by the `output.limit` setting
+//! (64M unless set, `none` for no limit). Past the limit the first and last
+//! halves are kept, with a line between them saying how much was left out.
+
+use std::collections::VecDeque;
+use std::io;
+
+/// Limit used unless `output.limit` is set.
+pub const DEFAULT_LIMIT: usize = 64 << 20;
+
+/// The limit set by `output.limit`: a number of bytes with an optional `K`,
+/// `M` or `G` suffix, or `none`.
+pub fn parse_limit(setting: Option<&str>) -> Result<Option<usize>, String> {
+    let Some(setting) = setting else {
+        return Ok(Some(DEFAULT_LIMIT));
+    };
+    if setting.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    let (number, shift) = match setting.trim_end_matches(['B', 'b']) {
+        number if number.ends_with(['K', 'k']) => (&number[..number.len() - 1], 10),
+        number if number.ends_with(['M', 'm']) => (&number[..number.len() - 1], 20),
+        number if number.ends_with(['G', 'g']) => (&number[..number.len() - 1], 30),
+        number => (number, 0),
+    };
+    number
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .map(Some)
+        .ok_or_else(|| {
+            format!(
+                "Invalid output.limit '{}': expected a size such as 10M, or none",
+                setting
+            )
+        })
+}
+
+/// Bytes kept up to a limit: the first ones up to the head's share, then
+/// only the most recent up to the tail's.
+#[derive(Debug)]
+pub struct Capped {
+    head: Vec<u8>,
+    head_limit: usize,
+    tail: VecDeque<u8>,
+    tail_limit: usize,
+    /// Bytes dropped between the head and the tail.
+    omitted: usize,
+    /// Whether the tail starts a line: the last byte dropped ended one.
+    tail_at_line: bool,
+}
+
+impl Capped {
+    /// Keep up to `limit` bytes, half from the start and half from the end.
+    pub fn new(limit: Option<usize>) -> Capped {
+        match limit {
+            Some(limit) => Capped::split(limit / 2, limit - limit / 2),
+            None => Capped::split(usize::MAX, 0),
+        }
+    }
+
+    /// Keep only the last `limit` bytes.
+    pub fn tail(limit: usize) -> Capped {
+        Capped::split(0, limit)
+    }
+
+    fn split(head_limit: usize, tail_limit: usize) -> Capped {
+        Capped {
+            head: Vec::new(),
+            head_limit,
+            tail: VecDeque::new(),
+            tail_limit,
+            omitted: 0,
+            tail_at_line: false,
+        }
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        let room = self.head_limit.saturating_sub(self.head.len());
+        let (head, mut rest) = data.split_at(room.min(data.len()));
+        self.head.extend_from_slice(head);
+        if rest.len() > self.tail_limit {
+            let dropped = rest.len() - self.tail_limit;
+            self.omitted += self.tail.len() + dropped;
+            self.tail_at_line = rest[dropped - 1] == b'\n';
+            self.tail.clear();
+            rest = &rest[dropped..];
+        }
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(self.tail_limit);
+        if excess > 0 {
+            self.tail_at_line = self.tail[excess - 1] == b'\n';
+            self.tail.drain(..excess);
+            self.omitted += excess;
+        }
+    }
+
+    /// Replace everything kept with `data`.
+    pub fn replace(&mut self, data: &[u8]) {
+        self.take();
+        self.extend(data);
+    }
+
+    /// Everything kept, with the truncation marker if anything was left
+    /// out, leaving the buffer empty.
+    pub fn take(&mut self) -> Vec<u8> {
+        let mut bytes = std::mem::take(&mut self.head);
+        if self.omitted > 0 {
+            // Cut both halves at line ends, so no line shows in part
+            if let Some(end) = bytes.iter().rposition(|&b| b == b'\n') {
+                self.omitted += bytes.len() - end - 1;
+                bytes.truncate(end + 1);
+            }
+            if !self.tail_at_line
+                && let Some(start) = self.tail.iter().position(|&b| b == b'\n')
+            {
+                self.omitted += start + 1;
+                self.tail.drain(..=start);
+            }
+            if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+                bytes.push(b'\n');
+            }
+            let marker = format!("[... {} bytes truncated ...]\n", self.omitted);
+            bytes.extend_from_slice(marker.as_bytes());
+            self.omitted = 0;
+        }
+        bytes.extend(self.tail.drain(..));
+        bytes
+    }
+}
+
+impl io::Write for Capped {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.extend(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
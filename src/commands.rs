@@ -0,0 +1,406 @@
+//! Built-in subcommands such as `run artifacts`.
+//!
+//! A subcommand only runs when the Runfile doesn't define a function with the
+//! same name, so existing Runfiles keep working as they did.
+
+use crate::artifacts::{self, ArtifactState};
+use crate::ast::Statement;
+use crate::i18n::tr;
+use crate::interpreter::{self, Interpreter};
+use crate::toolchain::Toolchain;
+use crate::{config, diff, hook, logs, package, pager, parser, refactor, secrets, shell, style};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+
+type CommandResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Run the built-in subcommand `name`, if there is one.
+pub fn dispatch(name: &str, args: &[String], interpreter: &Interpreter) -> Option<CommandResult> {
+    match name {
+        "artifacts" => Some(artifacts_command(args, interpreter)),
+        "check" => Some(check_command(args, interpreter)),
+        "env" => Some(env_command(args, interpreter)),
+        "eval" => Some(eval_command(args, interpreter)),
+        "export" => Some(export_command(args)),
+        "logs" => Some(logs_command(args, interpreter)),
+        "refactor" => Some(refactor_command(args)),
+        "secrets" => Some(secrets_command(args, interpreter)),
+        _ => None,
+    }
+}
+
+/// Built-in subcommands that don't load the Runfile.
+pub const STANDALONE: [&str; 2] = ["edit", "hook"];
+
+/// Run the built-in subcommand `name` that doesn't load the Runfile, if it is
+/// one: `run hook` is evaluated by shell startup files wherever the shell
+/// starts, and `run edit` is how to fix a Runfile that doesn't parse.
+pub fn dispatch_standalone(name: &str, args: &[String]) -> Option<CommandResult> {
+    match name {
+        "edit" => Some(edit_command(args)),
+        "hook" => Some(hook_command(args)),
+        _ => None,
+    }
+}
+
+/// `run edit`: print the path of the Runfile in effect, the one `run` would
+/// read here, and open it in `$VISUAL`, `$EDITOR` or else vi (Notepad on
+/// Windows).
+fn edit_command(args: &[String]) -> CommandResult {
+    if !args.is_empty() {
+        return Err("Usage: run edit".into());
+    }
+    let path = config::find_runfile().ok_or_else(|| tr!("No Runfile found"))?;
+    println!("Opening {}", path.display());
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        })
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    // Through the shell, as the editor may come with arguments (`code --wait`)
+    let shell = shell::default_shell();
+    let command = format!(
+        "{} {}",
+        editor,
+        interpreter::shell_quote(&path.to_string_lossy(), &shell)
+    );
+    let status = std::process::Command::new(&shell)
+        .arg(shell::command_flag(&shell))
+        .arg(&command)
+        .status()
+        .map_err(|e| format!("Cannot run '{}': {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("'{}' failed with {}", editor, status).into());
+    }
+    Ok(())
+}
+
+/// `run hook bash|zsh|fish`: print the shell hook defining the functions of
+/// the nearest Runfile as shell functions.
+fn hook_command(args: &[String]) -> CommandResult {
+    const USAGE: &str = "Usage: run hook bash|zsh|fish";
+    let [shell] = args else {
+        return Err(USAGE.into());
+    };
+    let script = hook::script(shell).ok_or(USAGE)?;
+    print!("{}", script);
+    Ok(())
+}
+
+/// `run artifacts [function] [--clean]`: list the recorded outputs of a
+/// function, or of every function when none is given, or delete them.
+fn artifacts_command(args: &[String], interpreter: &Interpreter) -> CommandResult {
+    let clean = args.iter().any(|arg| arg == "--clean");
+    let functions: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let base = std::env::current_dir()?;
+
+    let Some(function) = functions.first() else {
+        if clean {
+            return Err("Usage: run artifacts <function> --clean".into());
+        }
+        let recorded = artifacts::recorded_functions(&base);
+        if recorded.is_empty() {
+            println!("No artifacts recorded.");
+        }
+        for function in recorded {
+            let count = artifacts::load(&function, &base).unwrap_or_default().len();
+            println!("{}: {} file(s)", function, count);
+        }
+        return Ok(());
+    };
+    let base = interpreter.working_dir(function);
+
+    if clean {
+        let removed = artifacts::clean(function, &base)?;
+        for path in &removed {
+            println!("Removed {}", artifacts::display_path(path));
+        }
+        println!("Cleaned {} artifact(s) of '{}'", removed.len(), function);
+        return Ok(());
+    }
+
+    let Some(recorded) = artifacts::load(function, &base) else {
+        if !interpreter.has_function(function) {
+            return Err(format!("Function '{}' not found", function).into());
+        }
+        if interpreter.outputs(function).is_empty() {
+            println!("'{}' does not declare any @outputs.", function);
+        } else {
+            println!("No artifacts recorded for '{}' yet.", function);
+        }
+        return Ok(());
+    };
+
+    println!("Artifacts of '{}':", function);
+    for artifact in recorded {
+        let note = match artifacts::state(&artifact, &base) {
+            ArtifactState::Unchanged => "",
+            ArtifactState::Modified => "  (modified)",
+            ArtifactState::Missing => "  (missing)",
+        };
+        println!(
+            "  {}  {}{}",
+            &artifact.hash[..12.min(artifact.hash.len())],
+            artifacts::display_path(&artifact.path),
+            note
+        );
+    }
+    Ok(())
+}
+
+/// `run check --unused [--history]`: list the functions nothing in the
+/// Runfile refers to, neither a call, a `needs(...)`, a `capture(...)`, a
+/// `run_capture(...)` nor a `run name` command. With `--history`, show when each last ran according
+/// to its logs, to tell entry points still in use from dead tasks.
+fn check_command(args: &[String], interpreter: &Interpreter) -> CommandResult {
+    const USAGE: &str = "Usage: run check --unused [--history]";
+    let history = args.iter().any(|arg| arg == "--history");
+    if !args.iter().any(|arg| arg == "--unused")
+        || args
+            .iter()
+            .any(|arg| arg != "--unused" && arg != "--history")
+    {
+        return Err(USAGE.into());
+    }
+    if history && interpreter.setting("logs.enabled") != Some("true") {
+        return Err("--history needs the run logs: set logs.enabled = true".into());
+    }
+
+    let path = config::find_runfile().ok_or_else(|| tr!("No Runfile found"))?;
+    let source = std::fs::read_to_string(&path)?;
+    // Functions only ~/.runfile defines are there for every project, not
+    // tasks of this one
+    let global = config::global_runfile()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    let unused: Vec<&str> = interpreter
+        .function_names()
+        .into_iter()
+        .filter(|name| {
+            refactor::definition(&source, name).is_some()
+                || refactor::definition(&global, name).is_none()
+        })
+        .filter(|name| refactor::rename(&source, name, name).references == 0)
+        .collect();
+    if unused.is_empty() {
+        println!("Every function is referenced in {}.", path.display());
+        return Ok(());
+    }
+
+    println!("Functions nothing in {} refers to:", path.display());
+    let width = unused.iter().map(|name| name.len()).max().unwrap_or(0);
+    for name in unused {
+        if !history {
+            println!("  {}", name);
+            continue;
+        }
+        let last_run = logs::last_run(name, &interpreter.working_dir(name))
+            .map_or("never run".to_string(), |time| format!("last run {}", time));
+        println!("  {:width$}  {}", name, last_run, width = width);
+    }
+    Ok(())
+}
+
+/// `run env [--diff] <function>`: print the environment a function's
+/// commands receive, or only how it differs from the invoking shell.
+fn env_command(args: &[String], interpreter: &Interpreter) -> CommandResult {
+    let diff = args.iter().any(|arg| arg == "--diff");
+    let Some(function) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        return Err("Usage: run env [--diff] <function>".into());
+    };
+    if !interpreter.has_function(function) {
+        return Err(format!("Function '{}' not found", function).into());
+    }
+
+    let current: BTreeMap<String, OsString> = std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value)))
+        .collect();
+    let mut task = current.clone();
+    task.extend(interpreter.environment_overrides(function));
+
+    if !diff {
+        for (key, value) in &task {
+            println!("{}={}", key, value.to_string_lossy());
+        }
+        return Ok(());
+    }
+
+    let mut changes = 0;
+    for (key, value) in &task {
+        match current.get(key) {
+            None => println!("+ {}={}", key, value.to_string_lossy()),
+            Some(old) if old != value => println!(
+                "~ {}={} (was {})",
+                key,
+                value.to_string_lossy(),
+                old.to_string_lossy()
+            ),
+            Some(_) => continue,
+        }
+        changes += 1;
+    }
+    for key in current.keys().filter(|key| !task.contains_key(*key)) {
+        println!("- {}", key);
+        changes += 1;
+    }
+    if changes == 0 {
+        println!(
+            "'{}' receives the same environment as this shell.",
+            function
+        );
+    }
+    if interpreter.toolchain() == Some(Toolchain::Mise) {
+        println!("Note: commands run via `mise exec`, which applies its own environment on top.");
+    }
+    Ok(())
+}
+
+/// `run eval '<expression>'`: print the value of an expression as the
+/// Runfile's commands would see it, with its variables, builtins and command
+/// substitutions resolved, to debug how a command expands.
+fn eval_command(args: &[String], interpreter: &Interpreter) -> CommandResult {
+    if args.is_empty() {
+        return Err("Usage: run eval '<expression>'".into());
+    }
+    println!("{}", interpreter.eval(&args.join(" "))?);
+    Ok(())
+}
+
+/// `run export --format <brew-formula|scoop-manifest> [--artifacts <dir>]`:
+/// print a package manifest for this version of `run`, with the checksums of
+/// the release archives in `dir` or downloaded from the release.
+fn export_command(args: &[String]) -> CommandResult {
+    const USAGE: &str =
+        "Usage: run export --format <brew-formula|scoop-manifest> [--artifacts <dir>]";
+    let (mut format, mut artifacts) = (None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--format", Some(name)) => {
+                format = Some(package::Format::parse(name).ok_or_else(|| {
+                    format!(
+                        "Unknown format '{}' (expected brew-formula or scoop-manifest)",
+                        name
+                    )
+                })?)
+            }
+            ("--artifacts", Some(dir)) => artifacts = Some(std::path::PathBuf::from(dir)),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let format = format.ok_or(USAGE)?;
+    print!("{}", package::generate(format, artifacts.as_deref())?);
+    Ok(())
+}
+
+/// `run logs <function> [--follow]`: print the most recent log of a
+/// function, and with `--follow` keep printing it while the function runs.
+fn logs_command(args: &[String], interpreter: &Interpreter) -> CommandResult {
+    let follow = args.iter().any(|arg| arg == "--follow" || arg == "-f");
+    let Some(function) = args.iter().find(|arg| !arg.starts_with('-')) else {
+        return Err("Usage: run logs <function> [--follow]".into());
+    };
+    let name = function.split('[').next().unwrap_or(function);
+    let Some(path) = logs::latest(function, &interpreter.working_dir(name)) else {
+        if interpreter.setting("logs.enabled") != Some("true") {
+            println!(
+                "No logs recorded for '{}' (set logs.enabled = true to record them).",
+                function
+            );
+        } else {
+            println!("No logs recorded for '{}' yet.", function);
+        }
+        return Ok(());
+    };
+
+    if follow {
+        logs::follow(&path)?;
+    } else if interpreter.paginates() {
+        pager::show(&std::fs::read(&path)?);
+    } else {
+        std::io::stdout().write_all(&std::fs::read(&path)?)?;
+    }
+    Ok(())
+}
+
+/// `run secrets encrypt <name> [value]`: print a `secretvar` line holding
+/// the value (read from stdin when not given) encrypted to the
+/// `secrets.recipients`. `run secrets decrypt <name>`: print the value of a
+/// `secretvar`.
+fn secrets_command(args: &[String], interpreter: &Interpreter) -> CommandResult {
+    const USAGE: &str = "Usage: run secrets encrypt <name> [value] | run secrets decrypt <name>";
+    match args {
+        [action, name, value @ ..] if action == "encrypt" && value.len() <= 1 => {
+            let recipients = interpreter
+                .setting("secrets.recipients")
+                .ok_or("No recipients: set secrets.recipients in the Runfile")?;
+            let value = match value.first() {
+                Some(value) => value.clone(),
+                None => {
+                    let mut value = String::new();
+                    std::io::stdin().read_to_string(&mut value)?;
+                    value.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            let encrypted = secrets::encrypt(&value, recipients)?;
+            println!("secretvar {} = {}", name, encrypted);
+            Ok(())
+        }
+        [action, name] if action == "decrypt" => {
+            let value = interpreter
+                .secret(name)
+                .ok_or_else(|| format!("No secretvar '{}' in the Runfile", name))?;
+            println!("{}", secrets::decrypt(value)?);
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// `run refactor rename <old_name> <new_name>`: rename a function in the
+/// Runfile along with its call sites and `needs(...)` references, printing
+/// the change as a diff.
+fn refactor_command(args: &[String]) -> CommandResult {
+    const USAGE: &str = "Usage: run refactor rename <old_name> <new_name>";
+    let [action, old, new] = args else {
+        return Err(USAGE.into());
+    };
+    if action != "rename" {
+        return Err(USAGE.into());
+    }
+    let is_function_name = matches!(
+        parser::parse_script(&format!("{}() true\n", new)).as_ref().map(|p| p.statements.as_slice()),
+        Ok([Statement::SimpleFunctionDef { name, .. }]) if name == new
+    );
+    if !is_function_name {
+        return Err(format!("'{}' is not a valid function name", new).into());
+    }
+
+    let path = config::find_runfile().ok_or_else(|| tr!("No Runfile found"))?;
+    let source = std::fs::read_to_string(&path)?;
+    if refactor::rename(&source, new, new).definitions > 0 {
+        return Err(format!("Function '{}' already exists", new).into());
+    }
+    let renamed = refactor::rename(&source, old, new);
+    if renamed.definitions == 0 {
+        return Err(format!("Function '{}' is not defined in {}", old, path.display()).into());
+    }
+    if let Err(e) = parser::parse_script(&renamed.text) {
+        return Err(format!("Renaming would leave the Runfile invalid:\n{}", e).into());
+    }
+    std::fs::write(&path, &renamed.text)?;
+
+    let name = path.display().to_string();
+    if let Some(diff) = diff::unified(&name, &source, &name, &renamed.text, style::enabled()) {
+        print!("{}", diff);
+    }
+    println!(
+        "Renamed '{}' to '{}': {} definition(s), {} reference(s)",
+        old, new, renamed.definitions, renamed.references
+    );
+    Ok(())
+}
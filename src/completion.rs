@@ -1,19 +1,23 @@
 //! Shell completion installation and generation.
 
+use crate::style;
 use clap::ValueEnum;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Embed completion scripts at compile time
 const BASH_COMPLETION: &str = include_str!("../completions/run.bash");
 const ZSH_COMPLETION: &str = include_str!("../completions/run.zsh");
 const FISH_COMPLETION: &str = include_str!("../completions/run.fish");
+const POWERSHELL_COMPLETION: &str = include_str!("../completions/run.ps1");
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
 pub enum Shell {
     Bash,
     Zsh,
     Fish,
+    #[value(alias = "pwsh")]
+    Powershell,
 }
 
 impl Shell {
@@ -23,6 +27,7 @@ impl Shell {
             Shell::Bash => "bash",
             Shell::Zsh => "zsh",
             Shell::Fish => "fish",
+            Shell::Powershell => "powershell",
         }
     }
 
@@ -32,18 +37,36 @@ impl Shell {
             Shell::Bash => BASH_COMPLETION,
             Shell::Zsh => ZSH_COMPLETION,
             Shell::Fish => FISH_COMPLETION,
+            Shell::Powershell => POWERSHELL_COMPLETION,
         }
     }
 
-    /// Detect shell from the SHELL environment variable.
+    /// Where this shell looks for completions installed for every user,
+    /// relative to the filesystem root. PowerShell only loads them from
+    /// profiles, so it has none.
+    fn system_completion_path(self) -> Option<&'static str> {
+        match self {
+            Shell::Bash => Some("usr/share/bash-completion/completions/run"),
+            Shell::Zsh => Some("usr/share/zsh/site-functions/_run"),
+            Shell::Fish => Some("usr/share/fish/vendor_completions.d/run.fish"),
+            Shell::Powershell => None,
+        }
+    }
+
+    /// Detect shell from the SHELL environment variable, or PowerShell from
+    /// the PSModulePath it sets when there is none, as on Windows.
     pub fn detect() -> Option<Shell> {
-        let shell_var = std::env::var("SHELL").ok()?;
+        let Ok(shell_var) = std::env::var("SHELL") else {
+            return std::env::var_os("PSModulePath").map(|_| Shell::Powershell);
+        };
         if shell_var.contains("bash") {
             Some(Shell::Bash)
         } else if shell_var.contains("zsh") {
             Some(Shell::Zsh)
         } else if shell_var.contains("fish") {
             Some(Shell::Fish)
+        } else if shell_var.contains("pwsh") {
+            Some(Shell::Powershell)
         } else {
             None
         }
@@ -59,7 +82,7 @@ pub fn generate_completion_script(shell: Shell) {
 pub fn install_completion_interactive(shell_opt: Option<Shell>, get_home_dir: impl Fn() -> Option<PathBuf>) {
     // Detect the shell if not provided
     let shell = shell_opt.or_else(Shell::detect).unwrap_or_else(|| {
-        crate::fatal_error("Could not detect shell. Please specify: --install-completion <SHELL>\nSupported shells: bash, zsh, fish")
+        crate::fatal_error("Could not detect shell. Please specify: --install-completion <SHELL>\nSupported shells: bash, zsh, fish, powershell")
     });
 
     println!(
@@ -76,9 +99,85 @@ pub fn install_completion_interactive(shell_opt: Option<Shell>, get_home_dir: im
         Shell::Bash => install_bash_completion(&home),
         Shell::Zsh => install_zsh_completion(&home),
         Shell::Fish => install_fish_completion(&home),
+        Shell::Powershell => install_powershell_completion(&home),
     }
 
-    println!("\n✓ Installation complete!");
+    println!("\n{}Installation complete!", style::symbol("✓ ", ""));
+}
+
+/// Install shell completion for every user, for `shell_opt` or else every
+/// supported shell, under `DESTDIR` when it is set (for packaging).
+pub fn install_completion_system(shell_opt: Option<Shell>) {
+    let root = std::env::var_os("DESTDIR").map_or_else(|| PathBuf::from("/"), PathBuf::from);
+    let shells = match shell_opt {
+        Some(shell) => vec![shell],
+        None => Shell::value_variants().to_vec(),
+    };
+
+    for shell in shells {
+        let Some(path) = shell.system_completion_path() else {
+            if shell_opt.is_some() {
+                crate::fatal_error(&format!(
+                    "Error: {} has no directory for every user's completions\nInstall them in your profile instead:\n  run --install-completion {}",
+                    shell.name(),
+                    shell.name()
+                ));
+            }
+            continue;
+        };
+        let comp_file = root.join(path);
+        let written = comp_file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&comp_file, shell.completion_script()));
+        match written {
+            Ok(()) => println!(
+                "{}Installed {} completion to {}",
+                style::symbol("✓ ", ""),
+                shell.name(),
+                comp_file.display()
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied && !is_root() => {
+                let retry = match shell_opt {
+                    Some(shell) => format!("run --install-completion {} --system", shell.name()),
+                    None => "run --install-completion --system".to_string(),
+                };
+                let hint = if which::which("sudo").is_ok() {
+                    format!("Re-run it with sudo:\n  sudo {}", retry)
+                } else {
+                    format!("Re-run it as root:\n  {}", retry)
+                };
+                crate::fatal_error(&format!(
+                    "Error: Permission denied writing {}\n{}",
+                    comp_file.display(),
+                    hint
+                ));
+            }
+            Err(e) => crate::fatal_error(&format!(
+                "Error writing completion file {}: {}",
+                comp_file.display(),
+                e
+            )),
+        }
+    }
+
+    println!(
+        "\n{}Installation complete! New shells load the completions for every user.",
+        style::symbol("✓ ", "")
+    );
+}
+
+/// Whether `run` is running as root.
+fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
 }
 
 /// Write a completion file to the specified directory, creating the directory if needed.
@@ -95,22 +194,22 @@ fn write_completion_file(comp_dir: &PathBuf, filename: &str, content: &str) -> P
     comp_file
 }
 
-fn install_bash_completion(home: &PathBuf) {
+fn install_bash_completion(home: &Path) {
     // Install to ~/.local/share/bash-completion/completions/run
     let comp_dir = home.join(".local/share/bash-completion/completions");
     let comp_file = write_completion_file(&comp_dir, "run", BASH_COMPLETION);
 
-    println!("✓ Installed completion to {}", comp_file.display());
+    println!("{}Installed completion to {}", style::symbol("✓ ", ""), comp_file.display());
     println!("\nTo activate completions, restart your shell or run:");
     println!("  source ~/.bashrc");
 }
 
-fn install_zsh_completion(home: &PathBuf) {
+fn install_zsh_completion(home: &Path) {
     // Install to ~/.zsh/completion/_run
     let comp_dir = home.join(".zsh/completion");
     let comp_file = write_completion_file(&comp_dir, "_run", ZSH_COMPLETION);
 
-    println!("✓ Installed completion to {}", comp_file.display());
+    println!("{}Installed completion to {}", style::symbol("✓ ", ""), comp_file.display());
 
     // Check if .zshrc needs updating
     let zshrc = home.join(".zshrc");
@@ -158,14 +257,62 @@ fn install_zsh_completion(home: &PathBuf) {
     println!("  exec zsh");
 }
 
-fn install_fish_completion(home: &PathBuf) {
+fn install_fish_completion(home: &Path) {
     // Install to ~/.config/fish/completions/run.fish
     let comp_dir = home.join(".config/fish/completions");
     let comp_file = write_completion_file(&comp_dir, "run.fish", FISH_COMPLETION);
 
-    println!("✓ Installed completion to {}", comp_file.display());
+    println!("{}Installed completion to {}", style::symbol("✓ ", ""), comp_file.display());
     println!("\nCompletions will be automatically loaded on next shell startup.");
     println!("To activate now, restart fish or run:");
     println!("  exec fish");
 }
 
+
+fn install_powershell_completion(home: &Path) {
+    // Install next to the profile, which dot-sources it
+    let profile = powershell_profile(home);
+    let comp_dir = profile.parent().map_or_else(|| home.to_path_buf(), Path::to_path_buf);
+    let comp_file = write_completion_file(&comp_dir, "run-completion.ps1", POWERSHELL_COMPLETION);
+
+    println!("{}Installed completion to {}", style::symbol("✓ ", ""), comp_file.display());
+
+    let content = fs::read_to_string(&profile).unwrap_or_default();
+    if !content.contains("run-completion.ps1") {
+        let line = format!(". \"{}\"\n", comp_file.display());
+        let separator = if content.is_empty() || content.ends_with('\n') { "" } else { "\n" };
+        if let Err(e) = fs::write(&profile, format!("{}{}{}", content, separator, line)) {
+            crate::fatal_error(&format!("Error updating {}: {}", profile.display(), e));
+        }
+        println!("{}Added it to your profile, {}", style::symbol("✓ ", ""), profile.display());
+    }
+
+    println!("\nTo activate completions, restart PowerShell or run:");
+    println!("  . $PROFILE");
+}
+
+/// The current user's PowerShell profile, as PowerShell itself reports it
+/// (Documents may be redirected, e.g. to OneDrive), or where it usually is.
+fn powershell_profile(home: &Path) -> PathBuf {
+    for program in ["pwsh", "powershell"] {
+        let output = std::process::Command::new(program)
+            .args(["-NoProfile", "-NonInteractive", "-Command", "$PROFILE"])
+            .stderr(std::process::Stdio::null())
+            .output();
+        if let Ok(output) = output
+            && output.status.success()
+        {
+            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !path.is_empty() {
+                return PathBuf::from(path);
+            }
+        }
+    }
+    if cfg!(windows) {
+        home.join("Documents")
+            .join("PowerShell")
+            .join("Microsoft.PowerShell_profile.ps1")
+    } else {
+        home.join(".config/powershell/Microsoft.PowerShell_profile.ps1")
+    }
+}
@@ -1,7 +1,37 @@
 //! Configuration file (Runfile) discovery and loading.
 
+use crate::i18n::tr;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The Makefile read instead of a Runfile, with `--from-makefile`.
+static MAKEFILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Read the rules of the Makefile at `path` instead of a Runfile
+/// (`--from-makefile`).
+pub fn use_makefile(path: PathBuf) {
+    let _ = MAKEFILE.set(path);
+}
+
+/// The Makefile read instead of a Runfile, if `use_makefile` chose one.
+pub fn makefile() -> Option<&'static Path> {
+    MAKEFILE.get().map(PathBuf::as_path)
+}
+
+/// Set by `--no-global`: read the project's Runfile without ~/.runfile.
+static NO_GLOBAL: OnceLock<()> = OnceLock::new();
+
+/// Read only the project's Runfile, not ~/.runfile along with it
+/// (`--no-global`).
+pub fn disable_global() {
+    let _ = NO_GLOBAL.set(());
+}
+
+/// Whether `--no-global` was given.
+pub fn global_disabled() -> bool {
+    NO_GLOBAL.get().is_some()
+}
 
 /// Get the user's home directory in a cross-platform way.
 pub fn get_home_dir() -> Option<PathBuf> {
@@ -30,72 +60,131 @@ pub fn get_home_dir() -> Option<PathBuf> {
 /// Search for a Runfile in the current directory or upwards, then fallback to ~/.runfile.
 /// Returns Some(content) if a file is found (even if empty), or None if no file exists.
 pub fn load_config() -> Option<String> {
-    // Start from the current directory and search upwards
-    let mut current_dir = match std::env::current_dir() {
-        Ok(dir) => dir,
-        Err(_) => {
-            // If we can't get current dir, fall back to home directory only
-            return load_home_runfile();
-        }
-    };
+    fs::read_to_string(find_runfile()?).ok()
+}
+
+/// The path of the Runfile that `load_config` reads, if there is one, or of
+/// the Makefile read instead.
+pub fn find_runfile() -> Option<PathBuf> {
+    if let Some(path) = makefile() {
+        return Some(path.to_path_buf());
+    }
+    let search = search_runfile();
+    search
+        .probes
+        .into_iter()
+        .chain(search.fallback)
+        .find(|probe| probe.found)
+        .map(|probe| probe.path)
+}
+
+/// ~/.runfile when it is read along with the project's Runfile, so its
+/// functions are available in every project: when both exist, unless
+/// `--no-global` was given. The project's definitions replace those of the
+/// same name. Without a project Runfile, ~/.runfile is read as the Runfile.
+pub fn global_runfile() -> Option<PathBuf> {
+    if global_disabled() || makefile().is_some() {
+        return None;
+    }
+    let path = get_home_dir()?.join(".runfile");
+    let project = search_runfile().probes.iter().any(|probe| probe.found);
+    (project && path.exists()).then_some(path)
+}
+
+/// The path and content of ~/.runfile, when it is read along with the
+/// project's Runfile (see `global_runfile`).
+pub fn load_global() -> Option<(PathBuf, String)> {
+    let path = global_runfile()?;
+    let content = fs::read_to_string(&path).ok()?;
+    Some((path, content))
+}
+
+/// A path looked at for a Runfile, and whether one was there.
+pub struct Probe {
+    pub path: PathBuf,
+    pub found: bool,
+}
 
+/// How `find_runfile` searched: the directory it started from, the Runfile
+/// paths it looked at going up, why it stopped going up, if it did, and
+/// ~/.runfile if it was needed.
+pub struct Search {
+    pub start: Option<PathBuf>,
+    pub probes: Vec<Probe>,
+    pub boundary: Option<&'static str>,
+    pub fallback: Option<Probe>,
+}
+
+/// Search for a Runfile in the current directory and upwards, stopping at
+/// the home directory or the root, then fall back to ~/.runfile.
+pub fn search_runfile() -> Search {
+    let mut search = Search {
+        start: std::env::current_dir().ok(),
+        probes: Vec::new(),
+        boundary: None,
+        fallback: None,
+    };
     // Get home directory for boundary check
     let home_dir = get_home_dir();
 
-    // Search upwards from current directory
-    loop {
-        let runfile_path = current_dir.join("Runfile");
-        if runfile_path.exists() {
-            // File exists, read it (even if empty)
-            if let Ok(content) = fs::read_to_string(&runfile_path) {
-                return Some(content);
-            }
+    // Search upwards from current directory; if we can't get it, fall back
+    // to the home directory only
+    let mut current_dir = search.start.clone();
+    while let Some(dir) = current_dir {
+        let runfile_path = dir.join("Runfile");
+        let found = runfile_path.exists();
+        search.probes.push(Probe {
+            path: runfile_path,
+            found,
+        });
+        if found {
+            return search;
         }
 
         // Check if we've reached the home directory or root
-        let reached_boundary = if let Some(ref home) = home_dir {
-            current_dir == *home
-                || current_dir == PathBuf::from("/")
-                || current_dir == PathBuf::from("\\")
-        } else {
-            current_dir == PathBuf::from("/") || current_dir == PathBuf::from("\\")
-        };
-
-        if reached_boundary {
+        if home_dir.as_ref().is_some_and(|home| dir == *home) {
+            search.boundary = Some("the home directory");
+            break;
+        }
+        if dir == Path::new("/") || dir == Path::new("\\") {
+            search.boundary = Some("the root directory");
             break;
         }
 
         // Move up one directory
-        match current_dir.parent() {
-            Some(parent) => current_dir = parent.to_path_buf(),
-            None => break, // Reached root
-        }
+        current_dir = dir.parent().map(Path::to_path_buf);
     }
 
     // Finally, try ~/.runfile as a fallback
-    load_home_runfile()
-}
-
-/// Load ~/.runfile from the user's home directory.
-/// Returns Some(content) if found, or None otherwise.
-fn load_home_runfile() -> Option<String> {
-    if let Some(home) = get_home_dir() {
-        let runfile_path = home.join(".runfile");
-        if runfile_path.exists()
-            && let Ok(content) = fs::read_to_string(runfile_path)
-        {
-            return Some(content);
-        }
-    }
-    None
+    search.fallback = home_dir.map(|home| {
+        let path = home.join(".runfile");
+        let found = path.exists();
+        Probe { path, found }
+    });
+    search
 }
 
 /// Error message when no Runfile is found.
 pub const NO_RUNFILE_ERROR: &str =
     "Error: No Runfile found. Create ~/.runfile or ./Runfile to define functions.";
 
-/// Load config or exit with an error message.
+/// Load config or exit with an error message, pointing out a Makefile that
+/// `--from-makefile` could read instead.
 pub fn load_config_or_exit() -> String {
-    load_config().unwrap_or_else(|| crate::fatal_error(NO_RUNFILE_ERROR))
+    load_config().unwrap_or_else(|| {
+        let makefile = std::env::current_dir()
+            .ok()
+            .and_then(|dir| crate::makefile::find(&dir));
+        match makefile {
+            Some(path) => crate::fatal_error(&format!(
+                "{}\n{}",
+                tr!(NO_RUNFILE_ERROR),
+                tr!(
+                    "To call the rules of {} as functions, run with --from-makefile.",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                )
+            )),
+            None => crate::fatal_error(&tr!(NO_RUNFILE_ERROR)),
+        }
+    })
 }
-
@@ -0,0 +1,218 @@
+//! Dates for the `$(date ...)` builtin substitution: now, shifted by
+//! offsets such as `-7d`, in strftime-style formats, computed natively so
+//! tasks don't depend on the differences between GNU, BSD and Windows `date`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format used when none is given.
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d";
+
+const DAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A moment broken down into calendar fields, in UTC or local time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+    /// Seconds since the Unix epoch.
+    timestamp: i64,
+    /// Seconds east of UTC.
+    offset: i64,
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    /// Days since Sunday.
+    weekday: u32,
+    /// Day of the year, from 1.
+    yearday: u32,
+}
+
+impl DateTime {
+    /// The current time shifted by `seconds`, in UTC or local time.
+    pub fn now_plus(seconds: i64, utc: bool) -> DateTime {
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        let timestamp = now + seconds;
+        let offset = if utc { 0 } else { local_offset(timestamp) };
+        DateTime::at(timestamp, offset)
+    }
+
+    /// The moment `timestamp` seen `offset` seconds east of UTC.
+    fn at(timestamp: i64, offset: i64) -> DateTime {
+        let local = timestamp + offset;
+        let days = local.div_euclid(86_400);
+        let seconds = local.rem_euclid(86_400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        let yearday = (days - days_from_civil(year, 1, 1)) as u32 + 1;
+        DateTime {
+            timestamp,
+            offset,
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+            yearday,
+        }
+    }
+
+    /// Format with the strftime conversions `%Y %y %m %d %e %H %I %M %S %p
+    /// %j %a %A %b %B %u %w %s %z %F %T %%`; others are kept as written.
+    pub fn format(&self, format: &str) -> String {
+        let mut result = String::with_capacity(format.len() + 16);
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            let Some(conversion) = chars.next() else {
+                result.push('%');
+                break;
+            };
+            let day_name = DAY_NAMES[self.weekday as usize];
+            let month_name = MONTH_NAMES[self.month as usize - 1];
+            let text = match conversion {
+                'Y' => self.year.to_string(),
+                'y' => format!("{:02}", self.year.rem_euclid(100)),
+                'm' => format!("{:02}", self.month),
+                'd' => format!("{:02}", self.day),
+                'e' => format!("{:2}", self.day),
+                'H' => format!("{:02}", self.hour),
+                'I' => format!("{:02}", (self.hour + 11) % 12 + 1),
+                'M' => format!("{:02}", self.minute),
+                'S' => format!("{:02}", self.second),
+                'p' => (if self.hour < 12 { "AM" } else { "PM" }).to_string(),
+                'j' => format!("{:03}", self.yearday),
+                'a' => day_name[..3].to_string(),
+                'A' => day_name.to_string(),
+                'b' => month_name[..3].to_string(),
+                'B' => month_name.to_string(),
+                'u' => ((self.weekday + 6) % 7 + 1).to_string(),
+                'w' => self.weekday.to_string(),
+                's' => self.timestamp.to_string(),
+                'z' => {
+                    let sign = if self.offset < 0 { '-' } else { '+' };
+                    let minutes = self.offset.abs() / 60;
+                    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+                }
+                'F' => self.format("%Y-%m-%d"),
+                'T' => self.format("%H:%M:%S"),
+                '%' => "%".to_string(),
+                other => format!("%{}", other),
+            };
+            result.push_str(&text);
+        }
+        result
+    }
+}
+
+/// The year, month and day of the day `days` after 1970-01-01, by Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The number of days from 1970-01-01 to the given date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds east of UTC of local time at `timestamp`.
+#[cfg(unix)]
+fn local_offset(timestamp: i64) -> i64 {
+    let time = timestamp as libc::time_t;
+    // SAFETY: tm is plain data, filled in by localtime_r
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: valid pointers to a time_t and a tm
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+/// Seconds east of UTC of local time. Windows only reports the offset in
+/// effect now, so a date across a daylight saving change may be an hour off.
+#[cfg(windows)]
+fn local_offset(_timestamp: i64) -> i64 {
+    // Filled in by Windows; only the biases are read
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct SystemTime16([u16; 8]);
+
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct TimeZoneInformation {
+        bias: i32,
+        standard_name: [u16; 32],
+        standard_date: SystemTime16,
+        standard_bias: i32,
+        daylight_name: [u16; 32],
+        daylight_date: SystemTime16,
+        daylight_bias: i32,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetTimeZoneInformation(information: *mut TimeZoneInformation) -> u32;
+    }
+
+    const TIME_ZONE_ID_INVALID: u32 = u32::MAX;
+    const TIME_ZONE_ID_DAYLIGHT: u32 = 2;
+
+    // SAFETY: the structure is plain data, filled in by the call
+    let mut information: TimeZoneInformation = unsafe { std::mem::zeroed() };
+    // SAFETY: valid pointer to a TIME_ZONE_INFORMATION
+    let zone = unsafe { GetTimeZoneInformation(&mut information) };
+    let bias = match zone {
+        TIME_ZONE_ID_INVALID => return 0,
+        TIME_ZONE_ID_DAYLIGHT => information.bias + information.daylight_bias,
+        _ => information.bias + information.standard_bias,
+    };
+    // The bias is in minutes west of UTC
+    -i64::from(bias) * 60
+}
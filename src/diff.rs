@@ -0,0 +1,210 @@
+//! Line diffs for the `diff` builtin, printed in unified format and colored
+//! when writing to a terminal.
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// One line of the edit script: an index into the old text, the new text, or
+/// both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// The unified diff turning `old` into `new`, or `None` if they are equal.
+pub fn unified(
+    old_name: &str,
+    old: &str,
+    new_name: &str,
+    new: &str,
+    color: bool,
+) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edits(&old_lines, &new_lines);
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let mut out = String::new();
+    out.push_str(&paint(BOLD, &format!("--- {}", old_name)));
+    out.push('\n');
+    out.push_str(&paint(BOLD, &format!("+++ {}", new_name)));
+    out.push('\n');
+    for hunk in hunks(&edits) {
+        let hunk = &edits[hunk];
+        let (old_start, old_len) = range(hunk, |edit| match edit {
+            Edit::Same(i, _) | Edit::Removed(i) => Some(i),
+            Edit::Added(_) => None,
+        });
+        let (new_start, new_len) = range(hunk, |edit| match edit {
+            Edit::Same(_, j) | Edit::Added(j) => Some(j),
+            Edit::Removed(_) => None,
+        });
+        let header = format!(
+            "@@ -{} +{} @@",
+            format_range(old_start, old_len),
+            format_range(new_start, new_len)
+        );
+        out.push_str(&paint(CYAN, &header));
+        out.push('\n');
+        for edit in hunk {
+            let (prefix, line, code) = match *edit {
+                Edit::Same(i, _) => (' ', old_lines[i], None),
+                Edit::Removed(i) => ('-', old_lines[i], Some(RED)),
+                Edit::Added(j) => ('+', new_lines[j], Some(GREEN)),
+            };
+            let text = format!("{}{}", prefix, line.strip_suffix('\n').unwrap_or(line));
+            match code {
+                Some(code) => out.push_str(&paint(code, &text)),
+                None => out.push_str(&text),
+            }
+            out.push('\n');
+            if !line.ends_with('\n') {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    Some(out)
+}
+
+/// The shortest edit script from `old` to `new` (Myers' algorithm), after
+/// setting aside their common prefix and suffix.
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut middle = middle_edits(a, b);
+    for edit in &mut middle {
+        *edit = match *edit {
+            Edit::Same(i, j) => Edit::Same(i + prefix, j + prefix),
+            Edit::Removed(i) => Edit::Removed(i + prefix),
+            Edit::Added(j) => Edit::Added(j + prefix),
+        };
+    }
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Same(i, i)).collect();
+    edits.extend(middle);
+    edits.extend((0..suffix).map(|n| Edit::Same(old.len() - suffix + n, new.len() - suffix + n)));
+    edits
+}
+
+fn middle_edits(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max as usize;
+    let mut v = vec![0isize; 2 * offset + 2];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + max) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + max) as usize;
+        let previous_k = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[(previous_k + max) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Same(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == previous_x {
+                edits.push(Edit::Added(previous_y as usize));
+            } else {
+                edits.push(Edit::Removed(previous_x as usize));
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// Group the changes in `edits` into hunks with their surrounding context,
+/// merging hunks whose context would overlap.
+fn hunks(edits: &[Edit]) -> Vec<std::ops::Range<usize>> {
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+    for (position, edit) in edits.iter().enumerate() {
+        if matches!(edit, Edit::Same(..)) {
+            continue;
+        }
+        let start = position.saturating_sub(CONTEXT);
+        let end = (position + 1 + CONTEXT).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// The first line index and number of lines a hunk covers on one side.
+fn range(hunk: &[Edit], side: impl Fn(Edit) -> Option<usize>) -> (usize, usize) {
+    let lines: Vec<usize> = hunk.iter().filter_map(|&edit| side(edit)).collect();
+    match lines.first() {
+        Some(&first) => (first, lines.len()),
+        // Only a hunk without context lines can leave a side empty, so
+        // that file is empty
+        None => (0, 0),
+    }
+}
+
+/// A hunk range as `diff -u` prints it: 1-based, with the length omitted
+/// when it is one and the preceding line given when it is empty.
+fn format_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
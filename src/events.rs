@@ -0,0 +1,128 @@
+//! Machine-readable progress events (`--events ndjson`).
+//!
+//! Each event is one JSON object per line, written to `--events-file` (for
+//! example `/dev/fd/3`) or to stderr:
+//!
+//! ```text
+//! {"event":"start","task":"lint","time":1700000000000}
+//! {"event":"stdout","task":"lint","line":"checking...","time":1700000000012}
+//! {"event":"exit","task":"lint","status":"success","duration_ms":412,"time":1700000000412}
+//! ```
+
+use clap::ValueEnum;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EventFormat {
+    /// Newline-delimited JSON
+    Ndjson,
+}
+
+/// Which output stream a line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Writes events to their destination, one whole line at a time.
+pub struct Emitter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl std::fmt::Debug for Emitter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Emitter").finish_non_exhaustive()
+    }
+}
+
+impl Emitter {
+    /// Open the event stream, appending to `path` or writing to stderr.
+    pub fn open(format: EventFormat, path: Option<&Path>) -> io::Result<Emitter> {
+        let EventFormat::Ndjson = format;
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+            None => Box::new(io::stderr()),
+        };
+        Ok(Emitter {
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn start(&self, task: &str) {
+        self.emit("start", task, &[]);
+    }
+
+    pub fn line(&self, task: &str, stream: Stream, line: &str) {
+        self.emit(stream.name(), task, &[("line", json_string(line))]);
+    }
+
+    /// Report that a task finished, with the error if it failed.
+    pub fn exit(&self, task: &str, duration: Duration, error: Option<&str>) {
+        let status = if error.is_some() {
+            "failure"
+        } else {
+            "success"
+        };
+        let mut fields = vec![
+            ("status", json_string(status)),
+            ("duration_ms", duration.as_millis().to_string()),
+        ];
+        if let Some(error) = error {
+            fields.push(("error", json_string(error)));
+        }
+        self.emit("exit", task, &fields);
+    }
+
+    fn emit(&self, event: &str, task: &str, fields: &[(&str, String)]) {
+        let mut line = format!(
+            "{{\"event\":{},\"task\":{}",
+            json_string(event),
+            json_string(task)
+        );
+        for (key, value) in fields {
+            line.push_str(&format!(",{}:{}", json_string(key), value));
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        line.push_str(&format!(",\"time\":{}}}\n", time));
+
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // Events are best-effort: a closed reader must not fail the run
+        let _ = out.write_all(line.as_bytes()).and_then(|_| out.flush());
+    }
+}
+
+/// Encode `value` as a JSON string literal.
+pub fn json_string(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len() + 2);
+    encoded.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            c if (c as u32) < 0x20 => encoded.push_str(&format!("\\u{:04x}", c as u32)),
+            c => encoded.push(c),
+        }
+    }
+    encoded.push('"');
+    encoded
+}
@@ -1,8 +1,15 @@
 //! Script execution and error formatting.
 
-use crate::{ast, config, interpreter, parser};
+use crate::events::json_string;
+use crate::i18n::tr;
+use crate::{
+    ast, commands, config, github, glob, history, hook, interpreter, makefile, npm, panes, parser,
+    process, refactor, resume, style, watch, workspace,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 struct LineInfo {
     line: usize,
@@ -43,8 +50,13 @@ pub fn print_parse_error(error: &dyn std::error::Error, source: &str, filename:
     if let Some(line_info) = extract_line_from_error(&error_str) {
         let file_prefix = filename.map(|f| format!("{}:", f)).unwrap_or_default();
         eprintln!(
-            "Parse error in {}line {}: {}",
-            file_prefix, line_info.line, line_info.message
+            "{}",
+            tr!(
+                "Parse error in {}line {}: {}",
+                file_prefix,
+                line_info.line,
+                line_info.message
+            )
         );
 
         // Show the problematic line if we can extract it
@@ -57,9 +69,47 @@ pub fn print_parse_error(error: &dyn std::error::Error, source: &str, filename:
                 "^".repeat(line_content.trim().len().max(1))
             );
         }
+        if github::is_actions() {
+            println!(
+                "{}",
+                github::error_annotation(
+                    filename.unwrap_or("Runfile"),
+                    Some(line_info.line),
+                    error_summary(&error_str)
+                )
+            );
+        }
     } else {
-        eprintln!("Parse error: {}", error_str);
+        eprintln!("{}", tr!("Parse error: {}", error_str));
+        if github::is_actions() {
+            println!(
+                "{}",
+                github::error_annotation(filename.unwrap_or("Runfile"), None, &error_str)
+            );
+        }
+    }
+}
+
+/// The explanation line of a pest error (`= expected ...`), or the whole
+/// message if there isn't one.
+fn error_summary(error_str: &str) -> &str {
+    error_str
+        .lines()
+        .rev()
+        .find_map(|line| line.trim().strip_prefix("= "))
+        .unwrap_or(error_str.trim())
+}
+
+/// Report an error found while loading the Runfile and exit.
+fn load_error(error: &dyn std::fmt::Display) -> ! {
+    eprintln!("{}", tr!("Error loading functions: {}", error));
+    if github::is_actions() {
+        println!(
+            "{}",
+            github::error_annotation("Runfile", None, &error.to_string())
+        );
     }
+    std::process::exit(1);
 }
 
 /// Parse and execute a script file.
@@ -67,7 +117,8 @@ pub fn print_parse_error(error: &dyn std::error::Error, source: &str, filename:
 /// # Arguments
 /// * `script` - The script source code to parse and execute.
 /// * `filename` - Optional filename for better error messages.
-pub fn execute_script(script: &str, filename: Option<String>) {
+/// * `options` - Interpreter options from the command line.
+pub fn execute_script(script: &str, filename: Option<String>, options: &interpreter::Options) {
     // Parse the script
     let program = match parser::parse_script(script) {
         Ok(prog) => prog,
@@ -78,15 +129,15 @@ pub fn execute_script(script: &str, filename: Option<String>) {
     };
 
     // Execute the program
-    let mut interpreter = interpreter::Interpreter::new();
+    let mut interpreter = interpreter::Interpreter::with_options(options.clone());
     if let Err(e) = interpreter.execute(program) {
-        eprintln!("Execution error: {}", e);
-        std::process::exit(1);
+        eprintln!("{}", tr!("Execution error: {}", e));
+        std::process::exit(interpreter.failure_code().unwrap_or(1));
     }
 }
 
 /// Execute a script file by path.
-pub fn execute_file(path: &PathBuf) {
+pub fn execute_file(path: &PathBuf, options: &interpreter::Options) {
     let script = match fs::read_to_string(path) {
         Ok(content) => content,
         Err(e) => {
@@ -95,7 +146,7 @@ pub fn execute_file(path: &PathBuf) {
         }
     };
 
-    execute_script(&script, Some(path.to_string_lossy().to_string()));
+    execute_script(&script, Some(path.to_string_lossy().to_string()), options);
 }
 
 /// Load function definitions from config and call a function with arguments.
@@ -103,71 +154,975 @@ pub fn execute_file(path: &PathBuf) {
 /// # Arguments
 /// * `function_name` - The function to call (may be nested, e.g. "docker shell").
 /// * `args` - Arguments to pass to the function.
-pub fn run_function_call(function_name: &str, args: &[String]) {
-    // Load the config file from ~/.runfile or ./Runfile
-    let config_content = config::load_config_or_exit();
-
-    // Parse the config to load function definitions
-    let mut interpreter = interpreter::Interpreter::new();
+/// * `options` - Interpreter options from the command line.
+pub fn run_function_call(function_name: &str, args: &[String], options: &interpreter::Options) {
+    call_function(function_name, args, options, None);
+}
 
-    match parser::parse_script(&config_content) {
-        Ok(program) => {
-            // Execute to load function definitions
-            if let Err(e) = interpreter.execute(program) {
-                eprintln!("Error loading functions: {}", e);
-                std::process::exit(1);
-            }
+/// Call a function as `run_function_call` does, continuing the chain
+/// `resumed` if given.
+fn call_function(
+    function_name: &str,
+    args: &[String],
+    options: &interpreter::Options,
+    resumed: Option<resume::Chain>,
+) {
+    // Subcommands such as `run hook` run anywhere, without a Runfile
+    if commands::STANDALONE.contains(&function_name)
+        && !runfile_defines(function_name)
+        && let Some(result) = commands::dispatch_standalone(function_name, args)
+    {
+        if let Err(e) = result {
+            eprintln!("{}", tr!("Error: {}", e));
+            std::process::exit(1);
         }
-        Err(e) => {
-            print_parse_error(&e, &config_content, Some("Runfile"));
+        return;
+    }
+
+    let mut interpreter = load_interpreter(options);
+
+    // Built-in subcommands apply unless the Runfile defines the same name
+    if !interpreter.has_function(function_name)
+        && let Some(result) = commands::dispatch(function_name, args, &interpreter)
+    {
+        if let Err(e) = result {
+            eprintln!("{}", tr!("Error: {}", e));
             std::process::exit(1);
         }
+        return;
+    }
+
+    // With commands.package_json, package.json scripts fill in for
+    // functions the Runfile doesn't define
+    if !interpreter.has_function(function_name)
+        && let Some(scripts) = package_scripts(
+            interpreter.setting("commands.package_json"),
+            interpreter.runfile_dir(),
+        )
+        && scripts.get(function_name).is_some()
+    {
+        if options.dry_run {
+            println!("{}", scripts.command_line(function_name, args).join(" "));
+            return;
+        }
+        match scripts.run(function_name, args) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => crate::fatal_error(&tr!("Error: {}", e)),
+        }
     }
 
     // Now execute the function call with arguments
     // For nested commands, try different combinations:
     // e.g., "docker shell app" -> try "docker:shell" with arg "app"
+    let call = std::iter::once(function_name).chain(args.iter().map(String::as_str));
+    let chain = track_chain(&mut interpreter, call, resumed, options);
     if let Err(e) = interpreter.call_function_without_parens(function_name, args) {
-        eprintln!("Error: {}", e);
+        eprintln!("{}", tr!("Error: {}", e));
+        report_chain(chain.as_deref());
+        // Exit like the command that failed, so callers can tell failures apart
+        let code = interpreter.failure_code().unwrap_or(1);
+        // Removing the temporary paths created while the Runfile loaded
+        drop(interpreter);
+        std::process::exit(code);
+    }
+    finish_chain(chain.as_deref());
+    if !options.dry_run {
+        record_history(&interpreter, function_name, args);
+    }
+}
+
+/// Remember the arguments of a successful call for completions, when the
+/// Runfile sets `completion.history = true`.
+fn record_history(interpreter: &interpreter::Interpreter, function_name: &str, args: &[String]) {
+    if interpreter.setting("completion.history") != Some("true") {
+        return;
+    }
+    let Ok((function, args)) = interpreter.resolve_call(function_name, args) else {
+        return;
+    };
+    if let Err(e) = history::record(interpreter.runfile_dir(), &function, args) {
+        eprintln!("{}", tr!("Warning: {}", e));
+    }
+}
+
+/// Print the values the function `words` call was given before for its next
+/// argument, most recent first, for the completion scripts
+/// (`--complete-history`). Nothing is printed unless the Runfile sets
+/// `completion.history = true`.
+pub fn complete_history(words: &[String], options: &interpreter::Options) {
+    let Some((function_name, args)) = words.split_first() else {
+        return;
+    };
+    let interpreter = load_interpreter(options);
+    if interpreter.setting("completion.history") != Some("true") {
+        return;
+    }
+    let Ok((function, args)) = interpreter.resolve_call(function_name, args) else {
+        return;
+    };
+    for value in history::values(interpreter.runfile_dir(), &function, args.len()) {
+        println!("{}", value);
+    }
+}
+
+/// Print what calling `function_name` with `args` would do, without running
+/// anything (`--explain`): where the function is defined and its
+/// definition, the functions it needs and its commands with variables and
+/// arguments substituted.
+pub fn explain(function_name: &str, args: &[String], options: &interpreter::Options) {
+    let interpreter = load_interpreter(options);
+    let (function, args) = interpreter
+        .resolve_call(function_name, args)
+        .unwrap_or_else(|e| crate::fatal_error(&tr!("Error: {}", e)));
+    println!("{}", function);
+
+    // The project's Runfile first, then ~/.runfile read along with it
+    let runfiles = config::find_runfile()
+        .into_iter()
+        .chain(config::global_runfile());
+    let found = runfiles.clone().find_map(|path| {
+        let source = fs::read_to_string(&path).ok()?;
+        Some((path, refactor::definition(&source, &function)?))
+    });
+    let (path, definition) = match found {
+        Some((path, definition)) => (Some(path), Some(definition)),
+        None => (config::find_runfile(), None),
+    };
+    match (&path, &definition) {
+        (Some(path), Some((line, _))) => println!("  Defined in {}:{}", path.display(), line),
+        (Some(path), None) => println!("  Defined in {}", path.display()),
+        (None, _) => {}
+    }
+    for line in definition.iter().flat_map(|(_, text)| text.lines()) {
+        println!("    {}", line);
+    }
+
+    match interpreter.prerequisites(&function) {
+        Ok(needs) if needs.is_empty() => println!("  Needs nothing"),
+        Ok(needs) => println!("  Needs {}, run first in this order", needs.join(", ")),
+        Err(e) => println!("  Needs: {}", e),
+    }
+    match interpreter.resolved_commands(&function, args) {
+        Ok(commands) => {
+            println!("  Runs:");
+            for line in commands.iter().flat_map(|command| command.lines()) {
+                println!("    {}", line);
+            }
+        }
+        Err(e) => println!("  Runs: {}", e),
+    }
+}
+
+/// Call a function, then again whenever files matching `patterns` change
+/// (`--watch`), until interrupted. The Runfile is loaded again for each run,
+/// so changes to it apply too.
+pub fn watch_function_call(
+    patterns: &[String],
+    function_name: &str,
+    args: &[String],
+    options: &interpreter::Options,
+) {
+    let base = std::env::current_dir().unwrap_or_default();
+    let mut snapshot = watch::Snapshot::take(patterns, &base);
+    for pattern in patterns {
+        if glob::expand(pattern, &base).is_empty() {
+            eprintln!(
+                "Warning: --watch pattern '{}' matches no files yet",
+                pattern
+            );
+        }
+    }
+    loop {
+        let interpreter = load_interpreter(options);
+        if let Err(e) = interpreter.call_function_without_parens(function_name, args) {
+            eprintln!("{}", tr!("Error: {}", e));
+        }
+        if process::interrupted() {
+            break;
+        }
+        eprintln!(
+            "Watching {} for changes (Ctrl+C to stop)",
+            patterns.join(" ")
+        );
+        let Some((next, changed)) = watch::wait_for_change(patterns, &base, &snapshot) else {
+            break;
+        };
+        match changed.as_slice() {
+            [path] => eprintln!(
+                "{} changed, running '{}' again",
+                path.display(),
+                function_name
+            ),
+            _ => eprintln!(
+                "{} files changed, running '{}' again",
+                changed.len(),
+                function_name
+            ),
+        }
+        snapshot = next;
+    }
+    // Interrupted, as the shell reports Ctrl+C
+    std::process::exit(130);
+}
+
+/// The package.json scripts in `dir` if the `commands.package_json` setting
+/// is `true`, warning about a package.json that can't be read.
+fn package_scripts(setting: Option<&str>, dir: &Path) -> Option<npm::Scripts> {
+    if setting != Some("true") {
+        return None;
+    }
+    npm::load(dir)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", tr!("Warning: {}", e));
+            None
+        })
+        .filter(|scripts| !scripts.scripts.is_empty())
+}
+
+/// Load function definitions from config, exiting on errors.
+fn load_interpreter(options: &interpreter::Options) -> interpreter::Interpreter {
+    let program = match config::makefile() {
+        // The rules of a Makefile, with --from-makefile
+        Some(path) => makefile::load(path).unwrap_or_else(|e| load_error(&e)),
+        None => {
+            // Load the config file from ~/.runfile or ./Runfile
+            let config_content = config::load_config_or_exit();
+            match parser::parse_script(&config_content) {
+                Ok(program) => with_global(program),
+                Err(e) => {
+                    print_parse_error(&e, &config_content, Some("Runfile"));
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    // Execute to load function definitions
+    let mut interpreter = interpreter::Interpreter::with_options(options.clone());
+    if let Err(e) = interpreter.execute(program) {
+        load_error(&e);
+    }
+    if let Err(e) = interpreter.check_dependencies() {
+        load_error(&e);
+    }
+    if let Err(e) = interpreter.apply_profile() {
+        load_error(&e);
+    }
+    interpreter
+}
+
+/// `program`, the project's Runfile, after the statements of ~/.runfile when
+/// that is read along with it, leaving out the functions the Runfile
+/// defines again. Exits on errors parsing ~/.runfile.
+pub(crate) fn with_global(mut program: ast::Program) -> ast::Program {
+    let Some((path, content)) = config::load_global() else {
+        return program;
+    };
+    let global = parser::parse_script(&content).unwrap_or_else(|e| {
+        print_parse_error(&e, &content, Some(&path.display().to_string()));
         std::process::exit(1);
+    });
+    let defined: HashSet<&str> = program.statements.iter().filter_map(defined_name).collect();
+    let mut statements: Vec<ast::Statement> = global
+        .statements
+        .into_iter()
+        .filter(|statement| defined_name(statement).is_none_or(|name| !defined.contains(name)))
+        .collect();
+    statements.append(&mut program.statements);
+    program.statements = statements;
+    program
+}
+
+/// The name of the function a statement defines, if it defines one.
+fn defined_name(statement: &ast::Statement) -> Option<&str> {
+    match statement {
+        ast::Statement::SimpleFunctionDef { name, .. }
+        | ast::Statement::BlockFunctionDef { name, .. } => Some(name),
+        _ => None,
     }
 }
 
-/// List all available functions from the Runfile.
-pub fn list_functions() {
-    let config_content = config::load_config_or_exit();
+/// Whether the Runfile, or the Makefile with --from-makefile, defines a
+/// function called `name`; `false` without one.
+fn runfile_defines(name: &str) -> bool {
+    let program = match config::makefile() {
+        Some(path) => makefile::load(path).ok(),
+        None => config::load_config()
+            .and_then(|content| parser::parse_script(&content).ok())
+            .map(with_global),
+    };
+    program.is_some_and(|program| {
+        described_functions(&program.statements)
+            .iter()
+            .any(|function| function.name == name)
+    })
+}
+
+/// Load function definitions from config and run `names` concurrently
+/// (`--parallel`), waiting for all of them.
+pub fn run_parallel(names: &[String], options: &interpreter::Options) {
+    call_in_parallel(names, options, None);
+}
+
+/// Run `names` as `run_parallel` does, continuing the chain `resumed` if
+/// given.
+fn call_in_parallel(
+    names: &[String],
+    options: &interpreter::Options,
+    resumed: Option<resume::Chain>,
+) {
+    let mut interpreter = load_interpreter(options);
+    let call = std::iter::once("--parallel").chain(names.iter().map(String::as_str));
+    let chain = track_chain(&mut interpreter, call, resumed, options);
+    if let Err(e) = interpreter.call_functions_in_parallel(names) {
+        eprintln!("{}", tr!("Error: {}", e));
+        report_chain(chain.as_deref());
+        std::process::exit(interpreter.failure_code().unwrap_or(1));
+    }
+    finish_chain(chain.as_deref());
+}
+
+/// Record the functions `call` runs as they complete, for `run --resume`,
+/// continuing `resumed` if given. Dry runs aren't recorded.
+fn track_chain<'a>(
+    interpreter: &mut interpreter::Interpreter,
+    call: impl Iterator<Item = &'a str>,
+    resumed: Option<resume::Chain>,
+    options: &interpreter::Options,
+) -> Option<Arc<resume::Chain>> {
+    if options.dry_run {
+        return None;
+    }
+    let chain = resumed.unwrap_or_else(|| {
+        resume::Chain::new(interpreter.runfile_dir(), call.map(String::from).collect())
+    });
+    let chain = Arc::new(chain);
+    interpreter.track_chain(chain.clone());
+    Some(chain)
+}
+
+/// After a chain failed, tell which of its functions completed and how to
+/// continue from the others.
+fn report_chain(chain: Option<&resume::Chain>) {
+    let Some(completed) = chain
+        .map(resume::Chain::completed)
+        .filter(|completed| !completed.is_empty())
+    else {
+        return;
+    };
+    eprintln!(
+        "{}",
+        tr!(
+            "Completed: {}. Run 'run --resume' to continue from the first function that didn't.",
+            completed.join(", ")
+        )
+    );
+}
+
+/// Forget a chain that completed.
+fn finish_chain(chain: Option<&resume::Chain>) {
+    if let Some(Err(e)) = chain.map(resume::Chain::finish) {
+        eprintln!("{}", tr!("Warning: {}", e));
+    }
+}
+
+/// Call again what the interrupted or failed chain of functions saved in
+/// the project called, skipping the functions of it that completed
+/// (`--resume`).
+pub fn resume(options: &interpreter::Options) {
+    let dir = config::find_runfile()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+    let chain = match resume::Chain::load(&dir) {
+        Ok(Some(chain)) => chain,
+        Ok(None) => crate::fatal_error(&tr!(
+            "Error: {}",
+            tr!("Nothing to resume: no chain of functions was interrupted here")
+        )),
+        Err(e) => crate::fatal_error(&tr!("Error: {}", e)),
+    };
+    eprintln!(
+        "{}",
+        tr!(
+            "Resuming 'run {}', skipping what completed: {}",
+            chain.call().join(" "),
+            chain.completed().join(", ")
+        )
+    );
+    let call = chain.call().to_vec();
+    match call.split_first() {
+        Some((flag, names)) if flag == "--parallel" => {
+            call_in_parallel(names, options, Some(chain))
+        }
+        Some((function_name, args)) => call_function(function_name, args, options, Some(chain)),
+        None => {}
+    }
+}
+
+/// Run each of `names` in a pane of its own (`--split`) of the terminal
+/// multiplexer `run` is in, by running `run` again there with `flags`.
+/// Outside of one they run concurrently here, as with `--parallel`.
+pub fn run_split(names: &[String], flags: &[String], options: &interpreter::Options) {
+    let Some(multiplexer) = panes::detect() else {
+        eprintln!(
+            "Not inside tmux, zellij or Windows Terminal: running the functions here in parallel"
+        );
+        return run_parallel(names, options);
+    };
+    let interpreter = load_interpreter(options);
+    if let Some(name) = names.iter().find(|name| !interpreter.has_function(name)) {
+        crate::fatal_error(&tr!("Error: {}", tr!("Function '{}' not found", name)));
+    }
+    let exe = std::env::current_exe()
+        .unwrap_or_else(|e| crate::fatal_error(&format!("Error: Cannot find run itself: {}", e)));
+    let cwd = std::env::current_dir().unwrap_or_default();
+    for name in names {
+        let mut argv = vec![exe.display().to_string()];
+        argv.extend(flags.iter().cloned());
+        argv.push(name.clone());
+        if let Err(e) = multiplexer.open(name, &argv, &cwd) {
+            crate::fatal_error(&tr!("Error: {}", e));
+        }
+        println!("Started '{}' in a new {} pane", name, multiplexer.name());
+    }
+}
+
+/// Print every path looked at for a Runfile, whether one was there and
+/// which one is used (`--which-runfile`).
+pub fn which_runfile() {
+    let search = config::search_runfile();
+    match &search.start {
+        Some(start) => println!("Searching from {}:", start.display()),
+        None => println!("Searching without a current directory:"),
+    }
+    let status = |found| if found { "found" } else { "not found" };
+    let mut lines: Vec<(String, &str)> = search
+        .probes
+        .iter()
+        .map(|probe| (probe.path.display().to_string(), status(probe.found)))
+        .collect();
+    if let Some(boundary) = search.boundary {
+        lines.push((format!("(stopped at {})", boundary), ""));
+    }
+    // With a project Runfile, ~/.runfile is read along with it unless
+    // --no-global is given; without one, it is the fallback
+    let global = config::global_runfile();
+    match (&search.fallback, config::get_home_dir()) {
+        (Some(probe), _) => lines.push((probe.path.display().to_string(), status(probe.found))),
+        (None, Some(home)) if home.join(".runfile").exists() => lines.push((
+            home.join(".runfile").display().to_string(),
+            match global {
+                Some(_) => "exists, read first",
+                None => "exists, not used (--no-global)",
+            },
+        )),
+        _ => {}
+    }
+    let width = lines
+        .iter()
+        .filter(|(_, status)| !status.is_empty())
+        .map(|(path, _)| path.len())
+        .max()
+        .unwrap_or(0);
+    for (path, status) in &lines {
+        let line = format!("  {:width$}  {}", path, status, width = width);
+        println!("{}", line.trim_end());
+    }
+    let chosen = search
+        .probes
+        .iter()
+        .chain(&search.fallback)
+        .find(|probe| probe.found);
+    match (chosen, global) {
+        (Some(probe), Some(global)) => println!(
+            "Using {}, with the functions of {} it doesn't define itself",
+            probe.path.display(),
+            global.display()
+        ),
+        (Some(probe), None) => println!("Using {} (only this file is read)", probe.path.display()),
+        (None, _) => crate::fatal_error(&tr!(config::NO_RUNFILE_ERROR)),
+    }
+}
+
+/// Print the namespace separators set by the Runfile's `commands.separators`,
+/// for the completion scripts.
+/// Print the functions of the Runfile in or above the current directory, one
+/// per line, for the shell hooks to define (`--hook-functions`). Nothing is
+/// printed without one: ~/.runfile applies everywhere, so its functions
+/// aren't directory-local.
+pub fn hook_functions() {
+    let Some(path) = config::find_runfile()
+        .filter(|path| path.file_name().is_some_and(|name| name == "Runfile"))
+    else {
+        return;
+    };
+    let Some(program) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| parser::parse_script(&content).ok())
+    else {
+        return;
+    };
+    for function in described_functions(&program.statements) {
+        if hook::exposable(&function.name) {
+            println!("{}", function.name);
+        }
+    }
+}
 
-    // Parse the config to extract function names
-    match parser::parse_script(&config_content) {
-        Ok(program) => {
-            let mut functions = Vec::new();
-            for statement in program.statements {
-                match statement {
-                    ast::Statement::SimpleFunctionDef { name, .. } => {
-                        functions.push(name);
+pub fn list_separators() {
+    let config_content = config::load_config_or_exit();
+    let setting = parser::parse_script(&config_content)
+        .ok()
+        .map(with_global)
+        .and_then(|program| {
+            program
+                .statements
+                .into_iter()
+                .rev()
+                .find_map(|statement| match statement {
+                    ast::Statement::Setting { key, value } if key == "commands.separators" => {
+                        Some(value)
                     }
-                    ast::Statement::BlockFunctionDef { name, .. } => {
-                        functions.push(name);
+                    _ => None,
+                })
+        });
+    match interpreter::parse_separators(setting.as_deref()) {
+        Ok(separators) => println!("{}", separators.iter().collect::<String>()),
+        Err(e) => crate::fatal_error(&e),
+    }
+}
+
+/// How `run --list` prints the functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    /// Aligned names and descriptions, for people.
+    Text,
+    /// One JSON document, for editors and other tools.
+    Json,
+}
+
+/// A function as `run --list` shows it.
+struct Listed {
+    name: String,
+    description: Option<String>,
+    /// Positional parameters the body refers to.
+    params: Vec<Param>,
+    /// Whether the body takes the rest of the arguments (`$@`, `$2..`).
+    rest: bool,
+    /// The `@matrix` axes, whose values can be selected by argument.
+    matrix: Vec<ast::MatrixAxis>,
+    /// `danger` or `prod` for a `@danger` or `@prod` function.
+    danger: Option<String>,
+}
+
+/// A positional parameter, `$1` or `${1:-default}`, or named in the
+/// signature.
+#[derive(Debug, PartialEq)]
+struct Param {
+    position: usize,
+    default: Option<String>,
+    name: Option<String>,
+}
+
+/// List all available functions from the Runfile, and with `all` those of
+/// every workspace member, each name once with the members defining it.
+pub fn list_functions(all: bool, format: ListFormat) {
+    let program = match config::makefile() {
+        Some(path) => {
+            makefile::load(path).unwrap_or_else(|e| crate::fatal_error(&tr!("Error: {}", e)))
+        }
+        None => {
+            let config_content = config::load_config_or_exit();
+
+            // Parse the config to extract function names
+            match parser::parse_script(&config_content) {
+                Ok(program) => with_global(program),
+                Err(e) => {
+                    eprintln!("{}", tr!("Error parsing Runfile: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    let functions = described_functions(&program.statements);
+
+    let root = config::find_runfile()
+        .filter(|path| path.file_name().is_some_and(|name| name == "Runfile"))
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    let separators =
+        interpreter::parse_separators(setting(&program.statements, "commands.separators"))
+            .unwrap_or_else(|e| crate::fatal_error(&tr!("Error: {}", e)));
+    let (true, Some(root)) = (all, root) else {
+        if format == ListFormat::Json {
+            let functions = functions.into_iter().map(|function| (function, None));
+            println!("{}", functions_json(functions, &separators));
+            return;
+        }
+        // package.json scripts the Runfile's functions don't shadow
+        let dir = config::find_runfile()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let scripts = package_scripts(setting(&program.statements, "commands.package_json"), &dir);
+        let (manager, scripts) = match scripts {
+            Some(scripts) => (scripts.manager, scripts.scripts),
+            None => ("npm", Vec::new()),
+        };
+        let scripts: Vec<(String, String)> = scripts
+            .into_iter()
+            .filter(|(name, _)| !functions.iter().any(|function| function.name == *name))
+            .collect();
+        if functions.is_empty() && scripts.is_empty() {
+            println!("{}", tr!("No functions defined in Runfile."));
+            // Exit with success since the file was found and parsed correctly
+            std::process::exit(0);
+        }
+        if !functions.is_empty() {
+            let width = functions
+                .iter()
+                .filter(|function| function.description.is_some() || function.danger.is_some())
+                .map(|function| function.name.len())
+                .max()
+                .unwrap_or(0);
+            println!("{}", tr!("Available functions:"));
+            for function in &functions {
+                // @danger and @prod functions stand out before their description
+                let tag = function
+                    .danger
+                    .as_ref()
+                    .map(|marker| style::alert(&format!("[{}]", marker)));
+                let note: Vec<&str> = tag
+                    .iter()
+                    .chain(&function.description)
+                    .map(String::as_str)
+                    .collect();
+                match note.is_empty() {
+                    false => {
+                        println!(
+                            "  {:width$}  {}",
+                            function.name,
+                            note.join(" "),
+                            width = width
+                        )
                     }
-                    _ => {}
+                    true => println!("  {}", function.name),
                 }
             }
+        }
+        if !scripts.is_empty() {
+            if !functions.is_empty() {
+                println!();
+            }
+            let width = scripts
+                .iter()
+                .map(|(name, _)| name.len())
+                .max()
+                .unwrap_or(0);
+            println!("package.json scripts ({} run):", manager);
+            for (name, command) in scripts {
+                println!("  {:width$}  {}", name, command, width = width);
+            }
+        }
+        return;
+    };
+
+    // Each function with the members defining it, "." being the root
+    let mut sources: Vec<(Listed, Vec<String>)> = Vec::new();
+    let mut add = |functions: Vec<Listed>, member: String| {
+        for function in functions {
+            match sources
+                .iter_mut()
+                .find(|(listed, _)| listed.name == function.name)
+            {
+                Some((_, members)) => members.push(member.clone()),
+                None => sources.push((function, vec![member.clone()])),
+            }
+        }
+    };
+    add(functions, ".".to_string());
+    let patterns = workspace::member_patterns(setting(&program.statements, "workspace.members"));
+    for member in workspace::members(&root, &patterns) {
+        let path = root.join(&member).join("Runfile");
+        let member = member.to_string_lossy().replace('\\', "/");
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| parser::parse_script(&content).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(program) => add(described_functions(&program.statements), member),
+            Err(e) => eprintln!("Warning: skipping {}/Runfile: {}", member, e),
+        }
+    }
+
+    if format == ListFormat::Json {
+        let functions = sources
+            .into_iter()
+            .map(|(function, members)| (function, Some(members)));
+        println!("{}", functions_json(functions, &separators));
+        return;
+    }
+    if sources.is_empty() {
+        println!("{}", tr!("No functions defined in the workspace."));
+        return;
+    }
+    let width = sources
+        .iter()
+        .map(|(function, _)| function.name.len())
+        .max()
+        .unwrap_or(0);
+    println!("{}", tr!("Available functions:"));
+    for (function, members) in sources {
+        println!(
+            "  {:width$}  {}",
+            function.name,
+            members.join(", "),
+            width = width
+        );
+    }
+}
+
+/// The `run --list --format json` document: each function with its
+/// namespace (the parts of its name before the last separator), description,
+/// parameters, matrix axes, and the workspace members defining it if known.
+fn functions_json(
+    functions: impl Iterator<Item = (Listed, Option<Vec<String>>)>,
+    separators: &[char],
+) -> String {
+    let strings = |values: &[String]| {
+        let values: Vec<String> = values.iter().map(|value| json_string(value)).collect();
+        format!("[{}]", values.join(","))
+    };
+    let entries: Vec<String> = functions
+        .map(|(function, members)| {
+            let mut namespace: Vec<String> =
+                function.name.split(separators).map(String::from).collect();
+            let command = namespace.pop().unwrap_or_default();
+            let params: Vec<String> = function
+                .params
+                .iter()
+                .map(|param| {
+                    let name = match &param.name {
+                        Some(name) => format!(",\"name\":{}", json_string(name)),
+                        None => String::new(),
+                    };
+                    format!(
+                        "{{\"position\":{},\"default\":{}{}}}",
+                        param.position,
+                        param.default.as_deref().map_or("null".to_string(), json_string),
+                        name
+                    )
+                })
+                .collect();
+            let matrix: Vec<String> = function
+                .matrix
+                .iter()
+                .map(|axis| format!("{}:{}", json_string(&axis.name), strings(&axis.values)))
+                .collect();
+            let mut entry = format!(
+                "{{\"name\":{},\"namespace\":{},\"command\":{},\"description\":{},\"params\":[{}],\"rest\":{},\"matrix\":{{{}}},\"danger\":{}",
+                json_string(&function.name),
+                strings(&namespace),
+                json_string(&command),
+                function.description.as_deref().map_or("null".to_string(), json_string),
+                params.join(","),
+                function.rest,
+                matrix.join(","),
+                function.danger.as_deref().map_or("null".to_string(), json_string),
+            );
+            if let Some(members) = members {
+                entry.push_str(&format!(",\"members\":{}", strings(&members)));
+            }
+            entry.push('}');
+            entry
+        })
+        .collect();
+    format!("{{\"functions\":[{}]}}", entries.join(","))
+}
 
-            if functions.is_empty() {
-                println!("No functions defined in Runfile.");
-                // Exit with success since the file was found and parsed correctly
-                std::process::exit(0);
-            } else {
-                println!("Available functions:");
-                for func in functions {
-                    println!("  {}", func);
+/// The positional parameters `body` refers to, by position, and whether it
+/// takes the rest of the arguments, from the forms `substitute_args`
+/// replaces: `$1`, `${1}`, `${1:-default}`, `$@`, `$2..` and `${@:2}`.
+fn parameters(body: &str) -> (Vec<Param>, bool) {
+    let mut params: Vec<Param> = Vec::new();
+    let mut rest = false;
+    for (position, _) in body.match_indices('$') {
+        let after = &body[position + 1..];
+        let braced = after.strip_prefix('{');
+        let digits = braced.unwrap_or(after);
+        let end = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        if after.starts_with(['@', '*']) || after.starts_with("{@:") {
+            rest = true;
+            continue;
+        }
+        let Ok(number) = digits[..end].parse::<usize>() else {
+            continue;
+        };
+        let default = match braced {
+            Some(_) => match digits[end..].strip_prefix(":-") {
+                Some(value) => match value.find('}') {
+                    Some(close) => Some(value[..close].to_string()),
+                    None => continue,
+                },
+                None if digits[end..].starts_with('}') => None,
+                None => continue,
+            },
+            None if digits[end..].starts_with("..") => {
+                rest = true;
+                continue;
+            }
+            None => None,
+        };
+        if number == 0 {
+            continue;
+        }
+        match params.iter_mut().find(|param| param.position == number) {
+            Some(param) => {
+                if param.default.is_none() {
+                    param.default = default;
                 }
             }
+            None => params.push(Param {
+                position: number,
+                default,
+                name: None,
+            }),
         }
-        Err(e) => {
-            eprintln!("Error parsing Runfile: {}", e);
-            std::process::exit(1);
+    }
+    params.sort_by_key(|param| param.position);
+    (params, rest)
+}
+
+/// How `run --list` shows the function `name` with these attributes and body.
+fn listed(name: &str, attributes: &[ast::Attribute], body: &str) -> Listed {
+    let mut description = None;
+    let mut matrix = Vec::new();
+    let mut danger = None;
+    for attribute in attributes {
+        match attribute {
+            ast::Attribute::Description(text) => description = Some(text.clone()),
+            ast::Attribute::Matrix(axes) => matrix = axes.clone(),
+            ast::Attribute::Danger(marker) => danger = Some(marker.clone()),
+            _ => {}
+        }
+    }
+    let (mut params, rest) = parameters(body);
+    // Named in the signature: `$name` in the body doesn't show as `$1`
+    for (index, declared) in attributes
+        .iter()
+        .filter_map(|attribute| match attribute {
+            ast::Attribute::Params(parameters) => Some(parameters),
+            _ => None,
+        })
+        .flatten()
+        .enumerate()
+    {
+        let param = Param {
+            position: index + 1,
+            default: declared.default.as_ref().map(ToString::to_string),
+            name: Some(declared.name.clone()),
+        };
+        match params.iter_mut().find(|p| p.position == param.position) {
+            Some(existing) => *existing = param,
+            None => params.push(param),
         }
     }
+    params.sort_by_key(|param| param.position);
+    Listed {
+        name: name.to_string(),
+        description,
+        params,
+        rest,
+        matrix,
+        danger,
+    }
+}
+
+/// The functions `statements` define, in order, with their descriptions.
+fn described_functions(statements: &[ast::Statement]) -> Vec<Listed> {
+    let mut functions = Vec::new();
+    let mut templates = HashMap::new();
+    collect_function_names(statements, &mut templates, &mut functions);
+    functions
+}
+
+/// The value of a top-level setting.
+fn setting<'a>(statements: &'a [ast::Statement], key: &str) -> Option<&'a str> {
+    statements
+        .iter()
+        .rev()
+        .find_map(|statement| match statement {
+            ast::Statement::Setting { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
 }
 
+/// Collect the names of defined functions, following only the active branch
+/// of environment guards and expanding template instantiations.
+fn collect_function_names<'a>(
+    statements: &'a [ast::Statement],
+    templates: &mut HashMap<String, (&'a [String], &'a str)>,
+    functions: &mut Vec<Listed>,
+) {
+    for statement in statements {
+        match statement {
+            ast::Statement::SimpleFunctionDef {
+                name,
+                command_template,
+                attributes,
+            } if !functions.iter().any(|function| function.name == *name) => {
+                functions.push(listed(name, attributes, command_template));
+            }
+            ast::Statement::BlockFunctionDef {
+                name,
+                commands,
+                attributes,
+            } if !functions.iter().any(|function| function.name == *name) => {
+                functions.push(listed(name, attributes, &commands.join("\n")));
+            }
+            ast::Statement::EnvGuard {
+                variable,
+                negated,
+                then_branch,
+                else_branch,
+            } => {
+                let branch = if interpreter::env_guard_holds(variable, *negated) {
+                    then_branch
+                } else {
+                    else_branch
+                };
+                collect_function_names(branch, templates, functions);
+            }
+            ast::Statement::TemplateDef { name, params, body } => {
+                templates.insert(name.clone(), (params, body));
+            }
+            ast::Statement::Instantiate { calls } => {
+                for (name, args) in calls {
+                    let Some((params, body)) = templates.get(name).copied() else {
+                        continue;
+                    };
+                    // Only literal arguments are known without running the Runfile
+                    let args: Vec<String> = args
+                        .iter()
+                        .map(|arg| match arg {
+                            ast::Expression::String(value)
+                            | ast::Expression::Interpolated(value) => value.clone(),
+                            ast::Expression::List(_) => String::new(),
+                        })
+                        .collect();
+                    if params.len() != args.len() {
+                        continue;
+                    }
+                    let source = interpreter::bind_params(body, params, &args);
+                    if let Ok(program) = parser::parse_script(&source) {
+                        // A template can't instantiate itself, directly or not
+                        let mut nested = templates.clone();
+                        nested.remove(name);
+                        collect_function_names(&program.statements, &mut nested, functions);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! GitHub Actions workflow commands, used when running under Actions
+//! (`GITHUB_ACTIONS=true`) so errors and function output render natively.
+
+/// Whether we are running inside a GitHub Actions job.
+pub fn is_actions() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+/// An `::error` annotation pointing at `file` (and `line`, if known).
+pub fn error_annotation(file: &str, line: Option<usize>, message: &str) -> String {
+    let mut location = format!("file={}", escape_property(file));
+    if let Some(line) = line {
+        location.push_str(&format!(",line={}", line));
+    }
+    format!("::error {}::{}", location, escape_data(message))
+}
+
+/// Start a collapsible group of log lines titled `title`.
+pub fn group_start(title: &str) -> String {
+    format!("::group::{}", escape_data(title))
+}
+
+pub fn group_end() -> &'static str {
+    "::endgroup::"
+}
+
+/// Escape a workflow command message.
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a workflow command property value.
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,135 @@
+//! Minimal glob matching for declared task files.
+//!
+//! Patterns use `/` as the separator on every platform. `*` and `?` match
+//! within a single path component and `**` matches any number of components.
+//! A pattern naming a directory matches every file inside it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories never descended into while expanding patterns.
+const SKIPPED_DIRS: &[&str] = &[".git", ".run"];
+
+/// Expand `pattern` against the files under `base`, returning the matching
+/// file paths relative to `base`, sorted.
+pub fn expand(pattern: &str, base: &Path) -> Vec<PathBuf> {
+    let pattern = pattern.trim_start_matches("./");
+    let segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+
+    // Start walking from the longest literal prefix of the pattern
+    let literal = segments
+        .iter()
+        .take_while(|segment| !has_wildcard(segment))
+        .count();
+    let root: PathBuf = segments[..literal].iter().collect();
+
+    let mut found = Vec::new();
+    let start = base.join(&root);
+    if literal == segments.len() {
+        if start.is_file() {
+            found.push(root);
+        } else if start.is_dir() {
+            walk(&start, &root, &mut found);
+        }
+        return found;
+    }
+
+    let mut candidates = Vec::new();
+    walk(&start, &root, &mut candidates);
+    for candidate in candidates {
+        let components: Vec<String> = candidate
+            .iter()
+            .map(|c| c.to_string_lossy().into_owned())
+            .collect();
+        let components: Vec<&str> = components.iter().map(String::as_str).collect();
+        // A match on a leading directory covers everything inside it
+        if (1..=components.len()).any(|n| matches(&segments, &components[..n])) {
+            found.push(candidate);
+        }
+    }
+    found
+}
+
+/// Whether the relative `path` itself matches `pattern`.
+pub fn matches_path(pattern: &str, path: &Path) -> bool {
+    let pattern = pattern.trim_start_matches("./");
+    let segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let components: Vec<String> = path
+        .iter()
+        .map(|c| c.to_string_lossy().into_owned())
+        .collect();
+    let components: Vec<&str> = components.iter().map(String::as_str).collect();
+    matches(&segments, &components)
+}
+
+/// Whether `pattern` contains glob syntax.
+pub fn has_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Recursively collect the files under `dir`, as paths joined onto `relative`.
+fn walk(dir: &Path, relative: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let path = relative.join(&name);
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => {
+                if !SKIPPED_DIRS.contains(&name.to_string_lossy().as_ref()) {
+                    walk(&entry.path(), &path, found);
+                }
+            }
+            Ok(_) => found.push(path),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Match path components against pattern segments.
+fn matches(segments: &[&str], components: &[&str]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((&"**", rest)) => {
+            (0..=components.len()).any(|skip| matches(rest, &components[skip..]))
+        }
+        Some((segment, rest)) => match components.split_first() {
+            Some((component, remaining)) => {
+                matches_component(segment, component) && matches(rest, remaining)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match a single path component against a pattern segment with `*` and `?`.
+fn matches_component(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
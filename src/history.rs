@@ -0,0 +1,86 @@
+//! The arguments functions were called with, recorded per project in
+//! `.run/history` next to the Runfile when it sets `completion.history =
+//! true`, so that completions can offer the values used before, such as
+//! deploy targets and container names.
+//!
+//! Each line is a successful call from the command line: the function and
+//! its arguments, separated by tabs, with `\`, tabs and newlines in them
+//! escaped. The most recent `MAX_CALLS` are kept.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// File, relative to the Runfile's directory, holding the recorded calls.
+pub const HISTORY_FILE: &str = ".run/history";
+
+/// Calls kept in the history file.
+const MAX_CALLS: usize = 500;
+
+/// Record that `function` was called with `args`, in the project at `dir`.
+pub fn record(dir: &Path, function: &str, args: &[String]) -> io::Result<()> {
+    let path = dir.join(HISTORY_FILE);
+    let mut calls: Vec<String> = fs::read_to_string(&path)
+        .map(|text| text.lines().map(String::from).collect())
+        .unwrap_or_default();
+    let call: Vec<String> = std::iter::once(function)
+        .chain(args.iter().map(String::as_str))
+        .map(escape)
+        .collect();
+    calls.push(call.join("\t"));
+    if calls.len() > MAX_CALLS {
+        calls.drain(..calls.len() - MAX_CALLS);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, calls.join("\n") + "\n")
+}
+
+/// The values `function` was passed as its argument at `position` (from 0)
+/// in the project at `dir`, most recent first, each once.
+pub fn values(dir: &Path, function: &str, position: usize) -> Vec<String> {
+    let Ok(text) = fs::read_to_string(dir.join(HISTORY_FILE)) else {
+        return Vec::new();
+    };
+    let mut values: Vec<String> = Vec::new();
+    for line in text.lines().rev() {
+        let mut fields = line.split('\t').map(unescape);
+        if fields.next().as_deref() != Some(function) {
+            continue;
+        }
+        if let Some(value) = fields.nth(position)
+            && !values.contains(&value)
+        {
+            values.push(value);
+        }
+    }
+    values
+}
+
+/// `field` with `\`, tabs and newlines escaped, to be one field of a line.
+pub fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// A field written by `escape`.
+pub fn unescape(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
@@ -0,0 +1,28 @@
+//! Shell hooks for `run hook bash|zsh|fish`: evaluated in the shell's startup
+//! file, they define a shell function for each function of the Runfile in
+//! or above the current directory as you `cd` around, so `build` runs
+//! `run build`.
+
+const BASH_HOOK: &str = include_str!("../hooks/run.bash");
+const ZSH_HOOK: &str = include_str!("../hooks/run.zsh");
+const FISH_HOOK: &str = include_str!("../hooks/run.fish");
+
+/// The hook for `shell`, or `None` if there isn't one.
+pub fn script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH_HOOK),
+        "zsh" => Some(ZSH_HOOK),
+        "fish" => Some(FISH_HOOK),
+        _ => None,
+    }
+}
+
+/// Whether the hooks can define a shell function called `name`, which they
+/// put in shell code unquoted.
+pub fn exposable(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':' | '.'))
+}
@@ -0,0 +1,291 @@
+//! Translations of the messages `run` prints: errors, prompts and summaries.
+//!
+//! Messages are looked up by their English text, gettext-style, in the
+//! catalog of the language of the locale (`LC_ALL`, `LC_MESSAGES` or `LANG`,
+//! the first one set deciding, as for any POSIX program). A message a
+//! catalog lacks stays in English. `tr!` works like `format!`, with a `{}`
+//! for each value in the English text and in its translations.
+
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// The languages messages are translated to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Spanish,
+    German,
+}
+
+impl Language {
+    /// The language of a locale such as `de_DE.UTF-8` or `es`, English for
+    /// `C`, `POSIX` and languages without a catalog.
+    pub fn from_locale(locale: &str) -> Language {
+        let code = locale.split(['_', '-', '.', '@']).next().unwrap_or("");
+        match code.to_ascii_lowercase().as_str() {
+            "es" => Language::Spanish,
+            "de" => Language::German,
+            _ => Language::English,
+        }
+    }
+
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => &[],
+            Language::Spanish => SPANISH,
+            Language::German => GERMAN,
+        }
+    }
+}
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// Translate messages to `language` whatever the locale, before anything is
+/// translated.
+pub fn set_language(language: Language) {
+    let _ = LANGUAGE.set(language);
+}
+
+/// The language of the user's locale, looked up once.
+pub fn language() -> Language {
+    *LANGUAGE.get_or_init(|| {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+            .map_or(Language::English, |locale| Language::from_locale(&locale))
+    })
+}
+
+/// `message` in `language`, or as it is without a translation.
+pub fn translate(language: Language, message: &str) -> &str {
+    language
+        .catalog()
+        .iter()
+        .find(|(english, _)| *english == message)
+        .map_or(message, |(_, translated)| translated)
+}
+
+/// `message` in the user's language, with each `{}` replaced by the next of
+/// `args`. Use through `tr!`.
+pub fn format(message: &str, args: &[&dyn Display]) -> String {
+    let mut parts = translate(language(), message).split("{}");
+    let mut text = parts.next().unwrap_or_default().to_string();
+    let mut args = args.iter();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            text.push_str(&arg.to_string());
+        }
+        text.push_str(part);
+    }
+    text
+}
+
+/// Whether `answer` to a `[y/N]` question means yes, in English or the
+/// user's language.
+pub fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim().to_lowercase();
+    let translated = match language() {
+        Language::English => &[][..],
+        Language::Spanish => &["s", "si", "sí"][..],
+        Language::German => &["j", "ja"][..],
+    };
+    matches!(answer.as_str(), "y" | "yes") || translated.contains(&answer.as_str())
+}
+
+/// `format!` for a message in the user's language: `tr!("Function '{}' not
+/// found", name)`.
+macro_rules! tr {
+    ($message:expr $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::format($message, &[$(&$arg as &dyn ::std::fmt::Display),*])
+    };
+}
+pub(crate) use tr;
+
+const SPANISH: &[(&str, &str)] = &[
+    ("Error: {}", "Error: {}"),
+    ("Warning: {}", "Aviso: {}"),
+    ("Parse error: {}", "Error de sintaxis: {}"),
+    (
+        "Parse error in {}line {}: {}",
+        "Error de sintaxis en {}la línea {}: {}",
+    ),
+    (
+        "Error loading functions: {}",
+        "Error al cargar las funciones: {}",
+    ),
+    (
+        "Error parsing Runfile: {}",
+        "Error al analizar el Runfile: {}",
+    ),
+    ("Execution error: {}", "Error de ejecución: {}"),
+    (
+        "Error: No Runfile found. Create ~/.runfile or ./Runfile to define functions.",
+        "Error: no se encontró ningún Runfile. Crea ~/.runfile o ./Runfile para definir funciones.",
+    ),
+    (
+        "To call the rules of {} as functions, run with --from-makefile.",
+        "Para llamar a las reglas de {} como funciones, ejecuta con --from-makefile.",
+    ),
+    ("No Runfile found", "No se encontró ningún Runfile"),
+    ("Function '{}' not found", "No se encontró la función '{}'"),
+    ("Function '{}' was not run", "La función '{}' no se ejecutó"),
+    ("Available functions:", "Funciones disponibles:"),
+    (
+        "No functions defined in Runfile.",
+        "El Runfile no define ninguna función.",
+    ),
+    (
+        "No functions defined in the workspace.",
+        "El workspace no define ninguna función.",
+    ),
+    ("'{}' failed with {}", "'{}' falló ({})"),
+    (
+        "Command failed with {} (ignored)",
+        "El comando falló ({}) (ignorado)",
+    ),
+    ("{} (ignored)", "{} (ignorado)"),
+    ("exit status: {}", "código de salida: {}"),
+    (
+        "'{}' exited with status {}",
+        "'{}' terminó con el código {}",
+    ),
+    ("{} warning(s):", "{} aviso(s):"),
+    ("{} of {} functions failed:", "Fallaron {} de {} funciones:"),
+    (
+        "Not run because a prerequisite failed: {}",
+        "Sin ejecutar porque falló un requisito previo: {}",
+    ),
+    (
+        "Completed: {}. Run 'run --resume' to continue from the first function that didn't.",
+        "Completado: {}. Ejecuta 'run --resume' para continuar desde la primera función que no terminó.",
+    ),
+    (
+        "Resuming 'run {}', skipping what completed: {}",
+        "Reanudando 'run {}', sin repetir lo completado: {}",
+    ),
+    (
+        "Nothing to resume: no chain of functions was interrupted here",
+        "Nada que reanudar: aquí no se interrumpió ninguna cadena de funciones",
+    ),
+    ("Timings:", "Tiempos:"),
+    ("ok", "ok"),
+    ("failed", "falló"),
+    (
+        "{} function(s): {} succeeded, {} failed in {}",
+        "{} función(es): {} correcta(s), {} fallida(s) en {}",
+    ),
+    ("[y/N]", "[s/N]"),
+    ("Run '{}'?", "¿Ejecutar '{}'?"),
+    (
+        "Run '{}' in container '{}' instead?",
+        "¿Ejecutar '{}' en el contenedor '{}'?",
+    ),
+    (
+        "Function '{}' needs confirmation: {}\n  (run with --yes to confirm without a terminal)",
+        "La función '{}' necesita confirmación: {}\n  (ejecuta con --yes para confirmar sin terminal)",
+    ),
+    (
+        "Function '{}' is marked @{}: run with --allow-danger, or set RUN_ALLOW_DANGER=1 in CI",
+        "La función '{}' está marcada con @{}: ejecuta con --allow-danger, o define RUN_ALLOW_DANGER=1 en CI",
+    ),
+    (
+        "'{}' is marked @{}. Type its name to run it:",
+        "'{}' está marcada con @{}. Escribe su nombre para ejecutarla:",
+    ),
+];
+
+const GERMAN: &[(&str, &str)] = &[
+    ("Error: {}", "Fehler: {}"),
+    ("Warning: {}", "Warnung: {}"),
+    ("Parse error: {}", "Syntaxfehler: {}"),
+    (
+        "Parse error in {}line {}: {}",
+        "Syntaxfehler in {}Zeile {}: {}",
+    ),
+    (
+        "Error loading functions: {}",
+        "Fehler beim Laden der Funktionen: {}",
+    ),
+    (
+        "Error parsing Runfile: {}",
+        "Fehler beim Einlesen des Runfiles: {}",
+    ),
+    ("Execution error: {}", "Ausführungsfehler: {}"),
+    (
+        "Error: No Runfile found. Create ~/.runfile or ./Runfile to define functions.",
+        "Fehler: Kein Runfile gefunden. Lege ~/.runfile oder ./Runfile an, um Funktionen zu definieren.",
+    ),
+    (
+        "To call the rules of {} as functions, run with --from-makefile.",
+        "Um die Regeln von {} als Funktionen aufzurufen, starte mit --from-makefile.",
+    ),
+    ("No Runfile found", "Kein Runfile gefunden"),
+    ("Function '{}' not found", "Funktion '{}' nicht gefunden"),
+    (
+        "Function '{}' was not run",
+        "Funktion '{}' wurde nicht ausgeführt",
+    ),
+    ("Available functions:", "Verfügbare Funktionen:"),
+    (
+        "No functions defined in Runfile.",
+        "Das Runfile definiert keine Funktionen.",
+    ),
+    (
+        "No functions defined in the workspace.",
+        "Der Workspace definiert keine Funktionen.",
+    ),
+    ("'{}' failed with {}", "'{}' ist fehlgeschlagen ({})"),
+    (
+        "Command failed with {} (ignored)",
+        "Befehl fehlgeschlagen ({}) (ignoriert)",
+    ),
+    ("{} (ignored)", "{} (ignoriert)"),
+    ("exit status: {}", "Exit-Status: {}"),
+    ("'{}' exited with status {}", "'{}' endete mit Status {}"),
+    ("{} warning(s):", "{} Warnung(en):"),
+    (
+        "{} of {} functions failed:",
+        "{} von {} Funktionen fehlgeschlagen:",
+    ),
+    (
+        "Not run because a prerequisite failed: {}",
+        "Nicht ausgeführt, da eine Voraussetzung fehlschlug: {}",
+    ),
+    (
+        "Completed: {}. Run 'run --resume' to continue from the first function that didn't.",
+        "Abgeschlossen: {}. Mit 'run --resume' bei der ersten nicht abgeschlossenen Funktion weitermachen.",
+    ),
+    (
+        "Resuming 'run {}', skipping what completed: {}",
+        "'run {}' wird fortgesetzt, ohne Abgeschlossenes zu wiederholen: {}",
+    ),
+    (
+        "Nothing to resume: no chain of functions was interrupted here",
+        "Nichts fortzusetzen: hier wurde keine Kette von Funktionen unterbrochen",
+    ),
+    ("Timings:", "Laufzeiten:"),
+    ("ok", "ok"),
+    ("failed", "fehlgeschlagen"),
+    (
+        "{} function(s): {} succeeded, {} failed in {}",
+        "{} Funktion(en): {} erfolgreich, {} fehlgeschlagen in {}",
+    ),
+    ("[y/N]", "[j/N]"),
+    ("Run '{}'?", "'{}' ausführen?"),
+    (
+        "Run '{}' in container '{}' instead?",
+        "'{}' stattdessen im Container '{}' ausführen?",
+    ),
+    (
+        "Function '{}' needs confirmation: {}\n  (run with --yes to confirm without a terminal)",
+        "Funktion '{}' braucht eine Bestätigung: {}\n  (mit --yes ohne Terminal bestätigen)",
+    ),
+    (
+        "Function '{}' is marked @{}: run with --allow-danger, or set RUN_ALLOW_DANGER=1 in CI",
+        "Funktion '{}' ist mit @{} markiert: mit --allow-danger starten oder in CI RUN_ALLOW_DANGER=1 setzen",
+    ),
+    (
+        "'{}' is marked @{}. Type its name to run it:",
+        "'{}' ist mit @{} markiert. Zum Ausführen den Namen eingeben:",
+    ),
+];
@@ -1,26 +1,311 @@
-// Interpreter to execute the AST
+//! The interpreter running a parsed Runfile: [`Interpreter::execute`] loads
+//! its definitions and runs its top-level commands, after which its
+//! functions can be listed and called.
 
-use crate::ast::{Expression, Program, Statement};
-use std::collections::HashMap;
+use crate::artifacts;
+use crate::ast::{
+    Attribute, Condition, ExitAction, Expression, FileMode, MatrixAxis, NotifyOn, ParamDefault,
+    Parameter, Program, Statement, Supervision, Value,
+};
+use crate::builtins;
+use crate::cache;
+use crate::capped::{self, Capped};
+use crate::config;
+use crate::events::{self, Stream};
+use crate::github;
+use crate::i18n::{self, tr};
+use crate::logs;
+use crate::matrix;
+use crate::notify;
+use crate::pager;
+use crate::parser;
+use crate::perms;
+use crate::pipes;
+use crate::process;
+use crate::resume;
+use crate::scheduler;
+use crate::secrets;
+use crate::shell::{command_flag, default_shell, shell_name};
+use crate::status;
+use crate::style;
+use crate::timings;
+use crate::toolchain::Toolchain;
+use crate::tools;
+use crate::translate;
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+/// Options controlling how functions are run, usually set from CLI flags.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Run a function inside its `@container` image, without asking, when
+    /// required tools are missing locally.
+    pub auto_container: bool,
+    /// Ignore any configured remote cache.
+    pub no_remote_cache: bool,
+    /// Maximum number of functions to run at once when running prerequisites;
+    /// defaults to the available parallelism.
+    pub jobs: Option<usize>,
+    /// Re-run cacheable functions even when they are up to date.
+    pub force: bool,
+    /// Keep running independent functions after one fails.
+    pub keep_going: bool,
+    /// How to show the output of prerequisites running concurrently.
+    pub output: OutputMode,
+    /// Where to report machine-readable progress events, if anywhere.
+    pub events: Option<Arc<events::Emitter>>,
+    /// Status line showing what is running, on an interactive terminal.
+    pub status: Option<Arc<status::StatusLine>>,
+    /// How long each function took, reported when the invocation finishes
+    /// (`--timings`).
+    pub timings: Option<Arc<timings::Report>>,
+    /// Profile whose variables to apply (`--profile`).
+    pub profile: Option<String>,
+    /// Print each command instead of running it (`--dry-run`).
+    pub dry_run: bool,
+    /// Run `@confirm` functions without asking (`--yes`).
+    pub yes: bool,
+    /// Run `@danger` and `@prod` functions without their name being typed
+    /// (`--allow-danger`).
+    pub allow_danger: bool,
+    /// Collect the output and show it through a pager when it is longer
+    /// than the terminal (`--paginate`, with stdout a terminal).
+    pub paginate: bool,
+    /// Print each command, after substitution, and the shell running it
+    /// (`--verbose`).
+    pub verbose: bool,
+}
+
+/// How the output of functions running concurrently is shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputMode {
+    /// Lines appear as they are written, interleaved between functions.
+    #[default]
+    Interleave,
+    /// Each function's output is buffered and printed as one block when it
+    /// finishes.
+    Group,
+}
+
+/// Per-invocation settings applied to every command a function runs.
+#[derive(Debug, Clone, Default)]
+struct CommandContext {
+    /// Run commands inside this container image instead of on the host.
+    container: Option<String>,
+    /// Run commands with this shell instead of the default (`@shell`).
+    shell: Option<String>,
+    /// Variables of the function's `env { ... }` blocks.
+    env: Vec<(String, String)>,
+    /// Run each command in its own tracked process group, so it can be
+    /// cancelled when a concurrently running function fails.
+    process_group: bool,
+    /// Collect stdout and stderr here instead of passing them through.
+    output: Option<Buffer>,
+    /// Collect stdout here, as the value of a `capture(...)` variable.
+    capture: Option<Buffer>,
+    /// Collect stderr here, as the value of a `run_capture(...)` variable.
+    capture_errors: Option<Buffer>,
+    /// Record the exit code of a failing command here, as the value of a
+    /// `run_capture(...)` variable, instead of as `run`'s own.
+    failure: Option<Arc<AtomicI32>>,
+    /// Name of the function the commands belong to.
+    task: Option<String>,
+    /// Run the commands here instead of the directory `run` was invoked from.
+    cwd: Option<PathBuf>,
+    /// Keep the last lines of output here, for notifications.
+    tail: Option<Arc<Mutex<notify::OutputTail>>>,
+    /// Also copy all output here, to replay it for `@once` functions.
+    record: Option<Buffer>,
+    /// Values of the `@matrix` axes, for a variant of a matrix function.
+    variant: matrix::Variant,
+    /// Also write all output to this log file.
+    log: Option<Arc<Mutex<logs::LogFile>>>,
+    /// Start each line of output with this, the colored name of a function
+    /// running concurrently with others.
+    prefix: Option<String>,
+    /// Non-zero exit codes that don't count as failures.
+    exits: ExitPolicy,
+    /// Carry on after failing commands (`set ignore_errors`).
+    ignore_errors: bool,
+    /// Print each command before running it (`set verbose`).
+    verbose: bool,
+    /// Umask of the processes started (`@umask`).
+    umask: Option<u32>,
+    /// Exit status of the last command run, which the next one sees as `$?`.
+    last_status: Arc<AtomicI32>,
+    /// Exit codes downgraded to warnings by `@map-exit` during the
+    /// invocation, shared by all the functions it runs.
+    warnings: Warnings,
+    /// Temporary paths created during the invocation, removed at its end.
+    temp: builtins::TempPaths,
+}
+
+/// Output collected in memory, up to `output.limit`.
+type Buffer = Arc<Mutex<Capped>>;
+
+/// Warnings raised during an invocation, with the function and command that
+/// produced them.
+type Warnings = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Non-zero exit codes allowed by `@allow-exit` and `@map-exit`.
+#[derive(Debug, Clone, Default)]
+struct ExitPolicy {
+    /// Exit codes treated as success.
+    allowed: Vec<i32>,
+    /// Exit codes treated as success with a warning.
+    warn: Vec<i32>,
+    /// Treat every exit status as success, for the condition of an `if`.
+    any: bool,
+}
+
+impl ExitPolicy {
+    /// Add the exit codes of an `@allow-exit` or `@map-exit` annotation.
+    fn add(&mut self, attribute: &Attribute) {
+        match attribute {
+            Attribute::AllowExit(codes) => self.allowed.extend(codes),
+            Attribute::MapExit(mappings) => {
+                for &(code, action) in mappings {
+                    match action {
+                        ExitAction::Warn => self.warn.push(code),
+                        ExitAction::Ok => self.allowed.push(code),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Which result of a `run_capture(...)` run a variable holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunResult {
+    Stdout,
+    Stderr,
+    Code,
+}
+
+/// A loaded Runfile. Loading (`execute`, `apply_profile`) needs `&mut self`;
+/// everything after that takes `&self` and keeps the state it changes behind
+/// locks, so one interpreter in an `Arc` can run functions from several
+/// threads at once.
 pub struct Interpreter {
-    variables: HashMap<String, String>,
-    functions: HashMap<String, Vec<Statement>>,
+    variables: HashMap<String, Value>,
     simple_functions: HashMap<String, String>,
     block_functions: HashMap<String, Vec<String>>,
+    function_attributes: HashMap<String, Vec<Attribute>>,
+    settings: HashMap<String, String>,
+    options: Options,
+    /// Directory of the Runfile that was loaded, if any.
+    runfile_dir: Option<PathBuf>,
+    /// Directory `run` was invoked from.
+    invoked_from: PathBuf,
+    /// Output of the `@once` functions that have already run.
+    once_output: Mutex<HashMap<String, Vec<u8>>>,
+    /// Templates by name: their parameters and body.
+    templates: HashMap<String, (Vec<String>, String)>,
+    /// Templates currently being instantiated, innermost last.
+    instantiating: Vec<String>,
+    /// Profiles by name, with their variables in order.
+    profiles: HashMap<String, Vec<(String, String)>>,
+    /// Variables of the applied profile, exported to every command.
+    profile_env: Vec<(String, String)>,
+    /// Variables of top-level `env { ... }` blocks, exported to every command.
+    env: Vec<(String, String)>,
+    /// Values each `one_of` variable may hold.
+    allowed: HashMap<String, Vec<String>>,
+    /// Encrypted `secretvar` values by name.
+    secrets: HashMap<String, String>,
+    /// Secrets decrypted so far, substituted like variables.
+    unlocked: Mutex<HashMap<String, String>>,
+    /// Variables assigned `capture(function)`, with their function.
+    captures: HashMap<String, String>,
+    /// Variables assigned `run_capture(function)`, with their function and
+    /// the result they hold.
+    run_captures: HashMap<String, (String, RunResult)>,
+    /// Captured function output so far, substituted like variables.
+    captured: Mutex<HashMap<String, String>>,
+    /// Variables whose function is running to capture its output.
+    capturing: Mutex<HashSet<String>>,
+    /// Carry on after failing commands everywhere (a top-level
+    /// `set ignore_errors`).
+    ignore_errors: bool,
+    /// Print every command before running it (`--verbose` or a top-level
+    /// `set verbose`).
+    verbose: bool,
+    /// Exit code of the first command that failed, for `run` to exit with.
+    failure_code: Mutex<Option<i32>>,
+    /// Exit status of the last top-level command or `if` condition run.
+    last_status: Arc<AtomicI32>,
+    /// Chain the functions of each plan are recorded in as they complete,
+    /// and which those that completed before are skipped from.
+    chain: Option<Arc<resume::Chain>>,
+    /// Temporary paths created while the Runfile loaded, removed with the
+    /// interpreter.
+    temp: builtins::TempPaths,
+}
+
+// Shared between the scheduler's threads, and with other threads in an `Arc`
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Interpreter>();
+};
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
+    /// An interpreter with the default options, running commands in the
+    /// current directory.
     pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    /// An interpreter running functions as `options` say.
+    pub fn with_options(options: Options) -> Self {
+        let verbose = options.verbose;
         Self {
             variables: HashMap::new(),
-            functions: HashMap::new(),
             simple_functions: HashMap::new(),
             block_functions: HashMap::new(),
+            function_attributes: HashMap::new(),
+            settings: HashMap::new(),
+            options,
+            runfile_dir: config::find_runfile()
+                .and_then(|path| path.parent().map(Path::to_path_buf)),
+            invoked_from: std::env::current_dir().unwrap_or_default(),
+            once_output: Mutex::new(HashMap::new()),
+            templates: HashMap::new(),
+            instantiating: Vec::new(),
+            profiles: HashMap::new(),
+            profile_env: Vec::new(),
+            env: Vec::new(),
+            allowed: HashMap::new(),
+            secrets: HashMap::new(),
+            unlocked: Mutex::new(HashMap::new()),
+            captures: HashMap::new(),
+            run_captures: HashMap::new(),
+            captured: Mutex::new(HashMap::new()),
+            capturing: Mutex::new(HashSet::new()),
+            ignore_errors: false,
+            verbose,
+            failure_code: Mutex::new(None),
+            last_status: Arc::default(),
+            chain: None,
+            temp: builtins::TempPaths::default(),
         }
     }
 
+    /// Load the definitions of `program` and run its top-level commands.
     pub fn execute(&mut self, program: Program) -> Result<(), Box<dyn std::error::Error>> {
         for statement in program.statements {
             self.execute_statement(statement)?;
@@ -28,218 +313,3050 @@ impl Interpreter {
         Ok(())
     }
 
+    /// Record the functions of the plans run from now on in `chain`, for
+    /// `run --resume`, skipping those it has already completed.
+    pub(crate) fn track_chain(&mut self, chain: Arc<resume::Chain>) {
+        self.chain = Some(chain);
+    }
+
+    /// Call a function as `run` does from the command line, where
+    /// `run docker shell` may call `docker:shell`.
     pub fn call_function_without_parens(
-        &mut self,
+        &self,
         function_name: &str,
         args: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let (function_name, args) = self.resolve_call(function_name, args)?;
+        self.invoke(&function_name, args)
+    }
+
+    /// The function `run <function_name> <args>` calls and the arguments it
+    /// gets, as `call_function_without_parens` resolves them.
+    pub fn resolve_call<'a>(
+        &self,
+        function_name: &str,
+        args: &'a [String],
+    ) -> Result<(String, &'a [String]), Box<dyn std::error::Error>> {
         // Strategy: try to match function names in different ways
         // 1. Direct match: "docker_shell" with args
         // 2. If args exist, try first arg as subcommand: "docker" + "shell" -> "docker:shell"
         // 3. Try replacing underscores with colons: "docker_shell" -> "docker:shell"
+        // with each separator in commands.separators (just `:` by default).
+        // When more than one of 1 and 2 match, the call is ambiguous; a `--`
+        // before the args rules out 2.
+        let separators = parse_separators(self.setting("commands.separators"))?;
+        let (args, subcommand) = match args.split_first() {
+            Some((first, rest)) if first == "--" => (rest, false),
+            _ => (args, true),
+        };
+        let nested_names: Vec<String> = match args.first() {
+            Some(first) if subcommand => separators
+                .iter()
+                .map(|separator| format!("{}{}{}", function_name, separator, first))
+                .filter(|nested_name| self.has_function(nested_name))
+                .collect(),
+            _ => Vec::new(),
+        };
 
-        // Try direct match first - simple functions
-        if let Some(command_template) = self.simple_functions.get(function_name) {
-            let command = self.substitute_args(command_template, args);
-            return self.execute_command(&command);
-        }
-
-        // Try direct match - block functions
-        if let Some(commands) = self.block_functions.get(function_name).cloned() {
-            return self.execute_block_commands(&commands, args);
-        }
-
-        // If we have args, try treating the first arg as a subcommand
-        if !args.is_empty() {
-            let nested_name = format!("{}:{}", function_name, args[0]);
-            if let Some(command_template) = self.simple_functions.get(&nested_name) {
-                let command = self.substitute_args(command_template, &args[1..]);
-                return self.execute_command(&command);
-            }
-            if let Some(commands) = self.block_functions.get(&nested_name).cloned() {
-                return self.execute_block_commands(&commands, &args[1..]);
-            }
-        }
-
-        // Try replacing underscores with colons
-        let with_colons = function_name.replace("_", ":");
-        if with_colons != function_name {
-            if let Some(command_template) = self.simple_functions.get(&with_colons) {
-                let command = self.substitute_args(command_template, args);
-                return self.execute_command(&command);
-            }
-            if let Some(commands) = self.block_functions.get(&with_colons).cloned() {
-                return self.execute_block_commands(&commands, args);
+        match (self.has_function(function_name), nested_names.as_slice()) {
+            (false, []) => {}
+            (true, []) => return Ok((function_name.to_string(), args)),
+            (false, [nested_name]) => return Ok((nested_name.clone(), &args[1..])),
+            (direct, nested_names) => {
+                let first = &args[0];
+                let rest: String = args[1..].iter().map(|arg| format!(" {}", arg)).collect();
+                let mut message = format!("'{} {}' is ambiguous:", function_name, first);
+                for nested_name in nested_names {
+                    message.push_str(&format!(
+                        "\n  run {}{}  calls {}",
+                        nested_name, rest, nested_name
+                    ));
+                }
+                if direct {
+                    message.push_str(&format!(
+                        "\n  run {} -- {}{}  calls {} with '{}' as its first argument",
+                        function_name, first, rest, function_name, first
+                    ));
+                }
+                return Err(message.into());
             }
         }
 
-        // Check for full function definitions
-        if let Some(body) = self.functions.get(function_name).cloned() {
-            for stmt in body {
-                self.execute_statement(stmt)?;
+        // Try replacing underscores with separators
+        for separator in &separators {
+            let nested_name = function_name.replace('_', &separator.to_string());
+            if nested_name != function_name && self.has_function(&nested_name) {
+                return Ok((nested_name, args));
             }
-            return Ok(());
         }
 
-        Err(format!("Function '{}' not found", function_name).into())
+        Err(tr!("Function '{}' not found", function_name).into())
     }
 
+    /// Call the function named exactly `function_name` with `args`.
     pub fn call_function_with_args(
-        &mut self,
+        &self,
         function_name: &str,
         args: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Direct function call with args in parentheses
         // Try to find the function and execute it with substituted arguments
 
-        if let Some(command_template) = self.simple_functions.get(function_name) {
-            let command = self.substitute_args(command_template, args);
-            return self.execute_command(&command);
+        if self.has_function(function_name) {
+            return self.invoke(function_name, args);
         }
 
-        // Check for block function definitions
-        if let Some(commands) = self.block_functions.get(function_name).cloned() {
-            return self.execute_block_commands(&commands, args);
+        Err(tr!("Function '{}' not found", function_name).into())
+    }
+
+    /// Whether output is paged (`--paginate`).
+    pub fn paginates(&self) -> bool {
+        self.options.paginate
+    }
+
+    /// The Runfile variables currently defined.
+    pub fn variables(&self) -> &HashMap<String, Value> {
+        &self.variables
+    }
+
+    /// Remove a variable, returning whether it was defined.
+    pub fn unset_variable(&mut self, name: &str) -> bool {
+        self.variables.remove(name).is_some()
+    }
+
+    /// Apply the profile selected with `--profile`, if any: its variables
+    /// replace any Runfile variables of the same name and are exported to
+    /// every command.
+    pub fn apply_profile(&mut self) -> Result<(), String> {
+        let Some(name) = self.options.profile.clone() else {
+            return Ok(());
+        };
+        let Some(variables) = self.profiles.get(&name).cloned() else {
+            let mut available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            available.sort_unstable();
+            return Err(if available.is_empty() {
+                format!(
+                    "Profile '{}' not found: the Runfile defines no profiles",
+                    name
+                )
+            } else {
+                format!(
+                    "Profile '{}' not found (available: {})",
+                    name,
+                    available.join(", ")
+                )
+            });
+        };
+        for (key, value) in &variables {
+            self.check_allowed(key, value)?;
+            self.variables
+                .insert(key.clone(), Value::String(value.clone()));
+        }
+        self.profile_env = variables;
+        Ok(())
+    }
+
+    /// Check that `value` is one the variable `name` may hold, if it is
+    /// restricted with `one_of`.
+    fn check_allowed(&self, name: &str, value: &str) -> Result<(), String> {
+        match self.allowed.get(name) {
+            Some(allowed) if !allowed.iter().any(|v| v == value) => Err(format!(
+                "Invalid value '{}' for '{}' (allowed: {})",
+                value,
+                name,
+                allowed.join(", ")
+            )),
+            _ => Ok(()),
         }
+    }
+
+    /// The exit code of the first command that failed, if any did.
+    pub fn failure_code(&self) -> Option<i32> {
+        *self.failure_code.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
-        // Check for full function definitions
-        if let Some(body) = self.functions.get(function_name).cloned() {
-            for stmt in body {
-                self.execute_statement(stmt)?;
+    /// The encrypted value of `secretvar name`.
+    pub fn secret(&self, name: &str) -> Option<&str> {
+        self.secrets.get(name).map(String::as_str)
+    }
+
+    /// Decrypt the secrets `text` refers to, once each, so they can be
+    /// substituted. Secrets nothing refers to are never decrypted.
+    fn unlock_secrets(&self, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+        // A dry run shows secrets as the variables they are
+        if self.options.dry_run {
+            return Ok(());
+        }
+        for (name, value) in &self.secrets {
+            if !refers_to(text, name)
+                || self
+                    .unlocked
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .contains_key(name)
+            {
+                continue;
             }
+            let plaintext = secrets::decrypt(value)
+                .map_err(|e| format!("Cannot decrypt secret '{}': {}", name, e))?;
+            self.unlocked
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(name.clone(), plaintext);
+        }
+        Ok(())
+    }
+
+    /// Run the functions of the `capture(...)` variables `text` refers to,
+    /// and those it substitutes as `$(name())`, once each, so their output
+    /// can be substituted. They run as part of the invocation of `context`.
+    fn run_captures(
+        &self,
+        text: &str,
+        context: &CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.options.dry_run {
             return Ok(());
         }
+        for (name, function) in &self.captures {
+            if refers_to(text, name) {
+                self.capture(name, &format!("'{}'", name), function, context)?;
+            }
+        }
+        // Stored as `(name())`, so `$` and the key is the text replaced
+        for function in self.function_substitutions(text) {
+            let key = format!("({}())", function);
+            self.capture(&key, &format!("'${}'", key), &function, context)?;
+        }
+        for (name, (function, _)) in &self.run_captures {
+            if refers_to(text, name) {
+                self.run_capture(function, context)?;
+            }
+        }
+        Ok(())
+    }
 
-        Err(format!("Function '{}' not found", function_name).into())
+    /// The defined functions `text` substitutes as `$(name())`.
+    fn function_substitutions(&self, text: &str) -> Vec<String> {
+        text.match_indices("$(")
+            .filter_map(|(position, _)| {
+                let rest = &text[position + 2..];
+                let end = rest.find(|c: char| {
+                    !(c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '.' | '/'))
+                })?;
+                let name = &rest[..end];
+                (rest[end..].starts_with("())") && self.has_function(name))
+                    .then(|| name.to_string())
+            })
+            .collect()
     }
 
-    fn substitute_args(&self, template: &str, args: &[String]) -> String {
-        let mut result = template.to_string();
+    /// Run `function` once, keeping what it prints to stdout, without the
+    /// trailing newlines, as the captured value `key`.
+    fn capture(
+        &self,
+        key: &str,
+        label: &str,
+        function: &str,
+        parent: &CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self
+            .captured
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(key)
+        {
+            return Ok(());
+        }
+        if !self
+            .capturing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string())
+        {
+            return Err(format!("{} is captured from a function that uses it", label).into());
+        }
+        let output = Arc::new(Mutex::new(Capped::new(self.output_limit()?)));
+        let context = CommandContext {
+            capture: Some(output.clone()),
+            warnings: parent.warnings.clone(),
+            temp: parent.temp.clone(),
+            ..CommandContext::default()
+        };
+        let result = self.invoke_functions(function, &[], context);
+        self.capturing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(key);
+        result.map_err(|e| format!("Cannot capture {} from '{}': {}", label, function, e))?;
+        // Like the shell's $(...), without the trailing newlines
+        let output = output.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let value = String::from_utf8_lossy(&output)
+            .trim_end_matches(['\n', '\r'])
+            .to_string();
+        self.captured
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string(), value);
+        Ok(())
+    }
 
-        // First, handle ${N:-default} patterns (must be done before simple $N)
-        // This regex-like approach handles bash default value syntax
-        let mut i = 0;
-        while i < 10 {
-            // Handle ${N:-default} - use arg if provided, else use default
-            let pattern_with_default = format!("${{{}:-", i + 1);
-            while let Some(start) = result.find(&pattern_with_default) {
-                // Find the closing brace
-                if let Some(end_offset) = result[start..].find('}') {
-                    let end = start + end_offset;
-                    let default_value = &result[start + pattern_with_default.len()..end];
-                    let replacement = if i < args.len() {
-                        args[i].clone()
-                    } else {
-                        default_value.to_string()
-                    };
-                    result = format!("{}{}{}", &result[..start], replacement, &result[end + 1..]);
-                } else {
-                    break;
-                }
+    /// Run `function` once for its `run_capture(...)` variables, keeping its
+    /// stdout and stderr, without the trailing newlines, and its exit code.
+    /// Unlike with `capture`, its failure is a result rather than an error.
+    fn run_capture(
+        &self,
+        function: &str,
+        parent: &CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let names: Vec<(&String, RunResult)> = self
+            .run_captures
+            .iter()
+            .filter(|(_, (assigned, _))| assigned == function)
+            .map(|(name, (_, result))| (name, *result))
+            .collect();
+        let label = format!("'run_capture({})'", function);
+        {
+            let captured = self.captured.lock().unwrap_or_else(|e| e.into_inner());
+            if names.iter().all(|(name, _)| captured.contains_key(*name)) {
+                return Ok(());
+            }
+        }
+        if !self.has_function(function) {
+            return Err(format!("Cannot capture {}: function not found", label).into());
+        }
+        let key = format!("run_capture({})", function);
+        if !self
+            .capturing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.clone())
+        {
+            return Err(format!("{} is captured from a function that uses it", label).into());
+        }
+        let limit = self.output_limit()?;
+        let stdout = Arc::new(Mutex::new(Capped::new(limit)));
+        let stderr = Arc::new(Mutex::new(Capped::new(limit)));
+        let code = Arc::new(AtomicI32::new(0));
+        let context = CommandContext {
+            capture: Some(stdout.clone()),
+            capture_errors: Some(stderr.clone()),
+            failure: Some(code.clone()),
+            warnings: parent.warnings.clone(),
+            temp: parent.temp.clone(),
+            ..CommandContext::default()
+        };
+        let result = self.invoke_functions(function, &[], context);
+        self.capturing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&key);
+        if let Err(e) = result {
+            if process::is_cancelled() {
+                return Err(e);
+            }
+            // A failing command left its exit code; other errors, such as a
+            // builtin's, leave their message
+            if code.load(Ordering::SeqCst) == 0 {
+                code.store(1, Ordering::SeqCst);
+                stderr
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .extend(format!("{}\n", e).as_bytes());
             }
+        }
+        let text = |buffer: &Buffer| {
+            let output = buffer.lock().unwrap_or_else(|e| e.into_inner()).take();
+            String::from_utf8_lossy(&output)
+                .trim_end_matches(['\n', '\r'])
+                .to_string()
+        };
+        let (stdout, stderr) = (text(&stdout), text(&stderr));
+        let code = code.load(Ordering::SeqCst).to_string();
+        let mut captured = self.captured.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, result) in names {
+            let value = match result {
+                RunResult::Stdout => &stdout,
+                RunResult::Stderr => &stderr,
+                RunResult::Code => &code,
+            };
+            captured.insert(name.clone(), value.clone());
+        }
+        Ok(())
+    }
 
-            // Handle ${N} without default - same as $N
-            let pattern_braced = format!("${{{}}}",  i + 1);
-            if let Some(arg) = args.get(i) {
-                result = result.replace(&pattern_braced, arg);
-            } else {
-                result = result.replace(&pattern_braced, "");
+    /// Value of a Runfile setting such as `toolchain.manager`.
+    pub fn setting(&self, key: &str) -> Option<&str> {
+        self.settings.get(key).map(String::as_str)
+    }
+
+    /// The names of the simple and block functions defined, sorted.
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut functions: Vec<&str> = self
+            .simple_functions
+            .keys()
+            .chain(self.block_functions.keys())
+            .map(String::as_str)
+            .collect();
+        functions.sort();
+        functions
+    }
+
+    /// Whether a simple or block function with this exact name is defined.
+    pub fn has_function(&self, name: &str) -> bool {
+        self.simple_functions.contains_key(name) || self.block_functions.contains_key(name)
+    }
+
+    /// Run a defined function with the given arguments, then remove the
+    /// temporary paths created while it ran.
+    fn invoke(&self, name: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let context = self.invocation_context()?;
+        let result = self.invoke_functions(name, args, context.clone());
+        self.finish(result, context)
+    }
+
+    /// Run the defined functions `names` concurrently, with their
+    /// prerequisites, and wait for all of them. Every function not depending
+    /// on a failed one runs, and the error summarises all failures.
+    pub fn call_functions_in_parallel(
+        &self,
+        names: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let context = self.invocation_context()?;
+        let result = self.invoke_parallel(names, context.clone());
+        self.finish(result, context)
+    }
+
+    fn invoke_parallel(
+        &self,
+        names: &[String],
+        context: CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(name) = names.iter().find(|name| !self.has_function(name)) {
+            return Err(tr!("Function '{}' not found", name).into());
+        }
+        let plan = scheduler::Plan::build_all(names, |function| self.dependencies(function))?;
+        let expanded = self.expand_matrix(plan, "", &[])?;
+        // Each requested function gets a job of its own, so long-running
+        // ones such as servers don't hold the others back
+        let jobs = self
+            .options
+            .jobs
+            .unwrap_or_else(|| scheduler::default_jobs().max(names.len()));
+        self.execute_plan(&expanded, "", &[], context, jobs, true)
+    }
+
+    /// The context an invocation starts from, with temporary paths of its
+    /// own: with `--paginate`, its output is collected to be paged.
+    fn invocation_context(&self) -> Result<CommandContext, Box<dyn std::error::Error>> {
+        let output = match self.options.paginate {
+            true => Some(Arc::new(Mutex::new(Capped::new(self.output_limit()?)))),
+            false => None,
+        };
+        Ok(CommandContext {
+            output,
+            ..CommandContext::default()
+        })
+    }
+
+    /// Remove the temporary paths the invocation of `context` created, show
+    /// the output collected for paging and report the timings of its
+    /// functions and the warnings they raised.
+    fn finish(
+        &self,
+        result: Result<(), Box<dyn std::error::Error>>,
+        context: CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let CommandContext {
+            output,
+            warnings,
+            temp,
+            ..
+        } = context;
+        // Every other copy of the context is gone, so this removes them
+        drop(temp);
+        if let Some(output) = &output {
+            pager::show(&output.lock().unwrap_or_else(|e| e.into_inner()).take());
+        }
+        if let Some(report) = self.options.timings.as_ref().and_then(|t| t.take()) {
+            eprint!("{}", report);
+        }
+        let warnings = std::mem::take(&mut *warnings.lock().unwrap_or_else(|e| e.into_inner()));
+        if !warnings.is_empty() {
+            eprintln!("{}", tr!("{} warning(s):", warnings.len()));
+            for (task, warning) in warnings {
+                eprintln!("  {}: {}", task, warning);
             }
+        }
+        result
+    }
+
+    /// Run a defined function with the given arguments, after the functions
+    /// it `needs`. Independent prerequisites, and the variants of `@matrix`
+    /// functions, run concurrently, up to the configured number of jobs.
+    /// Every command runs in `context`, and with its `capture`, the stdout
+    /// of `name` is collected there.
+    fn invoke_functions(
+        &self,
+        name: &str,
+        args: &[String],
+        context: CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (selection, args) = match self.matrix(name) {
+            Some(axes) => matrix::split_selection(axes, args),
+            None => (Vec::new(), args.to_vec()),
+        };
+        if self.dependencies(name)?.is_empty() && self.matrix(name).is_none() {
+            return self.run_function(name, &args, context);
+        }
+
+        let plan = scheduler::Plan::build(name, |function| self.dependencies(function))?;
+        let expanded = self.expand_matrix(plan, name, &selection)?;
+        let jobs = self.options.jobs.unwrap_or_else(scheduler::default_jobs);
+        self.execute_plan(
+            &expanded,
+            name,
+            &args,
+            context,
+            jobs,
+            self.options.keep_going,
+        )
+    }
 
-            i += 1;
+    /// Run a plan expanded by `expand_matrix` on the scheduler, starting from
+    /// `context`. Only the requested function `name` receives `args` and has
+    /// its stdout collected in the context's `capture`.
+    fn execute_plan(
+        &self,
+        (plan, variants): &(scheduler::Plan, matrix::Labels),
+        name: &str,
+        args: &[String],
+        context: CommandContext,
+        jobs: usize,
+        keep_going: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let capture = context.capture.clone();
+        let capture_errors = context.capture_errors.clone();
+        // Commands of functions running one at a time stay in the foreground,
+        // where they can read the terminal
+        let context = CommandContext {
+            process_group: jobs > 1 && !plan.is_sequential(),
+            ..context
+        };
+        let limit = self.output_limit()?;
+        if let Some(grace) = self.setting("commands.grace_period") {
+            let grace = builtins::parse_duration(grace).ok_or_else(|| {
+                format!(
+                    "Invalid commands.grace_period '{}': expected a duration such as 10s",
+                    grace
+                )
+            })?;
+            process::set_grace_period(grace);
         }
+        // Lines of functions running at once are told apart by their names
+        let width = plan
+            .functions
+            .iter()
+            .map(|label| label.chars().count())
+            .max();
+        let prefixed = style::enabled()
+            && jobs > 1
+            && plan.functions.len() > 1
+            && self.options.output == OutputMode::Interleave;
+        // The invocation's own output, when that is collected
+        let collected = context.output.clone();
+        scheduler::execute(plan, jobs, keep_going, |label| {
+            // Completed before the chain was interrupted
+            if let Some(chain) = &self.chain
+                && chain.is_completed(label)
+            {
+                return Ok(());
+            }
+            let (function, variant) = &variants[label];
+            // Only the requested function receives the arguments
+            let args = if function == name { args } else { &[] };
+            // Actions log groups need each function's output in one block
+            let group = github::is_actions();
+            let output = (self.options.output == OutputMode::Group || group)
+                .then(|| Arc::new(Mutex::new(Capped::new(limit))));
+            let grouped = output.is_some();
+            let prefix = prefixed.then(|| {
+                let index = plan.functions.iter().position(|f| f == label);
+                style::prefix(label, index.unwrap_or(0), width.unwrap_or(0))
+            });
+            let context = CommandContext {
+                output: output.clone().or_else(|| collected.clone()),
+                capture: capture.clone().filter(|_| function == name),
+                capture_errors: capture_errors.clone().filter(|_| function == name),
+                variant: variant.clone(),
+                prefix,
+                ..context.clone()
+            };
+            let result = self
+                .run_function(function, args, context)
+                .map_err(|e| e.to_string());
+            if let Some(output) = output.filter(|_| grouped) {
+                let mut output = output.lock().unwrap_or_else(|e| e.into_inner()).take();
+                if group {
+                    let start = format!("{}\n", github::group_start(label));
+                    output.splice(0..0, start.into_bytes());
+                    output.extend_from_slice(format!("{}\n", github::group_end()).as_bytes());
+                }
+                match &collected {
+                    Some(outer) => outer
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .extend(&output),
+                    None => {
+                        let mut stdout = std::io::stdout().lock();
+                        let _ = stdout.write_all(&output).and_then(|_| stdout.flush());
+                    }
+                }
+            }
+            if let Some(chain) = self.chain.as_ref().filter(|_| result.is_ok())
+                && let Err(e) = chain.complete(label)
+            {
+                eprintln!("{}", tr!("Warning: {}", e));
+            }
+            result
+        })?;
+        Ok(())
+    }
+
+    /// Replace each `@matrix` function in `plan` with its variants, limited
+    /// to `selection` for the requested function `root`. Whatever needs a
+    /// matrix function needs all of its variants. Returns the new plan and
+    /// the function and variant behind each of its labels.
+    fn expand_matrix(
+        &self,
+        plan: scheduler::Plan,
+        root: &str,
+        selection: &[(String, String)],
+    ) -> Result<(scheduler::Plan, matrix::Labels), String> {
+        let mut expanded = scheduler::Plan::default();
+        let mut variants = matrix::Labels::new();
+        let mut positions: Vec<Vec<usize>> = Vec::new();
+        for (function, needs) in plan.functions.iter().zip(&plan.needs) {
+            let function_variants = match self.matrix(function) {
+                Some(axes) => {
+                    let selection = if function == root { selection } else { &[] };
+                    matrix::variants(axes, selection)
+                }
+                None => vec![matrix::Variant::new()],
+            };
+            if function_variants.is_empty() {
+                let selected: Vec<String> = selection
+                    .iter()
+                    .map(|(axis, value)| format!("{}={}", axis, value))
+                    .collect();
+                return Err(format!(
+                    "No variant of '{}' matches {}",
+                    function,
+                    selected.join(" ")
+                ));
+            }
 
-        // Replace simple $1, $2, $3, etc. with actual arguments
-        for (i, arg) in args.iter().enumerate() {
-            let placeholder = format!("${}", i + 1);
-            result = result.replace(&placeholder, arg);
+            let needs: Vec<usize> = needs
+                .iter()
+                .flat_map(|&need| positions[need].iter().copied())
+                .collect();
+            let mut function_positions = Vec::new();
+            for variant in function_variants {
+                let label = matrix::label(function, &variant);
+                function_positions.push(expanded.functions.len());
+                expanded.functions.push(label.clone());
+                expanded.needs.push(needs.clone());
+                variants.insert(label, (function.clone(), variant));
+            }
+            positions.push(function_positions);
         }
+        Ok((expanded, variants))
+    }
+
+    /// The `@matrix` axes of a function.
+    fn matrix(&self, name: &str) -> Option<&[MatrixAxis]> {
+        self.function_attributes
+            .get(name)?
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Matrix(axes) => Some(axes.as_slice()),
+                _ => None,
+            })
+    }
 
-        // Also support $@ for all arguments
-        if result.contains("$@") {
-            result = result.replace("$@", &args.join(" "));
+    /// Check the `needs(...)` graph of every defined function for cycles,
+    /// reporting the first one found as its full path.
+    pub fn check_dependencies(&self) -> Result<(), String> {
+        let functions = self.function_names();
+        let cycle = scheduler::find_cycle(&functions, |function| self.declared_needs(function));
+        match cycle {
+            Some(cycle) => Err(scheduler::describe_cycle(&cycle)),
+            None => Ok(()),
         }
+    }
 
-        // Replace user-defined variables (e.g., $myvar)
-        for (var_name, var_value) in &self.variables {
-            let placeholder = format!("${}", var_name);
-            result = result.replace(&placeholder, var_value);
+    /// The functions `name` needs, checking that they are all defined.
+    fn dependencies(&self, name: &str) -> Result<&[String], String> {
+        let needs = self.declared_needs(name);
+        if let Some(unknown) = needs
+            .iter()
+            .find(|dependency| !self.has_function(dependency))
+        {
+            return Err(format!(
+                "Function '{}' needs unknown function '{}'",
+                name, unknown
+            ));
         }
+        Ok(needs)
+    }
 
-        result
+    /// The functions `name` needs, directly or through others, in the order
+    /// they run before it one at a time.
+    pub fn prerequisites(&self, name: &str) -> Result<Vec<String>, String> {
+        let mut plan = scheduler::Plan::build(name, |function| self.dependencies(function))?;
+        plan.functions.pop();
+        Ok(plan.functions)
     }
 
-    fn execute_statement(
-        &mut self,
-        statement: Statement,
+    /// The commands of `name` with variables and `args` substituted, as
+    /// `--dry-run` prints them, without running anything: secrets and
+    /// captured output stay as their `$NAME`, and the lines of a block keep
+    /// its `if`s and loops.
+    pub fn resolved_commands(
+        &self,
+        name: &str,
+        args: &[String],
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let args = self.with_defaults(name, args)?;
+        let commands = match (
+            self.simple_functions.get(name),
+            self.block_functions.get(name),
+        ) {
+            (Some(command), _) => std::slice::from_ref(command),
+            (None, Some(commands)) => commands.as_slice(),
+            (None, None) => return Err(tr!("Function '{}' not found", name).into()),
+        };
+        Ok(commands
+            .iter()
+            .map(|command| self.substitute_args(command, Some(name), &args))
+            .collect())
+    }
+
+    /// The value of `expression` with everything in it substituted, as a
+    /// command at the top level of the Runfile would see it: its variables,
+    /// secrets and captures, then environment variables, builtin
+    /// substitutions such as `$(free_port)` and finally any other `$(...)`,
+    /// run by the default shell (`run eval`).
+    pub fn eval(&self, expression: &str) -> Result<String, Box<dyn std::error::Error>> {
+        self.unlock_secrets(expression)?;
+        let context = CommandContext {
+            temp: self.temp.clone(),
+            ..CommandContext::default()
+        };
+        self.run_captures(expression, &context)?;
+        let substituted = self.substitute_args(expression, None, &[]);
+        let substituted = self.interpolate_variables(&substituted);
+        let expanded = builtins::expand(&substituted, &self.invoked_from, &self.temp)?;
+        substitute_commands(&expanded, &self.invoked_from)
+    }
+
+    /// The functions listed in the `needs(...)` clause of `name`.
+    fn declared_needs(&self, name: &str) -> &[String] {
+        self.function_attributes
+            .get(name)
+            .and_then(|attributes| {
+                attributes.iter().find_map(|attribute| match attribute {
+                    Attribute::Needs(needs) => Some(needs.as_slice()),
+                    _ => None,
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Run a single defined function, reporting its start and exit as
+    /// events, printing a header before it when output is colored, wrapping
+    /// its output in a log group under GitHub Actions and sending its
+    /// `@notify-on` webhook, as configured.
+    fn run_function(
+        &self,
+        name: &str,
+        args: &[String],
+        mut context: CommandContext,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        match statement {
-            Statement::Assignment { name, value } => {
-                let Expression::String(val) = value;
-                self.variables.insert(name, val);
+        let title = matrix::label(name, &context.variant);
+        let events = self.options.events.as_ref();
+        let status = self.options.status.as_ref();
+        context.task = Some(title.clone());
+        let notify = self
+            .notify_on(name)
+            .zip(self.setting("notify.webhook"))
+            .filter(|_| !self.options.dry_run);
+        let tail = notify.map(|_| Arc::new(Mutex::new(notify::OutputTail::default())));
+        context.tail = tail.clone();
+        let grouped = context.output.is_none() && github::is_actions();
+        let log = self.start_log(name, &title)?;
+        context.log = log.as_ref().map(|log| log.file.clone());
+        if self.has_attribute(name, &Attribute::KeepTemp) {
+            context.temp.keep();
+        }
+
+        if grouped {
+            println!("{}", github::group_start(&title));
+        }
+        if let Some(events) = events {
+            events.start(&title);
+        }
+        if let Some(status) = status {
+            status.task_started(&title);
+        }
+        if style::enabled() {
+            let header = format!("{}\n", style::header(&title));
+            match (&context.output, status) {
+                (Some(output), _) => output
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .extend(header.as_bytes()),
+                (None, Some(status)) => status.write_above(Stream::Stderr, header.as_bytes()),
+                (None, None) => eprint!("{}", header),
             }
-            Statement::SimpleFunctionDef {
-                name,
-                command_template,
-            } => {
-                self.simple_functions.insert(name, command_template);
+        }
+        let started = Instant::now();
+        let result = match self.supervision(name) {
+            Some(supervision) if !self.options.dry_run => {
+                self.supervise(&title, supervision, || {
+                    self.execute_function(name, args, context.clone())
+                })
             }
-            Statement::BlockFunctionDef { name, commands } => {
-                self.block_functions.insert(name, commands);
+            _ => self.execute_function(name, args, context),
+        };
+        let duration = started.elapsed();
+        let error = result.as_ref().err().map(|e| e.to_string());
+        if let Some(timings) = &self.options.timings {
+            timings.function(&title, duration, error.is_none());
+        }
+        if let Some(status) = status {
+            status.task_finished(&title);
+        }
+        if let Some(events) = events {
+            events.exit(&title, duration, error.as_deref());
+        }
+        if grouped {
+            println!("{}", github::group_end());
+        }
+        if let Some(log) = log {
+            let keep = self.setting("logs.keep").and_then(|keep| keep.parse().ok());
+            log.finish(keep.unwrap_or(logs::DEFAULT_KEEP));
+        }
+
+        if let (Some((on, url)), Some(tail)) = (notify, tail)
+            && on.matches(error.is_none())
+        {
+            let mut tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(error) = &error {
+                tail.push(error);
             }
-            Statement::FunctionCall { name, args } => {
-                // Call the function with the provided arguments
-                self.call_function_with_args(&name, &args)?;
+            notify::send(url, &title, error.is_none(), duration, &tail.text());
+        }
+        result
+    }
+
+    /// Open a new log for this run of function `name` (shown as `title`),
+    /// when `logs.enabled` is set.
+    fn start_log(&self, name: &str, title: &str) -> Result<Option<logs::TaskLog>, String> {
+        if self.setting("logs.enabled") != Some("true") || self.options.dry_run {
+            return Ok(None);
+        }
+        match logs::TaskLog::start(title, &self.working_dir(name), self.output_limit()?) {
+            Ok(log) => Ok(Some(log)),
+            Err(e) => {
+                eprintln!("Warning: could not create log for '{}': {}", title, e);
+                Ok(None)
             }
-            Statement::Command { command } => {
-                // Substitute variables in the command before executing
-                let substituted_command = self.substitute_args(&command, &[]);
-                self.execute_command(&substituted_command)?;
+        }
+    }
+
+    /// Bytes of output kept in memory or in a log (`output.limit`).
+    fn output_limit(&self) -> Result<Option<usize>, String> {
+        capped::parse_limit(self.setting("output.limit"))
+    }
+
+    /// The `@notify-on` policy of a function.
+    fn notify_on(&self, name: &str) -> Option<NotifyOn> {
+        self.function_attributes
+            .get(name)?
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::NotifyOn(on) => Some(*on),
+                _ => None,
+            })
+    }
+
+    /// The `@umask` of a function.
+    fn umask(&self, name: &str) -> Option<u32> {
+        self.function_attributes
+            .get(name)?
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Umask(umask) => Some(*umask),
+                _ => None,
+            })
+    }
+
+    /// After a function has run, change the permissions of the files its
+    /// `@fix-perms` annotations name.
+    fn fix_permissions(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let fixes: Vec<(&FileMode, &[String])> = self
+            .function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|attribute| match attribute {
+                Attribute::FixPerms(mode, patterns) => Some((mode, patterns.as_slice())),
+                _ => None,
+            })
+            .collect();
+        if fixes.is_empty() {
+            return Ok(());
+        }
+        let base = self.working_dir(name);
+        for (mode, patterns) in fixes {
+            let files = artifacts::collect(patterns, &base).map_err(|unmatched| {
+                format!(
+                    "Function '{}' has no files matching its @fix-perms paths: {}",
+                    name,
+                    unmatched.join(", ")
+                )
+            })?;
+            for file in files {
+                perms::fix(mode, &base.join(&file)).map_err(|e| {
+                    format!("Cannot change the permissions of {}: {}", file.display(), e)
+                })?;
             }
         }
         Ok(())
     }
 
-    fn execute_block_commands(
+    /// The `@supervise` options of a function.
+    fn supervision(&self, name: &str) -> Option<&Supervision> {
+        self.function_attributes
+            .get(name)?
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Supervise(supervision) => Some(supervision),
+                _ => None,
+            })
+    }
+
+    /// Call `run` again each time it ends in a way `supervision` restarts,
+    /// until it doesn't or the restarts run out. The wait before each restart
+    /// doubles up to `MAX_BACKOFF`, and starts over after a run that lasted
+    /// at least that long.
+    fn supervise(
         &self,
-        commands: &[String],
-        args: &[String],
+        title: &str,
+        supervision: &Supervision,
+        mut run: impl FnMut() -> Result<(), Box<dyn std::error::Error>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        for cmd in commands {
-            let substituted = self.substitute_args(cmd, args);
-            self.execute_command(&substituted)?;
+        const MAX_BACKOFF: Duration = Duration::from_secs(30);
+        let mut restarts = 0;
+        let mut backoff = supervision.backoff;
+        loop {
+            let started = Instant::now();
+            let result = run();
+            let restart = result.is_err() || supervision.always;
+            if !restart || process::is_cancelled() || supervision.max == Some(restarts) {
+                if result.is_err() && restarts > 0 {
+                    eprintln!("'{}' failed after {} restart(s)", title, restarts);
+                }
+                return result;
+            }
+            if started.elapsed() >= MAX_BACKOFF {
+                backoff = supervision.backoff;
+            }
+            restarts += 1;
+            let limit = match supervision.max {
+                Some(max) => format!(" of {}", max),
+                None => String::new(),
+            };
+            let ended = match &result {
+                Ok(()) => "exited".to_string(),
+                Err(e) => format!("failed: {}", e),
+            };
+            let notice = format!(
+                "'{}' {}; restarting in {} (restart {}{})\n",
+                title,
+                ended,
+                builtins::format_duration(backoff),
+                restarts,
+                limit
+            );
+            match &self.options.status {
+                Some(status) => {
+                    status.write_above(Stream::Stderr, notice.as_bytes());
+                    status.task_restarted(title, restarts);
+                }
+                None => eprint!("{}", notice),
+            }
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
         }
-        Ok(())
     }
 
-    fn execute_command(&self, command: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Check for RUN_SHELL environment variable, otherwise use platform defaults
-        let shell_cmd = if let Ok(custom_shell) = std::env::var("RUN_SHELL") {
-            custom_shell
-        } else if cfg!(target_os = "windows") {
-            // Default to PowerShell on Windows
-            // Try to find pwsh (PowerShell 7+) first, then fallback to powershell (Windows PowerShell)
-            if which::which("pwsh").is_ok() {
-                "pwsh".to_string()
-            } else {
-                "powershell".to_string()
+    /// Run a single defined simple or block function with the given
+    /// arguments, honouring its annotations.
+    fn execute_function(
+        &self,
+        name: &str,
+        args: &[String],
+        mut context: CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(reason) = self.skip_reason(name) {
+            eprintln!("Skipping '{}': {}", name, reason);
+            return Ok(());
+        }
+        let args = &self.with_defaults(name, args)?;
+        // Variants of a matrix function are cached and memoized separately
+        let label = matrix::label(name, &context.variant);
+        if self.runs_once(name) {
+            let once_output = self.once_output.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(output) = once_output.get(&label) {
+                replay_output(output, &context);
+                return Ok(());
             }
+            context.record = Some(Arc::new(Mutex::new(Capped::new(self.output_limit()?))));
+        }
+
+        // A dry run shows every command, so nothing is up to date
+        let cache_key = if self.options.dry_run {
+            None
         } else {
-            // Default to sh on Unix-like systems
-            "sh".to_string()
+            self.cache_key(name, args, &context.variant)?
         };
+        if let Some(key) = &cache_key
+            && !self.always_runs(name)
+            && self.restore_cached(name, key)?
+        {
+            return Ok(());
+        }
 
-        let status = Command::new(&shell_cmd)
-            .arg("-c")
-            .arg(command)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()?;
+        self.confirm(name)?;
+        self.guard_danger(name)?;
 
-        if !status.success() {
-            eprintln!("Command failed with status: {}", status);
+        if self.cwd_override(name).is_some() {
+            let dir = self.working_dir(name);
+            if !dir.is_dir() {
+                return Err(format!(
+                    "Function '{}' runs in {}, which is not a directory",
+                    name,
+                    dir.display()
+                )
+                .into());
+            }
+            context.cwd = Some(dir);
+        } else if self.root_relative(name) {
+            context.cwd = self.runfile_dir.clone();
+        }
+        if !self.options.dry_run
+            && let Err(error) = self.check_requirements(name)
+        {
+            context.container = Some(self.container_fallback(name, error)?);
         }
 
-        Ok(())
+        let body = match (
+            self.simple_functions.get(name),
+            self.block_functions.get(name),
+        ) {
+            (Some(command), _) => command.clone(),
+            (None, Some(commands)) => commands.join("\n"),
+            (None, None) => String::new(),
+        };
+        self.unlock_secrets(&body)?;
+        self.run_captures(&body, &context)?;
+        for attribute in self.function_attributes.get(name).into_iter().flatten() {
+            context.exits.add(attribute);
+        }
+        context.shell = self.shell_override(name);
+        context.env = self.function_env(name);
+        context.ignore_errors |= self.ignore_errors;
+        context.verbose |= self.verbose;
+        context.umask = self.umask(name);
+        // `$?` starts over in each function
+        context.last_status = Arc::default();
+        if let Some(command_template) = self.simple_functions.get(name) {
+            let command = bind_variant(command_template, &context.variant);
+            let command = self.substitute_args(&command, Some(name), args);
+            self.execute_command(&command, &context)?;
+        } else if let Some(commands) = self.block_functions.get(name) {
+            let commands = self.unroll_loops(name, commands, args);
+            self.execute_block_commands(name, &commands, args, &context)?;
+        } else {
+            return Err(tr!("Function '{}' not found", name).into());
+        }
+        if !self.options.dry_run {
+            self.fix_permissions(name)?;
+            self.record_outputs(name)?;
+        }
+        if let Some(key) = &cache_key {
+            self.store_cached(name, key)?;
+        }
+        if let Some(record) = context.record {
+            let output = record.lock().unwrap_or_else(|e| e.into_inner()).take();
+            self.once_output
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(label, output);
+        }
+        Ok(())
+    }
+
+    /// The named arguments of a function's signature.
+    fn parameters(&self, name: &str) -> &[Parameter] {
+        self.function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .find_map(|attribute| match attribute {
+                Attribute::Params(parameters) => Some(parameters.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// The arguments of function `name`, with the defaults of its signature
+    /// for those not passed. A `$NAME` default is looked up in the variables
+    /// exported to its commands, then in the environment.
+    fn with_defaults(
+        &self,
+        name: &str,
+        args: &[String],
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut args = args.to_vec();
+        for parameter in self.parameters(name).iter().skip(args.len()) {
+            let value = match &parameter.default {
+                Some(ParamDefault::Value(value)) => value.clone(),
+                Some(ParamDefault::Env(variable)) => self
+                    .exported_env(&self.function_env(name))
+                    .into_iter()
+                    .rev()
+                    .find(|(key, _)| key == variable)
+                    .map(|(_, value)| value)
+                    .or_else(|| std::env::var(variable).ok())
+                    .ok_or_else(|| {
+                        format!(
+                            "Function '{}' needs argument '{}' (or {} set in the environment)",
+                            name, parameter.name, variable
+                        )
+                    })?,
+                None => {
+                    return Err(
+                        format!("Function '{}' needs argument '{}'", name, parameter.name).into(),
+                    );
+                }
+            };
+            args.push(value);
+        }
+        Ok(args)
+    }
+
+    /// The `@shell` a function's commands run with, if it sets one.
+    fn shell_override(&self, name: &str) -> Option<String> {
+        self.function_attributes
+            .get(name)?
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Shell(shell) => Some(shell.clone()),
+                _ => None,
+            })
+    }
+
+    /// Variables of the `env { ... }` blocks in a function's body.
+    fn function_env(&self, name: &str) -> Vec<(String, String)> {
+        self.function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|attribute| match attribute {
+                Attribute::Env(variables) => Some(variables.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Variables exported to the commands of `function`, later entries
+    /// overriding earlier ones: top-level `env` blocks, then the function's
+    /// own, then the applied profile.
+    fn exported_env(&self, function: &[(String, String)]) -> Vec<(String, String)> {
+        self.env
+            .iter()
+            .chain(function)
+            .chain(&self.profile_env)
+            .cloned()
+            .collect()
+    }
+
+    /// Declared `@outputs` patterns of a function.
+    pub fn outputs(&self, name: &str) -> Vec<String> {
+        self.function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|attribute| match attribute {
+                Attribute::Outputs(patterns) => Some(patterns.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The toolchain commands are run through, if any.
+    pub fn toolchain(&self) -> Option<Toolchain> {
+        Toolchain::resolve(self.setting("toolchain.manager"))
+    }
+
+    /// Variables set on the processes of function `name`, on top of the
+    /// environment `run` was started with.
+    pub fn environment_overrides(&self, name: &str) -> Vec<(String, OsString)> {
+        let mut overrides = self
+            .toolchain()
+            .map(|toolchain| toolchain.env())
+            .unwrap_or_default();
+        overrides.extend(
+            self.exported_env(&self.function_env(name))
+                .into_iter()
+                .map(|(key, value)| (key, OsString::from(value))),
+        );
+        overrides
+    }
+
+    /// Whether function `name` has the argument-less `attribute`.
+    fn has_attribute(&self, name: &str, attribute: &Attribute) -> bool {
+        self.function_attributes
+            .get(name)
+            .is_some_and(|attributes| attributes.contains(attribute))
+    }
+
+    /// Whether function `name` runs at most once per invocation (`@once`).
+    fn runs_once(&self, name: &str) -> bool {
+        self.has_attribute(name, &Attribute::Once)
+    }
+
+    /// Whether function `name` runs in the Runfile's directory, because of
+    /// `@root-relative` or the `commands.root_relative` setting.
+    fn root_relative(&self, name: &str) -> bool {
+        self.setting("commands.root_relative") == Some("true")
+            || self.has_attribute(name, &Attribute::RootRelative)
+    }
+
+    /// The `@cwd` directory of function `name`, as written.
+    fn cwd_override(&self, name: &str) -> Option<&str> {
+        self.function_attributes
+            .get(name)?
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Cwd(dir) => Some(dir.as_str()),
+                _ => None,
+            })
+    }
+
+    /// The directory containing the Runfile, or where `run` was invoked
+    /// without one.
+    pub fn runfile_dir(&self) -> &Path {
+        self.runfile_dir.as_deref().unwrap_or(&self.invoked_from)
+    }
+
+    /// The directory function `name` runs in, which its `@inputs` and
+    /// `@outputs` are relative to.
+    pub fn working_dir(&self, name: &str) -> PathBuf {
+        let runfile_dir = self.runfile_dir.as_ref().unwrap_or(&self.invoked_from);
+        match self.cwd_override(name) {
+            // Relative to the Runfile, like the other paths written in it
+            Some(dir) => runfile_dir.join(dir.trim_start_matches("./")),
+            None if self.root_relative(name) => runfile_dir.clone(),
+            None => self.invoked_from.clone(),
+        }
+    }
+
+    /// After a function has run, check that its declared outputs exist and
+    /// record their hashes.
+    fn record_outputs(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let patterns = self.outputs(name);
+        if patterns.is_empty() {
+            return Ok(());
+        }
+        let base = self.working_dir(name);
+        match artifacts::collect(&patterns, &base) {
+            Ok(files) => {
+                artifacts::record(name, &files, &base)?;
+                Ok(())
+            }
+            Err(unmatched) => Err(format!(
+                "Function '{}' did not produce its declared outputs: {}",
+                name,
+                unmatched.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    /// Declared `@inputs` patterns of a function.
+    fn inputs(&self, name: &str) -> Vec<String> {
+        self.function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|attribute| match attribute {
+                Attribute::Inputs(patterns) => Some(patterns.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The cache key for this invocation, for functions declaring both
+    /// `@inputs` and `@outputs`.
+    fn cache_key(
+        &self,
+        name: &str,
+        args: &[String],
+        variant: &[(String, String)],
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let inputs = self.inputs(name);
+        if inputs.is_empty() || self.outputs(name).is_empty() {
+            return Ok(None);
+        }
+        let body = match (
+            self.simple_functions.get(name),
+            self.block_functions.get(name),
+        ) {
+            (Some(template), _) => template.clone(),
+            (None, Some(commands)) => commands.join("\n"),
+            (None, None) => return Ok(None),
+        };
+        let base = self.working_dir(name);
+        let args: Vec<String> = variant
+            .iter()
+            .map(|(axis, value)| format!("{}={}", axis, value))
+            .chain(args.iter().cloned())
+            .collect();
+        Ok(Some(cache::input_key(name, &body, &args, &inputs, &base)?))
+    }
+
+    /// Whether cached results must be ignored for `name`, because of
+    /// `--force` or an `@always` annotation.
+    fn always_runs(&self, name: &str) -> bool {
+        self.options.force
+            || self
+                .function_attributes
+                .get(name)
+                .is_some_and(|attributes| attributes.contains(&Attribute::Always))
+    }
+
+    /// The remote cache to share outputs through, unless disabled for this run.
+    fn remote_cache(&self) -> Option<cache::RemoteCache> {
+        if self.options.no_remote_cache {
+            return None;
+        }
+        cache::RemoteCache::resolve(self.setting("cache.remote"), self.setting("cache.mode"))
+    }
+
+    /// Skip a cached function when its outputs are already up to date, or can
+    /// be restored from the remote cache. Returns whether it was skipped.
+    fn restore_cached(&self, name: &str, key: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let base = self.working_dir(name);
+        if cache::is_up_to_date(name, key, &base) {
+            eprintln!("'{}' is up to date", name);
+            return Ok(true);
+        }
+        let Some(remote) = self.remote_cache() else {
+            return Ok(false);
+        };
+
+        let archive = cache::scratch_path(key, &base)?;
+        let restored = match remote.fetch(key, &archive) {
+            Ok(true) => cache::unpack(&archive, &base).map(Some),
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        };
+        let _ = std::fs::remove_file(&archive);
+        match restored {
+            Ok(Some(files)) => {
+                artifacts::record(name, &files, &base)?;
+                cache::save_key(name, key, &base)?;
+                eprintln!("Restored '{}' outputs from remote cache", name);
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(e) => {
+                eprintln!("Warning: could not read remote cache: {}", e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Remember the key of a successful run and share its outputs through a
+    /// writable remote cache.
+    fn store_cached(&self, name: &str, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let base = self.working_dir(name);
+        cache::save_key(name, key, &base)?;
+        let Some(remote) = self.remote_cache() else {
+            return Ok(());
+        };
+        if remote.mode == cache::Mode::ReadOnly {
+            return Ok(());
+        }
+
+        let archive = cache::scratch_path(key, &base)?;
+        let recorded = artifacts::load(name, &base).unwrap_or_default();
+        let stored =
+            cache::pack(&recorded, &base, &archive).and_then(|_| remote.store(key, &archive));
+        let _ = std::fs::remove_file(&archive);
+        if let Err(e) = stored {
+            eprintln!("Warning: could not write to remote cache: {}", e);
+        }
+        Ok(())
+    }
+
+    /// When required tools are missing but the function declares a
+    /// `@container` image, decide whether to run it in the container instead:
+    /// automatically with `--auto-container`, otherwise by asking on a TTY.
+    /// Returns the image to use, or the original error.
+    fn container_fallback(
+        &self,
+        name: &str,
+        error: Box<dyn std::error::Error>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let image = self.function_attributes.get(name).and_then(|attributes| {
+            attributes.iter().find_map(|attribute| match attribute {
+                Attribute::Container(image) => Some(image.clone()),
+                _ => None,
+            })
+        });
+        let Some(image) = image else {
+            return Err(error);
+        };
+        if container_engine().is_none() {
+            return Err(format!(
+                "{}\n  (no docker or podman found to run '{}')",
+                error, image
+            )
+            .into());
+        }
+
+        if self.options.auto_container {
+            eprintln!("Running '{}' in container '{}'", name, image);
+            return Ok(image);
+        }
+        if !std::io::stdin().is_terminal() {
+            return Err(format!(
+                "{}\n  (run with --auto-container to use the '{}' container instead)",
+                error, image
+            )
+            .into());
+        }
+
+        eprint!(
+            "{}\n{} {} ",
+            error,
+            tr!("Run '{}' in container '{}' instead?", name, image),
+            tr!("[y/N]")
+        );
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if i18n::is_yes(&answer) {
+            Ok(image)
+        } else {
+            Err(tr!("Function '{}' was not run", name).into())
+        }
+    }
+
+    /// Ask whether to run a `@confirm` function, failing unless the answer
+    /// is yes. `--yes` and dry runs skip the question; without a terminal to
+    /// ask on, the function doesn't run.
+    fn confirm(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(question) = self
+            .function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .find_map(|attribute| match attribute {
+                Attribute::Confirm(question) => Some(question),
+                _ => None,
+            })
+        else {
+            return Ok(());
+        };
+        if self.options.yes || self.options.dry_run {
+            return Ok(());
+        }
+        let question = match question {
+            Some(question) => question.clone(),
+            None => tr!("Run '{}'?", name),
+        };
+        if !std::io::stdin().is_terminal() {
+            return Err(tr!(
+                "Function '{}' needs confirmation: {}\n  (run with --yes to confirm without a terminal)",
+                name,
+                question
+            )
+            .into());
+        }
+
+        // Holding stdin keeps functions running in parallel from asking at
+        // the same time
+        let mut stdin = std::io::stdin().lock();
+        eprint!("{} {} ", question, tr!("[y/N]"));
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        stdin.read_line(&mut answer)?;
+        if i18n::is_yes(&answer) {
+            Ok(())
+        } else {
+            Err(tr!("Function '{}' was not run", name).into())
+        }
+    }
+
+    /// Have the name of a `@danger` or `@prod` function typed before running
+    /// it, failing unless it matches. `--allow-danger`, `RUN_ALLOW_DANGER`
+    /// (for CI) and dry runs skip the question, but `--yes` doesn't; without
+    /// a terminal to ask on, the function doesn't run.
+    fn guard_danger(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(marker) = self
+            .function_attributes
+            .get(name)
+            .into_iter()
+            .flatten()
+            .find_map(|attribute| match attribute {
+                Attribute::Danger(marker) => Some(marker),
+                _ => None,
+            })
+        else {
+            return Ok(());
+        };
+        let allowed = std::env::var_os("RUN_ALLOW_DANGER")
+            .is_some_and(|allowed| !allowed.is_empty() && allowed != "0");
+        if self.options.allow_danger || allowed || self.options.dry_run {
+            return Ok(());
+        }
+        if !std::io::stdin().is_terminal() {
+            return Err(tr!(
+                "Function '{}' is marked @{}: run with --allow-danger, or set RUN_ALLOW_DANGER=1 in CI",
+                name,
+                marker
+            )
+            .into());
+        }
+
+        let mut stdin = std::io::stdin().lock();
+        eprint!(
+            "{} ",
+            tr!("'{}' is marked @{}. Type its name to run it:", name, marker)
+        );
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        stdin.read_line(&mut answer)?;
+        if answer.trim() == name {
+            Ok(())
+        } else {
+            Err(tr!("Function '{}' was not run", name).into())
+        }
+    }
+
+    /// Verify that every tool a function `requires` is on PATH at a suitable
+    /// version, reporting all problems at once before anything runs.
+    fn check_requirements(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(attributes) = self.function_attributes.get(name) else {
+            return Ok(());
+        };
+        let requirements = attributes
+            .iter()
+            .filter_map(|attribute| match attribute {
+                Attribute::Requires(requirements) => Some(requirements),
+                _ => None,
+            })
+            .flatten();
+
+        let problems = tools::unmet_requirements(requirements);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Function '{}' has unmet tool requirements:\n  {}",
+                name,
+                problems.join("\n  ")
+            )
+            .into())
+        }
+    }
+
+    /// Check `@skip-if` / `@only-if` annotations, returning why the function
+    /// should be skipped, if it should.
+    fn skip_reason(&self, name: &str) -> Option<String> {
+        for attribute in self.function_attributes.get(name)? {
+            match attribute {
+                Attribute::SkipIf(condition) if evaluate_condition(condition) => {
+                    return Some(format!("skip-if condition met: {}", condition));
+                }
+                Attribute::OnlyIf(condition) if !evaluate_condition(condition) => {
+                    return Some(format!("only-if condition not met: {}", condition));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Substitute the Runfile's variables, then those `other` gives a value
+    /// for and the automatic variables, unless the Runfile defines its own,
+    /// into `text`.
+    fn substitute_variables(&self, text: &str, other: impl Fn(&str) -> Option<String>) -> String {
+        substitute_references(text, |name, before| {
+            self.variable_value(name, before, &other)
+        })
+    }
+
+    /// The value of the variable `name`, referred to after `before`: the
+    /// Runfile's own, else the one `other` gives, else an automatic variable.
+    fn variable_value(
+        &self,
+        name: &str,
+        before: &str,
+        other: &impl Fn(&str) -> Option<String>,
+    ) -> Option<String> {
+        if let Some(value) = self.variable_reference(name) {
+            return Some(value);
+        }
+        other(name).or_else(|| {
+            let (_, value) = self
+                .automatic_variables()
+                .into_iter()
+                .find(|(automatic, _)| *automatic == name)?;
+            Some(quote_for_context(&value, before))
+        })
+    }
+
+    /// Substitute variables and the arguments of `function` (if the command
+    /// belongs to one) into a command template.
+    fn substitute_args(&self, template: &str, function: Option<&str>, args: &[String]) -> String {
+        let shell = function
+            .and_then(|function| self.shell_override(function))
+            .unwrap_or_else(default_shell);
+        self.substitute_args_in(template, function, args, &shell)
+    }
+
+    /// Substitute variables and arguments into a command template, with the
+    /// arguments quoted for `shell`.
+    fn substitute_args_in(
+        &self,
+        template: &str,
+        function: Option<&str>,
+        args: &[String],
+        shell: &str,
+    ) -> String {
+        // Named arguments first become positional ones, substituted below
+        let names: Vec<String> = function
+            .map(|function| self.parameters(function))
+            .unwrap_or_default()
+            .iter()
+            .map(|parameter| parameter.name.clone())
+            .collect();
+        let positions: Vec<String> = (1..=names.len()).map(|i| format!("${{{}}}", i)).collect();
+        let template = bind_params(template, &names, &positions);
+
+        // Then user-defined variables (e.g., $myvar or ${myvar}) along with
+        // the arguments, in one pass so no value is ever rescanned for
+        // references
+        let unlocked = self.unlocked.lock().unwrap_or_else(|e| e.into_inner());
+        let captured = self.captured.lock().unwrap_or_else(|e| e.into_inner());
+        let other = |name: &str| unlocked.get(name).or_else(|| captured.get(name)).cloned();
+        let variable = |after: &str, before: &str| {
+            // `$(name())` substitutions, kept as `(name())`
+            if after.starts_with('(') {
+                return captured
+                    .iter()
+                    .find(|(key, _)| key.starts_with('(') && after.starts_with(key.as_str()))
+                    .map(|(key, value)| (value.clone(), key.len()));
+            }
+            let (name, consumed) = reference_name(after);
+            if name.is_empty() {
+                return None;
+            }
+            self.variable_value(name, before, &other)
+                .map(|value| (value, consumed))
+        };
+        substitute_positional(&template, function, args, shell, variable)
+    }
+
+    fn execute_statement(
+        &mut self,
+        statement: Statement,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match statement {
+            Statement::Assignment { name, value } => {
+                // The variables assigned before it are substituted, as in
+                // `image=${app}-${tag}`, and builtin substitutions resolved
+                // once, so every use of `port = $(free_port)` sees the same port
+                let expand = |text: &str| {
+                    builtins::expand(text, &self.invoked_from, &self.temp)
+                        .map(|text| text.into_owned())
+                };
+                let val = match self.evaluate(&value) {
+                    Value::String(text) => {
+                        Value::String(expand(&self.substitute_variables(&text, |_| None))?)
+                    }
+                    // Items were substituted as they were evaluated
+                    Value::List(items) => Value::List(
+                        items
+                            .iter()
+                            .map(|item| expand(item))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                };
+                self.check_allowed(&name, &val.to_string())?;
+                self.captures.remove(&name);
+                self.run_captures.remove(&name);
+                self.variables.insert(name, val);
+            }
+            Statement::EnumVar {
+                name,
+                allowed,
+                value,
+            } => {
+                self.allowed.insert(name.clone(), allowed);
+                match value {
+                    Some(value) => self.execute_statement(Statement::Assignment { name, value })?,
+                    // A value assigned before the declaration must fit too
+                    None => {
+                        if let Some(value) = self.variables.get(&name) {
+                            self.check_allowed(&name, &value.to_string())?;
+                        }
+                    }
+                }
+            }
+            Statement::CaptureVar { name, function } => {
+                self.variables.remove(&name);
+                self.run_captures.remove(&name);
+                self.captures.insert(name, function);
+            }
+            Statement::RunCapture {
+                stdout,
+                stderr,
+                code,
+                function,
+            } => {
+                let results = [
+                    (stdout, RunResult::Stdout),
+                    (stderr, RunResult::Stderr),
+                    (code, RunResult::Code),
+                ];
+                for (name, result) in results {
+                    if let Some(name) = name {
+                        self.variables.remove(&name);
+                        self.captures.remove(&name);
+                        self.run_captures.insert(name, (function.clone(), result));
+                    }
+                }
+            }
+            Statement::SimpleFunctionDef {
+                name,
+                command_template,
+                attributes,
+            } => {
+                // A redefinition replaces any earlier body of either kind
+                self.block_functions.remove(&name);
+                self.function_attributes.insert(name.clone(), attributes);
+                self.simple_functions.insert(name, command_template);
+            }
+            Statement::BlockFunctionDef {
+                name,
+                commands,
+                attributes,
+            } => {
+                self.simple_functions.remove(&name);
+                self.function_attributes.insert(name.clone(), attributes);
+                self.block_functions.insert(name, commands);
+            }
+            Statement::FunctionCall { name, args } => {
+                // Resolve call-site variables before binding the arguments
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg).to_string())
+                    .collect();
+                self.call_function_with_args(&name, &args)?;
+            }
+            Statement::Command { command } if command == IGNORE_ERRORS => {
+                self.ignore_errors = true;
+            }
+            Statement::Command { command } if VERBOSE.contains(&command.as_str()) => {
+                self.verbose = true;
+            }
+            Statement::Command { command } => {
+                // Substitute variables in the command before executing
+                self.unlock_secrets(&command)?;
+                let context = CommandContext {
+                    ignore_errors: self.ignore_errors,
+                    verbose: self.verbose,
+                    last_status: self.last_status.clone(),
+                    temp: self.temp.clone(),
+                    ..CommandContext::default()
+                };
+                self.run_captures(&command, &context)?;
+                let substituted_command = self.substitute_args(&command, None, &[]);
+                self.execute_command(&substituted_command, &context)?;
+            }
+            Statement::ProfileDef { name, variables } => {
+                self.profiles.insert(name, variables);
+            }
+            Statement::EnvBlock { variables } => {
+                self.env.extend(variables);
+            }
+            Statement::TemplateDef { name, params, body } => {
+                self.templates.insert(name, (params, body));
+            }
+            Statement::Instantiate { calls } => {
+                for (name, args) in calls {
+                    let args: Vec<String> = args
+                        .iter()
+                        .map(|arg| self.evaluate(arg).to_string())
+                        .collect();
+                    self.instantiate(&name, &args)?;
+                }
+            }
+            Statement::Setting { key, value } => {
+                self.settings.insert(key, value);
+            }
+            Statement::SecretVar { name, value } => {
+                self.secrets.insert(name, value);
+            }
+            Statement::EnvGuard {
+                variable,
+                negated,
+                then_branch,
+                else_branch,
+            } => {
+                let branch = if env_guard_holds(&variable, negated) {
+                    then_branch
+                } else {
+                    else_branch
+                };
+                for stmt in branch {
+                    self.execute_statement(stmt)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate the functions of template `name`, with its parameters bound
+    /// to `args`.
+    fn instantiate(
+        &mut self,
+        name: &str,
+        args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (params, body) = self
+            .templates
+            .get(name)
+            .ok_or_else(|| format!("Template '{}' not found", name))?;
+        if params.len() != args.len() {
+            return Err(format!(
+                "Template '{}' takes {} argument(s), got {}",
+                name,
+                params.len(),
+                args.len()
+            )
+            .into());
+        }
+        if self.instantiating.iter().any(|template| template == name) {
+            return Err(format!("Template '{}' instantiates itself", name).into());
+        }
+
+        let source = bind_params(body, params, args);
+        let program = parser::parse_script(&source)
+            .map_err(|e| format!("In {}({}):\n{}", name, args.join(", "), e))?;
+        self.instantiating.push(name.to_string());
+        let result = self.execute(program);
+        self.instantiating.pop();
+        result
+    }
+
+    /// Evaluate an expression to its value.
+    fn evaluate(&self, expression: &Expression) -> Value {
+        match expression {
+            Expression::String(value) => Value::String(value.clone()),
+            Expression::Interpolated(template) => {
+                Value::String(self.interpolate_variables(template))
+            }
+            Expression::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| self.evaluate(item).to_string())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The value of a reference to a Runfile variable: `name`, the number
+    /// of items of a list (`#name`, or of characters of text, as in bash)
+    /// or one of its items (`name[0]`, counting from the end when negative,
+    /// and empty past it).
+    fn variable_reference(&self, reference: &str) -> Option<String> {
+        if let Some(name) = reference.strip_prefix('#') {
+            let length = match self.variables.get(name)? {
+                Value::String(text) => text.chars().count(),
+                Value::List(items) => items.len(),
+            };
+            return Some(length.to_string());
+        }
+        if let Some((name, index)) = reference
+            .strip_suffix(']')
+            .and_then(|reference| reference.split_once('['))
+        {
+            let items = self.variables.get(name)?.items();
+            let index: isize = index.trim().parse().ok()?;
+            let index = match usize::try_from(index) {
+                Ok(index) => Some(index),
+                Err(_) => items.len().checked_sub(index.unsigned_abs()),
+            };
+            let item = index.and_then(|index| items.get(index));
+            return Some(item.cloned().unwrap_or_default());
+        }
+        self.variables.get(reference).map(Value::to_string)
+    }
+
+    /// Variables describing where and how `run` was invoked.
+    fn automatic_variables(&self) -> [(&'static str, String); 3] {
+        let runfile_dir = self.runfile_dir.as_deref().unwrap_or(&self.invoked_from);
+        [
+            ("RUNFILE_DIR", runfile_dir.display().to_string()),
+            ("RUN_PWD", self.invoked_from.display().to_string()),
+            ("RUN_OS", std::env::consts::OS.to_string()),
+        ]
+    }
+
+    /// Substitute `$name` and `${name}` with Runfile variables, falling back to
+    /// environment variables; unknown names expand to nothing, as in the shell.
+    /// Positional references are kept. The escape sequences of double-quoted
+    /// text are resolved in the same pass: `\"`, `\\`, `\n`, `\t` and `\$`, a
+    /// literal dollar sign. Any other escaped character keeps its backslash.
+    fn interpolate_variables(&self, template: &str) -> String {
+        let mut result = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(pos) = rest.find(['$', '\\']) {
+            result.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            if rest[pos..].starts_with('\\') {
+                let mut chars = after.chars();
+                match chars.next() {
+                    Some('"') => result.push('"'),
+                    Some('\\') => result.push('\\'),
+                    Some('n') => result.push('\n'),
+                    Some('t') => result.push('\t'),
+                    Some('$') => result.push('$'),
+                    _ => {
+                        result.push('\\');
+                        rest = after;
+                        continue;
+                    }
+                }
+                rest = chars.as_str();
+                continue;
+            }
+
+            let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], end + 2),
+                    None => ("", 0),
+                }
+            } else {
+                let len = if after.starts_with(|c: char| c.is_ascii_digit()) {
+                    0
+                } else {
+                    after
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(after.len())
+                };
+                (&after[..len], len)
+            };
+
+            if let Some(value) = self.variable_reference(name) {
+                result.push_str(&value);
+                rest = &after[consumed..];
+                continue;
+            }
+            if name.is_empty() || !is_variable_name(name) {
+                result.push('$');
+                rest = after;
+                continue;
+            }
+            if let Some((_, value)) = self
+                .automatic_variables()
+                .into_iter()
+                .find(|(automatic, _)| *automatic == name)
+            {
+                result.push_str(&value);
+            } else if let Ok(value) = std::env::var(name) {
+                result.push_str(&value);
+            }
+            rest = &after[consumed..];
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// The lines of the block of `function` with each `for name in items`
+    /// loop replaced by its body once for each item, with the item bound to
+    /// `$name` and `${name}`.
+    fn unroll_loops(&self, function: &str, commands: &[String], args: &[String]) -> Vec<String> {
+        let mut unrolled = Vec::with_capacity(commands.len());
+        let mut rest = commands;
+        while let Some((line, after)) = rest.split_first() {
+            let Some((name, items)) = parser::loop_header(line) else {
+                unrolled.push(line.clone());
+                rest = after;
+                continue;
+            };
+            // The `}` closing this loop, past any nested in it
+            let mut depth = 1;
+            let end = after
+                .iter()
+                .position(|line| {
+                    if opens_block(line) {
+                        depth += 1;
+                    } else if line == "}" {
+                        depth -= 1;
+                    }
+                    depth == 0
+                })
+                .unwrap_or(after.len());
+            let shell = self.shell_override(function).unwrap_or_else(default_shell);
+            for item in self.loop_items(function, items, args) {
+                let body: Vec<String> = after[..end]
+                    .iter()
+                    .map(|line| bind_item(line, name, &item, &shell))
+                    .collect();
+                unrolled.extend(self.unroll_loops(function, &body, args));
+            }
+            rest = after.get(end + 1..).unwrap_or_default();
+        }
+        unrolled
+    }
+
+    /// The items a `for` loop of `function` goes through: those of a list
+    /// written as `[a, b]` or of a list variable given as `$list` or
+    /// `${list}`, or else the words of `items` with variables and arguments
+    /// substituted, each argument one word as in a command.
+    fn loop_items(&self, function: &str, items: &str, args: &[String]) -> Vec<String> {
+        if items.starts_with('[') {
+            return parser::list_items(items)
+                .unwrap_or_default()
+                .iter()
+                .map(|item| self.evaluate(item).to_string())
+                .collect();
+        }
+        let list = items.strip_prefix('$').map(|name| {
+            name.strip_prefix('{')
+                .and_then(|name| name.strip_suffix('}'))
+                .unwrap_or(name)
+        });
+        if let Some(Value::List(items)) = list.and_then(|name| self.variables.get(name)) {
+            return items.clone();
+        }
+        // Quoted as for `sh`, whatever the function runs in, to be split
+        // back into the words
+        shell_words(&self.substitute_args_in(items, Some(function), args, "sh"))
+    }
+
+    fn execute_block_commands(
+        &self,
+        name: &str,
+        commands: &[String],
+        args: &[String],
+        context: &CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Exit-code annotations on their own line apply to the next command
+        let mut line_exits: Option<ExitPolicy> = None;
+        let mut context = context.clone();
+        // Lines still to run, last first, so the branch an `if` takes can
+        // replace it
+        let mut pending: Vec<String> = commands.iter().rev().cloned().collect();
+        while let Some(cmd) = pending.pop() {
+            let cmd = &cmd;
+            if let Some(condition) = parser::if_header(cmd) {
+                let (then_branch, else_branch) = split_if(&mut pending);
+                let condition = bind_variant(condition, &context.variant);
+                let condition = self.substitute_args(&condition, Some(name), args);
+                let check = CommandContext {
+                    exits: ExitPolicy {
+                        any: true,
+                        ..ExitPolicy::default()
+                    },
+                    ..context.clone()
+                };
+                self.execute_command(&condition, &check)?;
+                let branch = match context.last_status.load(Ordering::SeqCst) {
+                    0 => then_branch,
+                    _ => else_branch,
+                };
+                pending.extend(branch.into_iter().rev());
+                continue;
+            }
+            if cmd == IGNORE_ERRORS {
+                context.ignore_errors = true;
+                continue;
+            }
+            if VERBOSE.contains(&cmd.as_str()) {
+                context.verbose = true;
+                continue;
+            }
+            if let Some((annotation, annotation_args)) = parser::line_annotation(cmd) {
+                if let Some(attribute) = parser::parse_exit_attribute(annotation, annotation_args) {
+                    line_exits
+                        .get_or_insert_with(|| context.exits.clone())
+                        .add(&attribute);
+                }
+                continue;
+            }
+            let substituted = bind_variant(cmd, &context.variant);
+            let substituted = self.substitute_args(&substituted, Some(name), args);
+            match return_value(&substituted) {
+                // A bare `return` keeps what the function printed
+                Some("") => return Ok(()),
+                Some(value) => return self.return_value(&parser::unquote(value), &context),
+                None => {}
+            }
+            match line_exits.take() {
+                Some(exits) => {
+                    let context = CommandContext {
+                        exits,
+                        ..context.clone()
+                    };
+                    self.execute_command(&substituted, &context)?;
+                }
+                None => self.execute_command(&substituted, &context)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Make `value` the output of the function `return` ends: the value of
+    /// a capture, replacing what the function printed, or else printed.
+    fn return_value(
+        &self,
+        value: &str,
+        context: &CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let line = format!("{}\n", value);
+        match (&context.capture, &context.output) {
+            (Some(capture), _) => capture
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .replace(line.as_bytes()),
+            (None, Some(output)) => output
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(line.as_bytes()),
+            (None, None) => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(line.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Route a command's stdout and stderr: straight through by default, or
+    /// through pipes drained on background threads when output is collected,
+    /// prefixed, reported as events or kept for notifications.
+    fn attach_output(
+        &self,
+        process: &mut Command,
+        context: &CommandContext,
+    ) -> std::io::Result<Option<pipes::Reading>> {
+        let route = Route {
+            output: context.output.clone(),
+            capture: context.capture.clone(),
+            capture_errors: context.capture_errors.clone(),
+            events: self.options.events.clone().zip(context.task.clone()),
+            tail: context.tail.clone(),
+            record: context.record.clone(),
+            log: context.log.clone(),
+            status: self.options.status.clone(),
+            prefix: context.prefix.clone(),
+        };
+        if route.is_passthrough() {
+            process.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+            return Ok(None);
+        }
+        if route.output.is_some()
+            && route.capture.is_none()
+            && route.capture_errors.is_none()
+            && route.events.is_none()
+        {
+            // Everything ends up in one buffer: a single pipe for both
+            // streams keeps their relative order
+            let (reader, writer) = std::io::pipe()?;
+            process.stdout(writer.try_clone()?).stderr(writer);
+            let pipes = vec![(reader, line_handler(Stream::Stdout, route))];
+            return Ok(Some(pipes::read_lines(pipes)));
+        }
+
+        let (stdout_reader, stdout_writer) = std::io::pipe()?;
+        let (stderr_reader, stderr_writer) = std::io::pipe()?;
+        process.stdout(stdout_writer).stderr(stderr_writer);
+        Ok(Some(pipes::read_lines(vec![
+            (stdout_reader, line_handler(Stream::Stdout, route.clone())),
+            (stderr_reader, line_handler(Stream::Stderr, route)),
+        ])))
+    }
+
+    /// Print `command`, about to be run by `runner`, as `set -x` would, with
+    /// the values of secrets shown as the variables they came from.
+    fn trace(&self, runner: &str, command: &str, context: &CommandContext) {
+        let mut command = command.to_string();
+        for (name, value) in self
+            .unlocked
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+        {
+            command = command.replace(value.as_str(), &format!("${}", name));
+        }
+        let line = format!("+ [{}] {}", runner, command);
+        let line = format!("{}\n", style::dim(&line));
+        match (&context.output, &self.options.status) {
+            (Some(output), _) => output
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(line.as_bytes()),
+            (None, Some(status)) => status.write_above(Stream::Stderr, line.as_bytes()),
+            (None, None) => eprint!("{}", line),
+        }
+    }
+
+    fn execute_command(
+        &self,
+        command: &str,
+        context: &CommandContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // A `-` prefix, as in Make, lets the command fail without stopping
+        let (command, ignore_errors) = match command.strip_prefix('-') {
+            Some(rest) => (rest.trim_start(), true),
+            None => (command, context.ignore_errors),
+        };
+        if self.options.dry_run {
+            println!("{}", command);
+            return Ok(());
+        }
+        let cwd = context.cwd.as_deref().unwrap_or(&self.invoked_from);
+        let command = &*builtins::expand(command, cwd, &context.temp)?;
+        if let Some((builtin, redirect)) = builtins::parse(command) {
+            if context.verbose {
+                self.trace("builtin", command, context);
+            }
+            let started = Instant::now();
+            let result = match (redirect, &context.capture) {
+                (Some(redirect), _) => redirect
+                    .open(cwd)
+                    .map_err(|e| format!("Cannot write to {}: {}", redirect.path, e).into())
+                    .and_then(|mut file| builtins::run(&builtin, cwd, &mut file, false)),
+                (None, Some(capture)) => {
+                    let mut capture = capture.lock().unwrap_or_else(|e| e.into_inner());
+                    builtins::run(&builtin, cwd, &mut *capture, false)
+                }
+                (None, None) => {
+                    builtins::run(&builtin, cwd, &mut std::io::stdout(), style::enabled())
+                }
+            };
+            if let (Some(timings), Some(task)) = (&self.options.timings, &context.task) {
+                timings.command(task, command, started.elapsed());
+            }
+            let status = if result.is_ok() { 0 } else { 1 };
+            context.last_status.store(status, Ordering::SeqCst);
+            return match result {
+                Err(e) if ignore_errors => {
+                    eprintln!("{}", tr!("{} (ignored)", e));
+                    Ok(())
+                }
+                result => result,
+            };
+        }
+
+        let env = self.exported_env(&context.env);
+        let last_status = context.last_status.load(Ordering::SeqCst);
+        let mut process = if let Some(image) = &context.container {
+            if context.verbose {
+                self.trace(&format!("container {}", image), command, context);
+            }
+            let mut process = container_command(image, cwd, &env)?;
+            process.arg(&*with_last_status(command, last_status, "sh"));
+            process
+        } else {
+            let shell_cmd = context.shell.clone().unwrap_or_else(default_shell);
+            // With `commands.translate`, the portable commands of functions
+            // without `@shell` are re-emitted for the default shell
+            let translated = (context.shell.is_none()
+                && self.setting("commands.translate") == Some("true"))
+            .then(|| translate::translate(command, &shell_cmd))
+            .flatten();
+            let command = translated.as_deref().unwrap_or(command);
+            if context.verbose {
+                self.trace(&shell_name(&shell_cmd), command, context);
+            }
+            let mut process = match &self.toolchain() {
+                Some(toolchain) => toolchain.command(&shell_cmd),
+                None => Command::new(&shell_cmd),
+            };
+            process
+                .arg(command_flag(&shell_cmd))
+                .arg(&*with_last_status(command, last_status, &shell_cmd));
+            process
+        };
+
+        process
+            .current_dir(cwd)
+            .envs(env.iter().map(|(key, value)| (key, value)));
+        if let Some(umask) = context.umask {
+            perms::set_umask(&mut process, umask);
+        }
+        if let (Some(status), Some(task)) = (&self.options.status, &context.task) {
+            status.command(task, command);
+        }
+        let reading = self.attach_output(&mut process, context)?;
+        let started = Instant::now();
+        let status = if context.process_group {
+            process::run_in_group(&mut process)
+        } else {
+            process.status()
+        };
+        // Close our copies of the pipes so the readers see the end of them
+        drop(process);
+        if let Some(reading) = reading {
+            reading.join();
+        }
+        if let (Some(timings), Some(task)) = (&self.options.timings, &context.task) {
+            timings.command(task, command, started.elapsed());
+        }
+        let status = status?;
+        context
+            .last_status
+            .store(exit_code(status), Ordering::SeqCst);
+
+        if !status.success() {
+            if process::is_cancelled() {
+                return Err("Cancelled".into());
+            }
+            match status.code() {
+                _ if context.exits.any => {}
+                Some(code) if context.exits.allowed.contains(&code) => {}
+                Some(code) if context.exits.warn.contains(&code) => {
+                    let warning = tr!("'{}' exited with status {}", command, code);
+                    eprintln!("{}", tr!("Warning: {}", warning));
+                    let task = context.task.clone().unwrap_or_default();
+                    context
+                        .warnings
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push((task, warning));
+                }
+                _ if ignore_errors => {
+                    eprintln!(
+                        "{}",
+                        tr!("Command failed with {} (ignored)", describe_status(status))
+                    )
+                }
+                _ => {
+                    match &context.failure {
+                        Some(failure) => failure.store(exit_code(status), Ordering::SeqCst),
+                        None => {
+                            self.failure_code
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .get_or_insert(exit_code(status));
+                        }
+                    }
+                    return Err(tr!("'{}' failed with {}", command, describe_status(status)).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the lines of a command's output go besides, or instead of, our
+/// own stdout and stderr.
+#[derive(Clone)]
+struct Route {
+    /// Collect the output here instead of writing it out.
+    output: Option<Buffer>,
+    /// Collect stdout here instead of writing it out.
+    capture: Option<Buffer>,
+    /// Collect stderr here instead of writing it out.
+    capture_errors: Option<Buffer>,
+    /// Report each line as an event for this task.
+    events: Option<(Arc<events::Emitter>, String)>,
+    /// Keep the last lines for notifications.
+    tail: Option<Arc<Mutex<notify::OutputTail>>>,
+    /// Also copy the output here.
+    record: Option<Buffer>,
+    /// Also write the output to this log file.
+    log: Option<Arc<Mutex<logs::LogFile>>>,
+    /// Write the output above this status line.
+    status: Option<Arc<status::StatusLine>>,
+    /// Start each line written out with this.
+    prefix: Option<String>,
+}
+
+impl Route {
+    /// Whether output goes nowhere but our own stdout and stderr.
+    fn is_passthrough(&self) -> bool {
+        self.output.is_none()
+            && self.capture.is_none()
+            && self.capture_errors.is_none()
+            && self.events.is_none()
+            && self.tail.is_none()
+            && self.record.is_none()
+            && self.log.is_none()
+            && self.status.is_none()
+            && self.prefix.is_none()
+    }
+}
+
+/// Copy the lines of a command's output on `stream`, following `route`.
+fn line_handler(stream: Stream, route: Route) -> pipes::LineHandler {
+    Box::new(move |line| {
+        if let (Stream::Stdout, Some(capture)) = (stream, &route.capture) {
+            capture
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(line);
+        } else if let (Stream::Stderr, Some(capture)) = (stream, &route.capture_errors) {
+            capture
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(line);
+        } else if let Some(output) = &route.output {
+            output
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(line);
+        } else {
+            let prefixed;
+            let shown = match &route.prefix {
+                Some(prefix) => {
+                    prefixed = [prefix.as_bytes(), line].concat();
+                    &prefixed
+                }
+                None => line,
+            };
+            if let Some(status) = &route.status {
+                status.write_above(stream, shown);
+            } else {
+                let _ = match stream {
+                    Stream::Stdout => std::io::stdout().lock().write_all(shown),
+                    Stream::Stderr => std::io::stderr().lock().write_all(shown),
+                };
+            }
+        }
+        if let Some(log) = &route.log {
+            let _ = log
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .write_all(line);
+        }
+        if let Some(record) = &route.record {
+            record
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(line);
+        }
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        if let Some((emitter, task)) = &route.events {
+            emitter.line(task, stream, text);
+        }
+        if let Some(tail) = &route.tail {
+            tail.lock().unwrap_or_else(|e| e.into_inner()).push(text);
+        }
+    })
+}
+
+/// Substitute template arguments for `$param` and `${param}` in its body.
+pub fn bind_params(body: &str, params: &[String], args: &[String]) -> String {
+    substitute_references(body, |name, _| {
+        let index = params.iter().position(|param| param == name)?;
+        Some(args[index].clone())
+    })
+}
+
+/// Bind the item of a `for` loop to `$name` and `${name}` in a line of its
+/// body, quoted for `shell` where the reference is bare and escaped for the
+/// quotes it is written in otherwise, as arguments are.
+fn bind_item(line: &str, name: &str, item: &str, shell: &str) -> String {
+    substitute_references(line, |reference, before| {
+        (reference == name).then(|| quote_in(&[item.to_string()], open_quote(before), shell))
+    })
+}
+
+/// The words of `text` as `sh` splits them, with their quotes removed.
+fn shell_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some(next @ ('\\' | '"' | '$' | '`')) => word.push(next),
+                    Some(next) => {
+                        word.push('\\');
+                        word.push(next);
+                    }
+                    None => word.push('\\'),
+                }
+            }
+            (Some(_), _) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.next());
+            }
+            (None, _) if c.is_whitespace() => words.extend(word.take()),
+            (None, _) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// Whether `text` may refer to the variable `name`, as `$name` or `${name}`.
+fn refers_to(text: &str, name: &str) -> bool {
+    text.contains(&format!("${}", name)) || text.contains(&format!("${{{}}}", name))
+}
+
+/// Replace the `$name` and `${name}` references in `text` that `value`
+/// gives a value for, passed the name and the text substituted so far, in
+/// one pass so values are never rescanned. A `$name` is the longest name
+/// it can be, so `$appname` never refers to `app`: `${app}name` does.
+/// Other references are left as they are, for the shell.
+fn substitute_references(text: &str, value: impl Fn(&str, &str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, consumed) = reference_name(after);
+        match value(name, &result).filter(|_| !name.is_empty()) {
+            Some(value) => {
+                result.push_str(&value);
+                rest = &after[consumed..];
+            }
+            None => {
+                result.push('$');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The name a reference starting with `after`, the text after its `$`,
+/// refers to, and the length of the reference in `after`: the content of
+/// `{...}`, or else the longest name there is. Empty when there is none.
+fn reference_name(after: &str) -> (&str, usize) {
+    match after.strip_prefix('{') {
+        Some(braced) => match braced.find('}') {
+            Some(end) => (&braced[..end], end + 2),
+            None => ("", 0),
+        },
+        None => {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..len], len)
+        }
+    }
+}
+
+/// Substitute the values of a matrix variant for `$axis` and `${axis}`.
+fn bind_variant(template: &str, variant: &[(String, String)]) -> String {
+    let mut result = template.to_string();
+    for (axis, value) in variant {
+        result = replace_placeholder(&result, &format!("${{{}}}", axis), value);
+        result = replace_placeholder(&result, &format!("${}", axis), value);
+    }
+    result
+}
+
+/// Write the output recorded for an `@once` function again, where the
+/// output of the current call would go.
+fn replay_output(output: &[u8], context: &CommandContext) {
+    if let Some(buffer) = &context.output {
+        buffer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(output);
+    } else {
+        let _ = std::io::stdout().lock().write_all(output);
+    }
+}
+
+/// The container engine available on PATH, preferring docker over podman.
+fn container_engine() -> Option<&'static str> {
+    ["docker", "podman"]
+        .into_iter()
+        .find(|engine| which::which(engine).is_ok())
+}
+
+/// Build a process running a shell inside `image`, with the current directory
+/// mounted as the working directory. The command string is appended by the caller.
+fn container_command(
+    image: &str,
+    cwd: &Path,
+    env: &[(String, String)],
+) -> Result<Command, Box<dyn std::error::Error>> {
+    let engine = container_engine().ok_or("No container engine (docker or podman) found")?;
+
+    let mut process = Command::new(engine);
+    process.args(["run", "--rm", "-i"]);
+    if std::io::stdin().is_terminal() {
+        process.arg("-t");
+    }
+    for (key, value) in env {
+        process.arg("-e").arg(format!("{}={}", key, value));
+    }
+    process
+        .arg("-v")
+        .arg(format!("{}:/workspace", cwd.display()))
+        .args(["-w", "/workspace", image, "sh", "-c"]);
+    Ok(process)
+}
+
+/// The code `run` exits with for a failed command: its own exit code, or
+/// 128 plus the signal that terminated it, as shells report it.
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// `exit status: N` in the user's language, or how else the process ended.
+fn describe_status(status: std::process::ExitStatus) -> String {
+    match status.code() {
+        Some(code) => tr!("exit status: {}", code),
+        None => status.to_string(),
+    }
+}
+
+/// `command` for `shell`, starting with `status`, the exit status of the
+/// command before it, as the shell's own: `$?` in sh and its relatives,
+/// `$LASTEXITCODE` in PowerShell. Commands not referring to it, and those
+/// of other shells, are left as they are.
+/// Whether a block line opens a nested block: a `for` loop or an `if`.
+fn opens_block(line: &str) -> bool {
+    parser::loop_header(line).is_some() || parser::if_header(line).is_some()
+}
+
+/// Take the lines of the `if` whose header was just taken off `pending`,
+/// which holds the lines still to run, last first, as the lines of its
+/// branches.
+fn split_if(pending: &mut Vec<String>) -> (Vec<String>, Vec<String>) {
+    let (mut then_branch, mut else_branch) = (Vec::new(), Vec::new());
+    let mut in_else = false;
+    let mut depth = 1;
+    while let Some(line) = pending.pop() {
+        if opens_block(&line) {
+            depth += 1;
+        } else if line == "}" {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        } else if depth == 1 && line == parser::ELSE {
+            in_else = true;
+            continue;
+        }
+        match in_else {
+            true => else_branch.push(line),
+            false => then_branch.push(line),
+        }
+    }
+    (then_branch, else_branch)
+}
+
+fn with_last_status<'a>(command: &'a str, status: i32, shell: &str) -> std::borrow::Cow<'a, str> {
+    if status == 0 {
+        return command.into();
+    }
+    match shell_name(shell).as_str() {
+        "pwsh" | "powershell" if command.contains("$LASTEXITCODE") => {
+            format!("$global:LASTEXITCODE = {}; {}", status, command).into()
+        }
+        "pwsh" | "powershell" | "cmd" | "fish" | "nu" => command.into(),
+        _ if command.contains("$?") => format!("(exit {}); {}", status, command).into(),
+        _ => command.into(),
+    }
+}
+
+/// Replace each `$(...)` in `text` with what the default shell prints
+/// running it in `cwd`, without the trailing newlines, as the shell's own
+/// command substitution does. Its stderr is shown as it is.
+fn substitute_commands(text: &str, cwd: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let shell = default_shell();
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("$(") {
+        // The closing parenthesis matching the opening one
+        let mut depth = 0;
+        let end = rest[start + 1..].char_indices().find_map(|(i, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(start + 1 + i)
+        });
+        let Some(end) = end else {
+            break;
+        };
+        let command = &rest[start + 2..end];
+        let output = Command::new(&shell)
+            .arg(command_flag(&shell))
+            .arg(command)
+            .current_dir(cwd)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("Cannot run '{}': {}", command, e))?;
+        result.push_str(&rest[..start]);
+        result.push_str(String::from_utf8_lossy(&output.stdout).trim_end_matches(['\n', '\r']));
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Evaluate a `@skip-if` / `@only-if` precondition.
+fn evaluate_condition(condition: &Condition) -> bool {
+    match condition {
+        Condition::Exists(path) => std::path::Path::new(path).exists(),
+        Condition::CommandExists(tool) => which::which(tool).is_ok(),
+        Condition::Env(name) => std::env::var_os(name).is_some(),
+        Condition::Not(inner) => !evaluate_condition(inner),
+    }
+}
+
+/// Characters that may separate a namespace from the rest of a function name.
+const SEPARATORS: [char; 3] = [':', '.', '/'];
+
+/// The namespace separators listed in the `commands.separators` setting
+/// (e.g. `: .`), or just `:` when it isn't set.
+pub fn parse_separators(setting: Option<&str>) -> Result<Vec<char>, String> {
+    let Some(setting) = setting else {
+        return Ok(vec![':']);
+    };
+    let separators: Vec<char> = setting
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ',')
+        .collect();
+    match separators.iter().find(|c| !SEPARATORS.contains(c)) {
+        Some(invalid) => Err(format!(
+            "Invalid commands.separators: '{}' (expected any of ':', '.', '/')",
+            invalid
+        )),
+        None => Ok(separators),
+    }
+}
+
+/// Evaluate an `ifdef`/`ifndef` guard against the current environment.
+pub fn env_guard_holds(variable: &str, negated: bool) -> bool {
+    std::env::var_os(variable).is_some() != negated
+}
+
+/// Directive making the failing commands after it non-fatal: at the top
+/// of the Runfile for every function, inside a block for the rest of it.
+const IGNORE_ERRORS: &str = "set ignore_errors";
+
+/// Directive printing the commands after it before they run, scoped like
+/// `IGNORE_ERRORS`; `set -x` as in the shell.
+const VERBOSE: [&str; 2] = ["set verbose", "set -x"];
+
+/// Whether `name` is a valid variable identifier.
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The value of a `return value` line of a block function, empty for a bare
+/// `return`.
+fn return_value(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("return")?;
+    (rest.is_empty() || rest.starts_with([' ', '\t'])).then(|| rest.trim())
+}
+
+/// Quote each argument for `shell` and join them with spaces.
+fn quote_args_for_shell(args: &[String], shell: &str) -> String {
+    args.iter()
+        .map(|arg| shell_quote(arg, shell))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quote a single argument so `shell` treats it as one literal word.
+/// Arguments made only of characters that are never special are left bare.
+pub(crate) fn shell_quote(arg: &str, shell: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if is_plain {
+        return arg.to_string();
+    }
+
+    match shell_name(shell).as_str() {
+        "pwsh" | "powershell" => format!("'{}'", arg.replace('\'', "''")),
+        "cmd" => format!("\"{}\"", arg.replace('"', "\"\"")),
+        _ => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+/// A reference to the arguments of a function in its body.
+#[derive(Debug, PartialEq)]
+enum Positional<'a> {
+    /// `$N` or `${N}`, numbered from 1; `$10` is the tenth.
+    Arg(usize),
+    /// `${N:-default}`: the argument, or the default when it isn't passed.
+    Default(usize, &'a str),
+    /// `$@`, `$N..` or `${@:N}`: the arguments from the Nth on.
+    Rest(usize),
+    /// `$0`: the function's name.
+    Name,
+}
+
+/// The positional reference at the start of `text`, which follows a `$`,
+/// and its length.
+fn positional(text: &str) -> Option<(Positional<'_>, usize)> {
+    let number = |text: &str| -> Option<usize> {
+        if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        text.parse().ok()
+    };
+    if text.starts_with('@') {
+        return Some((Positional::Rest(1), 1));
+    }
+    if let Some(braced) = text.strip_prefix('{') {
+        let end = braced.find('}')?;
+        let inner = &braced[..end];
+        let reference = match (inner.strip_prefix("@:"), inner.split_once(":-")) {
+            (Some(start), _) => Positional::Rest(number(start).filter(|&start| start > 0)?),
+            (None, Some((index, default))) => {
+                Positional::Default(number(index).filter(|&index| index > 0)?, default)
+            }
+            (None, None) => match number(inner)? {
+                0 => Positional::Name,
+                index => Positional::Arg(index),
+            },
+        };
+        return Some((reference, end + 2));
+    }
+    let len = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let index = number(&text[..len])?;
+    if index > 0 && text[len..].starts_with("..") {
+        return Some((Positional::Rest(index), len + 2));
+    }
+    match index {
+        0 => Some((Positional::Name, len)),
+        index => Some((Positional::Arg(index), len)),
+    }
+}
+
+/// Substitute the references to the arguments in `template`, such as `$1`,
+/// `${2:-default}` and `$@`, in a single pass, so values are never rescanned
+/// for references. Every argument stays one word: quoted for `shell` where
+/// the reference is bare, escaped for the quotes it is written in
+/// otherwise, and a whole `"$@"` is each argument quoted on its own.
+/// `\$1` is left for the shell. Any other reference is replaced by what
+/// `variable` gives for the text after its `$` and the text substituted so
+/// far, the value and the length of the reference, or else left as it is.
+fn substitute_positional(
+    template: &str,
+    function: Option<&str>,
+    args: &[String],
+    shell: &str,
+    variable: impl Fn(&str, &str) -> Option<(String, usize)>,
+) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut quote: Option<char> = None;
+    let mut rest = template;
+    while let Some(pos) = rest.find(['$', '"', '\'', '\\']) {
+        result.push_str(&rest[..pos]);
+        let c = rest[pos..].chars().next().unwrap_or('$');
+        let after = &rest[pos + 1..];
+        rest = after;
+        match c {
+            '\\' if quote != Some('\'') => {
+                // The escaped character is taken as it is
+                let len = after.chars().next().map_or(0, char::len_utf8);
+                result.push('\\');
+                result.push_str(&after[..len]);
+                rest = &after[len..];
+                continue;
+            }
+            '\\' => {
+                result.push(c);
+                continue;
+            }
+            '"' | '\'' => {
+                if quote.is_none() {
+                    if c == '"'
+                        && let Some(("$", reference)) = after.split_at_checked(1)
+                        && let Some((Positional::Rest(start), len)) = positional(reference)
+                        && reference[len..].starts_with('"')
+                    {
+                        let values = args.get(start - 1..).unwrap_or_default();
+                        result.push_str(&quote_args_for_shell(values, shell));
+                        rest = &reference[len + 1..];
+                        continue;
+                    }
+                    quote = Some(c);
+                } else if quote == Some(c) {
+                    quote = None;
+                }
+                result.push(c);
+                continue;
+            }
+            _ => {}
+        }
+        let Some((reference, len)) = positional(after) else {
+            match variable(after, &result) {
+                Some((value, len)) => {
+                    result.push_str(&value);
+                    rest = &after[len..];
+                }
+                None => result.push('$'),
+            }
+            continue;
+        };
+        rest = &after[len..];
+        let name = function.map(|function| [function.to_string()]);
+        let values: &[String] = match reference {
+            Positional::Arg(number) => args.get(number - 1..number).unwrap_or_default(),
+            Positional::Default(number, default) => match args.get(number - 1..number) {
+                Some(value) => value,
+                None => {
+                    result.push_str(default);
+                    continue;
+                }
+            },
+            Positional::Rest(start) => args.get(start - 1..).unwrap_or_default(),
+            Positional::Name => match &name {
+                Some(name) => name,
+                // Outside a function, `$0` is the shell's
+                None => {
+                    result.push('$');
+                    result.push_str(&after[..len]);
+                    continue;
+                }
+            },
+        };
+        result.push_str(&quote_in(values, quote, shell));
+    }
+    result.push_str(rest);
+    result
+}
+
+/// `values` as they are written in a command for `shell`, inside `quote`.
+fn quote_in(values: &[String], quote: Option<char>, shell: &str) -> String {
+    match quote {
+        None => quote_args_for_shell(values, shell),
+        Some('"') => escape_double_quoted(&values.join(" ")),
+        Some(_) => {
+            let value = values.join(" ");
+            match shell_name(shell).as_str() {
+                "pwsh" | "powershell" => value.replace('\'', "''"),
+                "cmd" => value,
+                _ => value.replace('\'', "'\\''"),
+            }
+        }
+    }
+}
+
+/// Replace every occurrence of `placeholder` in `text` with `value`, escaping
+/// the value when the occurrence sits inside a double-quoted string so that
+/// arguments containing quotes or backslashes can't break out of it.
+fn replace_placeholder(text: &str, placeholder: &str, value: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(placeholder) {
+        result.push_str(&rest[..pos]);
+        let escaped = quote_for_context(value, &result);
+        result.push_str(&escaped);
+        rest = &rest[pos + placeholder.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Escape `value` for insertion after `prefix`: as in a double-quoted string
+/// when `prefix` leaves one open, verbatim elsewhere.
+fn quote_for_context(value: &str, prefix: &str) -> String {
+    if !inside_double_quotes(prefix) {
+        return value.to_string();
+    }
+    escape_double_quoted(value)
+}
+
+/// `value` with `\`, `"`, `$` and `` ` `` backslash-escaped, to stand for
+/// itself inside a double-quoted string.
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether the end of `prefix` lies inside an unterminated double-quoted string.
+fn inside_double_quotes(prefix: &str) -> bool {
+    open_quote(prefix) == Some('"')
+}
+
+/// The quote left open at the end of `prefix`, if any.
+fn open_quote(prefix: &str) -> Option<char> {
+    let mut quote = None;
+    let mut chars = prefix.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some('"'), '"') => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            _ => {}
+        }
     }
+    quote
 }
@@ -0,0 +1,88 @@
+//! # devrun
+//!
+//! The Runfile language of `run` as a library, for tools that embed it:
+//! parse a Runfile with [`parser::parse_script`], load it into an
+//! [`Interpreter`] to enumerate its functions, and call them.
+//!
+//! ```
+//! use devrun::{Interpreter, parser};
+//!
+//! let program = parser::parse_script("greet() echo hello $1\nbuild() {\n    echo built\n}\n")
+//!     .expect("a valid Runfile");
+//! let mut interpreter = Interpreter::new();
+//! interpreter.execute(program).expect("definitions load");
+//! assert_eq!(interpreter.function_names(), ["build", "greet"]);
+//! interpreter
+//!     .call_function_with_args("greet", &["world".to_string()])
+//!     .expect("greet succeeds");
+//! ```
+//!
+//! Commands run as they do from the command line, with their output on
+//! stdout and stderr; [`interpreter::Options`] selects how, as the CLI flags
+//! do. The other modules are the internals of the `run` binary and are not
+//! part of the API.
+
+mod artifacts;
+pub mod ast;
+mod builtins;
+mod cache;
+mod capped;
+mod commands;
+#[doc(hidden)]
+pub mod completion;
+#[doc(hidden)]
+pub mod config;
+mod dates;
+mod diff;
+#[doc(hidden)]
+pub mod events;
+#[doc(hidden)]
+pub mod executor;
+mod github;
+mod glob;
+mod history;
+mod hook;
+#[doc(hidden)]
+pub mod i18n;
+pub mod interpreter;
+mod logs;
+#[doc(hidden)]
+pub mod makefile;
+mod matrix;
+mod notify;
+mod npm;
+mod package;
+mod pager;
+mod panes;
+pub mod parser;
+mod perms;
+mod pipes;
+mod process;
+mod readline;
+mod refactor;
+#[doc(hidden)]
+pub mod repl;
+mod resume;
+mod scheduler;
+mod secrets;
+#[doc(hidden)]
+pub mod shell;
+#[doc(hidden)]
+pub mod status;
+#[doc(hidden)]
+pub mod style;
+#[doc(hidden)]
+pub mod timings;
+mod toolchain;
+mod tools;
+mod translate;
+mod watch;
+mod workspace;
+
+pub use interpreter::Interpreter;
+
+/// Print an error message and exit with code 1.
+pub fn fatal_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
@@ -0,0 +1,210 @@
+//! Per-function logs (`logs.enabled = true`): the combined output of each
+//! run is written to `.run/logs/<function>/<timestamp>.log`, keeping the
+//! most recent `logs.keep` of them (10 by default). Past `output.limit`, a
+//! log has the first half of that written as the function runs and the last
+//! half when it finishes.
+
+use crate::artifacts::encode_name;
+use crate::capped::Capped;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory, relative to the working directory, holding one directory of
+/// logs per function.
+pub const LOGS_DIR: &str = ".run/logs";
+
+/// Number of logs kept per function unless `logs.keep` says otherwise.
+pub const DEFAULT_KEEP: usize = 10;
+
+/// Names the log being written while a function runs.
+const RUNNING_MARKER: &str = "running";
+
+/// The log of one run of a function, open for writing.
+pub struct TaskLog {
+    dir: PathBuf,
+    pub file: Arc<Mutex<LogFile>>,
+}
+
+/// A log file being written, up to the output limit.
+#[derive(Debug)]
+pub struct LogFile {
+    file: File,
+    /// Bytes still written straight to the file.
+    room: usize,
+    /// Whether what was written so far ends with a newline.
+    line_ended: bool,
+    /// The latest output past that, written when the function finishes.
+    overflow: Capped,
+}
+
+impl Write for LogFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = data.len().min(self.room);
+        self.file.write_all(&data[..written])?;
+        self.room -= written;
+        if written > 0 {
+            self.line_ended = data[written - 1] == b'\n';
+        }
+        self.overflow.extend(&data[written..]);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl TaskLog {
+    /// Create a new log for `function`, holding up to `limit` bytes, and
+    /// mark it as running.
+    pub fn start(function: &str, base: &Path, limit: Option<usize>) -> io::Result<TaskLog> {
+        let dir = log_dir(function, base);
+        fs::create_dir_all(&dir)?;
+        let stamp = timestamp(SystemTime::now());
+        let mut name = format!("{}.log", stamp);
+        let mut n = 1;
+        while dir.join(&name).exists() {
+            n += 1;
+            name = format!("{}-{}.log", stamp, n);
+        }
+        let file = File::create(dir.join(&name))?;
+        fs::write(dir.join(RUNNING_MARKER), &name)?;
+        let (room, overflow) = match limit {
+            Some(limit) => (limit / 2, Capped::tail(limit - limit / 2)),
+            None => (usize::MAX, Capped::tail(0)),
+        };
+        Ok(TaskLog {
+            dir,
+            file: Arc::new(Mutex::new(LogFile {
+                file,
+                room,
+                line_ended: true,
+                overflow,
+            })),
+        })
+    }
+
+    /// Write the end of the output, mark the log as complete and delete all
+    /// but the newest `keep` logs.
+    pub fn finish(self, keep: usize) {
+        let mut log = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let mut overflow = log.overflow.take();
+        if !log.line_ended && !overflow.is_empty() {
+            overflow.insert(0, b'\n');
+        }
+        let _ = log.file.write_all(&overflow);
+        drop(log);
+        let _ = fs::remove_file(self.dir.join(RUNNING_MARKER));
+        let logs = list(&self.dir);
+        for old in &logs[..logs.len().saturating_sub(keep.max(1))] {
+            let _ = fs::remove_file(old);
+        }
+    }
+}
+
+/// The most recent log of `function`, if any.
+pub fn latest(function: &str, base: &Path) -> Option<PathBuf> {
+    list(&log_dir(function, base)).pop()
+}
+
+/// The UTC date and time of the most recent log of `function`, such as
+/// `2024-01-31 23:59`.
+pub fn last_run(function: &str, base: &Path) -> Option<String> {
+    let path = latest(function, base)?;
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+    let stamp = stem.get(..13)?;
+    Some(format!(
+        "{}-{}-{} {}:{}",
+        &stamp[..4],
+        &stamp[4..6],
+        &stamp[6..8],
+        &stamp[9..11],
+        &stamp[11..13]
+    ))
+}
+
+/// Copy `path` to stdout, then keep printing what is appended to it while
+/// the function is still running.
+pub fn follow(path: &Path) -> io::Result<()> {
+    let running = path.with_file_name(RUNNING_MARKER);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    let is_running = || fs::read_to_string(&running).ok() == name;
+
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    loop {
+        // Check before reading so nothing written just before the end is lost
+        let still_running = is_running();
+        buffer.clear();
+        file.read_to_end(&mut buffer)?;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&buffer)?;
+        stdout.flush()?;
+        if !still_running {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+fn log_dir(function: &str, base: &Path) -> PathBuf {
+    base.join(LOGS_DIR).join(encode_name(function))
+}
+
+/// The logs in `dir`, oldest first.
+fn list(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    // Timestamps sort chronologically; same-second logs are numbered
+    logs.sort_by_key(|path| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (stamp, n) = stem.split_once('-').unwrap_or((&stem, "1"));
+        (stamp.to_string(), n.parse::<u32>().unwrap_or(1))
+    });
+    logs
+}
+
+/// A UTC timestamp such as `20240131T235959Z`.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -12,26 +12,17 @@
 //!
 //! See README.md for more details and examples.
 
-mod ast;
-mod completion;
-mod config;
-mod executor;
-mod interpreter;
-mod parser;
-mod repl;
-
+use devrun::{
+    completion, config, events, executor, fatal_error, interpreter, makefile, repl, shell, status,
+    style, timings,
+};
 use clap::Parser as ClapParser;
 use completion::Shell;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// Print an error message and exit with code 1.
-pub fn fatal_error(message: &str) -> ! {
-    eprintln!("{}", message);
-    std::process::exit(1);
-}
-
 /// CLI arguments for the run tool.
 #[derive(ClapParser)]
 #[command(name = "run")]
@@ -50,6 +41,34 @@ struct Cli {
     #[arg(short, long)]
     list: bool,
 
+    /// With --list, include the functions of every workspace member Runfile
+    #[arg(long, requires = "list")]
+    all: bool,
+
+    /// With --list, print the functions as text or as json for editors and tools
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<String>,
+
+    /// Print the shell commands run with by default and why it was chosen
+    #[arg(long)]
+    which_shell: bool,
+
+    /// Print every path searched for a Runfile and which one is used
+    #[arg(long)]
+    which_runfile: bool,
+
+    /// Print the namespace separators the Runfile configures (used by completions)
+    #[arg(long, hide = true)]
+    list_separators: bool,
+
+    /// Print the functions of the Runfile in or above the current directory (used by shell hooks)
+    #[arg(long, hide = true)]
+    hook_functions: bool,
+
+    /// Print the values the function was called with before for its next argument (used by completions)
+    #[arg(long, hide = true)]
+    complete_history: bool,
+
     /// Generate shell completion script
     #[arg(long, value_name = "SHELL")]
     generate_completion: Option<Shell>,
@@ -57,15 +76,129 @@ struct Cli {
     /// Install shell completion (automatically detects shell and updates config)
     #[arg(long, value_name = "SHELL")]
     install_completion: Option<Option<Shell>>,
+
+    /// With --install-completion, install for every user under /usr/share (all shells unless one is given)
+    #[arg(long, requires = "install_completion")]
+    system: bool,
+
+    /// Run tasks in their @container image when required tools are missing, without asking
+    #[arg(long)]
+    auto_container: bool,
+
+    /// Don't read from or write to the remote cache for this run
+    #[arg(long)]
+    no_remote_cache: bool,
+
+    /// Run up to N prerequisite functions at once (defaults to the number of CPUs)
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Run @danger and @prod functions without typing their name, as when RUN_ALLOW_DANGER is set
+    #[arg(long)]
+    allow_danger: bool,
+
+    /// Re-run functions even if their @inputs and @outputs are up to date
+    #[arg(long)]
+    force: bool,
+
+    /// Keep running independent functions when one fails, then report all failures
+    #[arg(short, long)]
+    keep_going: bool,
+
+    /// Continue the last chain of functions that failed or was interrupted, skipping those that completed
+    #[arg(long, conflicts_with_all = ["first_arg", "parallel", "split", "watch"])]
+    resume: bool,
+
+    /// Run the named functions concurrently, wait for all of them and fail if any fail
+    #[arg(long, requires = "first_arg")]
+    parallel: bool,
+
+    /// Run each named function in its own tmux or zellij pane, or Windows Terminal tab
+    #[arg(long, requires = "first_arg", conflicts_with_all = ["parallel", "watch"])]
+    split: bool,
+
+    /// Print where a function is defined, what it needs and its commands with variables substituted, without running it
+    #[arg(long, requires = "first_arg", conflicts_with_all = ["parallel", "split", "watch"])]
+    explain: bool,
+
+    /// How to show the output of prerequisites running in parallel
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = interpreter::OutputMode::Interleave)]
+    output: interpreter::OutputMode,
+
+    /// Emit machine-readable start/output/exit events for each function
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    events: Option<events::EventFormat>,
+
+    /// Write events to this file (e.g. /dev/fd/3) instead of stderr
+    #[arg(long, value_name = "PATH", requires = "events")]
+    events_file: Option<PathBuf>,
+
+    /// Show a status line with the running function, its command and elapsed time
+    #[arg(long)]
+    status: bool,
+
+    /// Report how long each function took, and what succeeded and failed, after the run
+    #[arg(long)]
+    timings: bool,
+
+    /// Apply the variables of this Runfile profile before calling the function
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Print the commands that would run, after substitution, without running them
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Print each command, after substitution, and the shell running it
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Run @confirm functions without asking, e.g. in CI
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Run the function again whenever files matching PATTERN change (repeatable)
+    #[arg(long, value_name = "PATTERN", requires = "first_arg", conflicts_with = "parallel")]
+    watch: Vec<String>,
+
+    /// Read only the project's Runfile, without the functions of ~/.runfile
+    #[arg(long)]
+    no_global: bool,
+
+    /// Call the rules of the Makefile in the current directory instead of Runfile functions
+    #[arg(long, conflicts_with = "which_runfile")]
+    from_makefile: bool,
+
+    /// Don't color output, as when NO_COLOR is set
+    #[arg(long)]
+    no_color: bool,
+
+    /// Print strictly line by line, without colors, status line, pager or symbols, as when RUN_PLAIN is set
+    #[arg(long)]
+    plain: bool,
+
+    /// Show output longer than the terminal through $PAGER, or a built-in pager
+    #[arg(long)]
+    paginate: bool,
 }
 
 /// Entry point for the CLI tool.
 fn main() {
     let cli = Cli::parse();
+    if cli.plain {
+        style::set_plain();
+    }
+    if cli.no_color {
+        style::disable();
+    }
 
     // Handle --install-completion flag
     if let Some(shell_opt) = cli.install_completion {
-        completion::install_completion_interactive(shell_opt, config::get_home_dir);
+        if cli.system {
+            completion::install_completion_system(shell_opt);
+        } else {
+            completion::install_completion_interactive(shell_opt, config::get_home_dir);
+        }
         return;
     }
 
@@ -75,22 +208,166 @@ fn main() {
         return;
     }
 
+    if cli.no_global {
+        config::disable_global();
+    }
+
+    if cli.from_makefile {
+        let dir = std::env::current_dir().unwrap_or_default();
+        match makefile::find(&dir) {
+            Some(path) => config::use_makefile(path),
+            None => fatal_error("Error: --from-makefile found no Makefile in the current directory"),
+        }
+        if cli.first_arg.is_none() && !cli.list && !cli.resume {
+            fatal_error("Error: --from-makefile needs a target to run, or --list");
+        }
+    }
+
     // Handle --list flag
     if cli.list {
-        executor::list_functions();
+        let format = match cli.format.as_deref() {
+            None | Some("text") => executor::ListFormat::Text,
+            Some("json") => executor::ListFormat::Json,
+            Some(other) => fatal_error(&format!(
+                "Error: unknown --list format '{}' (expected text or json)",
+                other
+            )),
+        };
+        executor::list_functions(cli.all, format);
+        return;
+    }
+    // Built-in subcommands such as `export` take a --format of their own
+    if cli.format.is_some() && (cli.first_arg.is_none() || cli.parallel) {
+        fatal_error("Error: --format needs --list");
+    }
+
+    if cli.which_runfile {
+        executor::which_runfile();
+        return;
+    }
+
+    if cli.which_shell {
+        let selection = shell::selection();
+        println!("{} ({})", selection.shell, selection.reason);
+        return;
+    }
+
+    if cli.list_separators {
+        executor::list_separators();
+        return;
+    }
+
+    if cli.hook_functions {
+        executor::hook_functions();
+        return;
+    }
+
+    // What the functions run in panes by --split need to run the same way
+    let mut forwarded: Vec<String> = Vec::new();
+    if let Some(profile) = &cli.profile {
+        forwarded.extend(["--profile".to_string(), profile.clone()]);
+    }
+    for (set, flag) in [
+        (cli.auto_container, "--auto-container"),
+        (cli.no_remote_cache, "--no-remote-cache"),
+        (cli.force, "--force"),
+        (cli.dry_run, "--dry-run"),
+        (cli.verbose, "--verbose"),
+        (cli.yes, "--yes"),
+        (cli.allow_danger, "--allow-danger"),
+        (cli.from_makefile, "--from-makefile"),
+        (cli.no_global, "--no-global"),
+        (cli.no_color, "--no-color"),
+        (cli.plain, "--plain"),
+    ] {
+        if set {
+            forwarded.push(flag.to_string());
+        }
+    }
+
+    let options = interpreter::Options {
+        auto_container: cli.auto_container,
+        no_remote_cache: cli.no_remote_cache,
+        jobs: cli.jobs,
+        force: cli.force,
+        keep_going: cli.keep_going,
+        output: cli.output,
+        events: cli.events.map(|format| {
+            let emitter = events::Emitter::open(format, cli.events_file.as_deref())
+                .unwrap_or_else(|e| fatal_error(&format!("Cannot open event stream: {}", e)));
+            std::sync::Arc::new(emitter)
+        }),
+        // The status line needs a terminal to redraw
+        status: (cli.status && std::io::stderr().is_terminal() && !style::plain())
+            .then(status::StatusLine::start),
+        timings: cli.timings.then(|| std::sync::Arc::new(timings::Report::start())),
+        profile: cli.profile,
+        dry_run: cli.dry_run,
+        verbose: cli.verbose,
+        yes: cli.yes,
+        allow_danger: cli.allow_danger,
+        // Paging is for reading at a terminal, not for pipes and files
+        paginate: cli.paginate && std::io::stdout().is_terminal(),
+    };
+
+    if cli.resume {
+        executor::resume(&options);
+        return;
+    }
+
+    if cli.explain
+        && let Some(first_arg) = &cli.first_arg
+    {
+        executor::explain(first_arg, &cli.args, &options);
+        return;
+    }
+
+    if cli.complete_history {
+        let mut words = cli.args;
+        words.splice(0..0, cli.first_arg);
+        executor::complete_history(&words, &options);
         return;
     }
 
     match cli.first_arg {
+        Some(first_arg) if cli.parallel => {
+            let mut names = cli.args;
+            names.insert(0, first_arg);
+            executor::run_parallel(&names, &options);
+        }
+        Some(first_arg) if cli.split => {
+            let mut names = cli.args;
+            names.insert(0, first_arg);
+            executor::run_split(&names, &forwarded, &options);
+        }
         Some(first_arg) => {
             // Check if it's a file that exists
             let path = PathBuf::from(&first_arg);
-            if path.exists() && path.is_file() {
+            // A Makefile's targets are often files themselves
+            if !cli.from_makefile && path.exists() && path.is_file() {
                 // File mode: read and execute script
-                executor::execute_file(&path);
+                executor::execute_file(&path, &options);
             } else {
-                // Function call mode: load config and call function with args
-                executor::run_function_call(&first_arg, &cli.args);
+                // Function call mode: load config and call function with args.
+                // clap drops a `--` after the function name, which marks the
+                // arguments as never naming a subcommand: keep it for dispatch
+                let raw: Vec<String> = std::env::args().collect();
+                let escaped = raw.iter().position(|arg| arg == "--").is_some_and(|i| {
+                    raw[i - 1] == first_arg && raw.len() - i - 1 == cli.args.len()
+                });
+                let mut args = cli.args;
+                // clap takes a --format right after the name for --list's
+                if let Some(format) = cli.format {
+                    args.splice(0..0, ["--format".to_string(), format]);
+                }
+                if escaped {
+                    args.insert(0, "--".to_string());
+                }
+                if !cli.watch.is_empty() {
+                    executor::watch_function_call(&cli.watch, &first_arg, &args, &options);
+                    return;
+                }
+                executor::run_function_call(&first_arg, &args, &options);
             }
         }
         None => {
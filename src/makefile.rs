@@ -0,0 +1,423 @@
+//! Reading a basic Makefile's rules as functions (`run --from-makefile`), so
+//! a project can call `run build` against its existing Makefile and move to
+//! a Runfile one target at a time.
+//!
+//! Each explicit rule becomes a function running its recipe, with the
+//! prerequisites that are rules themselves as its `needs(...)`. Variables
+//! (`=`, `:=`, `?=`, `+=`, `export`) and the automatic `$@`, `$<`, `$^` and
+//! `$+` are expanded as Make would. Pattern rules and special targets such
+//! as `.PHONY` are skipped; conditionals, includes, `define` and functions
+//! other than `$(shell ...)` are reported as unsupported.
+
+use crate::ast::{Attribute, Program, Statement};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The names Make looks for, in the order it tries them.
+const NAMES: [&str; 3] = ["GNUmakefile", "makefile", "Makefile"];
+
+/// Directives that change which lines apply, or pull in other files.
+const UNSUPPORTED: [&str; 13] = [
+    "ifeq", "ifneq", "ifdef", "ifndef", "else", "endif", "include", "-include", "sinclude",
+    "define", "endef", "override", "vpath",
+];
+
+/// Expansions nested deeper than this are taken to be a variable that
+/// refers to itself.
+const MAX_DEPTH: usize = 64;
+
+/// The Makefile in `dir` that Make would read, if there is one.
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Read the Makefile at `path` as a program of function definitions.
+pub fn load(path: &Path) -> Result<Program, String> {
+    let source =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    parse(&source).map_err(|e| format!("{}:{}", path.display(), e))
+}
+
+/// An explicit rule, merged from every line naming its target.
+struct Rule {
+    target: String,
+    prerequisites: Vec<String>,
+    /// Recipe lines with the line they start on, expanded once all the
+    /// variables are known.
+    recipe: Vec<(usize, String)>,
+    description: Option<String>,
+}
+
+/// The Makefile's variables, unexpanded, and which of them to export.
+#[derive(Default)]
+struct Variables {
+    values: HashMap<String, String>,
+    exported: Vec<String>,
+    export_all: bool,
+}
+
+/// Parse Makefile source; errors start with the line number.
+pub fn parse(source: &str) -> Result<Program, String> {
+    let mut variables = Variables::default();
+    let mut rules: Vec<Rule> = Vec::new();
+    // The rules recipe lines are added to, while inside one
+    let mut current: Option<Vec<usize>> = None;
+    // A `## text` comment waiting for the rule below it
+    let mut description: Option<String> = None;
+
+    for (number, line) in logical_lines(source) {
+        let fail = |message: String| format!("{}: {}", number, message);
+
+        if let Some(recipe) = line.strip_prefix('\t') {
+            match &current {
+                Some(indices) => {
+                    for &index in indices {
+                        rules[index].recipe.push((number, recipe.to_string()));
+                    }
+                }
+                None if recipe.trim().is_empty() || recipe.trim_start().starts_with('#') => {}
+                None => return Err(fail("recipe line outside a rule".to_string())),
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            description = comment
+                .strip_prefix('#')
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty());
+            continue;
+        }
+        if trimmed.is_empty() {
+            description = None;
+            continue;
+        }
+        current = None;
+        let pending = description.take();
+        let (text, comment) = match trimmed.find('#') {
+            Some(index) => (trimmed[..index].trim_end(), Some(&trimmed[index + 1..])),
+            None => (trimmed, None),
+        };
+
+        let first_word = text.split_whitespace().next().unwrap_or_default();
+        if UNSUPPORTED.contains(&first_word) {
+            return Err(fail(format!("'{}' is not supported", first_word)));
+        }
+        if first_word == "unexport" {
+            continue;
+        }
+        let (export, text) = match text.strip_prefix("export") {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                (true, rest.trim_start())
+            }
+            _ => (false, text),
+        };
+        if export && text.is_empty() {
+            variables.export_all = true;
+            continue;
+        }
+
+        if let Some((name, operator, value)) = assignment(text) {
+            set_variable(&mut variables, name, operator, value).map_err(fail)?;
+            if export {
+                variables.exported.push(name.to_string());
+            }
+            continue;
+        }
+        if export {
+            variables
+                .exported
+                .extend(text.split_whitespace().map(String::from));
+            continue;
+        }
+
+        let Some((targets, rest)) = text.split_once(':') else {
+            return Err(fail(format!(
+                "expected a rule or an assignment: '{}'",
+                text
+            )));
+        };
+        // Double-colon rules run like ordinary ones here
+        let rest = rest.strip_prefix(':').unwrap_or(rest);
+        let (prerequisites, inline) = match rest.split_once(';') {
+            Some((prerequisites, inline)) => (prerequisites, Some(inline.trim())),
+            None => (rest, None),
+        };
+        if prerequisites.contains('=') {
+            return Err(fail(
+                "target-specific variables are not supported".to_string(),
+            ));
+        }
+        let prerequisites: Vec<String> = expand(prerequisites, &variables, &[], 0)
+            .map_err(fail)?
+            .split_whitespace()
+            .filter(|prerequisite| *prerequisite != "|")
+            .map(String::from)
+            .collect();
+        let description = comment
+            .and_then(|comment| comment.strip_prefix('#'))
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+            .or(pending);
+
+        let mut indices = Vec::new();
+        for target in expand(targets, &variables, &[], 0)
+            .map_err(fail)?
+            .split_whitespace()
+        {
+            // Special targets such as .PHONY, and pattern rules
+            if target.starts_with('.') || target.contains('%') {
+                continue;
+            }
+            let index = match rules.iter().position(|rule| rule.target == target) {
+                Some(index) => index,
+                None => {
+                    rules.push(Rule {
+                        target: target.to_string(),
+                        prerequisites: Vec::new(),
+                        recipe: Vec::new(),
+                        description: None,
+                    });
+                    rules.len() - 1
+                }
+            };
+            let rule = &mut rules[index];
+            rule.prerequisites.extend(prerequisites.iter().cloned());
+            if description.is_some() {
+                rule.description.clone_from(&description);
+            }
+            if let Some(inline) = inline.filter(|inline| !inline.is_empty()) {
+                rule.recipe.push((number, inline.to_string()));
+            }
+            indices.push(index);
+        }
+        current = Some(indices);
+    }
+
+    let names: Vec<&str> = rules.iter().map(|rule| rule.target.as_str()).collect();
+    let mut exported_names = deduplicated(&variables.exported);
+    if variables.export_all {
+        exported_names = variables.values.keys().cloned().collect();
+        exported_names.sort();
+    }
+    let exported: Vec<(String, String)> = exported_names
+        .into_iter()
+        .filter_map(|name| {
+            let value = variables.values.get(&name)?;
+            Some(expand(value, &variables, &[], 0).map(|value| (name, value)))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut statements = Vec::new();
+    for rule in &rules {
+        let mut commands = Vec::new();
+        for (number, line) in &rule.recipe {
+            let automatic = [
+                ("@", rule.target.clone()),
+                ("<", rule.prerequisites.first().cloned().unwrap_or_default()),
+                ("^", deduplicated(&rule.prerequisites).join(" ")),
+                ("+", rule.prerequisites.join(" ")),
+            ];
+            let command = line.trim_start_matches([' ', '\t']);
+            // `@` (don't echo) and `+` don't apply; `-` (carry on past a
+            // failure) means the same in a Runfile
+            let flags = command.len() - command.trim_start_matches(['@', '-', '+', ' ']).len();
+            let ignore = command[..flags].contains('-');
+            let command = expand(command[flags..].trim(), &variables, &automatic, 0)
+                .map_err(|e| format!("{}: {}", number, e))?;
+            if command.is_empty() {
+                continue;
+            }
+            commands.push(if ignore {
+                format!("-{}", command)
+            } else {
+                command
+            });
+        }
+
+        let mut attributes = Vec::new();
+        if let Some(description) = &rule.description {
+            attributes.push(Attribute::Description(description.clone()));
+        }
+        let needs: Vec<String> = deduplicated(&rule.prerequisites)
+            .into_iter()
+            .filter(|prerequisite| names.contains(&prerequisite.as_str()))
+            .collect();
+        if !needs.is_empty() {
+            attributes.push(Attribute::Needs(needs));
+        }
+        if !exported.is_empty() {
+            attributes.push(Attribute::Env(exported.clone()));
+        }
+        statements.push(Statement::BlockFunctionDef {
+            name: rule.target.clone(),
+            commands,
+            attributes,
+        });
+    }
+    Ok(Program { statements })
+}
+
+/// The lines of `source` with their line numbers, backslash-continued lines
+/// joined: with a space outside recipes, and as the shell would join them
+/// inside one.
+fn logical_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (index, line) in source.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (number, text) = match pending.take() {
+            Some((number, mut text)) if text.starts_with('\t') => {
+                text.push_str(line.strip_prefix('\t').unwrap_or(line));
+                (number, text)
+            }
+            Some((number, mut text)) => {
+                text.push(' ');
+                text.push_str(line.trim_start());
+                (number, text)
+            }
+            None => (index + 1, line.to_string()),
+        };
+        match text.strip_suffix('\\') {
+            Some(joined) => pending = Some((number, joined.to_string())),
+            None => lines.push((number, text)),
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+/// Split `NAME op value` for the assignment operators `=`, `:=`, `::=`,
+/// `?=`, `+=` and `!=`, unless a `:` before the `=` makes it a rule.
+fn assignment(text: &str) -> Option<(&str, &str, &str)> {
+    let equals = text.find('=')?;
+    let before = &text[..equals];
+    let operator_start = before.trim_end_matches([':', '?', '+', '!']).len();
+    let (name, operator) = before.split_at(operator_start);
+    if name.contains(':') || name.trim().is_empty() {
+        return None;
+    }
+    Some((name.trim(), operator, text[equals + 1..].trim()))
+}
+
+fn set_variable(
+    variables: &mut Variables,
+    name: &str,
+    operator: &str,
+    value: &str,
+) -> Result<(), String> {
+    let value = match operator {
+        "" => value.to_string(),
+        // Expanded now; `$$` keeps the result from expanding again on use
+        ":" | "::" => expand(value, variables, &[], 0)?.replace('$', "$$"),
+        "?" if variables.values.contains_key(name) || std::env::var_os(name).is_some() => {
+            return Ok(());
+        }
+        "?" => value.to_string(),
+        "+" => match variables.values.get(name) {
+            Some(existing) => format!("{} {}", existing, value),
+            None => value.to_string(),
+        },
+        _ => {
+            return Err(format!(
+                "the '{}=' assignment of {} is not supported",
+                operator, name
+            ));
+        }
+    };
+    variables.values.insert(name.to_string(), value);
+    Ok(())
+}
+
+/// Expand the variable references in `text`: `$(NAME)`, `${NAME}`, `$X`,
+/// `$$` and `$(shell command)`, which becomes a command substitution.
+/// Variables not in the Makefile are read from the environment.
+fn expand(
+    text: &str,
+    variables: &Variables,
+    automatic: &[(&str, String)],
+    depth: usize,
+) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("a variable refers to itself in '{}'", text));
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(dollar) = rest.find('$') {
+        result.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+        let Some(next) = rest.chars().next() else {
+            result.push('$');
+            break;
+        };
+        let name = match next {
+            '$' => {
+                result.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+            '(' | '{' => {
+                let close = if next == '(' { ')' } else { '}' };
+                let end = matching(rest, next, close)
+                    .ok_or_else(|| format!("unterminated reference in '{}'", text))?;
+                let inner = &rest[1..end];
+                rest = &rest[end + 1..];
+                if let Some(command) = inner.strip_prefix("shell ") {
+                    let command = expand(command.trim(), variables, automatic, depth + 1)?;
+                    result.push_str(&format!("$({})", command));
+                    continue;
+                }
+                if inner.contains([' ', '\t', ',', ':', '=']) {
+                    return Err(format!("'${}{}{}' is not supported", next, inner, close));
+                }
+                expand(inner, variables, automatic, depth + 1)?
+            }
+            _ => {
+                rest = &rest[next.len_utf8()..];
+                next.to_string()
+            }
+        };
+        if let Some((_, value)) = automatic.iter().find(|(automatic, _)| *automatic == name) {
+            result.push_str(value);
+        } else if matches!(name.as_str(), "?" | "*" | "|" | "%") {
+            return Err(format!("'${}' is not supported", name));
+        } else if let Some(value) = variables.values.get(&name) {
+            result.push_str(&expand(value, variables, automatic, depth + 1)?);
+        } else if let Ok(value) = std::env::var(&name) {
+            result.push_str(&value);
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The index in `text`, which starts with `open`, of the `close` matching it.
+fn matching(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+fn deduplicated(values: &[String]) -> Vec<String> {
+    let mut seen = Vec::new();
+    for value in values {
+        if !seen.contains(value) {
+            seen.push(value.clone());
+        }
+    }
+    seen
+}
@@ -0,0 +1,68 @@
+//! Expansion of `@matrix` functions into one variant per combination of
+//! axis values, e.g. `build[target=aarch64,profile=release]`.
+
+use crate::ast::MatrixAxis;
+use std::collections::HashMap;
+
+/// The value of each axis in one variant, in axis order.
+pub type Variant = Vec<(String, String)>;
+
+/// The function and variant behind each label of an expanded plan.
+pub type Labels = HashMap<String, (String, Variant)>;
+
+/// Split `name=value` arguments that select values of one of `axes` from
+/// the arguments passed on to the function.
+pub fn split_selection(axes: &[MatrixAxis], args: &[String]) -> (Variant, Vec<String>) {
+    let mut selection = Variant::new();
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if axes.iter().any(|axis| axis.name == name) => {
+                selection.push((name.to_string(), value.to_string()));
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    (selection, rest)
+}
+
+/// Every combination of axis values that agrees with `selection`, varying
+/// the last axis fastest.
+pub fn variants(axes: &[MatrixAxis], selection: &[(String, String)]) -> Vec<Variant> {
+    let mut variants = vec![Variant::new()];
+    for axis in axes {
+        let values: Vec<&String> = axis
+            .values
+            .iter()
+            .filter(|value| {
+                selection
+                    .iter()
+                    .filter(|(name, _)| *name == axis.name)
+                    .all(|(_, selected)| selected == *value)
+            })
+            .collect();
+        variants = variants
+            .into_iter()
+            .flat_map(|variant| {
+                values.iter().map(move |value| {
+                    let mut variant = variant.clone();
+                    variant.push((axis.name.clone(), value.to_string()));
+                    variant
+                })
+            })
+            .collect();
+    }
+    variants
+}
+
+/// How a variant of function `name` is shown in output and events.
+pub fn label(name: &str, variant: &[(String, String)]) -> String {
+    if variant.is_empty() {
+        return name.to_string();
+    }
+    let values: Vec<String> = variant
+        .iter()
+        .map(|(axis, value)| format!("{}={}", axis, value))
+        .collect();
+    format!("{}[{}]", name, values.join(","))
+}
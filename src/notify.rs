@@ -0,0 +1,109 @@
+//! Webhook notifications when functions finish (`@notify-on`).
+//!
+//! The payload is a JSON object with the task, its status, duration, host and
+//! the last lines of its output. A `text` summary is included so it can be
+//! posted straight to Slack-style incoming webhooks.
+
+use crate::ast::NotifyOn;
+use crate::events::json_string;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Number of output lines included in a notification.
+pub const TAIL_LINES: usize = 20;
+
+/// The last lines a function wrote to stdout or stderr.
+#[derive(Debug, Default)]
+pub struct OutputTail {
+    lines: VecDeque<String>,
+}
+
+impl OutputTail {
+    pub fn push(&mut self, line: &str) {
+        if self.lines.len() == TAIL_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+    }
+
+    pub fn text(&self) -> String {
+        self.lines.iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl NotifyOn {
+    /// Whether a function finishing with this outcome should notify.
+    pub fn matches(self, succeeded: bool) -> bool {
+        match self {
+            NotifyOn::Always => true,
+            NotifyOn::Success => succeeded,
+            NotifyOn::Failure => !succeeded,
+        }
+    }
+}
+
+/// POST the outcome of `task` to `url`. Failures to deliver are reported as
+/// warnings; they never change the outcome of the run.
+pub fn send(url: &str, task: &str, succeeded: bool, duration: Duration, tail: &str) {
+    let status = if succeeded { "success" } else { "failure" };
+    let host = hostname();
+    let text = format!(
+        "{} {} on {} after {:.1}s",
+        task,
+        if succeeded { "succeeded" } else { "failed" },
+        host,
+        duration.as_secs_f64()
+    );
+    let payload = format!(
+        "{{\"text\":{},\"task\":{},\"status\":{},\"duration_ms\":{},\"host\":{},\"output_tail\":{}}}",
+        json_string(&text),
+        json_string(task),
+        json_string(status),
+        duration.as_millis(),
+        json_string(&host),
+        json_string(tail)
+    );
+
+    let result = Command::new("curl")
+        .args(["-fsS", "--max-time", "10", "-X", "POST"])
+        .args([
+            "-H",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+        ])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(payload.as_bytes())?;
+            }
+            child.wait()
+        });
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Warning: webhook for '{}' failed ({})", task, status),
+        Err(e) => eprintln!("Warning: could not send webhook for '{}': {}", task, e),
+    }
+}
+
+/// The name of this machine.
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the buffer is valid for its full length
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+        if result == 0 {
+            let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+            return String::from_utf8_lossy(&buffer[..end]).into_owned();
+        }
+    }
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
@@ -0,0 +1,260 @@
+//! The `scripts` of the `package.json` next to the Runfile, which
+//! `commands.package_json = true` makes callable like functions: `run dev`
+//! runs `npm run dev` when no function is called `dev`, or `pnpm`, `yarn` or
+//! `bun` when their lockfile is there instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Package managers and the lockfile that shows a project uses them.
+const MANAGERS: [(&str, &str); 4] = [
+    ("pnpm", "pnpm-lock.yaml"),
+    ("yarn", "yarn.lock"),
+    ("bun", "bun.lockb"),
+    ("bun", "bun.lock"),
+];
+
+pub struct Scripts {
+    dir: PathBuf,
+    /// The package manager that runs them.
+    pub manager: &'static str,
+    /// Each script's name and command, in the order they are written.
+    pub scripts: Vec<(String, String)>,
+}
+
+/// The scripts of `dir/package.json`, or `None` if there is no such file.
+pub fn load(dir: &Path) -> Result<Option<Scripts>, String> {
+    let path = dir.join("package.json");
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+    };
+    let package = Parser::new(&text)
+        .document()
+        .map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    let scripts = match package.get("scripts") {
+        Some(Json::Object(scripts)) => scripts
+            .iter()
+            .filter_map(|(name, command)| match command {
+                Json::String(command) => Some((name.clone(), command.clone())),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let manager = MANAGERS
+        .iter()
+        .find(|(_, lockfile)| dir.join(lockfile).exists())
+        .map_or("npm", |(manager, _)| manager);
+    Ok(Some(Scripts {
+        dir: dir.to_path_buf(),
+        manager,
+        scripts,
+    }))
+}
+
+impl Scripts {
+    /// The command of the script called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.scripts
+            .iter()
+            .find(|(script, _)| script == name)
+            .map(|(_, command)| command.as_str())
+    }
+
+    /// The words of the command line running script `name` with `args`.
+    /// npm needs a `--` before arguments for the script.
+    pub fn command_line(&self, name: &str, args: &[String]) -> Vec<String> {
+        let mut words = vec![
+            self.manager.to_string(),
+            "run".to_string(),
+            name.to_string(),
+        ];
+        if self.manager == "npm" && !args.is_empty() {
+            words.push("--".to_string());
+        }
+        words.extend(args.iter().cloned());
+        words
+    }
+
+    /// Run script `name` with `args` in the package's directory, returning
+    /// its exit code.
+    pub fn run(&self, name: &str, args: &[String]) -> Result<i32, String> {
+        let words = self.command_line(name, args);
+        // Resolved first, so `npm.cmd` is found on Windows
+        let program = which::which(self.manager).map_err(|_| {
+            format!(
+                "{} is needed to run '{}' but isn't on PATH",
+                self.manager, name
+            )
+        })?;
+        let status = Command::new(program)
+            .args(&words[1..])
+            .current_dir(&self.dir)
+            .status()
+            .map_err(|e| format!("Cannot run {}: {}", self.manager, e))?;
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+/// The parts of a JSON value that matter here.
+enum Json {
+    Object(Vec<(String, Json)>),
+    String(String),
+    /// Arrays, numbers, booleans and null.
+    Other,
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Parser<'a> {
+        Parser {
+            chars: text.char_indices().peekable(),
+        }
+    }
+
+    /// A whole document: one value and nothing after it.
+    fn document(&mut self) -> Result<Json, String> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        match self.chars.next() {
+            None => Ok(value),
+            Some((at, c)) => Err(format!("unexpected '{}' at byte {}", c, at)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((at, c)) => Err(format!(
+                "expected '{}' at byte {}, found '{}'",
+                expected, at, c
+            )),
+            None => Err(format!("expected '{}', found the end", expected)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some((_, '{')) => self.object(),
+            Some((_, '[')) => {
+                self.chars.next();
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == ']').is_none() {
+                    loop {
+                        self.value()?;
+                        self.skip_whitespace();
+                        match self.chars.next() {
+                            Some((_, ',')) => continue,
+                            Some((_, ']')) => break,
+                            _ => return Err("unterminated array".to_string()),
+                        }
+                    }
+                }
+                Ok(Json::Other)
+            }
+            Some((_, '"')) => self.string().map(Json::String),
+            Some((_, c)) if c == '-' || c.is_ascii_alphanumeric() => {
+                // Numbers, true, false and null
+                while self
+                    .chars
+                    .next_if(|(_, c)| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+                    .is_some()
+                {}
+                Ok(Json::Other)
+            }
+            Some((at, c)) => Err(format!("unexpected '{}' at byte {}", c, at)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(Json::Object(entries)),
+                _ => return Err("unterminated object".to_string()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(result),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, 'n')) => result.push('\n'),
+                    Some((_, 't')) => result.push('\t'),
+                    Some((_, 'r')) => result.push('\r'),
+                    Some((_, 'b')) => result.push('\u{8}'),
+                    Some((_, 'f')) => result.push('\u{c}'),
+                    Some((_, 'u')) => {
+                        let mut code = self.hex4()?;
+                        // A surrogate pair encodes one character
+                        if (0xd800..0xdc00).contains(&code)
+                            && self.chars.next_if(|(_, c)| *c == '\\').is_some()
+                            && self.chars.next_if(|(_, c)| *c == 'u').is_some()
+                        {
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00));
+                        }
+                        result.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    Some((_, c)) => result.push(c),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some((_, c)) => result.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or("invalid \\u escape")?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
@@ -0,0 +1,221 @@
+//! Package manifests for distributing this version of `run`
+//! (`run export --format brew-formula` / `scoop-manifest`).
+//!
+//! They point at the archives the release workflow uploads,
+//! `devrun-<target>.tar.gz` (`.zip` for Windows), with SHA-256 checksums taken
+//! from local copies of the archives when given, or else downloaded.
+
+use crate::artifacts::{hash_file, to_hex};
+use crate::events::json_string;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+const REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
+const LICENSE: &str = env!("CARGO_PKG_LICENSE");
+
+/// A package manifest format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A Homebrew formula for macOS and Linux.
+    BrewFormula,
+    /// A Scoop app manifest for Windows.
+    ScoopManifest,
+}
+
+impl Format {
+    /// The format called `name` on the command line.
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "brew-formula" => Some(Format::BrewFormula),
+            "scoop-manifest" => Some(Format::ScoopManifest),
+            _ => None,
+        }
+    }
+
+    /// The release targets the manifest installs.
+    fn targets(self) -> &'static [&'static str] {
+        match self {
+            Format::BrewFormula => &[
+                "aarch64-apple-darwin",
+                "x86_64-apple-darwin",
+                "x86_64-unknown-linux-musl",
+            ],
+            Format::ScoopManifest => &["x86_64-pc-windows-msvc"],
+        }
+    }
+}
+
+/// A release archive.
+struct Asset {
+    url: String,
+    sha256: String,
+}
+
+/// The manifest in `format` for this version, with checksums of the archives
+/// in `artifacts` (as laid out by the release workflow, or all in one
+/// directory), downloading those that are not there.
+pub fn generate(format: Format, artifacts: Option<&Path>) -> Result<String, String> {
+    let mut assets = Vec::new();
+    for target in format.targets() {
+        let archive = archive_name(target);
+        let url = format!("{}/releases/download/v{}/{}", REPOSITORY, VERSION, archive);
+        let sha256 = match artifacts.and_then(|dir| local_archive(dir, target)) {
+            Some(path) => {
+                hash_file(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?
+            }
+            None => download_checksum(&url)?,
+        };
+        assets.push(Asset { url, sha256 });
+    }
+    Ok(match format {
+        Format::BrewFormula => brew_formula(&assets),
+        Format::ScoopManifest => scoop_manifest(&assets[0]),
+    })
+}
+
+fn archive_name(target: &str) -> String {
+    let extension = if target.contains("windows") {
+        "zip"
+    } else {
+        "tar.gz"
+    };
+    format!("{}-{}.{}", NAME, target, extension)
+}
+
+/// The archive for `target` in `dir`, directly or in the directory the
+/// release workflow downloads each artifact to.
+fn local_archive(dir: &Path, target: &str) -> Option<std::path::PathBuf> {
+    let archive = archive_name(target);
+    [
+        dir.join(&archive),
+        dir.join(format!("{}-{}", NAME, target)).join(&archive),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+}
+
+fn download_checksum(url: &str) -> Result<String, String> {
+    let output = Command::new("curl")
+        .args(["-fsSL", url])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Cannot download {}: could not run curl: {}", url, e))?;
+    if !output.status.success() {
+        return Err(format!("Cannot download {} ({})", url, output.status));
+    }
+    Ok(to_hex(&Sha256::digest(&output.stdout)))
+}
+
+fn brew_formula(assets: &[Asset]) -> String {
+    let class: String = NAME
+        .split(['-', '_'])
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect();
+    let source = |asset: &Asset| {
+        format!(
+            "      url \"{}\"\n      sha256 \"{}\"\n",
+            asset.url, asset.sha256
+        )
+    };
+    format!(
+        r##"class {class} < Formula
+  desc "{desc}"
+  homepage "{homepage}"
+  version "{version}"
+  license "{license}"
+
+  on_macos do
+    on_arm do
+{arm_mac}    end
+    on_intel do
+{intel_mac}    end
+  end
+
+  on_linux do
+    on_intel do
+{intel_linux}    end
+  end
+
+  def install
+    bin.install "run"
+    generate_completions_from_executable(bin/"run", "--generate-completion", shells: [:bash, :zsh, :fish])
+  end
+
+  test do
+    assert_match version.to_s, shell_output("#{{bin}}/run --version")
+  end
+end
+"##,
+        class = class,
+        desc = brew_description(),
+        homepage = REPOSITORY,
+        version = VERSION,
+        license = LICENSE,
+        arm_mac = source(&assets[0]),
+        intel_mac = source(&assets[1]),
+        intel_linux = source(&assets[2]),
+    )
+}
+
+/// The package description as `brew audit` wants it: no leading article
+/// or trailing period.
+fn brew_description() -> String {
+    let description = DESCRIPTION.trim_end_matches('.');
+    let description = ["A ", "An ", "The "]
+        .iter()
+        .find_map(|article| description.strip_prefix(article))
+        .unwrap_or(description);
+    let mut chars = description.chars();
+    let first = chars.next().map(|c| c.to_ascii_uppercase());
+    first
+        .into_iter()
+        .chain(chars)
+        .collect::<String>()
+        .replace('"', "\\\"")
+}
+
+fn scoop_manifest(asset: &Asset) -> String {
+    let autoupdate_url = asset.url.replace(&format!("v{}", VERSION), "v$version");
+    format!(
+        r#"{{
+    "version": {version},
+    "description": {description},
+    "homepage": {homepage},
+    "license": {license},
+    "architecture": {{
+        "64bit": {{
+            "url": {url},
+            "hash": {hash}
+        }}
+    }},
+    "bin": "run.exe",
+    "checkver": "github",
+    "autoupdate": {{
+        "architecture": {{
+            "64bit": {{
+                "url": {autoupdate_url}
+            }}
+        }}
+    }}
+}}
+"#,
+        version = json_string(VERSION),
+        description = json_string(DESCRIPTION),
+        homepage = json_string(REPOSITORY),
+        license = json_string(LICENSE),
+        url = json_string(&asset.url),
+        hash = json_string(&asset.sha256),
+        autoupdate_url = json_string(&autoupdate_url),
+    )
+}
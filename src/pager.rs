@@ -0,0 +1,304 @@
+//! Paging of long output with `--paginate`: output that doesn't fit on the
+//! terminal is shown through `$PAGER`, or a built-in pager when it isn't
+//! set, instead of scrolling past.
+//!
+//! The built-in pager scrolls with the arrow keys, Space/b and g/G, searches
+//! with `/` (`n` and `N` go to the next and previous match) and quits with
+//! `q`. It needs a Unix terminal; elsewhere, output is printed as it is.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Show `output`, through a pager if it is longer than the terminal, unless
+/// output is plain.
+pub fn show(output: &[u8]) {
+    if crate::style::plain() {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(output).and_then(|_| stdout.flush());
+        return;
+    }
+    let (width, height) = terminal_size();
+    let text = String::from_utf8_lossy(output);
+    let rows = wrap(&text, width);
+    if height > 1 && rows.len() >= height {
+        if let Some(pager) = std::env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+        {
+            if external(&pager, output).is_ok() {
+                return;
+            }
+        } else {
+            #[cfg(unix)]
+            if std::io::IsTerminal::is_terminal(&io::stdin())
+                && builtin::page(&rows, height).is_ok()
+            {
+                return;
+            }
+        }
+    }
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(output).and_then(|_| stdout.flush());
+}
+
+/// Run `pager`, a command line such as `less -R`, with `output` as its input.
+fn external(pager: &str, output: &[u8]) -> io::Result<()> {
+    let shell = crate::shell::default_shell();
+    let mut child = Command::new(&shell)
+        .arg(crate::shell::command_flag(&shell))
+        .arg(pager)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may quit before reading everything
+        let _ = stdin.write_all(output);
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Columns and rows of the terminal on stdout.
+fn terminal_size() -> (usize, usize) {
+    #[cfg(unix)]
+    {
+        // SAFETY: winsize is plain data, valid when zeroed
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: TIOCGWINSZ writes a winsize through the valid pointer
+        let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 && size.ws_row > 0 {
+            return (size.ws_col as usize, size.ws_row as usize);
+        }
+    }
+    let variable = |name: &str, default| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|&value| value > 0)
+            .unwrap_or(default)
+    };
+    (variable("COLUMNS", 80), variable("LINES", 24))
+}
+
+/// The lines of `text` broken into rows of at most `width` columns. Color
+/// escape sequences are kept and take no room; tabs are expanded.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    for line in text.lines() {
+        let mut row = String::new();
+        let mut columns = 0;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    row.push(c);
+                    // Up to the final byte of a CSI sequence such as `ESC [ 1 m`
+                    if chars.next_if_eq(&'[').is_some() {
+                        row.push('[');
+                        for c in chars.by_ref() {
+                            row.push(c);
+                            if ('@'..='~').contains(&c) {
+                                break;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                '\r' => continue,
+                '\t' => {
+                    let spaces = 8 - columns % 8;
+                    row.extend(std::iter::repeat_n(' ', spaces.min(width - columns)));
+                    columns += spaces;
+                }
+                c => {
+                    row.push(c);
+                    columns += 1;
+                }
+            }
+            if columns >= width {
+                rows.push(std::mem::take(&mut row));
+                columns = 0;
+            }
+        }
+        if columns > 0 || row.is_empty() || rows.is_empty() {
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+/// `row` without its escape sequences, as it reads on screen.
+#[cfg(unix)]
+fn plain(row: &str) -> String {
+    let mut text = String::with_capacity(row.len());
+    let mut chars = row.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        text.push(c);
+    }
+    text
+}
+
+#[cfg(unix)]
+mod builtin {
+    use super::plain;
+    use crate::readline::{Key, RawMode, read_key};
+    use std::io::{self, Read, Write};
+
+    /// Show `rows` a screen of `height` rows at a time until `q` is pressed.
+    pub fn page(rows: &[String], height: usize) -> io::Result<()> {
+        let _raw = RawMode::enable()?;
+        let mut out = io::stdout().lock();
+        // Draw on the alternate screen, leaving the terminal as it was
+        write!(out, "\x1b[?1049h")?;
+        let result = browse(rows, height - 1, &mut io::stdin().lock(), &mut out);
+        write!(out, "\x1b[?1049l")?;
+        out.flush()?;
+        result
+    }
+
+    /// Handle keys until `q`, showing `screen` rows at a time above the prompt.
+    fn browse(
+        rows: &[String],
+        screen: usize,
+        input: &mut impl Read,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let bottom = rows.len().saturating_sub(screen);
+        let mut top = 0;
+        let mut pattern = String::new();
+        // The row of the last match, where `n` and `N` continue from
+        let mut found = 0;
+        let mut message = "";
+        loop {
+            draw(out, &rows[top..], screen, &pattern, message, top == bottom)?;
+            message = "";
+            let Some(key) = read_key(input)? else {
+                return Ok(());
+            };
+            let search = match key {
+                Key::Char('q') | Key::Ctrl(b'c') => return Ok(()),
+                Key::Enter | Key::Down | Key::Char('j') => {
+                    top = (top + 1).min(bottom);
+                    None
+                }
+                Key::Up | Key::Char('k') => {
+                    top = top.saturating_sub(1);
+                    None
+                }
+                Key::Char(' ' | 'f') | Key::PageDown => {
+                    top = (top + screen).min(bottom);
+                    None
+                }
+                Key::Char('b') | Key::PageUp => {
+                    top = top.saturating_sub(screen);
+                    None
+                }
+                Key::Char('g') | Key::Home => {
+                    top = 0;
+                    None
+                }
+                Key::Char('G') | Key::End => {
+                    top = bottom;
+                    None
+                }
+                Key::Char('/') => match read_pattern(input, out, screen)? {
+                    Some(typed) if !typed.is_empty() => {
+                        pattern = typed;
+                        Some(search(rows, &pattern, top, true))
+                    }
+                    _ => None,
+                },
+                Key::Char('n') if !pattern.is_empty() => {
+                    Some(search(rows, &pattern, found + 1, true))
+                }
+                Key::Char('N') if !pattern.is_empty() => Some(search(rows, &pattern, found, false)),
+                _ => None,
+            };
+            match search {
+                Some(Some(index)) => {
+                    found = index;
+                    top = index.min(bottom);
+                }
+                Some(None) => message = "Pattern not found",
+                None => {}
+            }
+        }
+    }
+
+    /// The first row containing `pattern` from `start` on, or the last one
+    /// before it when going backwards.
+    fn search(rows: &[String], pattern: &str, start: usize, forward: bool) -> Option<usize> {
+        let matches = |index: &usize| plain(&rows[*index]).contains(pattern);
+        if forward {
+            (start..rows.len()).find(matches)
+        } else {
+            (0..start).rev().find(matches)
+        }
+    }
+
+    /// Draw the first `screen` of `rows`, with matches of `pattern`
+    /// highlighted, and the prompt below them.
+    fn draw(
+        out: &mut impl Write,
+        rows: &[String],
+        screen: usize,
+        pattern: &str,
+        message: &str,
+        end: bool,
+    ) -> io::Result<()> {
+        write!(out, "\x1b[H")?;
+        for index in 0..screen {
+            match rows.get(index) {
+                // Rows with colors of their own are left as they are
+                Some(row) if !pattern.is_empty() && !row.contains('\x1b') => {
+                    let highlighted = format!("\x1b[7m{}\x1b[0m", pattern);
+                    write!(out, "{}", row.replace(pattern, &highlighted))?;
+                }
+                Some(row) => write!(out, "{}\x1b[0m", row)?,
+                None => write!(out, "~")?,
+            }
+            write!(out, "\x1b[K\r\n")?;
+        }
+        let prompt = match (message, end) {
+            ("", true) => "(END)",
+            ("", false) => ":",
+            (message, _) => message,
+        };
+        write!(out, "\x1b[7m{}\x1b[0m\x1b[K", prompt)?;
+        out.flush()
+    }
+
+    /// Read a search pattern typed after `/` on the prompt row, or `None`
+    /// when the search is abandoned.
+    fn read_pattern(
+        input: &mut impl Read,
+        out: &mut impl Write,
+        screen: usize,
+    ) -> io::Result<Option<String>> {
+        let mut pattern = String::new();
+        loop {
+            write!(out, "\x1b[{};1H/{}\x1b[K", screen + 1, pattern)?;
+            out.flush()?;
+            match read_key(input)? {
+                Some(Key::Enter) => return Ok(Some(pattern)),
+                Some(Key::Char(c)) => pattern.push(c),
+                Some(Key::Backspace) if !pattern.is_empty() => {
+                    pattern.pop();
+                }
+                Some(Key::Backspace) | Some(Key::Ctrl(b'c')) | Some(Key::Unknown) | None => {
+                    return Ok(None);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
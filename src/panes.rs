@@ -0,0 +1,96 @@
+//! Panes of the terminal multiplexer `run` was started in, for `--split`:
+//! each function runs in a pane of its own in tmux or zellij, or a tab of
+//! its own in Windows Terminal, so long-lived tasks such as a watcher and a
+//! server keep their output apart and can be stopped one at a time.
+
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplexer {
+    Tmux,
+    Zellij,
+    WindowsTerminal,
+}
+
+/// The multiplexer the current terminal is running in, found from the
+/// variables each one sets for the programs inside it.
+pub fn detect() -> Option<Multiplexer> {
+    let set = |name| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if set("TMUX") {
+        Some(Multiplexer::Tmux)
+    } else if set("ZELLIJ") {
+        Some(Multiplexer::Zellij)
+    } else if set("WT_SESSION") {
+        Some(Multiplexer::WindowsTerminal)
+    } else {
+        None
+    }
+}
+
+impl Multiplexer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::Zellij => "zellij",
+            Multiplexer::WindowsTerminal => "Windows Terminal",
+        }
+    }
+
+    /// The command opening a pane called `title` that runs `argv` in `cwd`.
+    /// Panes stay open after the command exits, showing how it ended, except
+    /// in Windows Terminal, where the profile's `closeOnExit` decides.
+    fn open_command(self, title: &str, argv: &[String], cwd: &Path) -> Command {
+        let mut command;
+        match self {
+            Multiplexer::Tmux => {
+                command = Command::new("tmux");
+                command.arg("split-window");
+                // Split the pane `run` was started in, wherever the focus is
+                if let Some(pane) = std::env::var_os("TMUX_PANE") {
+                    command.arg("-t").arg(pane);
+                }
+                command.arg("-c").arg(cwd).args(argv);
+                // The new pane is the current one for the rest of the sequence
+                command.args([";", "set-option", "-p", "remain-on-exit", "on"]);
+                command.args([";", "select-pane", "-T", title]);
+                command.args([";", "select-layout", "tiled"]);
+                command.args([";", "last-pane"]);
+            }
+            Multiplexer::Zellij => {
+                command = Command::new("zellij");
+                command
+                    .args(["run", "--name", title, "--cwd"])
+                    .arg(cwd)
+                    .arg("--")
+                    .args(argv);
+            }
+            Multiplexer::WindowsTerminal => {
+                command = Command::new("wt");
+                command
+                    .args(["-w", "0", "new-tab", "--title", title, "-d"])
+                    .arg(cwd)
+                    .args(argv);
+            }
+        }
+        command
+    }
+
+    /// Open a pane called `title` running `argv` in `cwd`.
+    pub fn open(self, title: &str, argv: &[String], cwd: &Path) -> Result<(), String> {
+        let output = self
+            .open_command(title, argv, cwd)
+            .output()
+            .map_err(|e| format!("Cannot run {}: {}", self.name(), e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "{} could not open a pane for '{}': {}",
+                self.name(),
+                title,
+                stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+}
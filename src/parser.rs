@@ -1,7 +1,12 @@
-// Parser implementation using pest
+//! The parser of the Runfile language, built with pest from `grammar.pest`.
 
-use crate::ast::{Expression, Program, Statement};
+use crate::ast::{
+    Attribute, Condition, ExitAction, Expression, FileMode, MatrixAxis, ModeChange, ModeOp,
+    NotifyOn, ParamDefault, Parameter, Program, Statement, Supervision, ToolRequirement,
+    VersionConstraint, VersionOp,
+};
 use pest::Parser;
+use pest::iterators::Pairs;
 use pest_derive::Parser;
 
 #[derive(Parser)]
@@ -34,6 +39,7 @@ fn preprocess_escaped_newlines(input: &str) -> String {
     result
 }
 
+/// Parse the text of a Runfile into its statements.
 pub fn parse_script(input: &str) -> Result<Program, Box<pest::error::Error<Rule>>> {
     let preprocessed = preprocess_escaped_newlines(input);
     let pairs = ScriptParser::parse(Rule::program, &preprocessed)?;
@@ -41,31 +47,521 @@ pub fn parse_script(input: &str) -> Result<Program, Box<pest::error::Error<Rule>
 
     for pair in pairs {
         match pair.as_rule() {
-            Rule::program => {
-                for inner_pair in pair.into_inner() {
-                    if inner_pair.as_rule() == Rule::item {
-                        // Item wraps the actual content
-                        if let Some(content) = inner_pair.into_inner().next() {
-                            match content.as_rule() {
-                                Rule::comment => {
-                                    // Skip comments
+            Rule::program => statements = parse_items(pair)?,
+            Rule::EOI => {}
+            _ => {}
+        }
+    }
+
+    Ok(Program { statements })
+}
+
+/// Build a parse error pointing at `span`, formatted like pest's own errors.
+fn custom_error(span: pest::Span, message: impl Into<String>) -> Box<pest::error::Error<Rule>> {
+    Box::new(pest::error::Error::new_from_span(
+        pest::error::ErrorVariant::CustomError {
+            message: message.into(),
+        },
+        span,
+    ))
+}
+
+/// Parse the `item` children of a program or guard body into statements,
+/// attaching any preceding annotations to the function definition they precede.
+fn parse_items(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<Vec<Statement>, Box<pest::error::Error<Rule>>> {
+    let mut statements = Vec::new();
+    let mut pending_attributes = Vec::new();
+    let mut pending_span = None;
+    let mut pending_description: Option<String> = None;
+
+    for inner_pair in pair.into_inner() {
+        if inner_pair.as_rule() == Rule::item {
+            // Item wraps the actual content
+            if let Some(content) = inner_pair.into_inner().next() {
+                match content.as_rule() {
+                    Rule::comment => {
+                        // Doc comments describe the next function; others are skipped
+                        if let Some(text) = description(content.as_str()) {
+                            pending_description = Some(match pending_description.take() {
+                                Some(earlier) => format!("{} {}", earlier, text),
+                                None => text.to_string(),
+                            });
+                        }
+                    }
+                    Rule::attribute => {
+                        pending_span.get_or_insert(content.as_span());
+                        pending_attributes.push(parse_attribute(content)?);
+                    }
+                    Rule::env_guard => {
+                        if let Some(span) = pending_span {
+                            return Err(custom_error(
+                                span,
+                                "Annotations must precede a function definition",
+                            ));
+                        }
+                        pending_description = None;
+                        statements.push(parse_env_guard(content)?);
+                    }
+                    _ => {
+                        let span = content.as_span();
+                        let Some(mut stmt) = parse_statement(content) else {
+                            continue;
+                        };
+                        if let Statement::BlockFunctionDef { commands, .. } = &stmt {
+                            check_line_annotations(commands, span)?;
+                        }
+                        let description = pending_description.take();
+                        if let (
+                            Some(description),
+                            Statement::SimpleFunctionDef { attributes, .. }
+                            | Statement::BlockFunctionDef { attributes, .. },
+                        ) = (description, &mut stmt)
+                        {
+                            attributes.push(Attribute::Description(description));
+                        }
+                        if let Some(span) = pending_span.take() {
+                            match &mut stmt {
+                                Statement::SimpleFunctionDef { attributes, .. }
+                                | Statement::BlockFunctionDef { attributes, .. } => {
+                                    attributes.append(&mut pending_attributes);
                                 }
                                 _ => {
-                                    if let Some(stmt) = parse_statement(content) {
-                                        statements.push(stmt);
-                                    }
+                                    return Err(custom_error(
+                                        span,
+                                        "Annotations must precede a function definition",
+                                    ));
                                 }
                             }
                         }
+                        statements.push(stmt);
                     }
                 }
             }
-            Rule::EOI => {}
-            _ => {}
         }
     }
 
-    Ok(Program { statements })
+    if let Some(span) = pending_span {
+        return Err(custom_error(
+            span,
+            "Annotations must precede a function definition",
+        ));
+    }
+    Ok(statements)
+}
+
+/// The text of a doc comment (`## text` or `# desc: text`), if `comment`
+/// is one.
+fn description(comment: &str) -> Option<&str> {
+    let text = match comment.strip_prefix("## ") {
+        Some(text) => text,
+        None => comment
+            .strip_prefix('#')?
+            .trim_start()
+            .strip_prefix("desc:")?,
+    };
+    Some(text.trim()).filter(|text| !text.is_empty())
+}
+
+fn parse_env_guard(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<Statement, Box<pest::error::Error<Rule>>> {
+    let mut inner = pair.into_inner();
+    let negated = inner.next().is_some_and(|p| p.as_str() == "ifndef");
+    let variable = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let then_branch = match inner.next() {
+        Some(body) => parse_items(body)?,
+        None => Vec::new(),
+    };
+    let else_branch = match inner.next() {
+        Some(body) => parse_items(body)?,
+        None => Vec::new(),
+    };
+    Ok(Statement::EnvGuard {
+        variable,
+        negated,
+        then_branch,
+        else_branch,
+    })
+}
+
+/// Parse an `@name args` annotation. Arguments may optionally be wrapped in
+/// parentheses, e.g. `@only-if(env("CI"))`.
+fn parse_attribute(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<Attribute, Box<pest::error::Error<Rule>>> {
+    let span = pair.as_span();
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str()).unwrap_or_default();
+    let raw_args = inner.next().map(|p| p.as_str().trim()).unwrap_or_default();
+    let args = raw_args
+        .strip_prefix('(')
+        .and_then(|a| a.strip_suffix(')'))
+        .unwrap_or(raw_args)
+        .trim();
+
+    let condition = || {
+        parse_condition(args).ok_or_else(|| {
+            custom_error(span, format!("Invalid condition for @{}: '{}'", name, args))
+        })
+    };
+
+    match name {
+        "skip-if" => Ok(Attribute::SkipIf(condition()?)),
+        "only-if" => Ok(Attribute::OnlyIf(condition()?)),
+        "requires" if !args.is_empty() => Ok(Attribute::Requires(parse_tool_list(args))),
+        "container" if !args.is_empty() => Ok(Attribute::Container(args.to_string())),
+        "shell" if !args.is_empty() => Ok(Attribute::Shell(args.to_string())),
+        "outputs" if !args.is_empty() => Ok(Attribute::Outputs(parse_path_list(args))),
+        "inputs" if !args.is_empty() => Ok(Attribute::Inputs(parse_path_list(args))),
+        "always" if args.is_empty() => Ok(Attribute::Always),
+        "root-relative" if args.is_empty() => Ok(Attribute::RootRelative),
+        "cwd" if !args.is_empty() => Ok(Attribute::Cwd(unquote(args))),
+        "once" if args.is_empty() => Ok(Attribute::Once),
+        "keep-temp" if args.is_empty() => Ok(Attribute::KeepTemp),
+        "danger" | "prod" if args.is_empty() => Ok(Attribute::Danger(name.to_string())),
+        "confirm" => Ok(Attribute::Confirm(
+            (!args.is_empty()).then(|| unquote(args)),
+        )),
+        "matrix" => parse_matrix(args).map(Attribute::Matrix).ok_or_else(|| {
+            custom_error(
+                span,
+                format!(
+                    "Invalid @matrix: '{}' (expected name=[value, ...] ...)",
+                    args
+                ),
+            )
+        }),
+        "allow-exit" | "map-exit" => parse_exit_attribute(name, args).ok_or_else(|| {
+            custom_error(
+                span,
+                format!(
+                    "Invalid @{}: '{}' (expected {})",
+                    name,
+                    args,
+                    if name == "allow-exit" {
+                        "exit codes such as 1 3"
+                    } else {
+                        "CODE=warn or CODE=ok"
+                    }
+                ),
+            )
+        }),
+        "supervise" => parse_supervision(args)
+            .map(Attribute::Supervise)
+            .ok_or_else(|| {
+                custom_error(
+                    span,
+                    format!(
+                        "Invalid @supervise: '{}' (expected restart=on-failure or always, max=N, backoff=DURATION)",
+                        args
+                    ),
+                )
+            }),
+        "umask" => parse_octal(args).map(Attribute::Umask).ok_or_else(|| {
+            custom_error(
+                span,
+                format!("Invalid @umask: '{}' (expected an octal mask such as 077)", args),
+            )
+        }),
+        "fix-perms" => args
+            .split_once(char::is_whitespace)
+            .and_then(|(mode, paths)| Some((parse_file_mode(mode)?, parse_path_list(paths))))
+            .map(|(mode, paths)| Attribute::FixPerms(mode, paths))
+            .ok_or_else(|| {
+                custom_error(
+                    span,
+                    format!(
+                        "Invalid @fix-perms: '{}' (expected a mode such as +x or 755, then paths)",
+                        args
+                    ),
+                )
+            }),
+        "notify-on" => match args {
+            "success" => Ok(Attribute::NotifyOn(NotifyOn::Success)),
+            "failure" => Ok(Attribute::NotifyOn(NotifyOn::Failure)),
+            "always" => Ok(Attribute::NotifyOn(NotifyOn::Always)),
+            _ => Err(custom_error(
+                span,
+                format!(
+                    "Invalid value for @notify-on: '{}' (expected success, failure or always)",
+                    args
+                ),
+            )),
+        },
+        _ => Err(custom_error(span, format!("Unknown annotation '@{}'", name))),
+    }
+}
+
+/// Parse the arguments of `@allow-exit 1 3` or `@map-exit 2=warn`, which
+/// may also stand on their own line inside a block, applying to the next.
+pub fn parse_exit_attribute(name: &str, args: &str) -> Option<Attribute> {
+    let words = args
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty());
+    match name {
+        "allow-exit" => {
+            let codes = words
+                .map(|word| word.parse().ok())
+                .collect::<Option<Vec<i32>>>()?;
+            (!codes.is_empty()).then_some(Attribute::AllowExit(codes))
+        }
+        "map-exit" => {
+            let mappings = words
+                .map(|word| {
+                    let (code, action) = word.split_once('=')?;
+                    let action = match action {
+                        "warn" => ExitAction::Warn,
+                        "ok" => ExitAction::Ok,
+                        _ => return None,
+                    };
+                    Some((code.parse().ok()?, action))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            (!mappings.is_empty()).then_some(Attribute::MapExit(mappings))
+        }
+        _ => None,
+    }
+}
+
+/// The `key=value` options of `@supervise`, each optional:
+/// `restart=on-failure|always`, `max=N` and `backoff=1s`.
+fn parse_supervision(args: &str) -> Option<Supervision> {
+    let mut supervision = Supervision {
+        always: false,
+        max: None,
+        backoff: std::time::Duration::from_secs(1),
+    };
+    for word in args.split_whitespace() {
+        match word.split_once('=')? {
+            ("restart", "on-failure") => supervision.always = false,
+            ("restart", "always") => supervision.always = true,
+            ("max", max) => supervision.max = Some(max.parse().ok()?),
+            ("backoff", backoff) => supervision.backoff = crate::builtins::parse_duration(backoff)?,
+            _ => return None,
+        }
+    }
+    Some(supervision)
+}
+
+/// Permission bits written in octal, such as `077` or `0755`.
+fn parse_octal(text: &str) -> Option<u32> {
+    if text.is_empty() || text.len() > 4 {
+        return None;
+    }
+    u32::from_str_radix(text, 8)
+        .ok()
+        .filter(|bits| *bits <= 0o777)
+}
+
+/// A chmod mode: octal, or comma-separated clauses such as `u+x,go-w`
+/// naming who (`u`, `g`, `o`, `a` or nobody, for everyone), an operator
+/// (`+`, `-` or `=`) and permissions out of `rwx`.
+fn parse_file_mode(text: &str) -> Option<FileMode> {
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        return parse_octal(text).map(FileMode::Octal);
+    }
+    let mut changes = Vec::new();
+    for clause in text.split(',') {
+        let split = clause.find(['+', '-', '='])?;
+        let (who, rest) = clause.split_at(split);
+        let mut who_bits = 0;
+        for c in who.chars() {
+            who_bits |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        let op = match &rest[..1] {
+            "+" => ModeOp::Add,
+            "-" => ModeOp::Remove,
+            _ => ModeOp::Set,
+        };
+        let mut perms = 0;
+        for c in rest[1..].chars() {
+            perms |= match c {
+                'r' => 0o4,
+                'w' => 0o2,
+                'x' => 0o1,
+                _ => return None,
+            };
+        }
+        changes.push(ModeChange {
+            who: if who_bits == 0 { 0o777 } else { who_bits },
+            op,
+            perms,
+        });
+    }
+    Some(FileMode::Symbolic(changes))
+}
+
+/// The annotation on a line of a block (`@allow-exit 1`), as its name and
+/// arguments.
+pub fn line_annotation(line: &str) -> Option<(&str, &str)> {
+    let annotation = line.strip_prefix('@')?;
+    Some(
+        annotation
+            .split_once(char::is_whitespace)
+            .unwrap_or((annotation, "")),
+    )
+}
+
+/// Only exit-code annotations may stand on their own line inside a block.
+fn check_line_annotations(
+    commands: &[String],
+    span: pest::Span,
+) -> Result<(), Box<pest::error::Error<Rule>>> {
+    for line in commands {
+        if let Some((name, args)) = line_annotation(line)
+            && parse_exit_attribute(name, args).is_none()
+        {
+            return Err(custom_error(
+                span,
+                format!(
+                    "Invalid annotation '{}' in block: only @allow-exit and @map-exit can apply to a single command",
+                    line
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Parse a list of paths or glob patterns separated by whitespace or commas.
+fn parse_path_list(text: &str) -> Vec<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|path| !path.is_empty())
+        .map(|path| path.trim_matches(['"', '\'']).to_string())
+        .collect()
+}
+
+/// Parse `@matrix` axes such as `target=[x86_64, aarch64] profile=[debug,release]`.
+fn parse_matrix(text: &str) -> Option<Vec<MatrixAxis>> {
+    let mut axes: Vec<MatrixAxis> = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=')?;
+        let name = name.trim();
+        let after = after.trim_start().strip_prefix('[')?;
+        let (values, after) = after.split_once(']')?;
+        let values: Vec<String> = values
+            .split(',')
+            .map(|value| value.trim().trim_matches(['"', '\'']).to_string())
+            .filter(|value| !value.is_empty())
+            .collect();
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name || values.is_empty() || axes.iter().any(|axis| axis.name == name) {
+            return None;
+        }
+        axes.push(MatrixAxis {
+            name: name.to_string(),
+            values,
+        });
+        rest = after.trim_start();
+    }
+    (!axes.is_empty()).then_some(axes)
+}
+
+/// The `KEY=value` entries of a `profile` or `env` block.
+fn parse_profile_vars(pairs: Pairs<Rule>) -> Vec<(String, String)> {
+    pairs
+        .filter(|part| part.as_rule() == Rule::profile_var)
+        .filter_map(|var| {
+            let mut parts = var.into_inner();
+            let key = parts.next()?.as_str().to_string();
+            let value = parts.next()?.as_str();
+            // Values are data, not shell text: drop surrounding quotes
+            let value = if value.len() >= 2 && value.starts_with('"') {
+                unescape_quoted(value)
+            } else if value.len() >= 2 && value.starts_with('\'') {
+                unescape_single_quoted(value)
+            } else {
+                value.to_string()
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Parse a `requires` list such as `docker node >= 18, terraform ~> 1.6`.
+/// Tools are separated by whitespace or commas; an operator binds the tool
+/// before it to the version after it.
+fn parse_tool_list(text: &str) -> Vec<ToolRequirement> {
+    let tokens: Vec<&str> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let mut requirements: Vec<ToolRequirement> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        if let Some(op) = parse_version_op(token)
+            && let Some(last) = requirements.last_mut()
+            && let Some(version) = tokens.get(i + 1).and_then(|v| parse_version(v))
+        {
+            last.constraint = Some(VersionConstraint { op, version });
+            i += 2;
+            continue;
+        }
+        requirements.push(ToolRequirement {
+            tool: token.to_string(),
+            constraint: None,
+        });
+        i += 1;
+    }
+    requirements
+}
+
+fn parse_version_op(token: &str) -> Option<VersionOp> {
+    match token {
+        "=" | "==" => Some(VersionOp::Eq),
+        ">" => Some(VersionOp::Gt),
+        ">=" => Some(VersionOp::Ge),
+        "<" => Some(VersionOp::Lt),
+        "<=" => Some(VersionOp::Le),
+        "~>" => Some(VersionOp::Pessimistic),
+        _ => None,
+    }
+}
+
+/// Parse a dotted version such as `1.6` or `v18.2.0` into its components.
+pub fn parse_version(text: &str) -> Option<Vec<u64>> {
+    let text = text.trim_start_matches('v');
+    let parts: Option<Vec<u64>> = text.split('.').map(|part| part.parse().ok()).collect();
+    parts.filter(|parts| !parts.is_empty())
+}
+
+/// Parse a precondition such as `exists(".skip")`, `command_exists("docker")`,
+/// `env("CI")`, optionally negated with `!`.
+fn parse_condition(text: &str) -> Option<Condition> {
+    let text = text.trim();
+    if let Some(negated) = text.strip_prefix('!') {
+        return parse_condition(negated).map(|c| Condition::Not(Box::new(c)));
+    }
+
+    let open = text.find('(')?;
+    let function = text[..open].trim();
+    let argument = text[open + 1..].strip_suffix(')')?.trim();
+    let argument = if argument.starts_with('"') {
+        unescape_quoted(argument)
+    } else if argument.starts_with('\'') {
+        unescape_single_quoted(argument)
+    } else {
+        argument.to_string()
+    };
+
+    match function {
+        "exists" => Some(Condition::Exists(argument)),
+        "command_exists" => Some(Condition::CommandExists(argument)),
+        "env" => Some(Condition::Env(argument)),
+        _ => None,
+    }
 }
 
 fn parse_statement(pair: pest::iterators::Pair<Rule>) -> Option<Statement> {
@@ -73,33 +569,118 @@ fn parse_statement(pair: pest::iterators::Pair<Rule>) -> Option<Statement> {
         Rule::assignment => {
             let mut inner = pair.into_inner();
             let name = inner.next()?.as_str().to_string();
-            let value_str = inner.next()?.as_str().to_string();
+            let value = inner.next()?;
+            if matches!(value.as_rule(), Rule::capture | Rule::function_substitution) {
+                let function = value.into_inner().next()?.as_str().to_string();
+                return Some(Statement::CaptureVar { name, function });
+            }
+            if value.as_rule() == Rule::list {
+                let items = value
+                    .into_inner()
+                    .map(|item| parse_list_item(item.as_str()));
+                return Some(Statement::Assignment {
+                    name,
+                    value: Expression::List(items.collect()),
+                });
+            }
+            let value_str = value.as_str().to_string();
             Some(Statement::Assignment {
                 name,
                 value: Expression::String(value_str),
             })
         }
+        Rule::run_capture_assignment => {
+            let mut names = Vec::new();
+            let mut function = None;
+            for part in pair.into_inner() {
+                match part.as_rule() {
+                    Rule::identifier => {
+                        names.push(Some(part.as_str().to_string()).filter(|name| name != "_"))
+                    }
+                    _ => function = Some(part.into_inner().next()?.as_str().to_string()),
+                }
+            }
+            let mut names = names.into_iter().chain(std::iter::repeat(None));
+            Some(Statement::RunCapture {
+                stdout: names.next()?,
+                stderr: names.next()?,
+                code: names.next()?,
+                function: function?,
+            })
+        }
+        Rule::enum_var => {
+            let mut inner = pair.into_inner();
+            let name = inner.next()?.as_str().to_string();
+            let mut allowed = Vec::new();
+            let mut value = None;
+            for part in inner {
+                match part.as_rule() {
+                    Rule::enum_value => allowed.push(unquote(part.as_str())),
+                    _ => value = Some(Expression::String(part.as_str().to_string())),
+                }
+            }
+            Some(Statement::EnumVar {
+                name,
+                allowed,
+                value,
+            })
+        }
+        Rule::secretvar => {
+            let mut inner = pair.into_inner().skip(1);
+            let name = inner.next()?.as_str().to_string();
+            let value = inner.next()?.as_str().trim().to_string();
+            Some(Statement::SecretVar { name, value })
+        }
+        Rule::setting => {
+            let mut inner = pair.into_inner();
+            let key = inner.next()?.as_str().to_string();
+            // Settings are configuration, not shell text: drop surrounding quotes
+            let value = unquote(inner.next()?.as_str().trim());
+            Some(Statement::Setting { key, value })
+        }
         Rule::function_def => {
             let mut inner = pair.into_inner();
             let name = inner.next()?.as_str().to_string();
 
+            // The parentheses may name the arguments, and an optional
+            // `needs(...)` clause lists prerequisite functions
+            let mut attributes = Vec::new();
+            let mut body = inner.next();
+            if let Some(params) = body.clone().filter(|p| p.as_rule() == Rule::param_list) {
+                attributes.push(Attribute::Params(params.into_inner().map(parse_param).collect()));
+                body = inner.next();
+            }
+            if let Some(needs) = body.clone().filter(|p| p.as_rule() == Rule::needs_clause) {
+                let dependencies = needs.into_inner().map(|p| p.as_str().to_string());
+                attributes.push(Attribute::Needs(dependencies.collect()));
+                body = inner.next();
+            }
+
             // The next element is either a command or a block
-            if let Some(body_pair) = inner.next() {
+            if let Some(body_pair) = body {
                 match body_pair.as_rule() {
                     Rule::block => {
-                        let commands: Vec<String> = body_pair
+                        let mut commands = Vec::new();
+                        let (env_blocks, lines): (Vec<_>, Vec<_>) = body_pair
                             .into_inner()
-                            .filter(|p| p.as_rule() == Rule::block_line)
-                            .map(|p| p.as_str().trim().to_string())
-                            .filter(|s| !s.is_empty())
-                            .collect();
-                        Some(Statement::BlockFunctionDef { name, commands })
+                            .partition(|p| p.as_rule() == Rule::env_block);
+                        for env_block in env_blocks {
+                            attributes
+                                .push(Attribute::Env(parse_profile_vars(env_block.into_inner())));
+                        }
+                        block_lines(lines, &mut commands, &mut attributes);
+                        Some(Statement::BlockFunctionDef {
+                            name,
+                            commands,
+                            attributes,
+                        })
                     }
                     Rule::command => {
                         let command_template = parse_command(body_pair);
                         Some(Statement::SimpleFunctionDef {
                             name,
                             command_template,
+                            attributes,
                         })
                     }
                     _ => None,
@@ -111,33 +692,44 @@ fn parse_statement(pair: pest::iterators::Pair<Rule>) -> Option<Statement> {
         Rule::function_call => {
             let mut inner = pair.into_inner();
             let name = inner.next()?.as_str().to_string();
-            let mut args = Vec::new();
-            if let Some(arg_list_pair) = inner.next()
-                && arg_list_pair.as_rule() == Rule::argument_list
-            {
-                for arg_pair in arg_list_pair.into_inner() {
-                    if arg_pair.as_rule() == Rule::argument {
-                        // Extract the actual argument value
-                        let arg_value =
-                            if let Some(inner_arg) = arg_pair.clone().into_inner().next() {
-                                match inner_arg.as_rule() {
-                                    Rule::quoted_string => {
-                                        // Remove quotes from quoted strings
-                                        inner_arg.as_str().trim_matches('"').to_string()
-                                    }
-                                    Rule::variable | Rule::argument_word => {
-                                        inner_arg.as_str().to_string()
-                                    }
-                                    _ => inner_arg.as_str().to_string(),
-                                }
-                            } else {
-                                arg_pair.as_str().to_string()
-                            };
-                        args.push(arg_value);
-                    }
+            let args = inner.next().map(parse_argument_list).unwrap_or_default();
+            Some(Statement::FunctionCall { name, args })
+        }
+        Rule::template_def => {
+            let mut inner = pair.into_inner().skip(1);
+            let name = inner.next()?.as_str().to_string();
+            let mut params = Vec::new();
+            let mut body = String::new();
+            for part in inner {
+                match part.as_rule() {
+                    Rule::identifier => params.push(part.as_str().to_string()),
+                    Rule::template_body => body = part.as_str().to_string(),
+                    _ => {}
                 }
             }
-            Some(Statement::FunctionCall { name, args })
+            Some(Statement::TemplateDef { name, params, body })
+        }
+        Rule::profile_def => {
+            let mut inner = pair.into_inner();
+            let name = inner.nth(1)?.as_str().to_string();
+            let variables = parse_profile_vars(inner);
+            Some(Statement::ProfileDef { name, variables })
+        }
+        Rule::env_block => Some(Statement::EnvBlock {
+            variables: parse_profile_vars(pair.into_inner()),
+        }),
+        Rule::instantiate => {
+            let calls = pair
+                .into_inner()
+                .filter(|part| part.as_rule() == Rule::template_call)
+                .filter_map(|call| {
+                    let mut inner = call.into_inner();
+                    let name = inner.next()?.as_str().to_string();
+                    let args = inner.next().map(parse_argument_list).unwrap_or_default();
+                    Some((name, args))
+                })
+                .collect();
+            Some(Statement::Instantiate { calls })
         }
         Rule::command => {
             let command = parse_command(pair);
@@ -147,66 +739,249 @@ fn parse_statement(pair: pest::iterators::Pair<Rule>) -> Option<Statement> {
     }
 }
 
-fn parse_command(pair: pest::iterators::Pair<Rule>) -> String {
-    let mut result = String::new();
-    let mut last_was_assignment_prefix = false;
+/// Add the lines of a block to `commands`, and the `requires` lines among
+/// them to `attributes`. A `for` loop becomes its `for name in items {`
+/// line, the lines of its body and a `}` line.
+fn block_lines<'a>(
+    pairs: impl IntoIterator<Item = pest::iterators::Pair<'a, Rule>>,
+    commands: &mut Vec<String>,
+    attributes: &mut Vec<Attribute>,
+) {
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::for_loop => {
+                let mut inner = pair.into_inner().skip(1);
+                let (Some(name), Some(items)) = (inner.next(), inner.next()) else {
+                    continue;
+                };
+                commands.push(format!("for {} in {} {{", name.as_str(), items.as_str()));
+                block_lines(inner, commands, attributes);
+                commands.push("}".to_string());
+            }
+            Rule::if_block => if_lines(pair, commands, attributes),
+            Rule::block_line => {
+                let line = pair.as_str().trim();
+                // `requires` lines are directives, not commands
+                match line.strip_prefix("requires ") {
+                    Some(tools) => attributes.push(Attribute::Requires(parse_tool_list(tools))),
+                    None if !line.is_empty() => commands.push(line.to_string()),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
 
-    for part in pair.into_inner() {
-        // command_part wraps the actual token, so we need to get the inner rule
-        let actual_part = if part.as_rule() == Rule::command_part {
-            part.into_inner().next()
-        } else {
-            Some(part)
-        };
+/// The lines of an `if` chain: `if condition {`, the body, then for an
+/// `else` a `} else {` line and its body, with `else if` nested in the
+/// `else`, and a closing `}`.
+fn if_lines(
+    pair: pest::iterators::Pair<Rule>,
+    commands: &mut Vec<String>,
+    attributes: &mut Vec<Attribute>,
+) {
+    let mut inner = pair.into_inner().skip(1);
+    let (Some(condition), Some(body)) = (inner.next(), inner.next()) else {
+        return;
+    };
+    commands.push(format!("if {} {{", condition.as_str().trim()));
+    block_lines(body.into_inner(), commands, attributes);
+    if let Some(otherwise) = inner.next() {
+        commands.push(ELSE.to_string());
+        match otherwise.as_rule() {
+            Rule::if_block => if_lines(otherwise, commands, attributes),
+            _ => block_lines(otherwise.into_inner(), commands, attributes),
+        }
+    }
+    commands.push("}".to_string());
+}
 
-        let Some(part) = actual_part else {
-            continue;
-        };
+/// The block line between the branches of an `if`.
+pub const ELSE: &str = "} else {";
 
-        match part.as_rule() {
-            Rule::quoted_string => {
-                if !result.is_empty() && !result.ends_with(' ') {
-                    result.push(' ');
-                }
-                result.push('"');
-                result.push_str(part.as_str().trim_matches('"'));
-                result.push('"');
-                last_was_assignment_prefix = false;
-            }
-            Rule::variable => {
-                // Don't add space before variable if last token ended with =
-                if !last_was_assignment_prefix && !result.is_empty() && !result.ends_with(' ') {
-                    result.push(' ');
-                }
-                result.push_str(part.as_str());
-                last_was_assignment_prefix = false;
-            }
-            Rule::operator => {
-                result.push(' ');
-                result.push_str(part.as_str());
-                result.push(' ');
-                last_was_assignment_prefix = false;
-            }
-            Rule::word => {
-                if !result.is_empty() && !result.ends_with(' ') {
-                    result.push(' ');
-                }
-                let word_str = part.as_str();
-                result.push_str(word_str);
-                // Check if this word ends with = (like --port=)
-                last_was_assignment_prefix = word_str.ends_with('=');
-            }
-            _ => {
-                if !result.is_empty() && !result.ends_with(' ') {
-                    result.push(' ');
+/// The condition of the `if` a block line starts, `if condition {`.
+pub fn if_header(line: &str) -> Option<&str> {
+    Some(line.strip_prefix("if ")?.strip_suffix('{')?.trim())
+}
+
+/// The `for` loop a block line starts, `for name in items {`, as the name
+/// and the items.
+pub fn loop_header(line: &str) -> Option<(&str, &str)> {
+    let (name, items) = line
+        .strip_prefix("for ")?
+        .strip_suffix('{')?
+        .split_once(" in ")?;
+    Some((name.trim(), items.trim()))
+}
+
+/// The items of a list written as `[a, b]`, as in `for t in [a, b] {`.
+pub fn list_items(text: &str) -> Option<Vec<Expression>> {
+    let list = ScriptParser::parse(Rule::list, text).ok()?.next()?;
+    Some(list.into_inner().map(|item| parse_list_item(item.as_str())).collect())
+}
+
+/// Parse the arguments of a function call or template instantiation.
+/// Double-quoted strings and variables are interpolated at the call site,
+/// the rest is literal.
+fn parse_argument_list(pair: pest::iterators::Pair<Rule>) -> Vec<Expression> {
+    let mut args = Vec::new();
+    if pair.as_rule() != Rule::argument_list {
+        return args;
+    }
+    for arg_pair in pair.into_inner() {
+        if arg_pair.as_rule() == Rule::argument {
+            let arg_value = if let Some(inner_arg) = arg_pair.clone().into_inner().next() {
+                match inner_arg.as_rule() {
+                    Rule::quoted_string => {
+                        // Escape sequences are resolved along with the variables
+                        Expression::Interpolated(double_quoted_text(inner_arg.as_str()))
+                    }
+                    Rule::single_quoted_argument => {
+                        Expression::String(unescape_single_quoted(inner_arg.as_str()))
+                    }
+                    Rule::variable => Expression::Interpolated(inner_arg.as_str().to_string()),
+                    _ => Expression::String(inner_arg.as_str().to_string()),
                 }
-                result.push_str(part.as_str());
-                last_was_assignment_prefix = false;
+            } else {
+                Expression::String(arg_pair.as_str().to_string())
+            };
+            args.push(arg_value);
+        }
+    }
+    args
+}
+
+/// Parse `name` or `name=default` in a function's signature. A default of
+/// `$NAME` or `${NAME}` refers to an environment variable.
+fn parse_param(pair: pest::iterators::Pair<Rule>) -> Parameter {
+    let mut inner = pair.into_inner();
+    let name = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let default = inner.next().map(|p| {
+        let text = p.as_str();
+        let variable = text
+            .strip_prefix("${")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .or_else(|| text.strip_prefix('$'))
+            .filter(|name| {
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            });
+        match variable {
+            Some(name) => ParamDefault::Env(name.to_string()),
+            None => ParamDefault::Value(unquote(text)),
+        }
+    });
+    Parameter { name, default }
+}
+
+/// `text` without the double or single quotes around it, if it has them.
+pub fn unquote(text: &str) -> String {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        unescape_quoted(text)
+    } else if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        unescape_single_quoted(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// An item of a list: literal in single quotes, otherwise with variables
+/// substituted, as one item even with spaces in quotes.
+fn parse_list_item(item: &str) -> Expression {
+    if item.starts_with('\'') {
+        Expression::String(unquote(item))
+    } else if item.starts_with('"') {
+        Expression::Interpolated(double_quoted_text(item))
+    } else {
+        Expression::Interpolated(item.to_string())
+    }
+}
+
+/// The text between the double quotes of a quoted string, with its escape
+/// sequences left for interpolation to resolve.
+fn double_quoted_text(quoted: &str) -> String {
+    quoted
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(quoted)
+        .to_string()
+}
+
+/// Strip the surrounding double quotes from a quoted string and resolve
+/// the escape sequences `\"`, `\\`, `\n` and `\t`. Any other escaped
+/// character keeps its backslash, matching shell double-quote semantics.
+fn unescape_quoted(quoted: &str) -> String {
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(quoted);
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
             }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Strip the surrounding single quotes from a quoted argument, resolving
+/// only `\'` and `\\`; everything else is taken literally.
+fn unescape_single_quoted(quoted: &str) -> String {
+    let inner = quoted
+        .strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .unwrap_or(quoted);
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\'
+            && let Some(&next) = chars.peek()
+            && (next == '\'' || next == '\\')
+        {
+            result.push(next);
+            chars.next();
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn parse_command(pair: pest::iterators::Pair<Rule>) -> String {
+    // Tokens are joined as they were written: a space only where the source
+    // had whitespace (or a line continuation), so redirections like `2>&1`
+    // or `>>out.log` and prefixes like `--port=$PORT` reach the shell intact
+    let mut result = String::new();
+    let mut last_end = None;
+
+    for part in pair.into_inner() {
+        if part.as_rule() == Rule::escaped_newline {
+            continue;
         }
+        let span = part.as_span();
+        if last_end.is_some_and(|end| span.start() > end) {
+            result.push(' ');
+        }
+        result.push_str(part.as_str());
+        last_end = Some(span.end());
     }
 
-    result.trim().to_string()
+    result
 }
 
 #[cfg(test)]
@@ -218,11 +993,639 @@ mod tests {
         let input = "server() echo port=${1:-8080}";
         let result = parse_script(input).unwrap();
 
-        if let Statement::SimpleFunctionDef { name, command_template } = &result.statements[0] {
+        if let Statement::SimpleFunctionDef { name, command_template, .. } = &result.statements[0] {
             assert_eq!(name, "server");
             assert_eq!(command_template, "echo port=${1:-8080}", "Command template has unexpected spacing");
         } else {
             panic!("Expected SimpleFunctionDef");
         }
     }
+
+    #[test]
+    fn test_parse_braced_variables() {
+        let input = "image=${app}-${tag}\necho ${app}name \"${app}\"-x\n";
+        let result = parse_script(input).unwrap();
+
+        assert_eq!(
+            result.statements[0],
+            Statement::Assignment {
+                name: "image".to_string(),
+                value: Expression::String("${app}-${tag}".to_string()),
+            }
+        );
+        assert_eq!(
+            result.statements[1],
+            Statement::Command {
+                command: "echo ${app}name \"${app}\"-x".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_lists_and_loops() {
+        let input = "targets = [web, \"api server\", '$x',]\ndeploy() {\n    for t in $targets {\n        for r in eu us { echo $t/$r }\n    }\n    echo done\n}\n";
+        let result = parse_script(input).unwrap();
+
+        assert_eq!(
+            result.statements[0],
+            Statement::Assignment {
+                name: "targets".to_string(),
+                value: Expression::List(vec![
+                    Expression::Interpolated("web".to_string()),
+                    Expression::Interpolated("api server".to_string()),
+                    Expression::String("$x".to_string()),
+                ]),
+            }
+        );
+        let Statement::BlockFunctionDef { commands, .. } = &result.statements[1] else {
+            panic!("Expected BlockFunctionDef");
+        };
+        assert_eq!(
+            commands,
+            &[
+                "for t in $targets {",
+                "for r in eu us {",
+                "echo $t/$r",
+                "}",
+                "}",
+                "echo done"
+            ]
+        );
+        assert_eq!(loop_header(&commands[0]), Some(("t", "$targets")));
+    }
+
+    #[test]
+    fn test_parse_descriptions() {
+        let input = "## Build the\n## project\nbuild() cargo build\n\n# desc: Run tests\n@always\ntest() cargo test\n\n## Not attached\nname = value\nlint() cargo clippy\n";
+        let result = parse_script(input).unwrap();
+
+        let descriptions: Vec<Option<&str>> = result
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::SimpleFunctionDef { attributes, .. } => {
+                    Some(attributes.iter().find_map(|attribute| match attribute {
+                        Attribute::Description(text) => Some(text.as_str()),
+                        _ => None,
+                    }))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            descriptions,
+            [Some("Build the project"), Some("Run tests"), None]
+        );
+    }
+
+    #[test]
+    fn test_parse_command_substitution() {
+        let input = "serve() PORT=$(free_port) node server.js --root $(dirname $(pwd))\n";
+        let result = parse_script(input).unwrap();
+
+        let Statement::SimpleFunctionDef {
+            command_template, ..
+        } = &result.statements[0]
+        else {
+            panic!("Expected SimpleFunctionDef");
+        };
+        assert_eq!(
+            command_template,
+            "PORT=$(free_port) node server.js --root $(dirname $(pwd))"
+        );
+    }
+
+    #[test]
+    fn test_parse_redirections() {
+        let input = "log() sort <in.txt >>out.log 2>&1 && cat out.log > copy.txt\n";
+        let result = parse_script(input).unwrap();
+
+        let Statement::SimpleFunctionDef {
+            command_template, ..
+        } = &result.statements[0]
+        else {
+            panic!("Expected SimpleFunctionDef");
+        };
+        assert_eq!(
+            command_template,
+            "sort <in.txt >>out.log 2>&1 && cat out.log > copy.txt"
+        );
+    }
+
+    #[test]
+    fn test_parse_capture() {
+        let input = "version = capture(get_version)\nlabel = capture(get_version) extra\nsha=$(git:sha())\n";
+        let result = parse_script(input).unwrap();
+
+        assert_eq!(
+            result.statements,
+            vec![
+                Statement::CaptureVar {
+                    name: "version".to_string(),
+                    function: "get_version".to_string(),
+                },
+                Statement::Assignment {
+                    name: "label".to_string(),
+                    value: Expression::String("capture(get_version) extra".to_string()),
+                },
+                Statement::CaptureVar {
+                    name: "sha".to_string(),
+                    function: "git:sha".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_run_capture() {
+        let input = "out, errors, code = run_capture(check)
+_, _, status = run_capture(db.ping)
+log = run_capture(check)
+";
+        let result = parse_script(input).unwrap();
+
+        assert_eq!(
+            result.statements,
+            vec![
+                Statement::RunCapture {
+                    stdout: Some("out".to_string()),
+                    stderr: Some("errors".to_string()),
+                    code: Some("code".to_string()),
+                    function: "check".to_string(),
+                },
+                Statement::RunCapture {
+                    stdout: None,
+                    stderr: None,
+                    code: Some("status".to_string()),
+                    function: "db.ping".to_string(),
+                },
+                Statement::RunCapture {
+                    stdout: Some("log".to_string()),
+                    stderr: None,
+                    code: None,
+                    function: "check".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_env_blocks() {
+        let input = "env { RUST_LOG=debug NAME=\"a b\" }\ndb() {\n    env {\n        URL=postgres://x # local\n    }\n    env FOO=1 printenv FOO\n}\n";
+        let result = parse_script(input).unwrap();
+
+        assert_eq!(
+            result.statements,
+            vec![
+                Statement::EnvBlock {
+                    variables: vec![
+                        ("RUST_LOG".to_string(), "debug".to_string()),
+                        ("NAME".to_string(), "a b".to_string()),
+                    ],
+                },
+                Statement::BlockFunctionDef {
+                    name: "db".to_string(),
+                    commands: vec!["env FOO=1 printenv FOO".to_string()],
+                    attributes: vec![Attribute::Env(vec![(
+                        "URL".to_string(),
+                        "postgres://x".to_string()
+                    )])],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_matrix_annotation() {
+        let input =
+            "@matrix target=[x86_64, aarch64] profile=[debug,release]\nbuild() cargo build\n";
+        let result = parse_script(input).unwrap();
+
+        let Statement::SimpleFunctionDef { attributes, .. } = &result.statements[0] else {
+            panic!("Expected SimpleFunctionDef");
+        };
+        let Attribute::Matrix(axes) = &attributes[0] else {
+            panic!("Expected @matrix, got {:?}", attributes);
+        };
+        assert_eq!(axes.len(), 2);
+        assert_eq!(axes[0].name, "target");
+        assert_eq!(axes[0].values, ["x86_64", "aarch64"]);
+        assert_eq!(axes[1].values, ["debug", "release"]);
+
+        assert!(parse_script("@matrix target=[]\nbuild() cargo build\n").is_err());
+        assert!(parse_script("@matrix target\nbuild() cargo build\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_template_and_instantiate() {
+        let input = "template service(name, ns) {\n    deploy:$name() {\n        kubectl -n $ns rollout restart deploy/$name\n    }\n}\ninstantiate service(api, prod) service(worker, \"jobs\")\n";
+        let result = parse_script(input).unwrap();
+
+        let Statement::TemplateDef { name, params, body } = &result.statements[0] else {
+            panic!("Expected TemplateDef, got {:?}", result.statements[0]);
+        };
+        assert_eq!(name, "service");
+        assert_eq!(params, &["name", "ns"]);
+        assert!(body.contains("deploy:$name() {") && body.trim_end().ends_with('}'));
+
+        let Statement::Instantiate { calls } = &result.statements[1] else {
+            panic!("Expected Instantiate, got {:?}", result.statements[1]);
+        };
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].0, "service");
+        assert_eq!(calls[1].1[1], Expression::Interpolated("jobs".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_guard_with_else() {
+        let input = "ifndef CI {\n    build() echo local\n} else {\n    build() {\n        echo ci\n    }\n}\n";
+        let result = parse_script(input).unwrap();
+
+        if let Statement::EnvGuard { variable, negated, then_branch, else_branch } = &result.statements[0] {
+            assert_eq!(variable, "CI");
+            assert!(negated);
+            assert_eq!(then_branch.len(), 1);
+            assert!(matches!(&else_branch[0], Statement::BlockFunctionDef { name, .. } if name == "build"));
+        } else {
+            panic!("Expected EnvGuard");
+        }
+    }
+
+    #[test]
+    fn test_parse_conditional_chain() {
+        let input = "install() {\n    if command -v brew >/dev/null {\n        brew install jq\n    } else if test -f \"${DEBIAN}\" { apt install jq }\n    else {\n    }\n    if [ -f x ]; then echo y; fi\n}\n";
+        let result = parse_script(input).unwrap();
+
+        let Statement::BlockFunctionDef { commands, .. } = &result.statements[0] else {
+            panic!("Expected BlockFunctionDef");
+        };
+        assert_eq!(
+            commands,
+            &[
+                "if command -v brew >/dev/null {",
+                "brew install jq",
+                ELSE,
+                "if test -f \"${DEBIAN}\" {",
+                "apt install jq",
+                ELSE,
+                "}",
+                "}",
+                // Shell syntax is still plain commands
+                "if [ -f x ]",
+                "then echo y",
+                "fi"
+            ]
+        );
+        assert_eq!(if_header(&commands[0]), Some("command -v brew >/dev/null"));
+        assert_eq!(if_header(&commands[8]), None);
+    }
+
+    #[test]
+    fn test_parse_attributes_attach_to_next_function() {
+        let input = "@skip-if exists(\".skip\")\n@only-if !env(\"CI\")\ne2e() npm test\n";
+        let result = parse_script(input).unwrap();
+
+        if let Statement::SimpleFunctionDef { attributes, .. } = &result.statements[0] {
+            assert_eq!(
+                attributes,
+                &vec![
+                    Attribute::SkipIf(Condition::Exists(".skip".to_string())),
+                    Attribute::OnlyIf(Condition::Not(Box::new(Condition::Env("CI".to_string())))),
+                ]
+            );
+        } else {
+            panic!("Expected SimpleFunctionDef");
+        }
+    }
+
+    #[test]
+    fn test_parse_confirm_annotation() {
+        let input = "@confirm(\"Really deploy to prod?\")\ndeploy() echo deploy\n@confirm\ndrop() echo drop\n";
+        let result = parse_script(input).unwrap();
+        let attributes: Vec<_> = result
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::SimpleFunctionDef { attributes, .. } => attributes.clone(),
+                _ => panic!("Expected SimpleFunctionDef"),
+            })
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                vec![Attribute::Confirm(Some("Really deploy to prod?".to_string()))],
+                vec![Attribute::Confirm(None)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_danger_annotations() {
+        let input = "@danger\ndrop() echo drop\n@prod\ndeploy() echo deploy\n";
+        let result = parse_script(input).unwrap();
+        let attributes: Vec<_> = result
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::SimpleFunctionDef { attributes, .. } => attributes.clone(),
+                _ => panic!("Expected SimpleFunctionDef"),
+            })
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                vec![Attribute::Danger("danger".to_string())],
+                vec![Attribute::Danger("prod".to_string())],
+            ]
+        );
+        assert!(parse_script("@danger(now)\ndrop() echo drop\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_supervise_annotation() {
+        let input = "@supervise restart=on-failure max=5\nserve() npm start\n@supervise restart=always backoff=500ms\nwatch() tsc -w\n";
+        let result = parse_script(input).unwrap();
+        let attributes: Vec<_> = result
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::SimpleFunctionDef { attributes, .. } => attributes.clone(),
+                _ => panic!("Expected SimpleFunctionDef"),
+            })
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                vec![Attribute::Supervise(Supervision {
+                    always: false,
+                    max: Some(5),
+                    backoff: std::time::Duration::from_secs(1),
+                })],
+                vec![Attribute::Supervise(Supervision {
+                    always: true,
+                    max: None,
+                    backoff: std::time::Duration::from_millis(500),
+                })],
+            ]
+        );
+
+        let error = parse_script("@supervise restart=sometimes\nserve() npm start\n").unwrap_err();
+        assert!(error.to_string().contains("Invalid @supervise: 'restart=sometimes'"));
+    }
+
+    #[test]
+    fn test_parse_permission_annotations() {
+        let input = "@umask 077\n@fix-perms +x dist/bin/*\n@fix-perms go-w,u=rw out/a.txt, out/b.txt\n@fix-perms 755 bin/tool\nrelease() make\n";
+        let result = parse_script(input).unwrap();
+        let Statement::SimpleFunctionDef { attributes, .. } = &result.statements[0] else {
+            panic!("Expected SimpleFunctionDef");
+        };
+        assert_eq!(
+            attributes,
+            &vec![
+                Attribute::Umask(0o077),
+                Attribute::FixPerms(
+                    FileMode::Symbolic(vec![ModeChange {
+                        who: 0o777,
+                        op: ModeOp::Add,
+                        perms: 0o1,
+                    }]),
+                    vec!["dist/bin/*".to_string()],
+                ),
+                Attribute::FixPerms(
+                    FileMode::Symbolic(vec![
+                        ModeChange {
+                            who: 0o077,
+                            op: ModeOp::Remove,
+                            perms: 0o2,
+                        },
+                        ModeChange {
+                            who: 0o700,
+                            op: ModeOp::Set,
+                            perms: 0o6,
+                        },
+                    ]),
+                    vec!["out/a.txt".to_string(), "out/b.txt".to_string()],
+                ),
+                Attribute::FixPerms(FileMode::Octal(0o755), vec!["bin/tool".to_string()]),
+            ]
+        );
+
+        for input in ["@umask 089\nf() true\n", "@umask 1777\nf() true\n"] {
+            let error = parse_script(input).unwrap_err();
+            assert!(error.to_string().contains("Invalid @umask"), "{}", error);
+        }
+        for input in ["@fix-perms +x\nf() true\n", "@fix-perms +z bin\nf() true\n"] {
+            let error = parse_script(input).unwrap_err();
+            assert!(
+                error.to_string().contains("Invalid @fix-perms"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_signature_parameters() {
+        let input = "deploy(env=$DEPLOY_ENV, region=\"eu west\", tag) needs(build) echo $env\n";
+        let result = parse_script(input).unwrap();
+        let Statement::SimpleFunctionDef { attributes, .. } = &result.statements[0] else {
+            panic!("Expected SimpleFunctionDef");
+        };
+        assert_eq!(
+            attributes,
+            &vec![
+                Attribute::Params(vec![
+                    Parameter {
+                        name: "env".to_string(),
+                        default: Some(ParamDefault::Env("DEPLOY_ENV".to_string())),
+                    },
+                    Parameter {
+                        name: "region".to_string(),
+                        default: Some(ParamDefault::Value("eu west".to_string())),
+                    },
+                    Parameter {
+                        name: "tag".to_string(),
+                        default: None,
+                    },
+                ]),
+                Attribute::Needs(vec!["build".to_string()]),
+            ]
+        );
+
+        // Without a body, the same text is still a call
+        let result = parse_script("deploy(env)\n").unwrap();
+        assert!(matches!(&result.statements[0], Statement::FunctionCall { .. }));
+    }
+
+    #[test]
+    fn test_parse_enum_variable() {
+        let input = "env: one_of(dev, staging, \"prod\") = dev\nlevel: one_of(low,high)\n";
+        let result = parse_script(input).unwrap();
+        assert_eq!(
+            result.statements,
+            vec![
+                Statement::EnumVar {
+                    name: "env".to_string(),
+                    allowed: vec!["dev".to_string(), "staging".to_string(), "prod".to_string()],
+                    value: Some(Expression::String("dev".to_string())),
+                },
+                Statement::EnumVar {
+                    name: "level".to_string(),
+                    allowed: vec!["low".to_string(), "high".to_string()],
+                    value: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_requires_directive_in_block() {
+        let input = "deploy() {\n    requires docker kubectl\n    docker build .\n}\n";
+        let result = parse_script(input).unwrap();
+
+        if let Statement::BlockFunctionDef {
+            commands,
+            attributes,
+            ..
+        } = &result.statements[0]
+        {
+            assert_eq!(commands, &vec!["docker build .".to_string()]);
+            let tools: Vec<String> = match &attributes[0] {
+                Attribute::Requires(requirements) => {
+                    requirements.iter().map(|r| r.tool.clone()).collect()
+                }
+                _ => panic!("Expected Requires"),
+            };
+            assert_eq!(tools, vec!["docker".to_string(), "kubectl".to_string()]);
+        } else {
+            panic!("Expected BlockFunctionDef");
+        }
+    }
+
+    #[test]
+    fn test_parse_requires_with_version_constraints() {
+        let requirements = parse_tool_list("docker node >= 18, terraform ~> 1.6");
+        let rendered: Vec<String> = requirements.iter().map(ToString::to_string).collect();
+        assert_eq!(rendered, vec!["docker", "node >= 18", "terraform ~> 1.6"]);
+    }
+
+    #[test]
+    fn test_parse_needs_clause() {
+        let program =
+            parse_script("ci() needs(lint, test) { echo ci }\nfmt() needs() cargo fmt").unwrap();
+        match &program.statements[0] {
+            Statement::BlockFunctionDef {
+                attributes,
+                commands,
+                ..
+            } => {
+                assert_eq!(
+                    attributes,
+                    &vec![Attribute::Needs(vec![
+                        "lint".to_string(),
+                        "test".to_string()
+                    ])]
+                );
+                assert_eq!(commands, &vec!["echo ci".to_string()]);
+            }
+            other => panic!("Expected block function, got {:?}", other),
+        }
+        match &program.statements[1] {
+            Statement::SimpleFunctionDef {
+                command_template, ..
+            } => {
+                assert_eq!(command_template, "cargo fmt");
+            }
+            other => panic!("Expected simple function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_dangling_attribute_is_error() {
+        assert!(parse_script("@skip-if exists(\"x\")\necho hi\n").is_err());
+        assert!(parse_script("@bogus\nf() echo hi\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_dotted_setting() {
+        let result = parse_script("toolchain.manager = \"mise\"\nname=value\n").unwrap();
+
+        assert_eq!(
+            result.statements[0],
+            Statement::Setting {
+                key: "toolchain.manager".to_string(),
+                value: "mise".to_string(),
+            }
+        );
+        assert!(
+            matches!(&result.statements[1], Statement::Assignment { name, .. } if name == "name")
+        );
+    }
+
+    #[test]
+    fn test_parse_call_arguments_with_escapes() {
+        let input = r#"greet("say \"hi\"", "a\\b", "tab\there")"#;
+        let result = parse_script(input).unwrap();
+
+        if let Statement::FunctionCall { name, args } = &result.statements[0] {
+            assert_eq!(name, "greet");
+            assert_eq!(
+                args,
+                &vec![
+                    // Escapes are resolved when the arguments are interpolated
+                    Expression::Interpolated(r#"say \"hi\""#.to_string()),
+                    Expression::Interpolated(r"a\\b".to_string()),
+                    Expression::Interpolated(r"tab\there".to_string()),
+                ]
+            );
+        } else {
+            panic!("Expected FunctionCall");
+        }
+    }
+
+    #[test]
+    fn test_parse_call_arguments_with_mixed_quotes() {
+        let input = r#"greet("say \"hi\", 'friend'", 'single, "double"', 'it\'s')"#;
+        let result = parse_script(input).unwrap();
+
+        if let Statement::FunctionCall { args, .. } = &result.statements[0] {
+            assert_eq!(
+                args,
+                &vec![
+                    Expression::Interpolated(r#"say \"hi\", 'friend'"#.to_string()),
+                    Expression::String("single, \"double\"".to_string()),
+                    Expression::String("it's".to_string()),
+                ]
+            );
+        } else {
+            panic!("Expected FunctionCall");
+        }
+    }
+
+    #[test]
+    fn test_parse_command_keeps_escaped_quotes() {
+        let input = r#"show() echo "a \"quoted\" word""#;
+        let result = parse_script(input).unwrap();
+
+        if let Statement::SimpleFunctionDef { command_template, .. } = &result.statements[0] {
+            assert_eq!(command_template, r#"echo "a \"quoted\" word""#);
+        } else {
+            panic!("Expected SimpleFunctionDef");
+        }
+    }
+
+    #[test]
+    fn test_parse_last_exit_status() {
+        let result = parse_script("status() echo \"last $?\" $?").unwrap();
+
+        if let Statement::SimpleFunctionDef {
+            command_template, ..
+        } = &result.statements[0]
+        {
+            assert_eq!(command_template, "echo \"last $?\" $?");
+        } else {
+            panic!("Expected SimpleFunctionDef");
+        }
+    }
 }
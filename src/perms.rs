@@ -0,0 +1,62 @@
+//! File permissions for `@umask` and `@fix-perms`, so functions producing
+//! artifacts don't need chmod chains that differ between platforms.
+//!
+//! Windows has no permission bits: `@umask` doesn't apply there, and
+//! `@fix-perms` only adds or removes the write permission, as the read-only
+//! attribute.
+
+use crate::ast::{FileMode, ModeOp};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// The permission bits of a file with `current` ones after `mode`.
+pub fn apply(mode: &FileMode, current: u32) -> u32 {
+    let changes = match mode {
+        FileMode::Octal(bits) => return *bits,
+        FileMode::Symbolic(changes) => changes,
+    };
+    changes.iter().fold(current & 0o777, |bits, change| {
+        // `rwx` for each class the change is for
+        let perms = (change.perms * 0o111) & change.who;
+        match change.op {
+            ModeOp::Add => bits | perms,
+            ModeOp::Remove => bits & !perms,
+            ModeOp::Set => (bits & !change.who) | perms,
+        }
+    })
+}
+
+/// Change the permissions of the file at `path` as `mode` says.
+pub fn fix(mode: &FileMode, path: &Path) -> io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(apply(mode, permissions.mode()));
+    }
+    #[cfg(not(unix))]
+    {
+        let current = if permissions.readonly() { 0o555 } else { 0o777 };
+        permissions.set_readonly(apply(mode, current) & 0o200 == 0);
+    }
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Start `command` with `umask` as its umask, leaving ours as it is for
+/// whatever else runs at the same time.
+pub fn set_umask(command: &mut Command, umask: u32) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // SAFETY: umask is async-signal-safe and touches nothing of ours
+        unsafe {
+            command.pre_exec(move || {
+                libc::umask(umask as libc::mode_t);
+                Ok(())
+            });
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (command, umask);
+}
@@ -0,0 +1,308 @@
+//! Reading the output pipes of running commands line by line.
+//!
+//! On Unix one thread polls the pipes of every running command, so running
+//! many commands at once doesn't take two reader threads each. Elsewhere
+//! each pipe gets a thread of its own. Either way the readers only hand
+//! what they read over to a thread of the command's own, which splits it
+//! into lines for its handlers, so a slow handler only holds up its own
+//! command.
+
+use std::io::PipeReader;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Lines longer than this are handed over in pieces, so output without
+/// newlines isn't held in memory however long it gets.
+const MAX_LINE: usize = 1 << 20;
+
+/// Called with each line read from a pipe, newline included; the last line,
+/// and the pieces of lines longer than `MAX_LINE`, may lack one.
+pub type LineHandler = Box<dyn FnMut(&[u8]) + Send>;
+
+/// The pipes of a command being read. [`Reading::join`] waits until all of
+/// them were handled.
+pub struct Reading(Receiver<()>);
+
+impl Reading {
+    /// Wait until the pipes are closed and their last lines were handled.
+    pub fn join(self) {
+        // Nothing is ever sent: the sender is dropped when handling ends
+        let _ = self.0.recv();
+    }
+}
+
+/// Read each of the pipes of a command until it closes, passing each line to
+/// its handler. Lines of different pipes are handled in the order they were
+/// read.
+pub fn read_lines(pipes: Vec<(PipeReader, LineHandler)>) -> Reading {
+    let (done, reading) = mpsc::channel();
+    let (chunks, received) = mpsc::channel();
+    let mut lines = Vec::with_capacity(pipes.len());
+    for (pipe, (reader, on_line)) in pipes.into_iter().enumerate() {
+        lines.push(Lines {
+            on_line,
+            pending: Vec::new(),
+        });
+        imp::start(Source {
+            reader,
+            pipe,
+            chunks: chunks.clone(),
+        });
+    }
+    drop(chunks);
+    std::thread::spawn(move || handle_chunks(received, lines, done));
+    Reading(reading)
+}
+
+/// What was read from one of the pipes of a command: some data, or `None`
+/// once it closed.
+struct Chunk {
+    pipe: usize,
+    data: Option<Vec<u8>>,
+}
+
+/// Split the chunks read from the pipes of a command into lines for their
+/// handlers, until every pipe closed. `_done` is dropped then, which ends
+/// the wait of its `Reading`.
+fn handle_chunks(received: Receiver<Chunk>, mut lines: Vec<Lines>, _done: Sender<()>) {
+    for Chunk { pipe, data } in received {
+        match data {
+            Some(data) => lines[pipe].feed(&data),
+            None => lines[pipe].finish(),
+        }
+    }
+}
+
+/// A pipe being read, on the reading side.
+struct Source {
+    reader: PipeReader,
+    /// Which pipe of its command this is.
+    pipe: usize,
+    /// Where what is read goes to be handled.
+    chunks: Sender<Chunk>,
+}
+
+impl Source {
+    /// Hand `data` over to be handled.
+    fn send(&self, data: &[u8]) {
+        let _ = self.chunks.send(Chunk {
+            pipe: self.pipe,
+            data: Some(data.to_vec()),
+        });
+    }
+
+    /// Report the pipe closed.
+    fn close(self) {
+        let _ = self.chunks.send(Chunk {
+            pipe: self.pipe,
+            data: None,
+        });
+    }
+}
+
+/// A pipe being read, on the handling side.
+struct Lines {
+    on_line: LineHandler,
+    /// Bytes read after the last complete line.
+    pending: Vec<u8>,
+}
+
+impl Lines {
+    /// Handle the complete lines of `data`, keeping the rest for later.
+    fn feed(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let mut start = 0;
+        while let Some(end) = self.pending[start..].iter().position(|&b| b == b'\n') {
+            let end = start + end + 1;
+            (self.on_line)(&self.pending[start..end]);
+            start = end;
+        }
+        while self.pending.len() - start >= MAX_LINE {
+            (self.on_line)(&self.pending[start..start + MAX_LINE]);
+            start += MAX_LINE;
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Handle the last line, if it had no newline.
+    fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            (self.on_line)(&self.pending);
+            self.pending.clear();
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::Source;
+    use std::io::{PipeReader, PipeWriter, Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::sync::{Mutex, OnceLock};
+
+    /// Pipes waiting to be picked up by the polling thread, and the pipe
+    /// that wakes it up to do so.
+    struct Poller {
+        added: Mutex<Vec<Source>>,
+        wake: PipeWriter,
+    }
+
+    static POLLER: OnceLock<Poller> = OnceLock::new();
+
+    pub fn start(source: Source) {
+        let poller = POLLER.get_or_init(|| {
+            let (wake_reader, wake) = std::io::pipe().expect("cannot create a pipe");
+            std::thread::spawn(move || poll_loop(wake_reader));
+            Poller {
+                added: Mutex::new(Vec::new()),
+                wake,
+            }
+        });
+        poller
+            .added
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(source);
+        let _ = (&poller.wake).write_all(&[0]);
+    }
+
+    fn poll_loop(mut wake: PipeReader) {
+        let mut sources: Vec<Source> = Vec::new();
+        let mut buffer = vec![0; 8192];
+        loop {
+            if let Some(poller) = POLLER.get() {
+                sources.append(&mut poller.added.lock().unwrap_or_else(|e| e.into_inner()));
+            }
+            let mut fds: Vec<libc::pollfd> = std::iter::once(wake.as_raw_fd())
+                .chain(sources.iter().map(|source| source.reader.as_raw_fd()))
+                .map(|fd| libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            // SAFETY: fds is a valid array of fds.len() pollfd structs
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if ready < 0 {
+                // Interrupted by the signals forwarded to process groups
+                continue;
+            }
+            if fds[0].revents != 0 {
+                let _ = wake.read(&mut buffer);
+            }
+            // Back to front, so removing a source doesn't move those not
+            // yet looked at
+            for index in (0..sources.len()).rev() {
+                if fds[index + 1].revents == 0 {
+                    continue;
+                }
+                let source = &sources[index];
+                match (&source.reader).read(&mut buffer) {
+                    Ok(n) if n > 0 => source.send(&buffer[..n]),
+                    _ => sources.swap_remove(index).close(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use super::Source;
+    use std::io::Read;
+
+    pub fn start(source: Source) {
+        std::thread::spawn(move || {
+            let mut buffer = vec![0; 8192];
+            while let Ok(n @ 1..) = (&source.reader).read(&mut buffer) {
+                source.send(&buffer[..n]);
+            }
+            source.close();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_slow_handler_holds_up_only_its_own_command() {
+        let (slow_reader, mut slow_writer) = std::io::pipe().unwrap();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let slow = read_lines(vec![(
+            slow_reader,
+            Box::new(move |_: &[u8]| {
+                // Blocks until every other command was read
+                let _ = released.lock().unwrap().recv();
+            }),
+        )]);
+        writeln!(slow_writer, "slow").unwrap();
+        drop(slow_writer);
+        // Give the poller time to hand the slow line over
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Several commands writing at once, each with stdout and stderr
+        let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+        let mut writers = Vec::new();
+        let mut readings = Vec::new();
+        for command in 0..4 {
+            let mut pipes: Vec<(PipeReader, LineHandler)> = Vec::new();
+            for stream in ["out", "err"] {
+                let (reader, writer) = std::io::pipe().unwrap();
+                let seen = seen.clone();
+                pipes.push((
+                    reader,
+                    Box::new(move |line: &[u8]| {
+                        let line = String::from_utf8_lossy(line);
+                        seen.lock().unwrap().push(line.into_owned());
+                    }),
+                ));
+                writers.push((command, stream, writer));
+            }
+            readings.push(read_lines(pipes));
+        }
+        let writing: Vec<_> = writers
+            .into_iter()
+            .map(|(command, stream, mut writer)| {
+                std::thread::spawn(move || {
+                    for line in 0..200 {
+                        writeln!(writer, "{} {} {}", command, stream, line).unwrap();
+                        if line % 50 == 0 {
+                            std::thread::sleep(Duration::from_millis(5));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writing in writing {
+            writing.join().unwrap();
+        }
+        for reading in readings {
+            reading.join();
+        }
+        release.send(()).unwrap();
+        slow.join();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 4 * 2 * 200);
+        // Every pipe's lines arrive whole and in order
+        for command in 0..4 {
+            for stream in ["out", "err"] {
+                let prefix = format!("{} {} ", command, stream);
+                let lines: Vec<&String> = seen.iter().filter(|l| l.starts_with(&prefix)).collect();
+                let expected: Vec<String> = (0..200)
+                    .map(|line| format!("{}{}\n", prefix, line))
+                    .collect();
+                assert_eq!(lines, expected.iter().collect::<Vec<_>>());
+            }
+        }
+        // and the commands' lines were handled interleaved, as they came
+        let first_of_last = seen.iter().position(|l| l.starts_with("3 ")).unwrap();
+        let last_of_first = seen.iter().rposition(|l| l.starts_with("0 ")).unwrap();
+        assert!(first_of_last < last_of_first);
+    }
+}
@@ -0,0 +1,399 @@
+//! Child processes of concurrently running functions.
+//!
+//! While functions run in parallel, each command is started in its own
+//! process group and registered here, so that a failure can terminate
+//! everything still running, including grandchildren of the shell. Signals
+//! such as Ctrl-C, which no longer reach those background groups from the
+//! terminal, are forwarded to them.
+//!
+//! Termination is graceful: groups get SIGTERM, and those still running
+//! after the grace period (`commands.grace_period`, 5s unless set) get
+//! SIGKILL, as does everything after a second Ctrl-C.
+//!
+//! On Windows a group is a job object holding the command and everything
+//! it starts, in a console process group of its own: terminating sends it
+//! Ctrl-Break, and killing terminates the whole job.
+
+use std::io;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Maximum number of process groups tracked at once; commands beyond this
+/// still run, they just can't be cancelled.
+const MAX_GROUPS: usize = 64;
+
+static GROUPS: [AtomicI32; MAX_GROUPS] = [const { AtomicI32::new(0) }; MAX_GROUPS];
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+/// Set when Ctrl-C (or SIGTERM) reaches `run` itself; unlike `CANCELLED`,
+/// never reset.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Grace period unless `commands.grace_period` is set.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// The grace period in milliseconds.
+static GRACE_MS: AtomicU64 = AtomicU64::new(DEFAULT_GRACE_PERIOD.as_millis() as u64);
+
+/// Set how long terminated process groups get to exit before they are killed.
+pub fn set_grace_period(grace: Duration) {
+    GRACE_MS.store(grace.as_millis() as u64, Ordering::SeqCst);
+}
+
+fn grace_period() -> Duration {
+    Duration::from_millis(GRACE_MS.load(Ordering::SeqCst))
+}
+
+/// Run `command` to completion in its own process group, registered so that
+/// [`cancel_all`] can terminate it.
+pub fn run_in_group(command: &mut Command) -> io::Result<ExitStatus> {
+    if is_cancelled() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        install_signal_forwarding();
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        job::install_ctrl_handler();
+        command.creation_flags(job::CREATE_NEW_PROCESS_GROUP);
+    }
+    let mut child = command.spawn()?;
+
+    let pid = child.id() as i32;
+    #[cfg(windows)]
+    job::assign(&child);
+    let slot = GROUPS.iter().position(|slot| {
+        slot.compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    });
+    // Cancellation may have happened while spawning
+    if is_cancelled() {
+        terminate(pid);
+    }
+    let status = child.wait();
+    // What the shell started may outlive it: give it the rest of the grace
+    // period, still registered so a second Ctrl-C reaches it
+    if is_cancelled() {
+        let deadline = Instant::now() + grace_period();
+        while group_alive(pid) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        if group_alive(pid) {
+            kill(pid);
+        }
+    }
+    if let Some(slot) = slot {
+        GROUPS[slot].store(0, Ordering::SeqCst);
+    }
+    #[cfg(windows)]
+    job::release(pid);
+    status
+}
+
+/// Terminate every registered process group, killing those still running
+/// after the grace period, and refuse to start new ones until [`reset`] is
+/// called.
+pub fn cancel_all() {
+    CANCELLED.store(true, Ordering::SeqCst);
+    let terminated: Vec<i32> = GROUPS
+        .iter()
+        .map(|slot| slot.load(Ordering::SeqCst))
+        .filter(|&pid| pid > 0)
+        .collect();
+    for &pid in &terminated {
+        terminate(pid);
+    }
+    if terminated.is_empty() {
+        return;
+    }
+    let grace = grace_period();
+    std::thread::spawn(move || {
+        std::thread::sleep(grace);
+        // Only groups whose command hasn't finished: a finished one's id may
+        // have been reused
+        for slot in &GROUPS {
+            let pid = slot.load(Ordering::SeqCst);
+            if pid > 0 && terminated.contains(&pid) {
+                kill(pid);
+            }
+        }
+    });
+}
+
+/// Whether running functions have been cancelled.
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Whether `run` was interrupted while forwarding signals to the groups.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Allow processes to start again after a cancelled run.
+pub fn reset() {
+    CANCELLED.store(false, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn terminate(pgid: i32) {
+    // SAFETY: kill has no memory-safety preconditions
+    unsafe {
+        libc::kill(-pgid, libc::SIGTERM);
+    }
+}
+
+#[cfg(windows)]
+fn terminate(pgid: i32) {
+    job::terminate(pgid);
+}
+
+#[cfg(unix)]
+fn kill(pgid: i32) {
+    // SAFETY: kill has no memory-safety preconditions
+    unsafe {
+        libc::kill(-pgid, libc::SIGKILL);
+    }
+}
+
+#[cfg(windows)]
+fn kill(pgid: i32) {
+    job::kill(pgid);
+}
+
+/// Whether any process of the group is still running. Zombies don't count:
+/// killed orphans may never be reaped, as in containers without an init.
+#[cfg(target_os = "linux")]
+fn group_alive(pgid: i32) -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            return false;
+        };
+        // `pid (command) state ppid pgrp ...`, where the command may hold
+        // anything, including parentheses
+        let Some((_, fields)) = stat.rsplit_once(')') else {
+            return false;
+        };
+        let mut fields = fields.split_whitespace();
+        let state = fields.next();
+        let pgrp = fields.nth(1).and_then(|pgrp| pgrp.parse::<i32>().ok());
+        pgrp == Some(pgid) && state != Some("Z")
+    })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn group_alive(pgid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the group exists
+    unsafe { libc::kill(-pgid, 0) == 0 }
+}
+
+#[cfg(windows)]
+fn group_alive(pgid: i32) -> bool {
+    job::alive(pgid)
+}
+
+/// Forward SIGINT and SIGTERM received by `run` to the registered groups.
+#[cfg(unix)]
+fn install_signal_forwarding() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+
+    extern "C" fn forward(signal: libc::c_int) {
+        // Only atomics and kill here: both are async-signal-safe. A second
+        // signal doesn't wait for the grace period
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let again = CANCELLED.swap(true, Ordering::SeqCst);
+        let signal = if again { libc::SIGKILL } else { signal };
+        for slot in &GROUPS {
+            let pid = slot.load(Ordering::SeqCst);
+            if pid > 0 {
+                // SAFETY: kill is async-signal-safe
+                unsafe {
+                    libc::kill(-pid, signal);
+                }
+            }
+        }
+    }
+
+    INSTALL.call_once(|| {
+        let handler = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: the handler only performs async-signal-safe operations
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    });
+}
+
+/// Job objects for the process groups of Windows, keyed by the process id
+/// of the command, which is also the id of its console process group.
+#[cfg(windows)]
+mod job {
+    use super::{CANCELLED, GROUPS, INTERRUPTED};
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use std::sync::Mutex;
+    use std::sync::atomic::Ordering;
+
+    type Handle = *mut c_void;
+
+    pub const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+    const JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION: i32 = 1;
+
+    /// `JOBOBJECT_BASIC_ACCOUNTING_INFORMATION`, filled in by Windows.
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct BasicAccountingInformation {
+        total_user_time: i64,
+        total_kernel_time: i64,
+        this_period_total_user_time: i64,
+        this_period_total_kernel_time: i64,
+        total_page_fault_count: u32,
+        total_processes: u32,
+        active_processes: u32,
+        total_terminated_processes: u32,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+        fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+        fn TerminateJobObject(job: Handle, exit_code: u32) -> i32;
+        fn QueryInformationJobObject(
+            job: Handle,
+            class: i32,
+            information: *mut c_void,
+            length: u32,
+            return_length: *mut u32,
+        ) -> i32;
+        fn CloseHandle(handle: Handle) -> i32;
+        fn GenerateConsoleCtrlEvent(event: u32, process_group: u32) -> i32;
+        fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+    }
+
+    /// The job of each running command, by process id. Handles are stored
+    /// as integers, as raw pointers can't be shared between threads.
+    static JOBS: Mutex<Vec<(i32, isize)>> = Mutex::new(Vec::new());
+
+    /// Put `child` in a job of its own, which the processes it starts join
+    /// too. Without a job, as when it can't be created, only the child
+    /// itself can be terminated.
+    pub fn assign(child: &Child) {
+        // SAFETY: null attributes and name create an unnamed job
+        let job = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+        if job.is_null() {
+            return;
+        }
+        // The child runs before it's assigned, but a shell takes longer to
+        // start than that before it starts anything itself.
+        // SAFETY: both handles are open: the child isn't waited on yet
+        if unsafe { AssignProcessToJobObject(job, child.as_raw_handle()) } == 0 {
+            // SAFETY: job was created above and isn't used after this
+            unsafe { CloseHandle(job) };
+            return;
+        }
+        JOBS.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((child.id() as i32, job as isize));
+    }
+
+    /// Close the job of a finished command. What it started keeps running,
+    /// as background processes do on Unix.
+    pub fn release(pid: i32) {
+        let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(index) = jobs.iter().position(|(id, _)| *id == pid) {
+            let (_, job) = jobs.swap_remove(index);
+            // SAFETY: the handle was removed, so nothing else uses it
+            unsafe { CloseHandle(job as Handle) };
+        }
+    }
+
+    /// Ask the group to stop, as Ctrl-Break at the console would.
+    pub fn terminate(pid: i32) {
+        // SAFETY: no memory-safety preconditions
+        unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid as u32) };
+    }
+
+    /// Terminate every process of the job.
+    pub fn kill(pid: i32) {
+        // The lock keeps the handle from being closed meanwhile
+        let jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(&(_, job)) = jobs.iter().find(|(id, _)| *id == pid) {
+            // SAFETY: the handle stays open while the lock is held
+            unsafe { TerminateJobObject(job as Handle, 1) };
+        }
+    }
+
+    /// Whether any process of the job is still running.
+    pub fn alive(pid: i32) -> bool {
+        let jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(job) = jobs
+            .iter()
+            .find(|(id, _)| *id == pid)
+            .map(|&(_, job)| job as Handle)
+        else {
+            return false;
+        };
+        let mut information = BasicAccountingInformation::default();
+        // SAFETY: the handle stays open while the lock is held, and the
+        // buffer is the size given
+        let ok = unsafe {
+            QueryInformationJobObject(
+                job,
+                JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION,
+                (&mut information as *mut BasicAccountingInformation).cast(),
+                std::mem::size_of::<BasicAccountingInformation>() as u32,
+                std::ptr::null_mut(),
+            )
+        };
+        ok != 0 && information.active_processes > 0
+    }
+
+    /// Forward Ctrl-C and Ctrl-Break, which don't reach commands in process
+    /// groups of their own, to the registered groups.
+    pub fn install_ctrl_handler() {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+
+        // Windows runs the handler on a thread of its own, so unlike a
+        // Unix signal handler it may take locks
+        unsafe extern "system" fn forward(event: u32) -> i32 {
+            if event != CTRL_C_EVENT && event != CTRL_BREAK_EVENT {
+                return 0;
+            }
+            // A second Ctrl-C doesn't wait for the grace period
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            let again = CANCELLED.swap(true, Ordering::SeqCst);
+            for slot in &GROUPS {
+                let pid = slot.load(Ordering::SeqCst);
+                if pid > 0 {
+                    if again {
+                        kill(pid);
+                    } else {
+                        terminate(pid);
+                    }
+                }
+            }
+            1
+        }
+
+        INSTALL.call_once(|| {
+            // SAFETY: forward has the signature of a console control handler
+            unsafe { SetConsoleCtrlHandler(Some(forward), 1) };
+        });
+    }
+}
@@ -0,0 +1,592 @@
+//! Line editing for the REPL: the cursor keys, Home/End and the usual
+//! Ctrl shortcuts edit the line, Up and Down walk the history, which is
+//! kept in `~/.run_history` between sessions, Tab completes the word before
+//! the cursor and Ctrl+C clears the line.
+//!
+//! Editing needs a Unix terminal; otherwise, as when input is piped or output
+//! is plain, lines are read as they are.
+
+// Elsewhere the editing below is left unused
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+/// Lines kept in the history file.
+const MAX_HISTORY: usize = 1000;
+
+#[cfg(unix)]
+pub use unix::{RawMode, read_key};
+
+/// A keypress, decoded from the bytes the terminal sends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Ctrl(u8),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Unknown,
+}
+
+/// What reading a line ended with.
+pub enum Input {
+    Line(String),
+    /// Ctrl+C: the line was abandoned.
+    Interrupted,
+    /// Ctrl+D on an empty line, or the end of the input.
+    Eof,
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    /// Where the history is saved, if there is a home directory.
+    path: Option<PathBuf>,
+    /// Words Tab completes.
+    words: Vec<String>,
+}
+
+impl Editor {
+    /// An editor with the history saved in `~/.run_history`.
+    pub fn new() -> Editor {
+        let path = crate::config::get_home_dir().map(|home| home.join(".run_history"));
+        let mut history: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(String::from).collect())
+            .unwrap_or_default();
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+            if let Some(path) = &path {
+                let _ = fs::write(path, history.join("\n") + "\n");
+            }
+        }
+        Editor {
+            history,
+            path,
+            words: Vec::new(),
+        }
+    }
+
+    /// Set the words Tab completes, such as function names and `$variables`.
+    pub fn set_completions(&mut self, words: Vec<String>) {
+        self.words = words;
+    }
+
+    /// Read a line after showing `prompt`.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        #[cfg(unix)]
+        if io::stdin().is_terminal() && io::stdout().is_terminal() && !crate::style::plain() {
+            return unix::edit_line(prompt, &self.history, &self.words);
+        }
+        let mut stdout = io::stdout();
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+        let mut line = String::new();
+        Ok(match io::stdin().lock().read_line(&mut line)? {
+            0 => Input::Eof,
+            _ => Input::Line(line.trim_end_matches(['\n', '\r']).to_string()),
+        })
+    }
+
+    /// Add an entered line to the history, unless it repeats the last one.
+    /// Only lines typed at a terminal are saved.
+    pub fn add_history(&mut self, line: &str) {
+        if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if !io::stdin().is_terminal() {
+            return;
+        }
+        if let Some(path) = &self.path {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+        }
+    }
+}
+
+/// The words of `words` that start with `prefix`, sorted, and what they
+/// all continue `prefix` with.
+fn complete<'a>(prefix: &str, words: &'a [String]) -> (Vec<&'a str>, String) {
+    let mut matches: Vec<&str> = words
+        .iter()
+        .map(String::as_str)
+        .filter(|word| word.starts_with(prefix))
+        .collect();
+    matches.sort_unstable();
+    matches.dedup();
+    let common = matches.first().map_or(String::new(), |first| {
+        let mut common = &first[prefix.len()..];
+        for word in &matches[1..] {
+            let shared = common
+                .char_indices()
+                .zip(word[prefix.len()..].chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(word.len() - prefix.len()), |((i, _), _)| i);
+            common = &common[..shared];
+        }
+        common.to_string()
+    });
+    (matches, common)
+}
+
+/// A line being edited: its characters, the cursor, and how far back Up
+/// has walked the history.
+#[derive(Debug, Default)]
+struct Buffer {
+    line: Vec<char>,
+    /// Characters before the cursor.
+    cursor: usize,
+    /// Entries back from the end of the history, 0 while not walking it.
+    back: usize,
+    /// The line being typed before walking the history started.
+    draft: Vec<char>,
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.line.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    /// Apply an editing key. False when it changes nothing.
+    fn edit(&mut self, key: Key) -> bool {
+        let (line, cursor) = (&mut self.line, &mut self.cursor);
+        match key {
+            Key::Char(c) => {
+                line.insert(*cursor, c);
+                *cursor += 1;
+            }
+            Key::Backspace if *cursor > 0 => {
+                *cursor -= 1;
+                line.remove(*cursor);
+            }
+            Key::Delete | Key::Ctrl(b'd') if *cursor < line.len() => {
+                line.remove(*cursor);
+            }
+            Key::Left | Key::Ctrl(b'b') => *cursor = cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl(b'f') => *cursor = (*cursor + 1).min(line.len()),
+            Key::Home | Key::Ctrl(b'a') => *cursor = 0,
+            Key::End | Key::Ctrl(b'e') => *cursor = line.len(),
+            Key::Ctrl(b'k') => line.truncate(*cursor),
+            Key::Ctrl(b'u') => {
+                line.drain(..*cursor);
+                *cursor = 0;
+            }
+            Key::Ctrl(b'w') => {
+                let mut start = *cursor;
+                while start > 0 && line[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && line[start - 1] != ' ' {
+                    start -= 1;
+                }
+                line.drain(start..*cursor);
+                *cursor = start;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Show the entry of `history` before the one shown, keeping the line
+    /// being typed for when Down comes back to it. False at the oldest.
+    fn previous(&mut self, history: &[String]) -> bool {
+        if self.back >= history.len() {
+            return false;
+        }
+        if self.back == 0 {
+            self.draft = self.line.clone();
+        }
+        self.back += 1;
+        self.show(history[history.len() - self.back].chars().collect());
+        true
+    }
+
+    /// Show the entry of `history` after the one shown, or the line that was
+    /// being typed after the newest. False when not walking the history.
+    fn next(&mut self, history: &[String]) -> bool {
+        if self.back == 0 {
+            return false;
+        }
+        self.back -= 1;
+        let line = match self.back {
+            0 => std::mem::take(&mut self.draft),
+            back => history[history.len() - back].chars().collect(),
+        };
+        self.show(line);
+        true
+    }
+
+    fn show(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    /// Complete the word before the cursor, as far back as a space or the
+    /// `(` of a call, with what all of `words` starting with it continue it
+    /// with. The matches to show when that is nothing and there are several.
+    fn complete<'a>(&mut self, words: &'a [String]) -> Vec<&'a str> {
+        let start = self.line[..self.cursor]
+            .iter()
+            .rposition(|c| c.is_whitespace() || *c == '(')
+            .map_or(0, |i| i + 1);
+        let prefix: String = self.line[start..self.cursor].iter().collect();
+        let (matches, common) = complete(&prefix, words);
+        self.insert(&common);
+        match common.is_empty() && matches.len() > 1 {
+            true => matches,
+            false => Vec::new(),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::{Buffer, Input, Key};
+    use std::io::{self, Read, Write};
+    use std::os::fd::RawFd;
+
+    /// The terminal in raw mode, restored when dropped, and so also when an
+    /// error is returned or a panic unwinds past it.
+    pub struct RawMode {
+        fd: RawFd,
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        /// Put the terminal of stdin in raw mode.
+        pub fn enable() -> io::Result<RawMode> {
+            RawMode::enable_on(libc::STDIN_FILENO)
+        }
+
+        /// Put the terminal `fd` refers to in raw mode.
+        pub fn enable_on(fd: RawFd) -> io::Result<RawMode> {
+            // SAFETY: termios is plain data, filled in by tcgetattr
+            let mut original: libc::termios = unsafe { std::mem::zeroed() };
+            // SAFETY: valid fd and pointer to a termios
+            if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            // Keys arrive one at a time, unechoed, with Ctrl+C as a key
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            // SAFETY: valid fd and pointer to a termios
+            if unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, &raw) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawMode { fd, original })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: restores the settings read in enable
+            unsafe {
+                libc::tcsetattr(self.fd, libc::TCSADRAIN, &self.original);
+            }
+        }
+    }
+
+    fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        Ok(match input.read(&mut byte)? {
+            0 => None,
+            _ => Some(byte[0]),
+        })
+    }
+
+    pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+        let Some(byte) = read_byte(input)? else {
+            return Ok(None);
+        };
+        let key = match byte {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x1b => read_escape(input)?,
+            0..=0x1f => Key::Ctrl(byte + b'a' - 1),
+            _ => {
+                // The rest of a UTF-8 sequence
+                let len = match byte {
+                    0xf0.. => 4,
+                    0xe0.. => 3,
+                    0xc0.. => 2,
+                    _ => 1,
+                };
+                let mut bytes = vec![byte];
+                for _ in 1..len {
+                    bytes.extend(read_byte(input)?);
+                }
+                match std::str::from_utf8(&bytes)
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                {
+                    Some(c) => Key::Char(c),
+                    None => Key::Unknown,
+                }
+            }
+        };
+        Ok(Some(key))
+    }
+
+    /// The key of an escape sequence such as `ESC [ A`.
+    fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+        let Some(b'[' | b'O') = read_byte(input)? else {
+            return Ok(Key::Unknown);
+        };
+        let mut parameter = Vec::new();
+        loop {
+            let Some(byte) = read_byte(input)? else {
+                return Ok(Key::Unknown);
+            };
+            if byte.is_ascii_digit() || byte == b';' {
+                parameter.push(byte);
+                continue;
+            }
+            return Ok(match (byte, parameter.as_slice()) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) | (b'~', b"1" | b"7") => Key::Home,
+                (b'F', _) | (b'~', b"4" | b"8") => Key::End,
+                (b'~', b"3") => Key::Delete,
+                (b'~', b"5") => Key::PageUp,
+                (b'~', b"6") => Key::PageDown,
+                _ => Key::Unknown,
+            });
+        }
+    }
+
+    /// Show `prompt` and `line` with the cursor after `cursor` characters.
+    fn redraw(out: &mut impl Write, prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+        let text: String = line.iter().collect();
+        write!(out, "\r{}{}\x1b[K", prompt, text)?;
+        if cursor < line.len() {
+            write!(out, "\x1b[{}D", line.len() - cursor)?;
+        }
+        out.flush()
+    }
+
+    pub fn edit_line(prompt: &str, history: &[String], words: &[String]) -> io::Result<Input> {
+        let _raw = RawMode::enable()?;
+        let mut input = io::stdin().lock();
+        let mut out = io::stdout().lock();
+        let mut buffer = Buffer::default();
+        redraw(&mut out, prompt, &buffer.line, buffer.cursor)?;
+        loop {
+            let Some(key) = read_key(&mut input)? else {
+                return Ok(Input::Eof);
+            };
+            let changed = match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Input::Line(buffer.text()));
+                }
+                Key::Ctrl(b'c') => {
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Ok(Input::Interrupted);
+                }
+                Key::Ctrl(b'd') if buffer.line.is_empty() => return Ok(Input::Eof),
+                Key::Tab => {
+                    let choices = buffer.complete(words);
+                    if !choices.is_empty() {
+                        // Nothing more in common: show the choices
+                        write!(out, "\r\n{}\r\n", choices.join("  "))?;
+                    }
+                    true
+                }
+                Key::Up | Key::Ctrl(b'p') => buffer.previous(history),
+                Key::Down | Key::Ctrl(b'n') => buffer.next(history),
+                key => buffer.edit(key),
+            };
+            if changed {
+                redraw(&mut out, prompt, &buffer.line, buffer.cursor)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> Buffer {
+        let mut buffer = Buffer::default();
+        buffer.insert(text);
+        buffer
+    }
+
+    #[test]
+    fn test_edit_keys() {
+        let mut buffer = typed("echo hello world");
+        assert!(buffer.edit(Key::Ctrl(b'w')));
+        assert_eq!(buffer.text(), "echo hello ");
+        assert!(buffer.edit(Key::Home));
+        assert!(buffer.edit(Key::Right));
+        assert!(buffer.edit(Key::Char('X')));
+        assert_eq!((buffer.text().as_str(), buffer.cursor), ("eXcho hello ", 2));
+        assert!(buffer.edit(Key::Backspace));
+        assert!(buffer.edit(Key::Delete));
+        assert_eq!(buffer.text(), "eho hello ");
+        assert!(buffer.edit(Key::Ctrl(b'k')));
+        assert_eq!(buffer.text(), "e");
+        assert!(buffer.edit(Key::Ctrl(b'u')));
+        assert_eq!((buffer.text().as_str(), buffer.cursor), ("", 0));
+        // Keys that change nothing ask for no redraw
+        assert!(!buffer.edit(Key::Backspace));
+        assert!(!buffer.edit(Key::Delete));
+        assert!(!buffer.edit(Key::Unknown));
+    }
+
+    #[test]
+    fn test_history_navigation() {
+        let history = vec!["first".to_string(), "second".to_string()];
+        let mut buffer = typed("draft");
+        assert!(!buffer.next(&history));
+
+        assert!(buffer.previous(&history));
+        assert_eq!((buffer.text().as_str(), buffer.cursor), ("second", 6));
+        assert!(buffer.previous(&history));
+        assert_eq!(buffer.text(), "first");
+        assert!(!buffer.previous(&history));
+        assert_eq!(buffer.text(), "first");
+
+        assert!(buffer.next(&history));
+        assert_eq!(buffer.text(), "second");
+        // Past the newest entry is the line that was being typed
+        assert!(buffer.next(&history));
+        assert_eq!(buffer.text(), "draft");
+        assert!(!buffer.next(&history));
+
+        let mut buffer = Buffer::default();
+        assert!(!buffer.previous(&[]));
+    }
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn test_complete_no_match() {
+        let words = words(&["build", "test"]);
+        assert_eq!(complete("deploy", &words), (vec![], String::new()));
+
+        let mut buffer = typed("run dep");
+        assert!(buffer.complete(&words).is_empty());
+        assert_eq!(buffer.text(), "run dep");
+    }
+
+    #[test]
+    fn test_complete_single_match() {
+        let words = words(&["build", "test", "build"]);
+        assert_eq!(complete("bu", &words), (vec!["build"], "ild".to_string()));
+
+        // The word starts after the `(` of a call
+        let mut buffer = typed("greet(bu");
+        assert!(buffer.complete(&words).is_empty());
+        assert_eq!((buffer.text().as_str(), buffer.cursor), ("greet(build", 11));
+    }
+
+    #[test]
+    fn test_complete_longer_common_prefix() {
+        let words = words(&["deploy:staging", "deploy:prod", "build"]);
+        assert_eq!(
+            complete("d", &words),
+            (vec!["deploy:prod", "deploy:staging"], "eploy:".to_string())
+        );
+
+        // What they share is completed first, then the choices are shown
+        let mut buffer = typed("d");
+        assert!(buffer.complete(&words).is_empty());
+        assert_eq!(buffer.text(), "deploy:");
+        assert_eq!(buffer.complete(&words), ["deploy:prod", "deploy:staging"]);
+        assert_eq!(buffer.text(), "deploy:");
+    }
+
+    #[test]
+    fn test_complete_empty_prefix() {
+        let words = words(&["test", "$version", "build"]);
+        assert_eq!(
+            complete("", &words),
+            (vec!["$version", "build", "test"], String::new())
+        );
+
+        let mut buffer = typed("echo ");
+        assert_eq!(buffer.complete(&words), ["$version", "build", "test"]);
+        assert_eq!(buffer.text(), "echo ");
+        assert_eq!(complete("", &[]), (vec![], String::new()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_raw_mode_restored_on_panic_and_error() {
+        let (mut leader, mut follower) = (0, 0);
+        // SAFETY: valid pointers to fds; the other arguments may be null
+        let opened = unsafe {
+            libc::openpty(
+                &mut leader,
+                &mut follower,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(opened, 0);
+        let canonical = || {
+            // SAFETY: termios is plain data, filled in by tcgetattr
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            // SAFETY: valid fd and pointer to a termios
+            assert_eq!(unsafe { libc::tcgetattr(follower, &mut termios) }, 0);
+            termios.c_lflag & libc::ICANON != 0
+        };
+        assert!(canonical());
+
+        let panicked = std::panic::catch_unwind(|| {
+            let _raw = RawMode::enable_on(follower).unwrap();
+            assert!(!canonical());
+            panic!("while editing");
+        });
+        assert!(panicked.is_err());
+        assert!(canonical());
+
+        let failed = (|| -> io::Result<()> {
+            let _raw = RawMode::enable_on(follower)?;
+            Err(io::Error::other("while editing"))
+        })();
+        assert!(failed.is_err());
+        assert!(canonical());
+
+        // SAFETY: both fds were opened above and aren't used after this
+        unsafe {
+            libc::close(follower);
+            libc::close(leader);
+        }
+    }
+}
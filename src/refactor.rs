@@ -0,0 +1,231 @@
+//! `run refactor rename`: rename a function and the references to it in the
+//! Runfile text, leaving everything else (comments, spacing, quoting) as it
+//! was written.
+//!
+//! References are the places the Runfile names a function: its definition,
+//! top-level calls, `needs(...)` lists, `capture(...)` and `run_capture(...)`
+//! values, `$(name())` substitutions and `run name` invocations inside
+//! commands, including `run docker shell` for `docker:shell`. The same
+//! scanning finds where a function is defined for `run --explain`.
+
+/// The result of renaming a function in Runfile text.
+pub struct Rename {
+    pub text: String,
+    /// Definitions of the function that were renamed.
+    pub definitions: usize,
+    /// Other references that were renamed.
+    pub references: usize,
+}
+
+/// A span of a line naming the function.
+struct Site {
+    start: usize,
+    len: usize,
+    definition: bool,
+    /// Written as separate words (`run docker shell`), not `docker:shell`.
+    spaced: bool,
+}
+
+/// Rename function `old` to `new` throughout `source`.
+pub fn rename(source: &str, old: &str, new: &str) -> Rename {
+    let mut text = String::with_capacity(source.len());
+    let (mut definitions, mut references) = (0, 0);
+    for line in source.split_inclusive('\n') {
+        let mut sites = Vec::new();
+        definition_sites(line, old, &mut sites);
+        list_sites(line, "needs(", old, &mut sites);
+        list_sites(line, "capture(", old, &mut sites);
+        list_sites(line, "run_capture(", old, &mut sites);
+        run_sites(line, old, &mut sites);
+        substitution_sites(line, old, &mut sites);
+        sites.sort_by_key(|site| site.start);
+        sites.dedup_by_key(|site| site.start);
+
+        let mut rest = 0;
+        for site in sites {
+            text.push_str(&line[rest..site.start]);
+            if site.spaced && new.contains(':') {
+                text.push_str(&new.replace(':', " "));
+            } else {
+                text.push_str(new);
+            }
+            rest = site.start + site.len;
+            if site.definition {
+                definitions += 1;
+            } else {
+                references += 1;
+            }
+        }
+        text.push_str(&line[rest..]);
+    }
+    Rename {
+        text,
+        definitions,
+        references,
+    }
+}
+
+/// The line (from 1) defining function `name` in `source`, and the text of
+/// the definition with the `@attribute` and comment lines right above it.
+pub fn definition(source: &str, name: &str) -> Option<(usize, String)> {
+    let lines: Vec<&str> = source.lines().collect();
+    let index = lines.iter().position(|line| {
+        let mut sites = Vec::new();
+        definition_sites(line, name, &mut sites);
+        sites.iter().any(|site| site.definition)
+    })?;
+    let start = lines[..index]
+        .iter()
+        .rposition(|line| !line.trim_start().starts_with(['@', '#']))
+        .map_or(0, |i| i + 1);
+    // A block ends where its braces balance again
+    let mut depth = 0;
+    let mut end = index;
+    for (i, line) in lines.iter().enumerate().skip(index) {
+        depth += line.matches('{').count() as isize - line.matches('}').count() as isize;
+        end = i;
+        if depth <= 0 {
+            break;
+        }
+    }
+    Some((index + 1, lines[start..=end].join("\n")))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '.' | '/')
+}
+
+/// Whether `name` appears in `line` at `start` as a whole name.
+fn name_at(line: &str, start: usize, name: &str) -> bool {
+    line[start..].starts_with(name)
+        && !line[start + name.len()..].starts_with(is_name_char)
+        && !line[..start].ends_with(is_name_char)
+}
+
+fn skip_whitespace(line: &str, start: usize) -> usize {
+    start + (line[start..].len() - line[start..].trim_start().len())
+}
+
+/// Definitions (`old() ...`, `function old ...`) and top-level calls
+/// (`old(args)`), at the start of a line or of a one-line `ifdef`, `if` or
+/// `else` body.
+fn definition_sites(line: &str, old: &str, sites: &mut Vec<Site>) {
+    let trimmed = line.trim_start();
+    let mut starts = vec![0];
+    let guarded = ["ifdef ", "ifndef ", "if ", "else", "} else"]
+        .iter()
+        .any(|keyword| trimmed.starts_with(keyword));
+    if guarded {
+        starts.extend(line.match_indices('{').map(|(i, _)| i + 1));
+    }
+
+    for start in starts {
+        let mut i = skip_whitespace(line, start);
+        let keyword = line[i..]
+            .strip_prefix("function")
+            .is_some_and(|rest| rest.starts_with([' ', '\t']));
+        if keyword {
+            i = skip_whitespace(line, i + "function".len());
+        }
+        if !name_at(line, i, old) {
+            continue;
+        }
+        let after = line[i + old.len()..].trim_start();
+        let Some(call) = after.strip_prefix('(') else {
+            if keyword {
+                sites.push(Site {
+                    start: i,
+                    len: old.len(),
+                    definition: true,
+                    spaced: false,
+                });
+            }
+            continue;
+        };
+        // `old(...)` followed by a body defines it; otherwise it is a call
+        let body = call
+            .find(')')
+            .is_some_and(|end| !call[end + 1..].trim().is_empty());
+        sites.push(Site {
+            start: i,
+            len: old.len(),
+            definition: keyword || body,
+            spaced: false,
+        });
+    }
+}
+
+/// Entries naming `old` in each comma-separated `prefix...)` list, such as
+/// `needs(a, b)`.
+fn list_sites(line: &str, prefix: &str, old: &str, sites: &mut Vec<Site>) {
+    for (position, _) in line.match_indices(prefix) {
+        if line[..position].ends_with(is_name_char) {
+            continue;
+        }
+        let open = position + prefix.len();
+        let Some(len) = line[open..].find(')') else {
+            continue;
+        };
+        let mut offset = open;
+        for entry in line[open..open + len].split(',') {
+            let start = skip_whitespace(line, offset);
+            if entry.trim() == old {
+                sites.push(Site {
+                    start,
+                    len: old.len(),
+                    definition: false,
+                    spaced: false,
+                });
+            }
+            offset += entry.len() + 1;
+        }
+    }
+}
+
+/// `run old ...` invocations inside commands, with the name written whole
+/// or, for a namespaced name, as separate words.
+fn run_sites(line: &str, old: &str, sites: &mut Vec<Site>) {
+    let spaced = old.contains(':').then(|| old.replace(':', " "));
+    for (position, _) in line.match_indices("run") {
+        let boundary = line[..position]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || ";&|(`\"'".contains(c));
+        let after = position + "run".len();
+        if !boundary || !line[after..].starts_with([' ', '\t']) {
+            continue;
+        }
+        let start = skip_whitespace(line, after);
+        if name_at(line, start, old) {
+            sites.push(Site {
+                start,
+                len: old.len(),
+                definition: false,
+                spaced: false,
+            });
+        } else if let Some(spaced) = &spaced
+            && line[start..].starts_with(spaced.as_str())
+            && !line[start + spaced.len()..].starts_with(is_name_char)
+        {
+            sites.push(Site {
+                start,
+                len: spaced.len(),
+                definition: false,
+                spaced: true,
+            });
+        }
+    }
+}
+
+/// `$(old())` substitutions of the function's output.
+fn substitution_sites(line: &str, old: &str, sites: &mut Vec<Site>) {
+    let call = format!("$({}())", old);
+    for (position, _) in line.match_indices(&call) {
+        sites.push(Site {
+            start: position + 2,
+            len: old.len(),
+            definition: false,
+            spaced: false,
+        });
+    }
+}
@@ -1,25 +1,13 @@
 //! Interactive REPL (Read-Eval-Print Loop) for the run scripting language.
 
-use crate::{config, parser, interpreter};
-use std::env;
-use std::io::{self, Write};
+use crate::ast::{Statement, Value};
+use crate::{config, events, executor, interpreter, parser, readline, shell};
 
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Start an interactive shell (REPL) for the run scripting language.
 pub fn run_repl() {
-    let run_shell = env::var("RUN_SHELL").unwrap_or_else(|_| {
-        if cfg!(target_os = "windows") {
-            // Try to find pwsh (PowerShell 7+) first, then fallback to powershell (Windows PowerShell)
-            if which::which("pwsh").is_ok() {
-                "pwsh".to_string()
-            } else {
-                "powershell".to_string()
-            }
-        } else {
-            "sh".to_string()
-        }
-    });
+    let run_shell = shell::default_shell();
     println!("Run Shell {} ({})", PKG_VERSION, run_shell);
     println!("Type 'exit' or press Ctrl+D to quit\n");
 
@@ -29,7 +17,7 @@ pub fn run_repl() {
     if let Some(config_content) = config::load_config() {
         match parser::parse_script(&config_content) {
             Ok(program) => {
-                if let Err(e) = interpreter.execute(program) {
+                if let Err(e) = interpreter.execute(executor::with_global(program)) {
                     eprintln!("Warning: Error loading Runfile functions: {}", e);
                 }
             }
@@ -39,53 +27,132 @@ pub fn run_repl() {
         }
     }
 
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+    let mut editor = readline::Editor::new();
 
     loop {
-        // Print prompt
-        print!("> ");
-        stdout.flush().unwrap();
-
-        // Read line
-        let mut input = String::new();
-        match stdin.read_line(&mut input) {
-            Ok(0) => {
+        editor.set_completions(completions(&interpreter));
+        let input = match editor.read_line("> ") {
+            Ok(readline::Input::Line(input)) => input,
+            // Ctrl+C abandons the line, not the REPL
+            Ok(readline::Input::Interrupted) => continue,
+            Ok(readline::Input::Eof) => {
                 // EOF (Ctrl+D)
                 println!("\nGoodbye!");
                 break;
             }
-            Ok(_) => {
-                let input = input.trim();
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                break;
+            }
+        };
+        let input = input.trim();
 
-                // Check for exit command
-                if input == "exit" || input == "quit" {
-                    println!("Goodbye!");
-                    break;
-                }
+        // Check for exit command
+        if input == "exit" || input == "quit" {
+            println!("Goodbye!");
+            break;
+        }
 
-                // Skip empty lines
-                if input.is_empty() {
-                    continue;
-                }
+        // Skip empty lines
+        if input.is_empty() {
+            continue;
+        }
+        editor.add_history(input);
 
-                // Try to parse and execute the input
-                match parser::parse_script(input) {
-                    Ok(program) => {
-                        if let Err(e) = interpreter.execute(program) {
-                            eprintln!("Error: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        crate::executor::print_parse_error(&e, input, None);
-                    }
+        if let Some(meta) = input.strip_prefix(':') {
+            if let Err(e) = meta_command(meta, &mut interpreter) {
+                eprintln!("Error: {}", e);
+            }
+            continue;
+        }
+
+        // Try to parse and execute the input
+        match parser::parse_script(input) {
+            Ok(program) => {
+                if let Err(e) = interpreter.execute(executor::with_global(program)) {
+                    eprintln!("Error: {}", e);
                 }
             }
             Err(e) => {
-                eprintln!("Error reading input: {}", e);
-                break;
+                crate::executor::print_parse_error(&e, input, None);
             }
         }
     }
 }
 
+/// The words Tab completes: function names, `$` and the names of the
+/// variables, and the meta commands.
+fn completions(interpreter: &interpreter::Interpreter) -> Vec<String> {
+    let functions = interpreter.function_names().into_iter().map(String::from);
+    let variables = interpreter
+        .variables()
+        .keys()
+        .map(|name| format!("${}", name));
+    let meta = [":set", ":unset", ":vars", "exit", "quit"].map(String::from);
+    functions.chain(variables).chain(meta).collect()
+}
+
+/// Run a REPL meta command (the input after `:`):
+/// `set name=value`, `unset name` and `vars [--json]`.
+fn meta_command(
+    input: &str,
+    interpreter: &mut interpreter::Interpreter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (command, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let rest = rest.trim();
+    match command {
+        "set" => {
+            // Parsed as a Runfile assignment, so quoting and $references
+            // behave the same as in the Runfile
+            let usage = "Usage: :set name=value";
+            let program = parser::parse_script(rest).map_err(|_| usage)?;
+            if !matches!(
+                program.statements.as_slice(),
+                [Statement::Assignment { .. }]
+            ) {
+                return Err(usage.into());
+            }
+            interpreter.execute(program)
+        }
+        "unset" if !rest.is_empty() => {
+            if !interpreter.unset_variable(rest) {
+                eprintln!("'{}' is not set", rest);
+            }
+            Ok(())
+        }
+        "vars" => {
+            let mut variables: Vec<_> = interpreter.variables().iter().collect();
+            variables.sort_by_key(|(name, _)| *name);
+            if rest == "--json" {
+                let fields: Vec<String> = variables
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = match value {
+                            Value::String(text) => events::json_string(text),
+                            Value::List(items) => {
+                                let items: Vec<String> =
+                                    items.iter().map(|item| events::json_string(item)).collect();
+                                format!("[{}]", items.join(","))
+                            }
+                        };
+                        format!("{}:{}", events::json_string(name), value)
+                    })
+                    .collect();
+                println!("{{{}}}", fields.join(","));
+            } else {
+                for (name, value) in variables {
+                    match value {
+                        Value::String(text) => println!("{}={}", name, text),
+                        Value::List(items) => println!("{}=[{}]", name, items.join(", ")),
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => Err(format!(
+            "Unknown command ':{}' (expected :set, :unset or :vars)",
+            input
+        )
+        .into()),
+    }
+}
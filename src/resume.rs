@@ -0,0 +1,108 @@
+//! The progress of a chain of functions, such as a function and the ones it
+//! `needs(...)` or the functions of a `--parallel` run, kept in
+//! `.run/resume` next to the Runfile so `run --resume` can continue from the
+//! first function that didn't complete instead of starting over.
+//!
+//! The state is saved as each function completes, so it survives `run`
+//! itself being interrupted, and removed once the whole chain has
+//! completed. Its first line is the call, as the arguments given to `run`,
+//! and each further line a function that completed.
+
+use crate::history::{escape, unescape};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File, relative to the Runfile's directory, holding the saved chain.
+pub const STATE_FILE: &str = ".run/resume";
+
+/// A chain of functions being run, and those of it that completed.
+#[derive(Debug)]
+pub struct Chain {
+    dir: PathBuf,
+    call: Vec<String>,
+    completed: Mutex<Vec<String>>,
+}
+
+impl Chain {
+    /// A chain started by the arguments `call`, in the project at `dir`.
+    pub fn new(dir: &Path, call: Vec<String>) -> Chain {
+        Chain {
+            dir: dir.to_path_buf(),
+            call,
+            completed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The chain saved in the project at `dir`, if there is one.
+    pub fn load(dir: &Path) -> io::Result<Option<Chain>> {
+        let text = match fs::read_to_string(dir.join(STATE_FILE)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut lines = text.lines();
+        let Some(call) = lines.next().filter(|call| !call.is_empty()) else {
+            return Ok(None);
+        };
+        Ok(Some(Chain {
+            dir: dir.to_path_buf(),
+            call: call.split('\t').map(unescape).collect(),
+            completed: Mutex::new(lines.map(unescape).collect()),
+        }))
+    }
+
+    /// The arguments `run` was called with.
+    pub fn call(&self) -> &[String] {
+        &self.call
+    }
+
+    /// The functions that completed, in the order they did.
+    pub fn completed(&self) -> Vec<String> {
+        self.completed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether the function or `@matrix` variant `label` has completed.
+    pub fn is_completed(&self, label: &str) -> bool {
+        self.completed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .any(|completed| completed == label)
+    }
+
+    /// Record that `label` completed and save the chain.
+    pub fn complete(&self, label: &str) -> io::Result<()> {
+        let mut completed = self.completed.lock().unwrap_or_else(|e| e.into_inner());
+        if !completed.iter().any(|completed| completed == label) {
+            completed.push(label.to_string());
+        }
+        let call: Vec<String> = self.call.iter().map(|arg| escape(arg)).collect();
+        let mut text = call.join("\t") + "\n";
+        for label in completed.iter() {
+            text.push_str(&escape(label));
+            text.push('\n');
+        }
+        let path = self.dir.join(STATE_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, text)
+    }
+
+    /// Remove the saved chain once all of it has completed. A chain that
+    /// saved nothing leaves the state of an earlier one alone.
+    pub fn finish(&self) -> io::Result<()> {
+        if self.completed().is_empty() {
+            return Ok(());
+        }
+        match fs::remove_file(self.dir.join(STATE_FILE)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
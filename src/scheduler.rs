@@ -0,0 +1,231 @@
+//! Dependency-ordered execution of functions and their `needs(...)`
+//! prerequisites, running independent functions concurrently.
+
+use crate::i18n::tr;
+use crate::process;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::mpsc;
+use std::thread;
+
+/// Prerequisites of the function being run, in the order to run them when
+/// working sequentially: each function appears after everything it needs.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub functions: Vec<String>,
+    /// For each function, the indices of the functions it needs.
+    pub needs: Vec<Vec<usize>>,
+}
+
+impl Plan {
+    /// Build the plan for `root` by walking `needs` depth-first, so the
+    /// sequential order is the one a reader of the Runfile expects.
+    pub fn build<'a>(
+        root: &str,
+        needs: impl Fn(&str) -> Result<&'a [String], String>,
+    ) -> Result<Plan, String> {
+        Self::build_all(&[root.to_string()], needs)
+    }
+
+    /// Build one plan for several `roots`, in the order given. Prerequisites
+    /// they share run once.
+    pub fn build_all<'a>(
+        roots: &[String],
+        needs: impl Fn(&str) -> Result<&'a [String], String>,
+    ) -> Result<Plan, String> {
+        let mut plan = Plan::default();
+        let mut index = HashMap::new();
+        let mut path = Vec::new();
+        for root in roots {
+            plan.visit(root, &needs, &mut index, &mut path)?;
+        }
+        Ok(plan)
+    }
+
+    /// Whether the functions can only run one after the other, each needing
+    /// the one before it.
+    pub fn is_sequential(&self) -> bool {
+        (1..self.functions.len()).all(|function| self.needs[function].contains(&(function - 1)))
+    }
+
+    fn visit<'a>(
+        &mut self,
+        name: &str,
+        needs: &impl Fn(&str) -> Result<&'a [String], String>,
+        index: &mut HashMap<String, usize>,
+        path: &mut Vec<String>,
+    ) -> Result<usize, String> {
+        if let Some(position) = index.get(name) {
+            return Ok(*position);
+        }
+        if let Some(start) = path.iter().position(|function| function == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(describe_cycle(&cycle));
+        }
+
+        path.push(name.to_string());
+        let mut dependencies = Vec::new();
+        for dependency in needs(name)? {
+            dependencies.push(self.visit(dependency, needs, index, path)?);
+        }
+        path.pop();
+
+        let position = self.functions.len();
+        self.functions.push(name.to_string());
+        self.needs.push(dependencies);
+        index.insert(name.to_string(), position);
+        Ok(position)
+    }
+}
+
+/// Find a dependency cycle among `functions`, returning it as the path of
+/// functions from the first one back to itself. Dependencies on undefined
+/// functions are ignored here.
+pub fn find_cycle<'a>(
+    functions: &[&'a str],
+    needs: impl Fn(&str) -> &'a [String],
+) -> Option<Vec<String>> {
+    fn visit<'a>(
+        name: &'a str,
+        needs: &impl Fn(&str) -> &'a [String],
+        done: &mut Vec<&'a str>,
+        path: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        if done.contains(&name) {
+            return None;
+        }
+        if let Some(start) = path.iter().position(|&function| function == name) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|f| f.to_string()).collect();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+        path.push(name);
+        for dependency in needs(name) {
+            if let Some(cycle) = visit(dependency, needs, done, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.push(name);
+        None
+    }
+
+    let mut done = Vec::new();
+    functions
+        .iter()
+        .find_map(|function| visit(function, &needs, &mut done, &mut Vec::new()))
+}
+
+/// Format a cycle as `Dependency cycle: a -> b -> a`.
+pub fn describe_cycle(cycle: &[String]) -> String {
+    format!("Dependency cycle: {}", cycle.join(" -> "))
+}
+
+/// Run every function in `plan` with `run`, starting each once all the
+/// functions it needs have finished and keeping at most `jobs` running at
+/// a time.
+///
+/// By default the first failure terminates the functions still running and
+/// its error is returned. With `keep_going`, every
+/// function not depending on a failed one still runs, and the error
+/// summarises all failures.
+pub fn execute<F>(plan: &Plan, jobs: usize, keep_going: bool, run: F) -> Result<(), String>
+where
+    F: Fn(&str) -> Result<(), String> + Sync,
+{
+    let count = plan.functions.len();
+    let mut waiting_on: Vec<usize> = plan.needs.iter().map(Vec::len).collect();
+    let mut dependents = vec![Vec::new(); count];
+    for (function, needs) in plan.needs.iter().enumerate() {
+        for &dependency in needs {
+            dependents[dependency].push(function);
+        }
+    }
+
+    // Always start the earliest ready function in sequential order, so
+    // `-j 1` runs exactly in plan order
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..count)
+        .filter(|&function| waiting_on[function] == 0)
+        .map(Reverse)
+        .collect();
+    let jobs = jobs.max(1);
+    let run = &run;
+
+    let mut finished = 0;
+    let mut failures = Vec::new();
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        let mut running = 0;
+        loop {
+            while (keep_going || failures.is_empty()) && running < jobs {
+                let Some(Reverse(function)) = ready.pop() else {
+                    break;
+                };
+                let sender = sender.clone();
+                let name = plan.functions[function].as_str();
+                scope.spawn(move || {
+                    let _ = sender.send((function, run(name)));
+                });
+                running += 1;
+            }
+            if running == 0 {
+                break;
+            }
+
+            let Ok((function, result)) = receiver.recv() else {
+                break;
+            };
+            running -= 1;
+            finished += 1;
+            match result {
+                Ok(()) => {
+                    for &dependent in &dependents[function] {
+                        waiting_on[dependent] -= 1;
+                        if waiting_on[dependent] == 0 {
+                            ready.push(Reverse(dependent));
+                        }
+                    }
+                }
+                Err(e) => {
+                    // Fail fast: stop whatever is still running
+                    if !keep_going && failures.is_empty() {
+                        process::cancel_all();
+                    }
+                    failures.push((function, e));
+                }
+            }
+        }
+    });
+    process::reset();
+
+    if !keep_going || failures.is_empty() {
+        return failures.into_iter().next().map_or(Ok(()), |(_, e)| Err(e));
+    }
+
+    let mut summary = tr!("{} of {} functions failed:", failures.len(), count);
+    for (function, error) in &failures {
+        summary.push_str(&format!(
+            "\n  {}: {}",
+            plan.functions[*function],
+            error.replace('\n', "\n    ")
+        ));
+    }
+    if finished < count {
+        let not_run: Vec<&str> = (0..count)
+            .filter(|&function| waiting_on[function] > 0)
+            .map(|function| plan.functions[function].as_str())
+            .collect();
+        summary.push('\n');
+        summary.push_str(&tr!(
+            "Not run because a prerequisite failed: {}",
+            not_run.join(", ")
+        ));
+    }
+    Err(summary)
+}
+
+/// The default number of concurrent jobs: the available parallelism.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
@@ -0,0 +1,125 @@
+//! Encrypted Runfile variables (`secretvar NAME = enc:...`).
+//!
+//! Values are encrypted with [age](https://age-encryption.org) to the
+//! recipients in the `secrets.recipients` setting and stored base64-encoded
+//! after an `enc:` prefix, so the Runfile can be committed. They are only
+//! decrypted when a command refers to them, with the identity file in
+//! `RUN_AGE_IDENTITY` or `~/.config/run/age.key`.
+
+use crate::config;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Marks an encrypted value.
+pub const PREFIX: &str = "enc:";
+
+/// Encrypt `plaintext` to the comma- or space-separated age `recipients`,
+/// returning the `enc:` value to store in the Runfile.
+pub fn encrypt(plaintext: &str, recipients: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let recipients: Vec<&str> = recipients
+        .split([',', ' '])
+        .map(str::trim)
+        .filter(|recipient| !recipient.is_empty())
+        .collect();
+    if recipients.is_empty() {
+        return Err("No recipients: set secrets.recipients in the Runfile".into());
+    }
+    let mut age = Command::new("age");
+    age.arg("--encrypt");
+    for recipient in recipients {
+        age.args(["-r", recipient]);
+    }
+    let ciphertext = run_age(age, plaintext.as_bytes())?;
+    Ok(format!("{}{}", PREFIX, base64_encode(&ciphertext)))
+}
+
+/// Decrypt an `enc:` value with the user's age identity.
+pub fn decrypt(value: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let encoded = value
+        .trim()
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("value does not start with '{}'", PREFIX))?;
+    let ciphertext = base64_decode(encoded).ok_or("value is not valid base64")?;
+    let identity = identity_file().ok_or("no age identity: set RUN_AGE_IDENTITY")?;
+    if !identity.exists() {
+        return Err(format!("age identity {} not found", identity.display()).into());
+    }
+    let mut age = Command::new("age");
+    age.arg("--decrypt").arg("-i").arg(&identity);
+    let plaintext = run_age(age, &ciphertext)?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// The age identity used to decrypt secrets.
+fn identity_file() -> Option<PathBuf> {
+    match std::env::var_os("RUN_AGE_IDENTITY") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(
+            config::get_home_dir()?
+                .join(".config")
+                .join("run")
+                .join("age.key"),
+        ),
+    }
+}
+
+/// Feed `input` to `age` and return what it writes to stdout.
+fn run_age(mut age: Command, input: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut child = age
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run age: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "age failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(output.stdout)
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
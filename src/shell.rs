@@ -0,0 +1,133 @@
+//! Which shell runs commands by default: `RUN_SHELL` when it is set, `sh` on
+//! Unix-like systems, and on Windows the first shell found in
+//! `RUN_SHELL_ORDER` (`pwsh,powershell,git-bash,cmd` unless set). The choice
+//! is made once per process.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Shells probed on Windows, in order, unless `RUN_SHELL_ORDER` is set.
+pub const DEFAULT_WINDOWS_ORDER: [&str; 4] = ["pwsh", "powershell", "git-bash", "cmd"];
+
+/// The default shell and how it was chosen.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// The executable: a name looked up on PATH or a full path.
+    pub shell: String,
+    /// Why this shell was chosen, for reporting.
+    pub reason: String,
+}
+
+static SELECTION: OnceLock<Selection> = OnceLock::new();
+
+/// The shell commands run with unless a function sets `@shell`.
+pub fn default_shell() -> String {
+    selection().shell.clone()
+}
+
+/// The default shell, resolved on first use.
+pub fn selection() -> &'static Selection {
+    SELECTION.get_or_init(resolve)
+}
+
+fn resolve() -> Selection {
+    if let Ok(shell) = std::env::var("RUN_SHELL") {
+        return Selection {
+            shell,
+            reason: "set by RUN_SHELL".to_string(),
+        };
+    }
+    if !cfg!(windows) {
+        return Selection {
+            shell: "sh".to_string(),
+            reason: "platform default".to_string(),
+        };
+    }
+
+    let configured = std::env::var("RUN_SHELL_ORDER").ok();
+    let order: Vec<String> = match &configured {
+        Some(order) => order
+            .split([',', ' '])
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_lowercase)
+            .collect(),
+        None => DEFAULT_WINDOWS_ORDER.map(String::from).to_vec(),
+    };
+    let source = match &configured {
+        Some(order) => format!("RUN_SHELL_ORDER={}", order),
+        None => order.join(", "),
+    };
+    for name in &order {
+        match probe(name) {
+            Ok(Some(shell)) => {
+                return Selection {
+                    shell,
+                    reason: format!("{}, the first available of {}", name, source),
+                };
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+    // Windows PowerShell ships with Windows, so this only happens when the
+    // order leaves it out
+    Selection {
+        shell: "powershell".to_string(),
+        reason: format!("none of {} found", source),
+    }
+}
+
+/// The executable of the shell called `name` in `RUN_SHELL_ORDER`, if it is
+/// installed.
+fn probe(name: &str) -> Result<Option<String>, String> {
+    let path = match name {
+        "pwsh" | "powershell" => which::which(name).ok(),
+        // Not `bash` on PATH, which is often WSL's launcher
+        "git-bash" | "bash" => git_bash(),
+        "cmd" => std::env::var_os("ComSpec")
+            .map(PathBuf::from)
+            .or_else(|| which::which("cmd").ok()),
+        _ => {
+            return Err(format!(
+                "unknown shell '{}' in RUN_SHELL_ORDER (expected any of {})",
+                name,
+                DEFAULT_WINDOWS_ORDER.join(", ")
+            ));
+        }
+    };
+    Ok(path.map(|path| path.to_string_lossy().into_owned()))
+}
+
+/// Git for Windows' `bash.exe`, found next to `git` on PATH or in its
+/// default install location.
+fn git_bash() -> Option<PathBuf> {
+    let beside_git = which::which("git").ok().and_then(|git| {
+        git.ancestors()
+            .skip(1)
+            .map(|dir| dir.join("bin").join("bash.exe"))
+            .find(|bash| bash.is_file())
+    });
+    beside_git.or_else(|| {
+        let program_files = std::env::var_os("ProgramFiles")?;
+        let bash = Path::new(&program_files).join(r"Git\bin\bash.exe");
+        bash.is_file().then_some(bash)
+    })
+}
+
+/// The lowercase name of a shell executable, without directory or `.exe`.
+pub fn shell_name(shell: &str) -> String {
+    Path::new(shell)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// The flag that makes `shell` run the command given after it.
+pub fn command_flag(shell: &str) -> &'static str {
+    match shell_name(shell).as_str() {
+        "pwsh" | "powershell" => "-Command",
+        "cmd" => "/C",
+        _ => "-c",
+    }
+}
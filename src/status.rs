@@ -0,0 +1,170 @@
+//! A single status line at the bottom of the terminal (`--status`), showing
+//! the running function, its current command and how long it has taken.
+//!
+//! Output of the functions is written above the line: it is cleared before
+//! each output line and drawn again after it, and removed once nothing is
+//! running.
+
+use crate::events::Stream;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// How often the elapsed time is redrawn.
+const TICK: Duration = Duration::from_millis(100);
+
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+#[derive(Debug)]
+pub struct StatusLine {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Running functions, oldest first: name, current command, start.
+    running: Vec<(String, String, Instant)>,
+    /// How often each `@supervise` function has been restarted.
+    restarts: Vec<(String, u32)>,
+    visible: bool,
+    frame: usize,
+}
+
+impl StatusLine {
+    /// Create the status line and start redrawing it in the background for
+    /// as long as it is in use.
+    pub fn start() -> Arc<StatusLine> {
+        let status = Arc::new(StatusLine {
+            state: Mutex::new(State::default()),
+        });
+        let weak: Weak<StatusLine> = Arc::downgrade(&status);
+        std::thread::spawn(move || {
+            while let Some(status) = weak.upgrade() {
+                status.tick();
+                drop(status);
+                std::thread::sleep(TICK);
+            }
+        });
+        status
+    }
+
+    pub fn task_started(&self, task: &str) {
+        let mut state = self.lock();
+        state
+            .running
+            .push((task.to_string(), String::new(), Instant::now()));
+        draw(&mut state);
+    }
+
+    /// Show `command` as what `task` is currently doing.
+    pub fn command(&self, task: &str, command: &str) {
+        let mut state = self.lock();
+        if let Some(entry) = state.running.iter_mut().find(|(name, ..)| name == task) {
+            entry.1 = command.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        draw(&mut state);
+    }
+
+    /// Show that `task` is being restarted for the `count`th time.
+    pub fn task_restarted(&self, task: &str, count: u32) {
+        let mut state = self.lock();
+        match state.restarts.iter_mut().find(|(name, _)| name == task) {
+            Some(entry) => entry.1 = count,
+            None => state.restarts.push((task.to_string(), count)),
+        }
+        draw(&mut state);
+    }
+
+    pub fn task_finished(&self, task: &str) {
+        let mut state = self.lock();
+        if let Some(position) = state.running.iter().position(|(name, ..)| name == task) {
+            state.running.remove(position);
+        }
+        if state.running.is_empty() {
+            clear(&mut state);
+        } else {
+            draw(&mut state);
+        }
+    }
+
+    /// Write a line of output above the status line.
+    pub fn write_above(&self, stream: Stream, line: &[u8]) {
+        let mut state = self.lock();
+        clear(&mut state);
+        let _ = match stream {
+            Stream::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(line).and_then(|_| stdout.flush())
+            }
+            Stream::Stderr => io::stderr().lock().write_all(line),
+        };
+        draw(&mut state);
+    }
+
+    fn tick(&self) {
+        let mut state = self.lock();
+        if !state.running.is_empty() {
+            state.frame = (state.frame + 1) % SPINNER.len();
+            draw(&mut state);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn draw(state: &mut State) {
+    let Some((task, command, started)) = state.running.first() else {
+        return;
+    };
+    let mut text = format!(
+        "{} {} {:.1}s",
+        SPINNER[state.frame],
+        task,
+        started.elapsed().as_secs_f64()
+    );
+    if let Some((_, count)) = state.restarts.iter().find(|(name, _)| name == task) {
+        text.push_str(&format!(" (restarted {}x)", count));
+    }
+    if state.running.len() > 1 {
+        text.push_str(&format!(" (+{} more)", state.running.len() - 1));
+    }
+    if !command.is_empty() {
+        text.push_str(" · ");
+        text.push_str(command);
+    }
+    let text: String = text
+        .chars()
+        .take(terminal_width().saturating_sub(1))
+        .collect();
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K\x1b[2m{}\x1b[0m", text).and_then(|_| stderr.flush());
+    state.visible = true;
+}
+
+fn clear(state: &mut State) {
+    if state.visible {
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K").and_then(|_| stderr.flush());
+        state.visible = false;
+    }
+}
+
+/// Width of the terminal on stderr, in columns.
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        // SAFETY: winsize is plain data, valid when zeroed
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: TIOCGWINSZ writes a winsize through the valid pointer
+        let result = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
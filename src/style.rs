@@ -0,0 +1,98 @@
+//! Colors for what `run` prints itself: the dimmed `▶ name` header before
+//! each function and the colored name before each line of functions running
+//! concurrently.
+//!
+//! Colors are used when stdout is a terminal, unless `NO_COLOR` is set or
+//! `--no-color` is given. `FORCE_COLOR` turns them on anywhere, as for CI
+//! logs that show them.
+//!
+//! Plain output (`--plain`, `RUN_PLAIN=1` or `TERM=dumb`) is strictly line
+//! by line, for screen readers and dumb terminals: no colors, no status line,
+//! pager or line editing redrawing the screen, and ASCII in place of symbols.
+
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+const ALERT: &str = "\x1b[1;31m";
+
+/// Colors of the names of functions running concurrently, in the order
+/// they are handed out.
+const PALETTE: [&str; 10] = [
+    "\x1b[36m", "\x1b[35m", "\x1b[33m", "\x1b[32m", "\x1b[34m", "\x1b[96m", "\x1b[95m", "\x1b[93m",
+    "\x1b[92m", "\x1b[94m",
+];
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+static PLAIN: OnceLock<bool> = OnceLock::new();
+
+/// Turn colors off (`--no-color`), before anything is printed.
+pub fn disable() {
+    let _ = ENABLED.set(false);
+}
+
+/// Make output plain (`--plain`), before anything is printed.
+pub fn set_plain() {
+    let _ = PLAIN.set(true);
+}
+
+/// Whether output is plain.
+pub fn plain() -> bool {
+    *PLAIN.get_or_init(|| {
+        std::env::var_os("RUN_PLAIN").is_some_and(|plain| !plain.is_empty() && plain != "0")
+            || std::env::var_os("TERM").is_some_and(|term| term == "dumb")
+    })
+}
+
+/// `symbol`, or `ascii` in plain output.
+pub fn symbol(symbol: &'static str, ascii: &'static str) -> &'static str {
+    if plain() { ascii } else { symbol }
+}
+
+/// Whether to color output.
+pub fn enabled() -> bool {
+    *ENABLED.get_or_init(|| {
+        if plain() || std::env::var_os("NO_COLOR").is_some() {
+            return false;
+        }
+        match std::env::var_os("FORCE_COLOR") {
+            Some(force) => force != "0",
+            None => std::io::stdout().is_terminal(),
+        }
+    })
+}
+
+/// `text` in `color`, or as it is without colors.
+fn paint(color: &str, text: &str) -> String {
+    if enabled() {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// `text` dimmed.
+pub fn dim(text: &str) -> String {
+    paint(DIM, text)
+}
+
+/// `text` in bold red, for what needs care.
+pub fn alert(text: &str) -> String {
+    paint(ALERT, text)
+}
+
+/// The header printed before function `name` runs.
+pub fn header(name: &str) -> String {
+    dim(&format!("{}{}", symbol("▶ ", "> "), name))
+}
+
+/// What starts each output line of `name`, the `index`th of functions
+/// running concurrently, padded to `width` so their lines align.
+pub fn prefix(name: &str, index: usize, width: usize) -> String {
+    format!(
+        "{} {} ",
+        paint(PALETTE[index % PALETTE.len()], &format!("{:width$}", name)),
+        dim("|")
+    )
+}
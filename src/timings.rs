@@ -0,0 +1,154 @@
+//! How long each function took, for `--timings`: reported when the
+//! invocation finishes, with the slowest command of each function that ran
+//! several and a summary of what succeeded and failed.
+
+use crate::i18n::tr;
+use crate::style;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Report {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// When the current invocation started.
+    started: Instant,
+    /// Functions that finished, in the order they did.
+    functions: Vec<Function>,
+    /// Commands run so far by each running function: how many, and the
+    /// slowest one with its duration.
+    commands: Vec<(String, usize, String, Duration)>,
+}
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    duration: Duration,
+    succeeded: bool,
+    commands: usize,
+    slowest: Option<(String, Duration)>,
+}
+
+impl Report {
+    pub fn start() -> Report {
+        Report {
+            state: Mutex::new(State {
+                started: Instant::now(),
+                functions: Vec::new(),
+                commands: Vec::new(),
+            }),
+        }
+    }
+
+    /// Record that `task` ran `command`, which took `duration`.
+    pub fn command(&self, task: &str, command: &str, duration: Duration) {
+        let mut state = self.lock();
+        let command = command.split_whitespace().collect::<Vec<_>>().join(" ");
+        match state.commands.iter_mut().find(|(name, ..)| name == task) {
+            Some((_, count, slowest, longest)) => {
+                *count += 1;
+                if duration > *longest {
+                    *slowest = command;
+                    *longest = duration;
+                }
+            }
+            None => state
+                .commands
+                .push((task.to_string(), 1, command, duration)),
+        }
+    }
+
+    /// Record that function `task` finished after `duration`.
+    pub fn function(&self, task: &str, duration: Duration, succeeded: bool) {
+        let mut state = self.lock();
+        let (commands, slowest) = match state.commands.iter().position(|(name, ..)| name == task) {
+            Some(position) => {
+                let (_, count, command, longest) = state.commands.remove(position);
+                (count, Some((command, longest)))
+            }
+            None => (0, None),
+        };
+        state.functions.push(Function {
+            name: task.to_string(),
+            duration,
+            succeeded,
+            commands,
+            slowest,
+        });
+    }
+
+    /// The report of the functions that finished since the invocation
+    /// started, which starts the next one, or `None` if none ran.
+    pub fn take(&self) -> Option<String> {
+        let mut state = self.lock();
+        let total = state.started.elapsed();
+        state.started = Instant::now();
+        let functions = std::mem::take(&mut state.functions);
+        if functions.is_empty() {
+            return None;
+        }
+        let width = functions.iter().map(|f| f.name.chars().count()).max();
+        let width = width.unwrap_or(0);
+        let mut text = format!("{}\n", tr!("Timings:"));
+        for function in &functions {
+            let status = tr!(if function.succeeded { "ok" } else { "failed" });
+            let mut line = format!(
+                "  {:width$}  {:>7}  {}",
+                function.name,
+                seconds(function.duration),
+                status,
+                width = width
+            );
+            // The slowest of several commands; a single one took it all
+            if let Some((command, duration)) =
+                function.slowest.as_ref().filter(|_| function.commands > 1)
+            {
+                let _ = write!(
+                    line,
+                    "  (slowest {}: {})",
+                    seconds(*duration),
+                    shorten(command)
+                );
+            }
+            text.push_str(&line);
+            text.push('\n');
+        }
+        let failed = functions.iter().filter(|f| !f.succeeded).count();
+        let summary = tr!(
+            "{} function(s): {} succeeded, {} failed in {}",
+            functions.len(),
+            functions.len() - failed,
+            failed,
+            seconds(total)
+        );
+        let _ = writeln!(text, "{}", summary);
+        Some(text)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// `duration` in seconds to a tenth, with minutes when it has any.
+fn seconds(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else {
+        format!("{}m{:04.1}s", duration.as_secs() / 60, seconds % 60.0)
+    }
+}
+
+/// `command`, cut short to fit a report line.
+fn shorten(command: &str) -> String {
+    const MAX: usize = 48;
+    match command.char_indices().nth(MAX) {
+        Some((end, _)) => format!("{}{}", &command[..end], style::symbol("…", "...")),
+        None => command.to_string(),
+    }
+}
@@ -0,0 +1,97 @@
+//! Integration with version managers (mise, asdf) so tasks run against the
+//! toolchain pinned in `.tool-versions` or `mise.toml`.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Files that pin tool versions, in the order they are looked for.
+const MISE_FILES: &[&str] = &["mise.toml", ".mise.toml"];
+const TOOL_VERSIONS_FILE: &str = ".tool-versions";
+
+/// A version manager to run commands through.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Toolchain {
+    /// Run commands via `mise exec --`.
+    Mise,
+    /// Put asdf's shims directory first on PATH.
+    Asdf { shims: PathBuf },
+}
+
+impl Toolchain {
+    /// Resolve the toolchain to use from the `toolchain.manager` setting,
+    /// overridden by `RUN_TOOLCHAIN`. Accepted values are `auto`, `mise`,
+    /// `asdf` and `none` (the default).
+    pub fn resolve(setting: Option<&str>) -> Option<Toolchain> {
+        let mode = std::env::var("RUN_TOOLCHAIN")
+            .ok()
+            .or_else(|| setting.map(str::to_string))?;
+
+        match mode.as_str() {
+            "mise" => Some(Toolchain::Mise),
+            "asdf" => asdf_shims().map(|shims| Toolchain::Asdf { shims }),
+            "auto" => detect(&std::env::current_dir().ok()?),
+            _ => None,
+        }
+    }
+
+    /// Build the process that runs `shell`, wrapped for this toolchain.
+    pub fn command(&self, shell: &str) -> Command {
+        match self {
+            Toolchain::Mise => {
+                let mut command = Command::new("mise");
+                command.args(["exec", "--", shell]);
+                command
+            }
+            Toolchain::Asdf { .. } => {
+                let mut command = Command::new(shell);
+                command.envs(self.env());
+                command
+            }
+        }
+    }
+
+    /// Variables this toolchain sets on the processes it runs. `mise exec`
+    /// applies its own environment, which isn't known until it runs.
+    pub fn env(&self) -> Vec<(String, OsString)> {
+        match self {
+            Toolchain::Mise => Vec::new(),
+            Toolchain::Asdf { shims } => {
+                let mut paths = vec![shims.clone()];
+                if let Some(path) = std::env::var_os("PATH") {
+                    paths.extend(std::env::split_paths(&path));
+                }
+                std::env::join_paths(paths)
+                    .map(|path| vec![("PATH".to_string(), path)])
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Look for a pin file in `start` or its ancestors and pick an installed
+/// manager that understands it.
+fn detect(start: &Path) -> Option<Toolchain> {
+    let mise_installed = which::which("mise").is_ok();
+    for dir in start.ancestors() {
+        if MISE_FILES.iter().any(|f| dir.join(f).is_file()) {
+            return mise_installed.then_some(Toolchain::Mise);
+        }
+        if dir.join(TOOL_VERSIONS_FILE).is_file() {
+            if mise_installed {
+                return Some(Toolchain::Mise);
+            }
+            return asdf_shims().map(|shims| Toolchain::Asdf { shims });
+        }
+    }
+    None
+}
+
+/// asdf's shims directory, if asdf is installed.
+fn asdf_shims() -> Option<PathBuf> {
+    let data_dir = std::env::var_os("ASDF_DATA_DIR")
+        .map(PathBuf::from)
+        .or_else(|| crate::config::get_home_dir().map(|home| home.join(".asdf")))?;
+    let shims = data_dir.join("shims");
+    shims.is_dir().then_some(shims)
+}
@@ -0,0 +1,125 @@
+//! Tool availability and version checks for `requires` directives.
+
+use crate::ast::{ToolRequirement, VersionConstraint, VersionOp};
+use std::cmp::Ordering;
+use std::process::Command;
+
+/// Arguments that print a tool's version, for tools that don't understand `--version`.
+const VERSION_ARGS: &[(&str, &[&str])] = &[
+    ("go", &["version"]),
+    ("java", &["-version"]),
+    ("javac", &["-version"]),
+    ("kubectl", &["version", "--client"]),
+    ("helm", &["version", "--short"]),
+    ("ssh", &["-V"]),
+];
+
+/// Check requirements, returning one human-readable line per unmet requirement.
+pub fn unmet_requirements<'a>(
+    requirements: impl IntoIterator<Item = &'a ToolRequirement>,
+) -> Vec<String> {
+    let mut missing = Vec::new();
+    let mut problems = Vec::new();
+
+    for requirement in requirements {
+        if which::which(&requirement.tool).is_err() {
+            missing.push(requirement.tool.as_str());
+            continue;
+        }
+        let Some(constraint) = &requirement.constraint else {
+            continue;
+        };
+        match probe_version(&requirement.tool) {
+            Some(found) if satisfies(&found, constraint) => {}
+            Some(found) => problems.push(format!(
+                "{} required, found {}",
+                requirement,
+                format_version(&found)
+            )),
+            None => problems.push(format!(
+                "{} required, but its version could not be determined",
+                requirement
+            )),
+        }
+    }
+
+    if !missing.is_empty() {
+        problems.insert(0, format!("missing required tools: {}", missing.join(", ")));
+    }
+    problems
+}
+
+/// Run the tool's version command and extract the first version number it prints.
+pub fn probe_version(tool: &str) -> Option<Vec<u64>> {
+    let args = VERSION_ARGS
+        .iter()
+        .find(|(name, _)| *name == tool)
+        .map(|(_, args)| *args)
+        .unwrap_or(&["--version"]);
+
+    let output = Command::new(tool).args(args).output().ok()?;
+    // Some tools (java, ssh) print their version on stderr
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    extract_version(&text)
+}
+
+/// Find the first dotted version number (e.g. `18.17.0`) in `text`,
+/// falling back to the first bare number.
+fn extract_version(text: &str) -> Option<Vec<u64>> {
+    let candidates: Vec<&str> = text
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|s| s.trim_matches('.'))
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    candidates
+        .iter()
+        .find(|s| s.contains('.'))
+        .or_else(|| candidates.first())
+        .and_then(|s| crate::parser::parse_version(s))
+}
+
+/// Whether `found` satisfies `constraint`.
+pub fn satisfies(found: &[u64], constraint: &VersionConstraint) -> bool {
+    let wanted = &constraint.version;
+    match constraint.op {
+        VersionOp::Eq => compare(found, wanted) == Ordering::Equal,
+        VersionOp::Gt => compare(found, wanted) == Ordering::Greater,
+        VersionOp::Ge => compare(found, wanted) != Ordering::Less,
+        VersionOp::Lt => compare(found, wanted) == Ordering::Less,
+        VersionOp::Le => compare(found, wanted) != Ordering::Greater,
+        VersionOp::Pessimistic => {
+            // ~> 1.6 means >= 1.6 and < 2; ~> 1.6.3 means >= 1.6.3 and < 1.7
+            let mut upper = wanted[..wanted.len().saturating_sub(1).max(1)].to_vec();
+            if let Some(last) = upper.last_mut() {
+                *last += 1;
+            }
+            compare(found, wanted) != Ordering::Less && compare(found, &upper) == Ordering::Less
+        }
+    }
+}
+
+/// Compare versions component-wise, treating missing components as zero.
+fn compare(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    for i in 0..len {
+        let ordering = a
+            .get(i)
+            .copied()
+            .unwrap_or(0)
+            .cmp(&b.get(i).copied().unwrap_or(0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+fn format_version(version: &[u64]) -> String {
+    let parts: Vec<String> = version.iter().map(u64::to_string).collect();
+    parts.join(".")
+}
@@ -0,0 +1,426 @@
+//! Commands written in a portable subset of shell syntax, re-emitted for the
+//! shell that runs them when the Runfile sets `commands.translate = true`,
+//! so one Runfile runs unmodified with `sh`, PowerShell, `cmd` and fish.
+//!
+//! The subset is words, in single or double quotes or with `\` escapes,
+//! `$NAME` and `${NAME}` environment variables, `|`, `&&`, `||`, `;`, `>`,
+//! `>>`, `2>`, `2>>`, `2>&1` and `<`. A command using anything else, such as
+//! globs, `$(...)` or `NAME=value` prefixes, runs as written. The subset is
+//! the shell's own syntax for `sh` and its relatives, so their commands are
+//! never changed.
+
+use crate::shell::shell_name;
+
+/// A shell the subset is re-emitted for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dialect {
+    /// PowerShell 7 and later (`pwsh`).
+    Pwsh,
+    /// Windows PowerShell 5.1, without `&&` and `||`.
+    WindowsPowerShell,
+    Cmd,
+    Fish,
+}
+
+/// Part of a word: literal text or an environment variable.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Env(String),
+}
+
+type Word = Vec<Part>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Pipe,
+    And,
+    Or,
+    Then,
+    /// `>`, `>>`, `2>` or `2>>`.
+    Write {
+        errors: bool,
+        append: bool,
+    },
+    /// `2>&1`.
+    ErrorsToOutput,
+    /// `<`.
+    Read,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(Word),
+    Operator(Operator),
+}
+
+/// A command of a pipeline: its words and redirections, the target of each
+/// but `2>&1`.
+#[derive(Debug, Default)]
+struct Simple {
+    words: Vec<Word>,
+    redirects: Vec<(Operator, Option<Word>)>,
+}
+
+/// Pipelines, each followed by the `&&`, `||` or `;` joining it to the next.
+type List = Vec<(Vec<Simple>, Option<Operator>)>;
+
+/// `command` re-emitted for `shell`, or `None` to run it as written: when
+/// it is outside the subset, or `shell` needs no translating.
+pub fn translate(command: &str, shell: &str) -> Option<String> {
+    let dialect = match shell_name(shell).as_str() {
+        "pwsh" => Dialect::Pwsh,
+        "powershell" => Dialect::WindowsPowerShell,
+        "cmd" => Dialect::Cmd,
+        "fish" => Dialect::Fish,
+        _ => return None,
+    };
+    let list = structure(lex(command)?)?;
+    render(&list, dialect)
+}
+
+/// The tokens of `command`, or `None` if it is outside the subset.
+fn lex(command: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut word: Option<Word> = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => tokens.extend(word.take().map(Token::Word)),
+            '\'' => {
+                let word = word.get_or_insert_with(Vec::new);
+                push_text(word, "");
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => push_char(word, c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(Vec::new);
+                push_text(word, "");
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => {
+                            let c = chars.next()?;
+                            if !matches!(c, '"' | '\\' | '$' | '`') {
+                                push_char(word, '\\');
+                            }
+                            push_char(word, c);
+                        }
+                        '$' => word.push(Part::Env(variable(&mut chars)?)),
+                        '`' => return None,
+                        c => push_char(word, c),
+                    }
+                }
+            }
+            '\\' => push_char(word.get_or_insert_with(Vec::new), chars.next()?),
+            '$' => word
+                .get_or_insert_with(Vec::new)
+                .push(Part::Env(variable(&mut chars)?)),
+            '|' | '&' | ';' => {
+                tokens.extend(word.take().map(Token::Word));
+                let operator = match (c, chars.peek()) {
+                    ('|', Some('|')) => Operator::Or,
+                    ('|', _) => Operator::Pipe,
+                    ('&', Some('&')) => Operator::And,
+                    (';', _) => Operator::Then,
+                    _ => return None,
+                };
+                if matches!(operator, Operator::Or | Operator::And) {
+                    chars.next();
+                }
+                tokens.push(Token::Operator(operator));
+            }
+            '>' | '<' => {
+                // A `2` right before the `>` is the descriptor it redirects
+                let errors = c == '>' && word == Some(vec![Part::Text("2".to_string())]);
+                if errors {
+                    word = None;
+                }
+                tokens.extend(word.take().map(Token::Word));
+                let operator = if c == '<' {
+                    Operator::Read
+                } else if chars.next_if_eq(&'&').is_some() {
+                    if !errors || chars.next() != Some('1') {
+                        return None;
+                    }
+                    Operator::ErrorsToOutput
+                } else {
+                    let append = chars.next_if_eq(&'>').is_some();
+                    Operator::Write { errors, append }
+                };
+                tokens.push(Token::Operator(operator));
+            }
+            '~' | '#' if word.is_none() => return None,
+            '`' | '(' | ')' | '*' | '?' | '[' | '\r' | '\n' => return None,
+            c => push_char(word.get_or_insert_with(Vec::new), c),
+        }
+    }
+    tokens.extend(word.map(Token::Word));
+    Some(tokens)
+}
+
+/// The name of the variable after a `$`: `NAME` or `{NAME}`.
+fn variable(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let braced = chars.next_if_eq(&'{').is_some();
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    if braced && chars.next() != Some('}') {
+        return None;
+    }
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        .then_some(name)
+}
+
+fn push_char(word: &mut Word, c: char) {
+    push_text(word, c.encode_utf8(&mut [0; 4]));
+}
+
+/// Add `text` to the literal text at the end of `word`. Adding nothing
+/// still makes the word exist, as `''` does.
+fn push_text(word: &mut Word, text: &str) {
+    match word.last_mut() {
+        Some(Part::Text(last)) => last.push_str(text),
+        _ => word.push(Part::Text(text.to_string())),
+    }
+}
+
+/// The pipelines of `tokens`, or `None` if they don't make complete ones.
+fn structure(tokens: Vec<Token>) -> Option<List> {
+    let mut list: List = Vec::new();
+    let mut pipeline: Vec<Simple> = Vec::new();
+    let mut simple = Simple::default();
+    let mut target: Option<Operator> = None;
+    for token in tokens {
+        match token {
+            Token::Word(word) => match target.take() {
+                Some(operator) => simple.redirects.push((operator, Some(word))),
+                // `NAME=value command` sets a variable for the command
+                None if simple.words.is_empty() && assigns(&word) => return None,
+                None => simple.words.push(word),
+            },
+            _ if target.is_some() => return None,
+            Token::Operator(Operator::ErrorsToOutput) => {
+                simple.redirects.push((Operator::ErrorsToOutput, None))
+            }
+            Token::Operator(operator @ (Operator::Write { .. } | Operator::Read)) => {
+                target = Some(operator)
+            }
+            Token::Operator(operator) => {
+                if simple.words.is_empty() {
+                    return None;
+                }
+                pipeline.push(std::mem::take(&mut simple));
+                if operator != Operator::Pipe {
+                    list.push((std::mem::take(&mut pipeline), Some(operator)));
+                }
+            }
+        }
+    }
+    if target.is_some() {
+        return None;
+    }
+    if simple.words.is_empty() {
+        // A trailing `;` ends the last pipeline; anything else needs a command
+        match list.last_mut() {
+            Some((_, operator)) if pipeline.is_empty() && *operator == Some(Operator::Then) => {
+                *operator = None
+            }
+            _ => return None,
+        }
+    } else {
+        pipeline.push(simple);
+        list.push((pipeline, None));
+    }
+    Some(list)
+}
+
+/// Whether `word` starts with `NAME=`.
+fn assigns(word: &Word) -> bool {
+    let Some(Part::Text(text)) = word.first() else {
+        return false;
+    };
+    text.split_once('=').is_some_and(|(name, _)| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// `list` in the syntax of `dialect`.
+fn render(list: &List, dialect: Dialect) -> Option<String> {
+    let pipelines: Vec<String> = list
+        .iter()
+        .map(|(pipeline, _)| render_pipeline(pipeline, dialect))
+        .collect::<Option<_>>()?;
+    let operators: Vec<Operator> = list.iter().filter_map(|(_, operator)| *operator).collect();
+    if dialect != Dialect::WindowsPowerShell {
+        let mut result = pipelines[0].clone();
+        for (operator, pipeline) in operators.iter().zip(&pipelines[1..]) {
+            let separator = match (operator, dialect) {
+                (Operator::And, _) => " && ",
+                (Operator::Or, _) => " || ",
+                (_, Dialect::Cmd) => " & ",
+                _ => "; ",
+            };
+            result.push_str(separator);
+            result.push_str(pipeline);
+        }
+        return Some(result);
+    }
+
+    // Windows PowerShell checks `$?` instead: `a; if ($?) { b }`. A run of
+    // `&&`s, or of `||`s, nests; mixing them in one run has no equivalent
+    let mut groups: Vec<(Vec<&str>, Option<Operator>)> = vec![(Vec::new(), None)];
+    for (i, pipeline) in pipelines.iter().enumerate() {
+        let (group, kind) = groups.last_mut()?;
+        group.push(pipeline);
+        match operators.get(i) {
+            Some(Operator::Then) => groups.push((Vec::new(), None)),
+            Some(operator) if kind.is_none_or(|kind| kind == *operator) => *kind = Some(*operator),
+            Some(_) => return None,
+            None => {}
+        }
+    }
+    let groups: Vec<String> = groups
+        .into_iter()
+        .map(|(group, kind)| {
+            let test = if kind == Some(Operator::Or) {
+                "-not $?"
+            } else {
+                "$?"
+            };
+            let (last, rest) = group.split_last().expect("a group has a pipeline");
+            rest.iter().rev().fold(last.to_string(), |inner, pipeline| {
+                format!("{}; if ({}) {{ {} }}", pipeline, test, inner)
+            })
+        })
+        .collect();
+    Some(groups.join("; "))
+}
+
+fn render_pipeline(pipeline: &[Simple], dialect: Dialect) -> Option<String> {
+    let powershell = matches!(dialect, Dialect::Pwsh | Dialect::WindowsPowerShell);
+    let mut commands = Vec::new();
+    for (i, simple) in pipeline.iter().enumerate() {
+        let mut parts: Vec<String> = Vec::new();
+        for (n, word) in simple.words.iter().enumerate() {
+            let quoted = quote(word, dialect)?;
+            // PowerShell calls a quoted command name with `&`
+            if n == 0 && powershell && quoted.starts_with(['\'', '"']) {
+                parts.push("&".to_string());
+            }
+            parts.push(quoted);
+        }
+        for (operator, target) in &simple.redirects {
+            let target = match target {
+                Some(word) => quote(word, dialect)?,
+                None => String::new(),
+            };
+            match operator {
+                // PowerShell has no `<`: the file is piped in
+                Operator::Read if powershell => {
+                    if i > 0 {
+                        return None;
+                    }
+                    parts.splice(
+                        0..0,
+                        ["Get-Content".into(), "-Raw".into(), target, "|".into()],
+                    );
+                }
+                Operator::Read => parts.push(format!("< {}", target)),
+                Operator::Write { errors, append } => parts.push(format!(
+                    "{}{} {}",
+                    if *errors { "2" } else { "" },
+                    if *append { ">>" } else { ">" },
+                    target
+                )),
+                Operator::ErrorsToOutput => parts.push("2>&1".to_string()),
+                _ => return None,
+            }
+        }
+        commands.push(parts.join(" "));
+    }
+    Some(commands.join(" | "))
+}
+
+/// `word` as one argument in `dialect`, or `None` if it can't be written.
+fn quote(word: &Word, dialect: Dialect) -> Option<String> {
+    let plain = |text: &str, extra: &str| {
+        !text.is_empty()
+            && text
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_./:+-=".contains(c) || extra.contains(c))
+    };
+    let text_only = word.iter().all(|part| matches!(part, Part::Text(_)));
+    match dialect {
+        Dialect::Pwsh | Dialect::WindowsPowerShell if text_only => {
+            let text = word_text(word);
+            if plain(&text, "\\%") {
+                return Some(text);
+            }
+            Some(format!("'{}'", text.replace('\'', "''")))
+        }
+        Dialect::Pwsh | Dialect::WindowsPowerShell => {
+            let mut quoted = String::from("\"");
+            for part in word {
+                match part {
+                    Part::Text(text) => {
+                        for c in text.chars() {
+                            if matches!(c, '`' | '"' | '$') {
+                                quoted.push('`');
+                            }
+                            quoted.push(c);
+                        }
+                    }
+                    Part::Env(name) => quoted.push_str(&format!("${{env:{}}}", name)),
+                }
+            }
+            quoted.push('"');
+            Some(quoted)
+        }
+        Dialect::Cmd => {
+            let mut text = String::new();
+            for part in word {
+                match part {
+                    // `cmd` has no way to quote these
+                    Part::Text(part) if part.contains(['"', '%']) => return None,
+                    Part::Text(part) => text.push_str(part),
+                    Part::Env(name) => text.push_str(&format!("%{}%", name)),
+                }
+            }
+            if plain(&text, "\\%") {
+                return Some(text);
+            }
+            Some(format!("\"{}\"", text))
+        }
+        Dialect::Fish => {
+            let mut quoted = String::new();
+            for part in word {
+                match part {
+                    Part::Text(text) if plain(text, ",@") => quoted.push_str(text),
+                    Part::Text(text) => quoted.push_str(&format!(
+                        "'{}'",
+                        text.replace('\\', "\\\\").replace('\'', "\\'")
+                    )),
+                    Part::Env(name) => quoted.push_str(&format!("\"${}\"", name)),
+                }
+            }
+            Some(quoted)
+        }
+    }
+}
+
+/// The literal text of a word with no variables.
+fn word_text(word: &Word) -> String {
+    word.iter()
+        .map(|part| match part {
+            Part::Text(text) => text.as_str(),
+            Part::Env(_) => "",
+        })
+        .collect()
+}
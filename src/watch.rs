@@ -0,0 +1,88 @@
+//! Waiting for files to change, for `run --watch`.
+//!
+//! Files matching the patterns are polled for their modification time and
+//! size, so no platform watcher is needed; a change counts once the files
+//! have stopped changing for a moment, so a save touching several files, or
+//! a formatter rewriting them, re-runs the function once.
+
+use crate::{glob, process};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the files are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long the files must stay unchanged before a change counts.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// The modification time and size of each file matching the patterns.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot(BTreeMap<PathBuf, (Option<SystemTime>, u64)>);
+
+impl Snapshot {
+    /// The files under `base` matching `patterns` as they are now.
+    pub fn take(patterns: &[String], base: &Path) -> Snapshot {
+        let files = patterns
+            .iter()
+            .flat_map(|pattern| glob::expand(pattern, base))
+            .filter_map(|path| {
+                let metadata = fs::metadata(base.join(&path)).ok()?;
+                Some((path, (metadata.modified().ok(), metadata.len())))
+            })
+            .collect();
+        Snapshot(files)
+    }
+
+    /// Files added, removed or modified since `earlier`.
+    fn changed_since(&self, earlier: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .0
+            .iter()
+            .filter(|(path, state)| earlier.0.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            earlier
+                .0
+                .keys()
+                .filter(|path| !self.0.contains_key(*path))
+                .cloned(),
+        );
+        changed.sort();
+        changed
+    }
+}
+
+/// Wait until files matching `patterns` differ from `since` and then stay
+/// unchanged for the debounce period, returning them as they are then and
+/// which ones changed. Returns `None` if `run` is interrupted meanwhile.
+pub fn wait_for_change(
+    patterns: &[String],
+    base: &Path,
+    since: &Snapshot,
+) -> Option<(Snapshot, Vec<PathBuf>)> {
+    let mut current = loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if process::interrupted() {
+            return None;
+        }
+        let current = Snapshot::take(patterns, base);
+        if current != *since {
+            break current;
+        }
+    };
+    loop {
+        std::thread::sleep(DEBOUNCE);
+        if process::interrupted() {
+            return None;
+        }
+        let next = Snapshot::take(patterns, base);
+        if next == current {
+            let changed = current.changed_since(since);
+            return Some((current, changed));
+        }
+        current = next;
+    }
+}
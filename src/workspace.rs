@@ -0,0 +1,59 @@
+//! Monorepo workspaces: the Runfiles of member projects in subdirectories
+//! of the root Runfile's directory.
+//!
+//! Members are the directories matching the `workspace.members` setting of
+//! the root Runfile (e.g. `"apps/*, libs/*"`), or every subdirectory with a
+//! Runfile when it isn't set.
+
+use crate::glob;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories never searched for member Runfiles.
+const IGNORED_DIRS: &[&str] = &["node_modules", "target", "vendor"];
+
+/// Directories below `root` containing a Runfile and matching one of
+/// `patterns` (all of them when empty), relative to `root` and sorted.
+pub fn members(root: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    walk(root, Path::new(""), &mut found);
+    found.retain(|member| {
+        patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| glob::matches_path(pattern, member))
+    });
+    found
+}
+
+fn walk(dir: &Path, relative: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let name_str = name.to_string_lossy();
+        if name_str.starts_with('.') || IGNORED_DIRS.contains(&name_str.as_ref()) {
+            continue;
+        }
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            let path = relative.join(&name);
+            if entry.path().join("Runfile").is_file() {
+                found.push(path.clone());
+            }
+            walk(&entry.path(), &path, found);
+        }
+    }
+}
+
+/// Parse the `workspace.members` setting into its patterns.
+pub fn member_patterns(setting: Option<&str>) -> Vec<String> {
+    setting
+        .unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|pattern| pattern.trim().trim_end_matches('/').to_string())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
@@ -14,13 +14,13 @@ fn get_binary_path() -> PathBuf {
         path.pop(); // Go up to debug or release
     }
 
-    path.push("run");
+    path.push(format!("run{}", env::consts::EXE_SUFFIX));
 
     // If the binary doesn't exist in debug, try building it first
     if !path.exists() {
         // Try to build the binary
         let build_output = Command::new("cargo")
-            .args(&["build", "--bin", "run"])
+            .args(["build", "--bin", "run"])
             .output()
             .expect("Failed to build binary");
 
@@ -46,12 +46,43 @@ fn create_runfile(dir: &std::path::Path, content: &str) {
     fs::write(runfile_path, content).unwrap();
 }
 
+/// Helper to get an empty home directory, shared by the tests, so the
+/// developer's ~/.runfile isn't read along with theirs
+fn empty_home() -> PathBuf {
+    let home = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("home");
+    fs::create_dir_all(&home).unwrap();
+    home
+}
+
+/// Helper to create a command running the binary, with its messages in
+/// English whatever the locale of the one running the tests, and an empty
+/// home directory unless the test sets HOME itself
+fn binary_command(binary: &std::path::Path) -> Command {
+    let mut command = Command::new(binary);
+    command
+        .env_remove("LC_ALL")
+        .env_remove("LC_MESSAGES")
+        .env_remove("LANG")
+        .env("HOME", empty_home());
+    command
+}
+
+/// Helper to run the binary with `args` in `dir`, outside of GitHub Actions
+fn run_in(dir: &std::path::Path, args: &[&str]) -> std::process::Output {
+    binary_command(&get_binary_path())
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command")
+}
+
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[test]
 fn test_version_flag() {
     let binary = get_binary_path();
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--version")
         .output()
         .expect("Failed to execute command");
@@ -67,7 +98,7 @@ fn test_list_flag_no_runfile() {
     let binary = get_binary_path();
     let temp_dir = create_temp_dir();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--list")
         .current_dir(temp_dir.path())
         .env("HOME", temp_dir.path()) // Override HOME to avoid loading ~/.runfile
@@ -93,7 +124,7 @@ deploy() echo "Deploying..."
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--list")
         .current_dir(temp_dir.path())
         .output()
@@ -107,6 +138,41 @@ deploy() echo "Deploying..."
     assert!(stdout.contains("deploy"));
 }
 
+#[test]
+fn test_list_shows_descriptions() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+## Build the project
+build() cargo build
+
+# A plain comment is not a description
+fmt() cargo fmt
+
+# desc: Deploy to production
+@always
+deploy:prod() {
+    echo deploying
+}
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("--list")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Available functions:\n  build        Build the project\n  fmt\n  deploy:prod  Deploy to production\n"
+    );
+}
+
 #[test]
 fn test_list_flag_short() {
     let binary = get_binary_path();
@@ -119,7 +185,7 @@ hello() echo "Hello, World!"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("-l")
         .current_dir(temp_dir.path())
         .output()
@@ -142,7 +208,7 @@ greet() echo "Hello from run!"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .current_dir(temp_dir.path())
         .output()
@@ -165,7 +231,7 @@ greet() echo "Hello, $1!"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .arg("Alice")
         .current_dir(temp_dir.path())
@@ -190,7 +256,7 @@ server() echo "Starting server on port ${1:-8080}"
     );
 
     // Test with default value (no argument provided) - bash handles the default
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("server")
         .current_dir(temp_dir.path())
         .output()
@@ -201,7 +267,7 @@ server() echo "Starting server on port ${1:-8080}"
     assert!(stdout.contains("port 8080"));
 
     // Test with provided value - bash substitutes the provided arg
-    let output2 = Command::new(&binary)
+    let output2 = binary_command(&binary)
         .arg("server")
         .arg("3000")
         .current_dir(temp_dir.path())
@@ -229,7 +295,7 @@ server() echo "port=${1:-8080}"
     );
 
     // Test with default value
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("server")
         .current_dir(temp_dir.path())
         .output()
@@ -240,7 +306,7 @@ server() echo "port=${1:-8080}"
     assert!(stdout.contains("port=8080"), "Expected 'port=8080' but got: {}", stdout);
 
     // Test with provided value
-    let output2 = Command::new(&binary)
+    let output2 = binary_command(&binary)
         .arg("server")
         .arg("3000")
         .current_dir(temp_dir.path())
@@ -266,7 +332,7 @@ server() echo port=${1:-8080}
     );
 
     // Test with default value
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("server")
         .current_dir(temp_dir.path())
         .output()
@@ -277,7 +343,7 @@ server() echo port=${1:-8080}
     assert!(stdout.contains("port=8080"), "Expected 'port=8080' but got: {}", stdout);
 
     // Test with provided value
-    let output2 = Command::new(&binary)
+    let output2 = binary_command(&binary)
         .arg("server")
         .arg("3000")
         .current_dir(temp_dir.path())
@@ -301,7 +367,7 @@ add() echo "$1 + $2 = $(($1 + $2))"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("add")
         .arg("5")
         .arg("3")
@@ -326,7 +392,7 @@ docker:shell() echo "Opening Docker shell for $1"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("docker")
         .arg("shell")
         .arg("myapp")
@@ -339,6 +405,169 @@ docker:shell() echo "Opening Docker shell for $1"
     assert!(stdout.contains("Opening Docker shell for myapp"));
 }
 
+#[test]
+fn test_ambiguous_nested_function_call() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+docker() echo "docker $@"
+docker:shell() echo "shell $@"
+docker_shell() echo "underscore $@"
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["docker", "shell", "myapp"]);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'docker shell' is ambiguous"), "got: {}", stderr);
+    assert!(stderr.contains("run docker:shell myapp"), "got: {}", stderr);
+    assert!(stderr.contains("run docker -- shell myapp"), "got: {}", stderr);
+
+    // Exact names and `--` resolve it
+    let output = run(&["docker:shell", "myapp"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "shell myapp\n");
+    let output = run(&["docker", "--", "shell", "myapp"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "docker shell myapp\n");
+    let output = run(&["docker_shell", "myapp"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "underscore myapp\n");
+    let output = run(&["docker", "ps"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "docker ps\n");
+}
+
+#[test]
+fn test_configurable_namespace_separators() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+commands.separators = : .
+docker.shell() echo "dot $@"
+docker:logs() echo "colon $@"
+net/up() echo "slash $@"
+all() needs(docker.shell, net/up) echo "all"
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+    let stdout = |args: &[&str]| String::from_utf8_lossy(&run(args).stdout).into_owned();
+
+    assert_eq!(stdout(&["docker", "shell", "app"]), "dot app\n");
+    assert_eq!(stdout(&["docker_shell", "app"]), "dot app\n");
+    assert_eq!(stdout(&["docker", "logs"]), "colon \n");
+    // `/` isn't configured, so only the exact name calls net/up
+    assert_eq!(stdout(&["net/up"]), "slash \n");
+    assert!(!run(&["net", "up"]).status.success());
+    assert_eq!(stdout(&["all"]), "dot \nslash \nall\n");
+    assert_eq!(stdout(&["--list-separators"]), ":.\n");
+
+    create_runfile(temp_dir.path(), "commands.separators = -\ndocker:logs() echo\n");
+    let output = run(&["docker", "logs"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid commands.separators: '-'"));
+}
+
+#[test]
+fn test_explain_function() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+region = eu-west-1
+build() echo building > built
+test() needs(build) echo testing > tested
+
+## Deploy to an environment
+deploy(env) needs(test) {
+    echo deploying $env to $region > deployed
+}
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["--explain", "deploy", "prod"]);
+    assert!(output.status.success());
+    let runfile = temp_dir.path().join("Runfile");
+    let expected = format!(
+        "deploy
+  Defined in {}:7
+    ## Deploy to an environment
+    deploy(env) needs(test) {{
+        echo deploying $env to $region > deployed
+    }}
+  Needs build, test, run first in this order
+  Runs:
+    echo deploying prod to eu-west-1 > deployed
+",
+        runfile.display()
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    // Nothing ran
+    for file in ["built", "tested", "deployed"] {
+        assert!(!temp_dir.path().join(file).exists());
+    }
+
+    let stdout = String::from_utf8_lossy(&run(&["--explain", "deploy"]).stdout).into_owned();
+    assert!(
+        stdout.contains("  Runs: Function 'deploy' needs argument 'env'"),
+        "got: {}",
+        stdout
+    );
+    let stdout = String::from_utf8_lossy(&run(&["--explain", "build"]).stdout).into_owned();
+    assert!(stdout.contains("Runfile:3\n    build() echo building > built\n  Needs nothing\n"));
+
+    let output = run(&["--explain", "missing"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Function 'missing' not found"));
+}
+
+#[test]
+fn test_complete_history() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+completion.history = true
+deploy() echo "deploy $@"
+docker:logs() echo "logs $@"
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+    let stdout = |args: &[&str]| String::from_utf8_lossy(&run(args).stdout).into_owned();
+
+    assert_eq!(stdout(&["--complete-history", "deploy"]), "");
+    stdout(&["deploy", "staging", "eu"]);
+    stdout(&["deploy", "prod", "us"]);
+    stdout(&["deploy", "staging", "us"]);
+    stdout(&["docker", "logs", "web"]);
+    // Dry runs and failed calls aren't recorded
+    stdout(&["--dry-run", "deploy", "dry"]);
+    stdout(&["missing", "value"]);
+
+    // Most recent first, each once, for the argument being completed
+    assert_eq!(stdout(&["--complete-history", "deploy"]), "staging\nprod\n");
+    assert_eq!(
+        stdout(&["--complete-history", "--", "deploy", "prod"]),
+        "us\neu\n"
+    );
+    // Names resolve as calls do
+    assert_eq!(stdout(&["--complete-history", "docker", "logs"]), "web\n");
+    assert_eq!(stdout(&["--complete-history", "docker:logs"]), "web\n");
+    assert!(temp_dir.path().join(".run/history").exists());
+
+    // Opt-in: nothing is offered or recorded without the setting
+    create_runfile(temp_dir.path(), "deploy() echo \"deploy $@\"\n");
+    stdout(&["deploy", "qa"]);
+    assert_eq!(stdout(&["--complete-history", "deploy"]), "");
+    let history = std::fs::read_to_string(temp_dir.path().join(".run/history")).unwrap();
+    assert!(!history.contains("qa"), "got: {}", history);
+}
+
 #[test]
 fn test_runfile_search_upward() {
     let binary = get_binary_path();
@@ -356,7 +585,7 @@ parent() echo "Called from parent"
     let subdir = temp_dir.path().join("subdir");
     fs::create_dir(&subdir).unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("parent")
         .current_dir(&subdir)
         .output()
@@ -386,7 +615,7 @@ test() echo "From local"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("test")
         .current_dir(&local_dir)
         .env("HOME", temp_dir.path())
@@ -414,7 +643,7 @@ hello()
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -437,7 +666,7 @@ build() echo "Building..."
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("nonexistent")
         .current_dir(temp_dir.path())
         .output()
@@ -460,7 +689,7 @@ invalid syntax here
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("test")
         .current_dir(temp_dir.path())
         .output()
@@ -484,7 +713,7 @@ echo_all() echo "All args: $@"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("echo_all")
         .arg("foo")
         .arg("bar")
@@ -510,9 +739,10 @@ count() echo "one\ntwo\nthree" | wc -l
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("count")
         .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
         .output()
         .expect("Failed to execute command");
 
@@ -536,7 +766,7 @@ test() echo "Testing"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--list")
         .current_dir(temp_dir.path())
         .output()
@@ -561,7 +791,7 @@ multiline() echo "This is a" \
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("multiline")
         .current_dir(temp_dir.path())
         .output()
@@ -587,7 +817,7 @@ greet(World)
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -613,7 +843,7 @@ docker:logs(app)
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -639,7 +869,7 @@ greet("Alice", "Bob")
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -665,7 +895,7 @@ show(bare, "quoted")
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -691,7 +921,7 @@ echo "Hello, $name!"
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -718,7 +948,7 @@ show(production)
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -745,7 +975,7 @@ echo "$first and $second"
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -771,7 +1001,7 @@ echo "Application: $app_name"
     )
     .unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg(script_path.to_str().unwrap())
         .current_dir(temp_dir.path())
         .output()
@@ -782,10 +1012,152 @@ echo "Application: $app_name"
     assert!(stdout.contains("Application: myapp"));
 }
 
+#[test]
+fn test_braced_variables() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+app=web
+appname=other
+tag=1.2
+image=${app}-${tag}
+show() echo "${app}name $appname $image ${RUN_OS}x $app_undefined."
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("show")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("webname other web-1.2 {}x .\n", std::env::consts::OS)
+    );
+}
+
+#[test]
+fn test_variables_and_arguments_substituted_in_one_pass() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+pattern = $1x
+FOO = short
+show() echo '$pattern' "$1"
+names() echo "$FOOBAR $FOO ${FOO}BAR"
+"#,
+    );
+
+    // A variable's value is never rescanned for argument references
+    let output = binary_command(&binary)
+        .args(["show", "arg"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "$1x arg\n");
+
+    // `$FOO` doesn't match the start of `$FOOBAR`, left for the shell
+    let output = binary_command(&binary)
+        .arg("names")
+        .current_dir(temp_dir.path())
+        .env("FOOBAR", "long")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "long short shortBAR\n"
+    );
+}
+
+#[test]
+fn test_list_variables() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+region = eu
+targets = [web, "api server", ${region}-worker]
+show() echo "${targets[1]}|${targets[-1]}|${#targets}|[${targets[5]}]|$targets"
+deploy() {
+    for t in $targets {
+        echo "deploying $t"
+    }
+    for r in a $1 {
+        echo "to $r"
+    }
+}
+each() {
+    for a in $@ {
+        printf '[%s]\n' $a
+    }
+}
+words() {
+    for n in "it's" "a b" $1 {
+        printf '[%s]\n' $n "<$n>"
+    }
+}
+literal() {
+    for u in [a, "b c", ${region}] {
+        echo $u
+    }
+}
+"#,
+    );
+    let run = |args: &[&str]| {
+        let output = run_in(temp_dir.path(), args);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert_eq!(
+        run(&["show"]),
+        "api server|eu-worker|3|[]|web api server eu-worker\n"
+    );
+    assert_eq!(
+        run(&["deploy", "b"]),
+        "deploying web\ndeploying api server\ndeploying eu-worker\nto a\nto b\n"
+    );
+    // A dry run shows the commands of each time through the loop
+    assert_eq!(
+        run(&["--dry-run", "deploy"]),
+        "echo \"deploying web\"\necho \"deploying api server\"\necho \"deploying eu-worker\"\necho \"to a\"\n"
+    );
+    // Each argument is one item, and items are quoted where they are used
+    assert_eq!(run(&["each", "x y", "it's"]), "[x y]\n[it's]\n");
+    assert_eq!(
+        run(&["words", "\"q\" $HOME"]),
+        "[it's]\n[<it's>]\n[a b]\n[<a b>]\n[\"q\" $HOME]\n[<\"q\" $HOME>]\n"
+    );
+    assert_eq!(run(&["literal"]), "a\nb c\neu\n");
+
+    // A list that isn't closed is an error, not words
+    create_runfile(
+        temp_dir.path(),
+        "broken() {\n    for u in [a, b {\n        echo $u\n    }\n}\n",
+    );
+    let output = run_in(temp_dir.path(), &["broken"]);
+    assert!(!output.status.success());
+}
+
 #[test]
 fn test_generate_completion_bash() {
     let binary = get_binary_path();
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--generate-completion")
         .arg("bash")
         .output()
@@ -801,7 +1173,7 @@ fn test_generate_completion_bash() {
 #[test]
 fn test_generate_completion_zsh() {
     let binary = get_binary_path();
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--generate-completion")
         .arg("zsh")
         .output()
@@ -816,7 +1188,7 @@ fn test_generate_completion_zsh() {
 #[test]
 fn test_generate_completion_fish() {
     let binary = get_binary_path();
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--generate-completion")
         .arg("fish")
         .output()
@@ -828,13 +1200,28 @@ fn test_generate_completion_fish() {
     assert!(stdout.contains("complete -c run"));
 }
 
+#[test]
+fn test_generate_completion_powershell() {
+    let binary = get_binary_path();
+    let output = binary_command(&binary)
+        .arg("--generate-completion")
+        .arg("powershell")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("# PowerShell completion script"));
+    assert!(stdout.contains("Register-ArgumentCompleter -Native -CommandName run"));
+}
+
 #[test]
 fn test_install_completion_zsh() {
     let binary = get_binary_path();
     let temp_dir = create_temp_dir();
 
     // Set HOME to temp directory
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("zsh")
         .env("HOME", temp_dir.path())
@@ -863,7 +1250,7 @@ fn test_install_completion_bash() {
     let binary = get_binary_path();
     let temp_dir = create_temp_dir();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("bash")
         .env("HOME", temp_dir.path())
@@ -890,7 +1277,7 @@ fn test_install_completion_fish() {
     let binary = get_binary_path();
     let temp_dir = create_temp_dir();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("fish")
         .env("HOME", temp_dir.path())
@@ -912,6 +1299,47 @@ fn test_install_completion_fish() {
     assert!(content.contains("complete -c run"));
 }
 
+#[test]
+fn test_install_completion_powershell() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let profile = temp_dir
+        .path()
+        .join(".config/powershell/Microsoft.PowerShell_profile.ps1");
+    fs::create_dir_all(profile.parent().unwrap()).unwrap();
+    fs::write(&profile, "Set-PSReadLineOption -EditMode Emacs").unwrap();
+
+    // Twice: the profile only gains one line. PATH without PowerShell, so
+    // the usual profile location is used
+    for _ in 0..2 {
+        let output = binary_command(&binary)
+            .args(["--install-completion", "pwsh"])
+            .env("HOME", temp_dir.path())
+            .env("PATH", temp_dir.path())
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Installing powershell completion"));
+    }
+
+    let comp_file = temp_dir.path().join(".config/powershell/run-completion.ps1");
+    let content = fs::read_to_string(&comp_file).unwrap();
+    assert!(content.contains("Register-ArgumentCompleter"));
+    assert_eq!(
+        fs::read_to_string(&profile).unwrap(),
+        format!("Set-PSReadLineOption -EditMode Emacs\n. \"{}\"\n", comp_file.display())
+    );
+
+    // There is nowhere to install them for every user
+    let output = binary_command(&binary)
+        .args(["--install-completion", "powershell", "--system"])
+        .env("DESTDIR", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+}
+
 #[test]
 fn test_install_completion_detects_missing_zshrc_config() {
     let binary = get_binary_path();
@@ -921,7 +1349,7 @@ fn test_install_completion_detects_missing_zshrc_config() {
     let zshrc_path = temp_dir.path().join(".zshrc");
     fs::write(&zshrc_path, "# Empty zshrc\n").unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("zsh")
         .env("HOME", temp_dir.path())
@@ -949,7 +1377,7 @@ fpath=(~/.zsh/completion $fpath)
 autoload -Uz compinit && compinit
 "#).unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("zsh")
         .env("HOME", temp_dir.path())
@@ -981,7 +1409,7 @@ fn test_install_completion_detects_partial_zshrc_config() {
 fpath=(~/.zsh/completion $fpath)
 "#).unwrap();
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("zsh")
         .env("HOME", temp_dir.path())
@@ -1000,13 +1428,55 @@ fpath=(~/.zsh/completion $fpath)
         "Should suggest compinit since it's missing");
 }
 
+#[test]
+fn test_install_completion_system() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let output = binary_command(&binary)
+        .args(["--install-completion", "--system"])
+        .env("DESTDIR", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for (path, marker) in [
+        ("usr/share/bash-completion/completions/run", "complete -F"),
+        ("usr/share/zsh/site-functions/_run", "#compdef run"),
+        ("usr/share/fish/vendor_completions.d/run.fish", "complete -c run"),
+    ] {
+        let content = fs::read_to_string(temp_dir.path().join(path)).unwrap();
+        assert!(content.contains(marker), "{} lacks {}", path, marker);
+    }
+
+    // Naming a shell installs only that one
+    let temp_dir = create_temp_dir();
+    let output = binary_command(&binary)
+        .args(["--install-completion", "fish", "--system"])
+        .env("DESTDIR", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(temp_dir.path().join("usr/share/fish/vendor_completions.d/run.fish").exists());
+    assert!(!temp_dir.path().join("usr/share/zsh").exists());
+
+    let output = binary_command(&binary)
+        .arg("--system")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+}
+
 #[test]
 fn test_install_completion_auto_detect_fails_with_unknown_shell() {
     let binary = get_binary_path();
     let temp_dir = create_temp_dir();
 
     // Set SHELL to something unsupported
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .env("HOME", temp_dir.path())
         .env("SHELL", "/bin/ksh")  // Unsupported shell
@@ -1032,7 +1502,7 @@ fn test_install_completion_overwrites_existing_file() {
     fs::write(&comp_file, "# Old completion content\n").unwrap();
 
     // Install new completion
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("--install-completion")
         .arg("zsh")
         .env("HOME", temp_dir.path())
@@ -1063,7 +1533,7 @@ function greet {
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .current_dir(temp_dir.path())
         .output()
@@ -1088,7 +1558,7 @@ function greet() {
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .current_dir(temp_dir.path())
         .output()
@@ -1111,7 +1581,7 @@ function greet echo "Hello inline!"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .current_dir(temp_dir.path())
         .output()
@@ -1134,7 +1604,7 @@ function greet() echo "Hello parens inline!"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .current_dir(temp_dir.path())
         .output()
@@ -1159,7 +1629,7 @@ function docker:shell {
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("docker:shell")
         .current_dir(temp_dir.path())
         .output()
@@ -1182,7 +1652,7 @@ function docker:logs echo "Showing logs"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("docker:logs")
         .current_dir(temp_dir.path())
         .output()
@@ -1205,7 +1675,7 @@ function greet echo "Hello, $1!"
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("greet")
         .arg("World")
         .current_dir(temp_dir.path())
@@ -1233,7 +1703,7 @@ function deploy {
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("deploy")
         .current_dir(temp_dir.path())
         .output()
@@ -1258,7 +1728,7 @@ function quick { echo "one"; echo "two"; echo "three"; }
 "#,
     );
 
-    let output = Command::new(&binary)
+    let output = binary_command(&binary)
         .arg("quick")
         .current_dir(temp_dir.path())
         .output()
@@ -1296,7 +1766,7 @@ function keyword_inline echo "keyword inline"
     );
 
     // Test all four variants
-    let output1 = Command::new(&binary)
+    let output1 = binary_command(&binary)
         .arg("traditional")
         .current_dir(temp_dir.path())
         .output()
@@ -1304,7 +1774,7 @@ function keyword_inline echo "keyword inline"
     assert!(output1.status.success());
     assert!(String::from_utf8_lossy(&output1.stdout).contains("traditional"));
 
-    let output2 = Command::new(&binary)
+    let output2 = binary_command(&binary)
         .arg("keyword_block")
         .current_dir(temp_dir.path())
         .output()
@@ -1312,7 +1782,7 @@ function keyword_inline echo "keyword inline"
     assert!(output2.status.success());
     assert!(String::from_utf8_lossy(&output2.stdout).contains("keyword block"));
 
-    let output3 = Command::new(&binary)
+    let output3 = binary_command(&binary)
         .arg("keyword_parens")
         .current_dir(temp_dir.path())
         .output()
@@ -1320,7 +1790,7 @@ function keyword_inline echo "keyword inline"
     assert!(output3.status.success());
     assert!(String::from_utf8_lossy(&output3.stdout).contains("keyword parens"));
 
-    let output4 = Command::new(&binary)
+    let output4 = binary_command(&binary)
         .arg("keyword_inline")
         .current_dir(temp_dir.path())
         .output()
@@ -1329,3 +1799,4612 @@ function keyword_inline echo "keyword inline"
     assert!(String::from_utf8_lossy(&output4.stdout).contains("keyword inline"));
 }
 
+
+#[test]
+fn test_argument_with_quotes_inside_quoted_template() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+greet() printf '%s\n' "Hello, $1!"
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("greet")
+        .arg(r#"say "hi" \o/"#)
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(r#"Hello, say "hi" \o/!"#), "got: {}", stdout);
+}
+
+#[test]
+fn test_escape_sequences_in_call_arguments() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let script_path = temp_dir.path().join("test_escapes.run");
+    fs::write(
+        &script_path,
+        r#"
+show() printf '[%s]\n' "$1"
+show("say \"hi\"")
+show("back\\slash")
+show("tab\tstop")
+show("\\$RUN_ESCAPE_MARK")
+show("\$RUN_ESCAPE_MARK")
+"#,
+    )
+    .unwrap();
+
+    let output = binary_command(&binary)
+        .arg(script_path.to_str().unwrap())
+        .current_dir(temp_dir.path())
+        .env("RUN_ESCAPE_MARK", "marked")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(r#"[say "hi"]"#), "got: {}", stdout);
+    assert!(stdout.contains(r"[back\slash]"), "got: {}", stdout);
+    assert!(stdout.contains("[tab\tstop]"), "got: {}", stdout);
+    // Each escape is resolved once: an escaped backslash before a variable
+    // leaves the variable to expand
+    assert!(stdout.contains(r"[\marked]"), "got: {}", stdout);
+    assert!(stdout.contains("[$RUN_ESCAPE_MARK]"), "got: {}", stdout);
+}
+
+#[test]
+fn test_function_call_with_mixed_quoting() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let script_path = temp_dir.path().join("test_mixed_quotes.run");
+    fs::write(
+        &script_path,
+        r#"
+show() printf '[%s]' "$1" "$2"
+show("a, b", 'single')
+show('say "hi"', "it's")
+echo
+echo 'two  spaces "kept"'
+"#,
+    )
+    .unwrap();
+
+    let output = binary_command(&binary)
+        .arg(script_path.to_str().unwrap())
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[a, b][single]"), "got: {}", stdout);
+    assert!(stdout.contains(r#"[say "hi"][it's]"#), "got: {}", stdout);
+    assert!(stdout.contains(r#"two  spaces "kept""#), "got: {}", stdout);
+}
+
+#[test]
+fn test_quoted_all_args_preserves_boundaries() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+wrap() printf '[%s]' "$@"
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["wrap", "commit", "-m", "two words", "it's"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "[commit][-m][two words][it's]");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_arguments_stay_single_words() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+words() printf '[%s]' $1 $2 $3; echo
+tenth() echo "$1 $10 ${11}"
+single() echo '$1'
+escaped() echo \$1 $1
+"#,
+    );
+    let run = |args: &[&str]| {
+        let output = run_in(temp_dir.path(), args);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Spaces, quotes and shell syntax in an argument don't split or run it,
+    // and references in its value aren't substituted again
+    assert_eq!(
+        run(&["words", "two words", "it's \"quoted\"", "$1; echo injected"]),
+        "[two words][it's \"quoted\"][$1; echo injected]\n"
+    );
+    // A missing argument is no word at all
+    assert_eq!(run(&["words", "one"]), "[one]\n");
+
+    // `$10` is the tenth argument, not the first followed by a 0
+    let args = ["tenth", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k"];
+    assert_eq!(run(&args), "a j k\n");
+
+    assert_eq!(run(&["single", "it's"]), "it's\n");
+    assert_eq!(run(&["escaped", "hi"]), "$1 hi\n");
+
+    // The dry run shows the arguments quoted as they are passed
+    assert_eq!(
+        run(&["--dry-run", "words", "a b", "c'd"]),
+        "printf '[%s]' 'a b' 'c'\\''d' ; echo\n"
+    );
+}
+
+#[test]
+fn test_call_site_variable_interpolation() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let script_path = temp_dir.path().join("test_call_site_vars.run");
+    fs::write(
+        &script_path,
+        r#"
+version=1.2.3
+deploy() echo "Deploying $2 to $1"
+deploy($TARGET_ENV, "v$version")
+deploy(${TARGET_ENV}, 'v$version')
+"#,
+    )
+    .unwrap();
+
+    let output = binary_command(&binary)
+        .arg(script_path.to_str().unwrap())
+        .current_dir(temp_dir.path())
+        .env("TARGET_ENV", "staging")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Deploying v1.2.3 to staging"), "got: {}", stdout);
+    assert!(stdout.contains("Deploying v$version to staging"), "got: {}", stdout);
+}
+
+#[test]
+fn test_ifdef_env_guard() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+ifdef CI {
+    test() echo "Running in CI"
+} else {
+    test() {
+        echo "Running locally"
+    }
+    dev() echo "Dev server"
+}
+
+ifndef CI {
+    mode=local
+}
+mode() echo "mode: $mode"
+"#,
+    );
+
+    let ci_output = binary_command(&binary)
+        .arg("test")
+        .current_dir(temp_dir.path())
+        .env("CI", "true")
+        .output()
+        .expect("Failed to execute command");
+    assert!(ci_output.status.success());
+    assert!(String::from_utf8_lossy(&ci_output.stdout).contains("Running in CI"));
+
+    let local_output = binary_command(&binary)
+        .arg("test")
+        .current_dir(temp_dir.path())
+        .env_remove("CI")
+        .output()
+        .expect("Failed to execute command");
+    assert!(local_output.status.success());
+    assert!(String::from_utf8_lossy(&local_output.stdout).contains("Running locally"));
+
+    let mode_output = binary_command(&binary)
+        .arg("mode")
+        .current_dir(temp_dir.path())
+        .env_remove("CI")
+        .output()
+        .expect("Failed to execute command");
+    assert!(String::from_utf8_lossy(&mode_output.stdout).contains("mode: local"));
+
+    let list_output = binary_command(&binary)
+        .arg("--list")
+        .current_dir(temp_dir.path())
+        .env("CI", "1")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&list_output.stdout);
+    assert!(stdout.contains("test"));
+    assert!(!stdout.contains("dev"));
+}
+
+#[test]
+fn test_skip_if_and_only_if_annotations() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@skip-if exists(".skip-e2e")
+e2e() echo "running e2e"
+
+@only-if command_exists("definitely-not-a-real-tool-xyz")
+docker:up() echo "docker up"
+
+@only-if command_exists("sh")
+lint() echo "linting"
+"#,
+    );
+    fs::write(temp_dir.path().join(".skip-e2e"), "").unwrap();
+
+    let output = binary_command(&binary)
+        .arg("e2e")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("running e2e"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipping 'e2e'"), "got: {}", stderr);
+    assert!(stderr.contains(r#"exists(".skip-e2e")"#));
+
+    let output = binary_command(&binary)
+        .args(["docker", "up"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("docker up"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only-if condition not met"));
+
+    let output = binary_command(&binary)
+        .arg("lint")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("linting"));
+}
+
+#[test]
+fn test_requires_reports_all_missing_tools() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+deploy() {
+    requires sh no-such-tool-abc no-such-tool-xyz
+    echo "should not run"
+}
+
+@requires sh
+greet() echo "hello"
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("deploy")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("should not run"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("missing required tools: no-such-tool-abc, no-such-tool-xyz"),
+        "got: {}",
+        stderr
+    );
+
+    let output = binary_command(&binary)
+        .arg("greet")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_requires_version_constraints() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // A fake tool that reports its version like most CLIs do
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let tool = bin_dir.join("faketool");
+    fs::write(&tool, "#!/bin/sh\necho \"faketool version 2.3.1 (build 42)\"\n").unwrap();
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@requires faketool >= 3, no-such-tool-xyz
+too_old() echo "should not run"
+
+@requires faketool ~> 2.3
+compatible() echo "compatible"
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("too_old")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing required tools: no-such-tool-xyz"), "got: {}", stderr);
+    assert!(stderr.contains("faketool >= 3 required, found 2.3.1"), "got: {}", stderr);
+
+    let output = binary_command(&binary)
+        .arg("compatible")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("compatible"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_toolchain_manager_wraps_commands() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Fake `mise` that announces itself, then runs what follows `exec --`
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let mise = bin_dir.join("mise");
+    fs::write(&mise, "#!/bin/sh\nshift 2\necho \"[mise]\"\nexec \"$@\"\n").unwrap();
+    fs::set_permissions(&mise, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    fs::write(temp_dir.path().join(".tool-versions"), "nodejs 20.11.0\n").unwrap();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+toolchain.manager = auto
+build() echo "building"
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .env_remove("RUN_TOOLCHAIN")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[mise]\nbuilding"), "got: {}", stdout);
+
+    // RUN_TOOLCHAIN=none disables the wrapper
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .env("RUN_TOOLCHAIN", "none")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("[mise]"), "got: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_container_fallback_for_missing_tools() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Fake `docker` that just reports how it was invoked
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let docker = bin_dir.join("docker");
+    fs::write(&docker, "#!/bin/sh\necho \"[docker] $*\"\n").unwrap();
+    fs::set_permissions(&docker, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@container node:20
+@requires no-such-node-xyz
+build() no-such-node-xyz build.js
+"#,
+    );
+
+    // Without --auto-container and no TTY, fail with a hint
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--auto-container"), "got: {}", stderr);
+
+    let output = binary_command(&binary)
+        .args(["--auto-container", "build"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[docker] run --rm -i -v"), "got: {}", stdout);
+    assert!(stdout.contains("-w /workspace node:20 sh -c no-such-node-xyz build.js"), "got: {}", stdout);
+}
+
+#[test]
+fn test_outputs_are_recorded_and_cleaned() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@outputs dist/**
+build() mkdir -p dist/js && echo app > dist/js/app.js && echo style > dist/site.css
+
+@outputs missing.txt
+broken() echo nothing
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let manifest = temp_dir.path().join(".run/artifacts/build.sha256");
+    let recorded = fs::read_to_string(&manifest).unwrap();
+    assert!(recorded.contains("  dist/js/app.js\n"), "got: {}", recorded);
+    assert!(recorded.contains("  dist/site.css\n"), "got: {}", recorded);
+
+    fs::write(temp_dir.path().join("dist/site.css"), "changed").unwrap();
+    let output = binary_command(&binary)
+        .args(["artifacts", "build"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dist/js/app.js\n"), "got: {}", stdout);
+    assert!(stdout.contains("dist/site.css  (modified)"), "got: {}", stdout);
+
+    let output = binary_command(&binary)
+        .args(["artifacts", "build", "--clean"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(!temp_dir.path().join("dist").exists());
+    assert!(!manifest.exists());
+
+    // A function that doesn't produce its outputs fails
+    let output = binary_command(&binary)
+        .arg("broken")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("did not produce its declared outputs: missing.txt"),
+        "got: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_inputs_outputs_cache() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let remote_dir = create_temp_dir();
+    let project = temp_dir.path();
+
+    fs::create_dir(project.join("src")).unwrap();
+    fs::write(project.join("src/main.txt"), "v1").unwrap();
+    create_runfile(
+        project,
+        &format!(
+            r#"
+cache.remote = "file://{}"
+
+@inputs src/**
+@outputs out/**
+build() mkdir -p out && cp src/main.txt out/main.txt && echo building
+"#,
+            remote_dir.path().display()
+        ),
+    );
+    let run_build = |extra: &[&str]| {
+        binary_command(&binary)
+            .args(extra)
+            .arg("build")
+            .current_dir(project)
+            .output()
+            .expect("Failed to execute command")
+    };
+    let ran = |output: &std::process::Output| {
+        String::from_utf8_lossy(&output.stdout).contains("building")
+    };
+
+    let output = run_build(&[]);
+    assert!(output.status.success());
+    assert!(ran(&output));
+
+    // Unchanged inputs and outputs: skipped
+    let output = run_build(&[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'build' is up to date"), "got: {}", stderr);
+    assert!(!ran(&output));
+
+    // Lost outputs are restored from the remote cache instead of rebuilt
+    fs::remove_dir_all(project.join("out")).unwrap();
+    fs::remove_dir_all(project.join(".run")).unwrap();
+    let output = run_build(&[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Restored 'build' outputs from remote cache"), "got: {}", stderr);
+    assert_eq!(fs::read_to_string(project.join("out/main.txt")).unwrap(), "v1");
+    assert!(!ran(&output));
+
+    // --no-remote-cache rebuilds instead
+    fs::remove_dir_all(project.join(".run")).unwrap();
+    assert!(ran(&run_build(&["--no-remote-cache"])));
+
+    // Changed inputs run again; read-only mode doesn't upload the result
+    fs::write(project.join("src/main.txt"), "v2").unwrap();
+    let stored = fs::read_dir(remote_dir.path()).unwrap().count();
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(project)
+        .env("RUN_CACHE_MODE", "read-only")
+        .output()
+        .expect("Failed to execute command");
+    assert!(ran(&output));
+    assert_eq!(fs::read_dir(remote_dir.path()).unwrap().count(), stored);
+}
+
+#[test]
+fn test_needs_runs_prerequisites_in_order() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+clean() echo clean
+lint() echo lint
+test() echo test
+build() needs(clean) echo build
+ci() needs(lint, test, build) echo ci $1
+broken() needs(nope) echo never
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["-j", "1", "ci", "fast"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "lint\ntest\nclean\nbuild\nci fast\n");
+
+    let output = binary_command(&binary)
+        .arg("broken")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Function 'broken' needs unknown function 'nope'"),
+        "got: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_shared_prerequisite_runs_once() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+fmt() echo fmt
+lint() needs(fmt) echo lint
+test() needs(fmt) echo test
+ci() needs(lint, test, fmt) echo ci
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["-j", "1", "ci"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "fmt\nlint\ntest\nci\n"
+    );
+}
+
+#[test]
+fn test_independent_prerequisites_run_in_parallel() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+lint() sleep 1 && echo lint
+test() sleep 1 && echo test
+check() sleep 1 && echo check
+ci() needs(lint, test, check) echo ci
+"#,
+    );
+
+    let start = std::time::Instant::now();
+    let output = binary_command(&binary)
+        .args(["-j", "3", "ci"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    let elapsed = start.elapsed();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.ends_with("ci\n"), "got: {}", stdout);
+    assert!(
+        elapsed < std::time::Duration::from_millis(2500),
+        "prerequisites ran sequentially: {:?}",
+        elapsed
+    );
+}
+
+#[test]
+fn test_dependency_cycle_is_reported() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+a() needs(b) echo a
+b() needs(c) echo b
+c() needs(a) echo c
+hello() echo hello
+"#,
+    );
+
+    for function in ["a", "hello"] {
+        let output = run_in(temp_dir.path(), &[function]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Dependency cycle: a -> b -> c -> a"),
+            "got: {}",
+            stderr
+        );
+        assert!(String::from_utf8_lossy(&output.stdout).is_empty());
+    }
+}
+
+#[test]
+fn test_force_and_always_bypass_cache() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let project = temp_dir.path();
+
+    fs::write(project.join("input.txt"), "data").unwrap();
+    create_runfile(
+        project,
+        r#"
+@inputs input.txt
+@outputs out.txt
+build() cp input.txt out.txt && echo building
+
+@always
+@inputs input.txt
+@outputs stamp.txt
+stamp() cp input.txt stamp.txt && echo stamping
+"#,
+    );
+    let run = |args: &[&str]| {
+        let output = binary_command(&binary)
+            .args(args)
+            .current_dir(project)
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert!(run(&["build"]).contains("building"));
+    assert!(!run(&["build"]).contains("building"));
+    assert!(run(&["--force", "build"]).contains("building"));
+    assert!(!run(&["build"]).contains("building"));
+
+    assert!(run(&["stamp"]).contains("stamping"));
+    assert!(run(&["stamp"]).contains("stamping"));
+}
+
+#[test]
+fn test_keep_going_reports_all_failures() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@requires no-such-linter-xyz
+lint() echo lint
+
+@requires no-such-tester-xyz
+test() echo test
+
+fmt() echo fmt
+ci() needs(lint, test, fmt) echo ci
+"#,
+    );
+
+    // Fail fast by default: nothing after the first failure starts
+    let output = binary_command(&binary)
+        .args(["-j", "1", "ci"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).is_empty());
+
+    let output = binary_command(&binary)
+        .args(["-j", "1", "--keep-going", "ci"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "fmt\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 of 4 functions failed:"), "got: {}", stderr);
+    assert!(stderr.contains("  lint: Function 'lint' has unmet"), "got: {}", stderr);
+    assert!(stderr.contains("  test: Function 'test' has unmet"), "got: {}", stderr);
+    assert!(
+        stderr.contains("Not run because a prerequisite failed: ci"),
+        "got: {}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resume_failed_chain() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+build() echo build >> log
+test() needs(build) echo test >> log && test -f ready
+deploy(env) needs(test) echo deploy $env >> log
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+    let log = || std::fs::read_to_string(temp_dir.path().join("log")).unwrap_or_default();
+
+    let output = run(&["--resume"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Nothing to resume"));
+
+    let output = run(&["-j", "1", "deploy", "prod"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Completed: build."), "got: {}", stderr);
+    assert!(stderr.contains("run --resume"), "got: {}", stderr);
+    assert_eq!(log(), "build\ntest\n");
+
+    // The same call again, from the first function that didn't complete
+    std::fs::write(temp_dir.path().join("ready"), "").unwrap();
+    let output = run(&["--resume"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Resuming 'run deploy prod', skipping what completed: build"),
+        "got: {}",
+        stderr
+    );
+    assert_eq!(log(), "build\ntest\ntest\ndeploy prod\n");
+    assert!(!temp_dir.path().join(".run/resume").exists());
+    assert!(!run(&["--resume"]).status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fail_fast_terminates_running_siblings() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+slow() sleep 3 && echo slow-done
+
+@requires no-such-tool-xyz
+bad() echo bad
+
+ci() needs(slow, bad) echo ci
+"#,
+    );
+
+    let start = std::time::Instant::now();
+    let output = binary_command(&binary)
+        .args(["-j", "2", "ci"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(
+        start.elapsed() < std::time::Duration::from_secs(2),
+        "sibling was not terminated: {:?}",
+        start.elapsed()
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("slow-done"), "got: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serial_command_reads_terminal_stdin() {
+    use std::io::{Read, Write};
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+    use std::time::{Duration, Instant};
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "prepare() echo ready\nask() needs(prepare) read answer && echo \"got $answer\"\n",
+    );
+
+    let mut leader = 0;
+    let mut follower = 0;
+    // SAFETY: valid pointers to fds; the name, termios and size are optional
+    let opened = unsafe {
+        libc::openpty(
+            &mut leader,
+            &mut follower,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0);
+    // SAFETY: openpty opened both fds, owned here from now on
+    let (leader, follower) =
+        unsafe { (OwnedFd::from_raw_fd(leader), OwnedFd::from_raw_fd(follower)) };
+
+    // The terminal is the controlling one of `run`, as in an interactive
+    // shell, so a command reading it from a background process group would
+    // be stopped. Several jobs are allowed, but the functions can only run
+    // one after the other.
+    let mut command = binary_command(&binary);
+    command
+        .args(["-j", "4", "ask"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .stdin(follower.try_clone().unwrap())
+        .stdout(follower.try_clone().unwrap())
+        .stderr(follower.try_clone().unwrap());
+    // SAFETY: only async-signal-safe calls between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().expect("Failed to execute command");
+    // Only the child has the terminal open now, so reading it ends with it
+    drop((command, follower));
+
+    let mut terminal = std::fs::File::from(leader);
+    writeln!(terminal, "yes").unwrap();
+    let mut reader = terminal.try_clone().unwrap();
+    let reading = std::thread::spawn(move || {
+        // Reading ends with an error once the child closed the terminal
+        let mut output = Vec::new();
+        let _ = reader.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).into_owned()
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break Some(status);
+        }
+        if Instant::now() > deadline {
+            // SAFETY: the child's process group is its own session
+            unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+            child.wait().unwrap();
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let output = reading.join().unwrap();
+    let status = status.expect("the command reading the terminal was stopped");
+    assert!(status.success(), "got: {}", output);
+    assert!(output.contains("got yes"), "got: {}", output);
+}
+
+#[test]
+fn test_output_group_prints_each_function_as_a_block() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+first() {
+    echo first-1
+    sleep 0.4
+    echo first-2 >&2
+}
+second() {
+    sleep 0.2
+    echo second-1
+    sleep 0.4
+    echo second-2
+}
+both() needs(first, second) echo done
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["-j", "2", "--output", "group", "both"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "first-1\nfirst-2\nsecond-1\nsecond-2\ndone\n");
+}
+
+#[test]
+fn test_events_ndjson_stream() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let events_path = temp_dir.path().join("events.ndjson");
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+prep() {
+    echo "preparing \"x\""
+    echo oops >&2
+}
+build() needs(prep) echo built
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["--events", "ndjson", "--events-file"])
+        .arg(&events_path)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    // Output still reaches the terminal streams
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "preparing \"x\"\nbuilt\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("oops"));
+
+    let events = fs::read_to_string(&events_path).unwrap();
+    let lines: Vec<&str> = events.lines().collect();
+    assert!(lines.iter().all(|l| l.starts_with("{\"event\":") && l.ends_with('}')));
+    let find = |needle: &str| {
+        lines
+            .iter()
+            .position(|l| l.contains(needle))
+            .unwrap_or_else(|| panic!("missing {} in:\n{}", needle, events))
+    };
+    let start = find(r#"{"event":"start","task":"prep""#);
+    let line = find(r#"{"event":"stdout","task":"prep","line":"preparing \"x\"""#);
+    find(r#"{"event":"stderr","task":"prep","line":"oops""#);
+    let exit = find(r#"{"event":"exit","task":"prep","status":"success","duration_ms":"#);
+    let build = find(r#"{"event":"start","task":"build""#);
+    assert!(start < line && line < exit && exit < build);
+    find(r#"{"event":"exit","task":"build","status":"success""#);
+}
+
+#[test]
+fn test_github_actions_annotations_and_groups() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(temp_dir.path(), "ok() echo ok\n@bogus\nfoo() echo x\n");
+    let output = binary_command(&binary)
+        .arg("ok")
+        .current_dir(temp_dir.path())
+        .env("GITHUB_ACTIONS", "true")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("::error file=Runfile,line=2::Unknown annotation '@bogus'"),
+        "got: {}",
+        stdout
+    );
+
+    create_runfile(temp_dir.path(), "a() echo a\nb() needs(a) echo b\n");
+    let output = binary_command(&binary)
+        .args(["-j", "2", "b"])
+        .current_dir(temp_dir.path())
+        .env("GITHUB_ACTIONS", "true")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout,
+        "::group::a\na\n::endgroup::\n::group::b\nb\n::endgroup::\n"
+    );
+
+    // Outside Actions nothing changes
+    let output = binary_command(&binary)
+        .arg("a")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_notify_on_posts_webhook() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Fake `curl` that records its arguments and the posted payload
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let curl = bin_dir.join("curl");
+    let log = temp_dir.path().join("curl.log");
+    fs::write(
+        &curl,
+        format!("#!/bin/sh\necho \"$*\" >> '{0}'\ncat >> '{0}'\n", log.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&curl, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+notify.webhook = "https://hooks.example.com/abc"
+
+@notify-on always
+deploy() echo shipping
+
+@notify-on failure
+lint() echo clean
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("deploy")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let posted = fs::read_to_string(&log).unwrap();
+    assert!(posted.contains("https://hooks.example.com/abc"), "got: {}", posted);
+    assert!(posted.contains(r#""task":"deploy","status":"success""#), "got: {}", posted);
+    assert!(posted.contains(r#""output_tail":"shipping""#), "got: {}", posted);
+    // Output still reaches the terminal
+    assert!(String::from_utf8_lossy(&output.stdout).contains("shipping"));
+
+    // A successful function that only notifies on failure stays quiet
+    fs::remove_file(&log).unwrap();
+    let output = binary_command(&binary)
+        .arg("lint")
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(!log.exists());
+}
+
+#[test]
+fn test_env_diff_shows_task_environment() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let shims = temp_dir.path().join("asdf/shims");
+    fs::create_dir_all(&shims).unwrap();
+
+    create_runfile(temp_dir.path(), "build() echo building\n");
+    let output = binary_command(&binary)
+        .args(["env", "--diff", "build"])
+        .current_dir(temp_dir.path())
+        .env_remove("RUN_TOOLCHAIN")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "'build' receives the same environment as this shell.\n"
+    );
+
+    // asdf puts its shims first on PATH
+    let output = binary_command(&binary)
+        .args(["env", "--diff", "build"])
+        .current_dir(temp_dir.path())
+        .env("RUN_TOOLCHAIN", "asdf")
+        .env("ASDF_DATA_DIR", temp_dir.path().join("asdf"))
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with(&format!("~ PATH={}", shims.display())),
+        "got: {}",
+        stdout
+    );
+    assert!(stdout.contains("(was "), "got: {}", stdout);
+
+    // Without --diff, the full environment is printed
+    let output = binary_command(&binary)
+        .args(["env", "build"])
+        .current_dir(temp_dir.path())
+        .env("RUN_ENV_MARKER", "present")
+        .output()
+        .expect("Failed to execute command");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("RUN_ENV_MARKER=present\n"));
+
+    let output = binary_command(&binary)
+        .args(["env", "--diff", "missing"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_eval_expression() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        "app = web\ntag = 1.2\ngreet() echo hi\ngreeting = capture(greet)\n",
+    );
+    let eval = |expression: &str| {
+        binary_command(&binary)
+            .args(["eval", expression])
+            .current_dir(temp_dir.path())
+            .env("RUN_EVAL_MARKER", "from-env")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output =
+        eval("$app:$tag $RUN_EVAL_MARKER [$greeting] $(seconds 1m) $(echo $(echo nested))");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "web:1.2 from-env [hi] 60 nested\n"
+    );
+
+    let output = binary_command(&binary)
+        .arg("eval")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage: run eval"));
+
+    // A Runfile function named eval takes precedence
+    create_runfile(temp_dir.path(), "eval() echo own eval\n");
+    let output = eval("$anything");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "own eval\n");
+}
+
+#[test]
+fn test_argument_slices() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+dc() echo compose $1 -- ${@:2}
+wrap() printf '[%s]' $1 "$2.."
+"#,
+    );
+    let run = |args: &[&str]| {
+        let output = run_in(temp_dir.path(), args);
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert_eq!(run(&["dc", "up", "-d", "web"]), "compose up -- -d web\n");
+    assert_eq!(run(&["dc", "ps"]), "compose ps --\n");
+    assert_eq!(
+        run(&["wrap", "commit", "-m", "two words", "it's"]),
+        "[commit][-m][two words][it's]"
+    );
+}
+
+#[test]
+fn test_automatic_variables() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let sub_dir = temp_dir.path().join("sub");
+    fs::create_dir(&sub_dir).unwrap();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+dist = "$RUNFILE_DIR/dist"
+info() echo "$0|$RUNFILE_DIR|${RUN_PWD}|$RUN_OS|$dist"
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("info")
+        .current_dir(&sub_dir)
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let root = temp_dir.path().canonicalize().unwrap();
+    let pwd = sub_dir.canonicalize().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "info|{0}|{1}|{2}|{0}/dist\n",
+            root.display(),
+            pwd.display(),
+            env::consts::OS
+        )
+    );
+}
+
+#[test]
+fn test_root_relative_functions() {
+    let temp_dir = create_temp_dir();
+    let sub_dir = temp_dir.path().join("sub");
+    fs::create_dir(&sub_dir).unwrap();
+    let root = temp_dir.path().canonicalize().unwrap();
+    let pwd = sub_dir.canonicalize().unwrap();
+
+    let run = |function: &str| {
+        let output = run_in(&sub_dir, &[function]);
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@root-relative
+@outputs out.txt
+build() pwd && echo built > out.txt
+here() pwd
+"#,
+    );
+    assert_eq!(run("build"), format!("{}\n", root.display()));
+    assert!(temp_dir.path().join("out.txt").exists());
+    assert_eq!(run("here"), format!("{}\n", pwd.display()));
+
+    // The setting applies to every function
+    create_runfile(
+        temp_dir.path(),
+        "commands.root_relative = true\nhere() pwd\n",
+    );
+    assert_eq!(run("here"), format!("{}\n", root.display()));
+}
+
+#[test]
+fn test_once_functions_run_once_per_invocation() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@once
+login() {
+    echo logged in
+    echo x >> logins
+}
+
+login()
+deploy() needs(login) echo deploying
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("deploy")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    // The second call replays the output of the first
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "logged in\nlogged in\ndeploying\n"
+    );
+    let logins = fs::read_to_string(temp_dir.path().join("logins")).unwrap();
+    assert_eq!(logins, "x\n");
+}
+
+#[test]
+fn test_matrix_variants() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@matrix target=[x86_64, aarch64] profile=[debug,release]
+build() echo "$target/${profile} $1"
+ship() needs(build) echo shipped
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["-j", "1", "build"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "x86_64/debug \nx86_64/release \naarch64/debug \naarch64/release \n"
+    );
+
+    let output = run(&["build", "target=aarch64", "profile=release", "--locked"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "aarch64/release --locked\n");
+
+    // Everything needing a matrix function waits for all of its variants
+    let output = run(&["-j", "4", "ship"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 5, "got: {}", stdout);
+    assert!(stdout.ends_with("shipped\n"), "got: {}", stdout);
+
+    let output = run(&["build", "target=mips"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No variant of 'build' matches target=mips"), "got: {}", stderr);
+}
+
+#[test]
+fn test_templates_generate_functions() {
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+template service(name, ns) {
+    deploy:$name() echo restart deploy/$name in $ns
+    logs:${name}() {
+        echo "logs of $name"
+    }
+}
+
+instantiate service(api, prod) service(worker, "jobs")
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["deploy", "worker"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "restart deploy/worker in jobs\n"
+    );
+    let output = run(&["logs:api"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "logs of api\n");
+
+    let output = run(&["--list"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for function in ["deploy:api", "logs:api", "deploy:worker", "logs:worker"] {
+        assert!(stdout.contains(function), "missing {}: {}", function, stdout);
+    }
+
+    create_runfile(
+        temp_dir.path(),
+        "template one(x) { hello:$x() echo $x }\ninstantiate one(a, b)\n",
+    );
+    let output = run(&["hello:a"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Template 'one' takes 1 argument(s), got 2"), "got: {}", stderr);
+}
+
+#[test]
+fn test_list_all_aggregates_workspace_members() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let root = temp_dir.path();
+    for member in ["apps/web", "apps/api", "libs/core", "node_modules/dep"] {
+        fs::create_dir_all(root.join(member)).unwrap();
+    }
+
+    create_runfile(root, "build() echo root\ntest() echo root\n");
+    create_runfile(&root.join("apps/web"), "build() echo web\nserve() echo web\n");
+    create_runfile(&root.join("apps/api"), "build() echo api\nmigrate() echo api\n");
+    create_runfile(&root.join("libs/core"), "test() echo core\n");
+    create_runfile(&root.join("node_modules/dep"), "ignored() echo\n");
+
+    let list_all = || {
+        let output = binary_command(&binary)
+            .args(["--list", "--all"])
+            .current_dir(root)
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert_eq!(
+        list_all(),
+        "Available functions:\n  build    ., apps/api, apps/web\n  test     ., libs/core\n  migrate  apps/api\n  serve    apps/web\n"
+    );
+
+    // workspace.members limits which members are included
+    create_runfile(
+        root,
+        "workspace.members = \"apps/*\"\nbuild() echo root\n",
+    );
+    let stdout = list_all();
+    assert!(stdout.contains("  build    ., apps/api, apps/web\n"), "got: {}", stdout);
+    assert!(!stdout.contains("libs/core"), "got: {}", stdout);
+    assert!(!stdout.contains("ignored"), "got: {}", stdout);
+
+    // Without --all only the nearest Runfile is listed
+    let output = binary_command(&binary)
+        .arg("--list")
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Available functions:\n  build\n");
+}
+
+#[test]
+fn test_logs_are_recorded_and_shown() {
+    let temp_dir = create_temp_dir();
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    create_runfile(temp_dir.path(), "build() echo building\n");
+    run(&["build"]);
+    let output = run(&["logs", "build"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("logs.enabled = true"));
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+logs.enabled = true
+logs.keep = 2
+build() {
+    echo building $1
+    echo warning >&2
+}
+"#,
+    );
+    for arg in ["a", "b", "c"] {
+        let output = run(&["build", arg]);
+        // Output still reaches the terminal
+        assert_eq!(String::from_utf8_lossy(&output.stdout), format!("building {}\n", arg));
+    }
+
+    // stdout and stderr are both logged, in the order they were read
+    let sorted_lines = |output: std::process::Output| {
+        let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    };
+    let output = run(&["logs", "build"]);
+    assert!(output.status.success());
+    assert_eq!(sorted_lines(output), ["building c", "warning"]);
+    let output = run(&["logs", "build", "--follow"]);
+    assert_eq!(sorted_lines(output), ["building c", "warning"]);
+
+    let logs = fs::read_dir(temp_dir.path().join(".run/logs/build")).unwrap();
+    assert_eq!(logs.count(), 2);
+}
+
+#[test]
+fn test_status_line_needs_a_terminal() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(temp_dir.path(), "build() echo building\n");
+    // Without a terminal, --status leaves the output untouched
+    let output = binary_command(&binary)
+        .args(["--status", "build"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "building\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_repl_variable_commands() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "greet() echo hello $name\n");
+
+    let mut child = binary_command(&binary)
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start REPL");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(
+            b":set name=world\ngreet()\n:vars\n:vars --json\n:unset name\n:vars\n:unset name\n:bogus\n",
+        )
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("hello world\n"), "got: {}", stdout);
+    assert_eq!(stdout.matches("name=world\n").count(), 1, "got: {}", stdout);
+    assert!(stdout.contains(r#"{"name":"world"}"#), "got: {}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'name' is not set"), "got: {}", stderr);
+    assert!(stderr.contains("Unknown command ':bogus'"), "got: {}", stderr);
+}
+
+#[test]
+fn test_sleep_and_wait_for_builtins() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let closed_port = {
+        let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().port()
+    };
+    create_runfile(
+        temp_dir.path(),
+        &format!(
+            r#"ready() {{
+    sleep 100ms
+    wait_for tcp://127.0.0.1:{} timeout=5s
+    echo ready
+}}
+
+late() {{
+    wait_for tcp://127.0.0.1:{} timeout=300ms
+    echo unreachable
+}}
+
+bad() {{
+    wait_for ftp://example.com
+}}
+"#,
+            open_port, closed_port
+        ),
+    );
+
+    let output = binary_command(&binary)
+        .arg("ready")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ready\n");
+
+    let output = binary_command(&binary)
+        .arg("late")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("unreachable"), "got: {}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Timed out after 0.3s waiting for tcp://127.0.0.1:{}",
+            closed_port
+        )),
+        "got: {}",
+        stderr
+    );
+
+    let output = binary_command(&binary)
+        .arg("bad")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unsupported target"));
+    drop(listener);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_port_and_process_builtins() {
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"port = $(free_port)
+
+ports() {
+    echo $port
+    echo $port
+    echo $(free_port)
+}
+
+check() {
+    is_running sleep
+    echo "sleep: $(is_running sleep), nothing: $(is_running no-such-process)"
+}
+
+missing() {
+    is_running no-such-process
+    echo unreachable
+}
+
+stop() kill_port $1
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("ports")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let ports: Vec<u16> = stdout.lines().map(|line| line.parse().unwrap()).collect();
+    assert_eq!(ports.len(), 3, "got: {}", stdout);
+    assert_eq!(ports[0], ports[1]);
+    assert!(ports.iter().all(|&port| port > 0));
+
+    let mut sleeper = Command::new("sleep").arg("30").spawn().unwrap();
+    let output = binary_command(&binary)
+        .arg("check")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    sleeper.kill().unwrap();
+    sleeper.wait().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "sleep: true, nothing: false\n"
+    );
+
+    let output = binary_command(&binary)
+        .arg("missing")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("unreachable"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'no-such-process' is not running"));
+
+    // A listener in another process, stopped through its port
+    let Ok(mut server) = Command::new("python3")
+        .args([
+            "-c",
+            "import socket,sys,time\ns=socket.socket()\ns.bind(('127.0.0.1',0))\ns.listen()\nprint(s.getsockname()[1],flush=True)\ntime.sleep(30)",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+    else {
+        return;
+    };
+    let mut line = String::new();
+    std::io::BufRead::read_line(
+        &mut std::io::BufReader::new(server.stdout.take().unwrap()),
+        &mut line,
+    )
+    .unwrap();
+    let port = line.trim().to_string();
+
+    let output = binary_command(&binary)
+        .args(["stop", &port])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("Stopped 1 process(es) listening on port {}\n", port)
+    );
+    assert!(!server.wait().unwrap().success());
+
+    let output = binary_command(&binary)
+        .args(["stop", &port])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("Nothing is listening on port {}\n", port)
+    );
+}
+
+#[test]
+fn test_temp_builtins_are_cleaned_up() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"scratch = $(mktemp_dir)
+
+fill() {
+    echo data > $scratch/file
+    cat $scratch/file
+    echo $scratch
+    echo $(mktemp)
+}
+
+@keep-temp
+keep() echo $(mktemp_dir)
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("fill")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "got: {}", stdout);
+    assert_eq!(lines[0], "data");
+    assert!(!std::path::Path::new(lines[1]).exists());
+    assert!(!std::path::Path::new(lines[2]).exists());
+    assert_ne!(lines[1], lines[2]);
+
+    let output = binary_command(&binary)
+        .arg("keep")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let kept = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    assert!(std::path::Path::new(&kept).is_dir(), "got: {}", kept);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("Keeping {}", kept)), "got: {}", stderr);
+    std::fs::remove_dir_all(&kept).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_temp_paths_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "@keep-temp\nkeep() echo $(mktemp) $(mktemp_dir)\n",
+    );
+
+    let output = run_in(temp_dir.path(), &["keep"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let paths: Vec<&str> = stdout.split_whitespace().collect();
+    assert_eq!(paths.len(), 2, "got: {}", stdout);
+    let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let modes = (mode(paths[0]), mode(paths[1]));
+    std::fs::remove_file(paths[0]).unwrap();
+    std::fs::remove_dir(paths[1]).unwrap();
+    assert_eq!(modes, (0o600, 0o700));
+}
+
+#[test]
+fn test_temp_paths_belong_to_their_invocation() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"scratch = $(mktemp_dir)
+
+prepare() echo prepared > $scratch/prepared
+prepare()
+
+inner() echo $(mktemp)
+
+outer() {
+    cat $scratch/prepared
+    test -f $(inner()) && echo "inner $(inner())"
+    echo $scratch
+}
+"#,
+    );
+
+    // Neither the call of prepare() while loading nor the capture of inner()
+    // removes the paths of the invocation around it
+    let output = binary_command(&binary)
+        .arg("outer")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "got: {}", stdout);
+    assert_eq!(lines[0], "prepared");
+    let inner = lines[1].strip_prefix("inner ").unwrap();
+    // All of them are gone once the run is over
+    assert!(!std::path::Path::new(inner).exists());
+    assert!(!std::path::Path::new(lines[2]).exists());
+}
+
+#[test]
+fn test_date_builtins() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"today() {
+    echo $(date utc "%Y-%m-%d %H")
+    date -u "+%Y-%m-%d %H"
+}
+
+stamps() {
+    echo $(date "%s") $(date -1d "%s") $(date +1w2h "%s")
+}
+
+durations() {
+    echo $(duration 5400) $(duration 90061) $(seconds 1h30m) $(seconds 2d)
+}
+
+shell_date() echo $(date +%Y)
+"#,
+    );
+
+    let run = |name: &str| {
+        let output = run_in(temp_dir.path(), &[name]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    // Matches the system's date, unless the hour turned in between
+    let mut today = run("today");
+    let lines: Vec<&str> = today.lines().collect();
+    if lines.len() == 2 && lines[0] != lines[1] {
+        today = run("today");
+    }
+    let lines: Vec<&str> = today.lines().collect();
+    assert_eq!(lines.len(), 2, "got: {}", today);
+    assert_eq!(lines[0], lines[1]);
+
+    let stamps = run("stamps");
+    let stamps: Vec<i64> = stamps.split_whitespace().map(|s| s.parse().unwrap()).collect();
+    assert!((stamps[0] - stamps[1] - 86_400).abs() <= 1, "got: {:?}", stamps);
+    assert!((stamps[2] - stamps[0] - 612_000).abs() <= 1, "got: {:?}", stamps);
+
+    assert_eq!(run("durations"), "1h30m 1d1h1m1s 5400 172800\n");
+
+    // Shell syntax is left to the shell's date
+    let year = run("shell_date");
+    assert_eq!(year.trim().len(), 4, "got: {}", year);
+}
+
+#[test]
+fn test_checksum_builtins() {
+    let temp_dir = create_temp_dir();
+    // SHA-256 of "hello\n"
+    let hash = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+    fs::create_dir(temp_dir.path().join("dist")).unwrap();
+    fs::write(temp_dir.path().join("dist/app.txt"), "hello\n").unwrap();
+    fs::write(
+        temp_dir.path().join("dist/SHA256SUMS"),
+        format!("{}  other.txt\n{} *app.txt\n", "0".repeat(64), hash),
+    )
+    .unwrap();
+    create_runfile(
+        temp_dir.path(),
+        &format!(
+            r#"sum = $(sha256 dist/app.txt)
+
+show() echo $sum
+good() verify_checksum dist/app.txt {hash}
+listed() verify_checksum dist/app.txt dist/SHA256SUMS
+bad() verify_checksum dist/app.txt sha256:{zeros}
+
+@cwd dist
+inside() verify_checksum app.txt SHA256SUMS
+"#,
+            hash = hash,
+            zeros = "0".repeat(64)
+        ),
+    );
+
+    let run = |name: &str| run_in(temp_dir.path(), &[name]);
+
+    assert_eq!(String::from_utf8_lossy(&run("show").stdout), format!("{}\n", hash));
+    for name in ["good", "listed"] {
+        let output = run(name);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "dist/app.txt: OK\n");
+    }
+    let output = run("inside");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "app.txt: OK\n");
+
+    let output = run("bad");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Checksum mismatch for dist/app.txt")
+            && stderr.contains(&format!("actual   {}", hash)),
+        "got: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_diff_builtin() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    fs::write(temp_dir.path().join("expected.txt"), "one\ntwo\nthree\n").unwrap();
+    fs::write(temp_dir.path().join("actual.txt"), "one\n2\nthree\nfour\n").unwrap();
+    create_runfile(
+        temp_dir.path(),
+        "check() diff expected.txt actual.txt\nsame() diff expected.txt expected.txt\nmissing() diff expected.txt nope.txt\n",
+    );
+
+    let output = binary_command(&binary)
+        .arg("check")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "--- expected.txt\n+++ actual.txt\n@@ -1,3 +1,4 @@\n one\n-two\n+2\n three\n+four\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected.txt and actual.txt differ"));
+
+    let output = binary_command(&binary)
+        .arg("same")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let output = binary_command(&binary)
+        .arg("missing")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("diff: nope.txt:"));
+}
+
+#[test]
+fn test_redirections() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    fs::write(temp_dir.path().join("expected.txt"), "one\n").unwrap();
+    fs::write(temp_dir.path().join("actual.txt"), "1\n").unwrap();
+    fs::write(temp_dir.path().join("in.txt"), "b\nc\na\n").unwrap();
+    create_runfile(
+        temp_dir.path(),
+        r#"report() {
+    -diff expected.txt actual.txt > report.diff
+    -diff expected.txt actual.txt >>report.diff
+    echo done 2>&1 >>log.txt
+    sort <in.txt >sorted.txt
+}
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("report")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let diff = "--- expected.txt\n+++ actual.txt\n@@ -1 +1 @@\n-one\n+1\n";
+    let read = |name: &str| fs::read_to_string(temp_dir.path().join(name)).unwrap();
+    assert_eq!(read("report.diff"), diff.repeat(2));
+    assert_eq!(read("log.txt"), "done\n");
+    assert_eq!(read("sorted.txt"), "a\nb\nc\n");
+}
+
+#[test]
+fn test_dry_run() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"target = dist
+deploy() {
+    -rm -rf $target
+    touch deployed-$1.txt
+    echo "done" >> deploy.log
+}
+"#,
+    );
+
+    for flag in ["--dry-run", "-n"] {
+        let output = binary_command(&binary)
+            .args([flag, "deploy", "prod"])
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "rm -rf dist\ntouch deployed-prod.txt\necho \"done\" >> deploy.log\n"
+        );
+    }
+    assert!(!temp_dir.path().join("deployed-prod.txt").exists());
+    assert!(!temp_dir.path().join("deploy.log").exists());
+}
+
+#[test]
+fn test_capture_variables() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"get_version() {
+    echo "computing" >&2
+    touch computed.txt
+    echo 1.2.3
+}
+version = capture(get_version)
+release() echo "releasing v$version and v$version"
+other() echo other
+
+loop = capture(loopy)
+loopy() echo $loop
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["other"]);
+    assert!(output.status.success());
+    assert!(!temp_dir.path().join("computed.txt").exists());
+
+    let output = run(&["--dry-run", "release"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "echo \"releasing v$version and v$version\"\n"
+    );
+    assert!(!temp_dir.path().join("computed.txt").exists());
+
+    let output = run(&["release"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "releasing v1.2.3 and v1.2.3\n"
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "computing\n");
+
+    let output = run(&["loopy"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("'loop' is captured from a function that uses it")
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_run_capture_variables() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"out, errors, code = run_capture(lint)
+_, _, ok = run_capture(fine)
+partial = run_capture(lint)
+
+lint() {
+    echo checking
+    echo "bad thing" >&2
+    sh -c 'exit 3'
+    echo never
+}
+fine() echo fine >&2
+
+report() {
+    echo "out=[$out] errors=[$errors] code=[$code] ok=[$ok]"
+    [ "$code" -eq 0 ] || echo "lint failed: $errors"
+}
+partial() echo "$partial"
+later() {
+    echo $code
+    sh -c 'exit 2'
+}
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["report"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "out=[checking] errors=[bad thing] code=[3] ok=[0]\nlint failed: bad thing\n"
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let output = run(&["partial"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "checking\n");
+
+    // The captured failure doesn't become run's exit code
+    let output = run(&["later"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_conditionals() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"missing = missing.txt
+pick() {
+    if test -f $missing {
+        echo missing
+    } else if test -f present.txt {
+        echo present
+    } else {
+        echo neither
+    }
+    echo picked
+}
+## Create the file the other branch checks for
+toggle() {
+    if test -f present.txt { rm present.txt } else { touch present.txt; echo created }
+}
+probe() {
+    if touch probed.txt { echo probed }
+}
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    // Conditions run with the function, not when the Runfile loads
+    let output = run(&["--list"]);
+    assert!(output.status.success());
+    let output = run(&["pick"]);
+    assert!(output.status.success());
+    assert!(!temp_dir.path().join("probed.txt").exists());
+    let output = run(&["probe"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "probed\n");
+    assert!(temp_dir.path().join("probed.txt").exists());
+
+    let output = run(&["pick"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "neither\npicked\n");
+
+    let output = run(&["toggle"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "created\n");
+    let output = run(&["pick"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "present\npicked\n");
+
+    let output = run(&["toggle"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(!temp_dir.path().join("present.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shell_annotation() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Fake shells that show the flag and command they were given
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    for shell in ["pwsh", "cmd"] {
+        let path = bin_dir.join(shell);
+        fs::write(&path, format!("#!/bin/sh\necho \"{} $1 $2\"\n", shell)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    create_runfile(
+        temp_dir.path(),
+        r#"@shell pwsh
+win_build() Write-Output "$@"
+
+@shell(cmd)
+legacy() {
+    echo one
+    echo two
+}
+
+plain() echo plain
+"#,
+    );
+    let run = |args: &[&str]| {
+        let output = binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("PATH", &path)
+            .env_remove("RUN_SHELL")
+            .env_remove("GITHUB_ACTIONS")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Arguments are quoted for PowerShell
+    assert_eq!(
+        run(&["win_build", "it's"]),
+        "pwsh -Command Write-Output 'it''s'\n"
+    );
+    assert_eq!(run(&["legacy"]), "cmd /C echo one\ncmd /C echo two\n");
+    assert_eq!(run(&["plain"]), "plain\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_translation() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Fake shells that show the command they were given
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    for shell in ["pwsh", "powershell", "cmd"] {
+        let path = bin_dir.join(shell);
+        fs::write(&path, "#!/bin/sh\nprintf '%s\\n' \"$2\"\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    create_runfile(
+        temp_dir.path(),
+        r#"commands.translate = true
+build() echo "hello $USER" 'it'"'"'s' && sort < in.txt > out.txt 2>&1; echo done
+check() test -f a || test -f b
+globbed() echo *.txt
+
+@shell pwsh
+own() Write-Output "$env:USER"
+"#,
+    );
+    let run = |shell: &str, function: &str| {
+        let output = binary_command(&binary)
+            .arg(function)
+            .current_dir(temp_dir.path())
+            .env("PATH", &path)
+            .env("RUN_SHELL", shell)
+            .env_remove("GITHUB_ACTIONS")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert_eq!(
+        run("pwsh", "build"),
+        "echo \"hello ${env:USER}\" 'it''s' && Get-Content -Raw in.txt | sort > out.txt 2>&1; echo done\n"
+    );
+    assert_eq!(
+        run("cmd", "build"),
+        "echo \"hello %USER%\" \"it's\" && sort < in.txt > out.txt 2>&1 & echo done\n"
+    );
+    // Windows PowerShell has no && or ||
+    assert_eq!(
+        run("powershell", "check"),
+        "test -f a; if (-not $?) { test -f b }\n"
+    );
+    // Globs are outside the portable subset, and @shell functions are
+    // written for their shell
+    assert_eq!(run("pwsh", "globbed"), "echo *.txt\n");
+    assert_eq!(run("cmd", "own"), "Write-Output \"$env:USER\"\n");
+}
+
+#[test]
+fn test_refactor_rename() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"# build everything (the build comment stays)
+build() echo building
+function docker:shell {
+    docker compose exec app sh
+}
+test() needs(build,lint) cargo test
+lint() needs( build ) cargo clippy
+ci() {
+    run build && run docker shell
+    echo "build done"; run  build
+}
+version = capture(build)
+_, _, built = run_capture(build)
+"#,
+    );
+    let run = |args: &[&str]| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run(&["refactor", "rename", "build", "compile"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("-build() echo building\n+compile() echo building\n"));
+    assert!(stdout.contains("+_, _, built = run_capture(compile)\n"));
+    assert!(stdout.contains("Renamed 'build' to 'compile': 1 definition(s), 6 reference(s)"));
+
+    let output = run(&["refactor", "rename", "docker:shell", "dev:sh"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("Runfile")).unwrap(),
+        r#"# build everything (the build comment stays)
+compile() echo building
+function dev:sh {
+    docker compose exec app sh
+}
+test() needs(compile,lint) cargo test
+lint() needs( compile ) cargo clippy
+ci() {
+    run compile && run dev sh
+    echo "build done"; run  compile
+}
+version = capture(compile)
+_, _, built = run_capture(compile)
+"#
+    );
+
+    for (args, error) in [
+        (["rename", "lint", "test"], "Function 'test' already exists"),
+        (["rename", "nope", "x"], "Function 'nope' is not defined"),
+        (["rename", "lint", "bad name"], "'bad name' is not a valid function name"),
+    ] {
+        let output = run(&[&["refactor"][..], &args].concat());
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(error));
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_colored_output() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "lint() echo checked\ntest() echo passed >&2\n",
+    );
+
+    // Each function gets a header, and its lines its name in a color
+    let output = binary_command(&binary)
+        .args(["-j", "2", "--parallel", "lint", "test"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .env_remove("NO_COLOR")
+        .env_remove("RUN_PLAIN")
+        .env("TERM", "xterm")
+        .env("FORCE_COLOR", "1")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\x1b[36mlint\x1b[0m \x1b[2m|\x1b[0m checked\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\x1b[2m▶ lint\x1b[0m\n"), "got: {}", stderr);
+    assert!(stderr.contains("\x1b[2m▶ test\x1b[0m\n"), "got: {}", stderr);
+    assert!(
+        stderr.contains("\x1b[35mtest\x1b[0m \x1b[2m|\x1b[0m passed\n"),
+        "got: {}",
+        stderr
+    );
+
+    // --no-color and NO_COLOR, and plain output, leave the output as the
+    // commands wrote it
+    for (flag, env) in [
+        (Some("--no-color"), None),
+        (None, Some(("NO_COLOR", "1"))),
+        (Some("--plain"), None),
+        (None, Some(("RUN_PLAIN", "1"))),
+        (None, Some(("TERM", "dumb"))),
+    ] {
+        let mut command = binary_command(&binary);
+        command
+            .args(flag)
+            .args(["-j", "2", "--parallel", "lint", "test"])
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("NO_COLOR")
+            .env_remove("RUN_PLAIN")
+            .env("TERM", "xterm")
+            .env("FORCE_COLOR", "1");
+        if let Some((name, value)) = env {
+            command.env(name, value);
+        }
+        let output = command.output().expect("Failed to execute command");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "checked\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "passed\n");
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_plain_output() {
+    use std::io::Read;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+## Build the project
+build() echo built
+## Drop the database
+@danger
+db:drop() echo dropped
+broken() {
+    sleep 300ms
+    exit 3
+}
+all() needs(build, broken) echo all
+"#,
+    );
+
+    // Run in a terminal, where colors are forced on and the status line
+    // would be drawn, and return all it printed
+    let run_in_terminal = |args: &[&str]| -> String {
+        let mut leader = 0;
+        let mut follower = 0;
+        // SAFETY: valid pointers to fds; the name, termios and size are optional
+        let opened = unsafe {
+            libc::openpty(
+                &mut leader,
+                &mut follower,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(opened, 0);
+        // SAFETY: openpty opened both fds, owned here from now on
+        let (leader, follower) =
+            unsafe { (OwnedFd::from_raw_fd(leader), OwnedFd::from_raw_fd(follower)) };
+        let mut child = binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("NO_COLOR")
+            .env_remove("RUN_PLAIN")
+            .env("TERM", "xterm")
+            .env("FORCE_COLOR", "1")
+            .stdin(Stdio::null())
+            .stdout(follower.try_clone().unwrap())
+            .stderr(follower)
+            .spawn()
+            .expect("Failed to execute command");
+        // Reading ends with an error once the child closed the terminal
+        let mut output = Vec::new();
+        let _ = std::fs::File::from(leader).read_to_end(&mut output);
+        child.wait().unwrap();
+        String::from_utf8_lossy(&output).into_owned()
+    };
+
+    let listing = run_in_terminal(&["--plain", "--list"]);
+    assert!(
+        listing.contains("  build    Build the project"),
+        "got: {}",
+        listing
+    );
+    assert!(
+        listing.contains("[danger] Drop the database"),
+        "got: {}",
+        listing
+    );
+    let failure = run_in_terminal(&["--plain", "--status", "--timings", "all"]);
+    assert!(failure.contains("s  failed  (slowest "), "got: {}", failure);
+    assert!(
+        failure.contains("Error: 'exit 3' failed with exit status: 3"),
+        "got: {}",
+        failure
+    );
+    assert!(
+        failure.contains("2 function(s): 1 succeeded, 1 failed in "),
+        "got: {}",
+        failure
+    );
+    // Nothing but ASCII lines: no colors, symbols or a spinner returning to
+    // the start of the line to redraw it
+    for output in [&listing, &failure] {
+        assert!(output.is_ascii(), "got: {}", output);
+        assert!(!output.contains('\x1b'), "got: {}", output);
+        assert!(
+            !output.replace("\r\n", "\n").contains('\r'),
+            "got: {}",
+            output
+        );
+    }
+
+    // Without --plain the same run is colored and draws the status line
+    let styled = run_in_terminal(&["--status", "--timings", "all"]);
+    assert!(styled.contains("\x1b[2m▶ broken\x1b[0m"), "got: {}", styled);
+    assert!(styled.contains('⠋'), "got: {}", styled);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_split_into_panes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "profile dev { PORT=3000 }\nwatch() echo watching\nserve() echo serving $PORT\n",
+    );
+
+    // Fake tmux recording the arguments of each call
+    let bin = temp_dir.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let tmux = bin.join("tmux");
+    std::fs::write(&tmux, "#!/bin/sh\necho \"$*\" >> \"$(dirname \"$0\")/calls\"\n").unwrap();
+    std::fs::set_permissions(&tmux, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+
+    let output = binary_command(&binary)
+        .args(["--split", "--profile", "dev", "watch", "serve"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .env("TMUX", "/tmp/tmux-1000/default,1,0")
+        .env("TMUX_PANE", "%3")
+        .env_remove("ZELLIJ")
+        .env_remove("WT_SESSION")
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Started 'watch' in a new tmux pane\nStarted 'serve' in a new tmux pane\n"
+    );
+    let calls = std::fs::read_to_string(bin.join("calls")).unwrap();
+    let calls: Vec<&str> = calls.lines().collect();
+    assert_eq!(calls.len(), 2);
+    let cwd = temp_dir.path().canonicalize().unwrap();
+    let start = format!("split-window -t %3 -c {} {}", cwd.display(), binary.display());
+    assert_eq!(
+        calls[0],
+        format!(
+            "{} --profile dev watch ; set-option -p remain-on-exit on ; select-pane -T watch ; select-layout tiled ; last-pane",
+            start
+        )
+    );
+    assert!(calls[1].starts_with(&format!("{} --profile dev serve ;", start)));
+
+    // Unknown functions are reported before any pane opens
+    let output = binary_command(&binary)
+        .args(["--split", "watch", "missing"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .env("TMUX", "/tmp/tmux-1000/default,1,0")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Function 'missing' not found"));
+    assert_eq!(std::fs::read_to_string(bin.join("calls")).unwrap().lines().count(), 2);
+
+    // Outside a multiplexer the functions run here, in parallel
+    let output = binary_command(&binary)
+        .args(["--split", "watch", "serve"])
+        .current_dir(temp_dir.path())
+        .env_remove("TMUX")
+        .env_remove("ZELLIJ")
+        .env_remove("WT_SESSION")
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+    assert_eq!(lines, ["serving", "watching"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Not inside tmux, zellij or Windows Terminal"));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_paginate() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "long() seq 1 500\nshort() echo hi\n");
+
+    // Without a terminal the output is printed as usual
+    let output = binary_command(&binary)
+        .args(["--paginate", "long"])
+        .current_dir(temp_dir.path())
+        .env("PAGER", "sed s/^/paged:/")
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("1\n2\n"));
+
+    // `script` gives run a terminal, 10 lines high unless it has a size
+    if Command::new("script").arg("--version").output().is_err() {
+        return;
+    }
+    let paged = |function: &str| {
+        let output = Command::new("script")
+            .args(["-qec", &format!("{} --paginate {}", binary.display(), function)])
+            .arg("/dev/null")
+            .current_dir(temp_dir.path())
+            .env("PAGER", "sed s/^/paged:/")
+            .env("LINES", "10")
+            .env("NO_COLOR", "1")
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("LC_ALL")
+            .env_remove("LC_MESSAGES")
+            .env_remove("LANG")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).replace('\r', "")
+    };
+    let long = paged("long");
+    assert!(long.starts_with("paged:1\npaged:2\n"), "got: {}", long);
+    assert!(long.ends_with("paged:500\n"), "got: {}", long);
+    // Output that fits on the screen is printed as it is
+    assert_eq!(paged("short"), "hi\n");
+}
+
+#[test]
+fn test_timings() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+lint() echo lint
+test() {
+    echo unit
+    sleep 300ms
+}
+bad() exit 4
+ci() needs(lint, test) echo ci
+all() needs(ci, bad) echo all
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["--timings", "-j", "1", "ci"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    // The report goes to stderr, leaving the output as it was
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "lint\nunit\nci\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines[0], "Timings:");
+    assert!(lines[1].starts_with("  lint ") && lines[1].ends_with("s  ok"), "got: {}", stderr);
+    // The slowest of several commands is named
+    assert!(lines[2].starts_with("  test "), "got: {}", stderr);
+    assert!(lines[2].contains("s  ok  (slowest "), "got: {}", stderr);
+    assert!(lines[2].ends_with("s: sleep 300ms)"), "got: {}", stderr);
+    assert!(lines[3].starts_with("  ci ") && lines[3].ends_with("s  ok"), "got: {}", stderr);
+    assert!(lines[4].starts_with("3 function(s): 3 succeeded, 0 failed in "), "got: {}", stderr);
+
+    let output = binary_command(&binary)
+        .args(["--timings", "-k", "all"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("s  failed\n"), "got: {}", stderr);
+    assert!(stderr.contains("4 function(s): 3 succeeded, 1 failed in "), "got: {}", stderr);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_supervise() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@supervise restart=on-failure max=5 backoff=10ms
+flaky() {
+    echo attempt >> attempts
+    [ $(wc -l < attempts) -ge 3 ]
+}
+@supervise max=2 backoff=10ms
+broken() exit 3
+@supervise restart=always max=1 backoff=10ms
+server() echo serving
+"#,
+    );
+    let run = |function: &str| run_in(temp_dir.path(), &[function]);
+
+    // Restarted after each failure, with the wait doubling, until it succeeds
+    let output = run("flaky");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {}", stderr);
+    let attempts = std::fs::read_to_string(temp_dir.path().join("attempts")).unwrap();
+    assert_eq!(attempts.lines().count(), 3);
+    assert!(
+        stderr.contains("'flaky' failed: '[ $(wc -l < attempts) -ge 3 ]' failed with exit status: 1; restarting in 10ms (restart 1 of 5)\n"),
+        "got: {}",
+        stderr
+    );
+    assert!(stderr.contains("restarting in 20ms (restart 2 of 5)\n"), "got: {}", stderr);
+
+    // Once the restarts run out, the failure stands
+    let output = run("broken");
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("restarting in").count(), 2, "got: {}", stderr);
+    assert!(stderr.contains("'broken' failed after 2 restart(s)"), "got: {}", stderr);
+
+    // restart=always restarts it when it succeeds too
+    let output = run("server");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "serving\nserving\n");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("'server' exited; restarting in 10ms (restart 1 of 1)")
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_verbose() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "name = world\n\ngreet() echo hello $name\n\nbuild() {\n    echo quiet\n    set -x\n    echo loud\n    sleep 0\n}\n",
+    );
+    let run = |args: &[&str]| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env("NO_COLOR", "1")
+            .env("SHELL", "/bin/sh")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // Each command is printed to stderr after substitution, with its shell
+    let output = run(&["-v", "greet"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello world\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "+ [sh] echo hello world\n");
+
+    // Without it, `set -x` traces the rest of the block, builtins included
+    let output = run(&["build"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "quiet\nloud\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "+ [sh] echo loud\n+ [builtin] sleep 0\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_edit_runfile() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let project = temp_dir.path().join("project");
+    let nested = project.join("src");
+    fs::create_dir_all(&nested).unwrap();
+    // Even a Runfile that doesn't parse can be opened to fix it
+    create_runfile(&project, "broken( {\n");
+    let log = temp_dir.path().join("edited.txt");
+    let editor = format!("sh -c 'echo \"$0\" >> {}'", log.display());
+    let run = |dir: &std::path::Path, env: &[(&str, &str)]| {
+        binary_command(&binary)
+            .arg("edit")
+            .current_dir(dir)
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("VISUAL")
+            .env("HOME", temp_dir.path())
+            .envs(env.iter().copied())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let runfile = project.join("Runfile");
+    let output = run(&nested, &[("EDITOR", &editor)]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("Opening {}\n", runfile.display())
+    );
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        format!("{}\n", runfile.display())
+    );
+
+    // $VISUAL comes first
+    let output = run(&nested, &[("EDITOR", "false"), ("VISUAL", &editor)]);
+    assert!(output.status.success());
+
+    // Without a Runfile around, ~/.runfile is the one in effect
+    let elsewhere = temp_dir.path().join("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    let output = run(&elsewhere, &[("EDITOR", &editor)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: No Runfile found"));
+    fs::write(temp_dir.path().join(".runfile"), "hello() echo hi\n").unwrap();
+    let output = run(&elsewhere, &[("EDITOR", &editor)]);
+    assert!(output.status.success());
+    assert!(fs::read_to_string(&log).unwrap().ends_with(".runfile\n"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_shell_hook() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let project = temp_dir.path().join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    let elsewhere = temp_dir.path().join("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    create_runfile(
+        &project,
+        "build() echo building $1\ntest() echo shadowed\ndocker:shell() echo in docker\n",
+    );
+    // `run` itself, first on the PATH
+    let path = format!(
+        "{}:{}",
+        binary.parent().unwrap().display(),
+        env::var("PATH").unwrap_or_default()
+    );
+    let run = |args: &[&str], dir: &std::path::Path| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(dir)
+            .env("HOME", &elsewhere)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // The functions of the nearest Runfile, for the hooks to define
+    let output = run(&["--hook-functions"], &project.join("src"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "build\ntest\ndocker:shell\n");
+    let output = run(&["--hook-functions"], &elsewhere);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    // `run hook` needs no Runfile, as in shell startup files
+    let output = run(&["hook", "zsh"], &elsewhere);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("add-zsh-hook chpwd _run_hook"));
+    let output = run(&["hook", "tcsh"], &elsewhere);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage: run hook bash|zsh|fish"));
+
+    // Tasks run without `run` inside the project, builtins keep their
+    // meaning, and the tasks go away outside it
+    let script = format!(
+        "eval \"$(run hook bash)\"\ncd {}\n_run_hook\nbuild now\ndocker:shell\ntest 1 = 1 && echo builtin\ncd {}\n_run_hook\ntype -t build || echo gone\n",
+        project.join("src").display(),
+        elsewhere.display()
+    );
+    let output = Command::new("bash")
+        .args(["--norc", "-c", &script])
+        .env("PATH", &path)
+        .env("HOME", &elsewhere)
+        .output()
+        .expect("Failed to run bash");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "building now\nin docker\nbuiltin\ngone\n",
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // A Runfile function called `hook` still wins
+    create_runfile(&project, "hook() echo my hook\n");
+    let output = run(&["hook", "bash"], &project);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "my hook\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_permission_annotations() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "@umask 077\nsecret() echo key > key.pem\n\n@umask 022\n@fix-perms +x dist/bin/*\n@fix-perms go-w dist/notes.txt\nrelease() {\n    mkdir -p dist/bin\n    echo tool > dist/bin/tool\n    echo notes > dist/notes.txt\n    chmod 666 dist/notes.txt\n}\n\n@fix-perms +x missing/*\nbroken() true\n",
+    );
+    let run = |function: &str| run_in(temp_dir.path(), &[function]);
+    let mode = |path: &str| {
+        fs::metadata(temp_dir.path().join(path))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777
+    };
+
+    let output = run("secret");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mode("key.pem"), 0o600);
+
+    let output = run("release");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mode("dist/bin/tool"), 0o755);
+    assert_eq!(mode("dist/notes.txt"), 0o644);
+
+    let output = run("broken");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Function 'broken' has no files matching its @fix-perms paths: missing/*"),
+        "got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_localized_messages() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "build() echo built\nfail() exit 3\n");
+    let run = |locale: &str, args: &[&str]| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("LC_ALL")
+            .env_remove("LC_MESSAGES")
+            .env("LANG", locale)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run("de_DE.UTF-8", &["nope"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Fehler: Funktion 'nope' nicht gefunden\n"
+    );
+    let output = run("de_DE.UTF-8", &["fail"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Fehler: 'exit 3' ist fehlgeschlagen (Exit-Status: 3)")
+    );
+
+    let output = run("es_ES.UTF-8", &["--list"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Funciones disponibles:\n"));
+
+    // Languages without a catalog, and the C locale, stay in English
+    for locale in ["fr_FR.UTF-8", "C"] {
+        let output = run(locale, &["nope"]);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Error: Function 'nope' not found\n"
+        );
+    }
+}
+
+#[test]
+fn test_locale_variables_in_order() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "build() echo built\n");
+    let error = |env: &[(&str, &str)]| {
+        let output = binary_command(&binary)
+            .arg("nope")
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .envs(env.iter().copied())
+            .output()
+            .expect("Failed to execute command");
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    // LC_ALL decides over LC_MESSAGES, and LC_MESSAGES over LANG
+    let spanish = "Error: No se encontró la función 'nope'\n";
+    let german = "Fehler: Funktion 'nope' nicht gefunden\n";
+    assert_eq!(
+        error(&[
+            ("LC_ALL", "es_ES.UTF-8"),
+            ("LC_MESSAGES", "de_DE.UTF-8"),
+            ("LANG", "de_DE.UTF-8")
+        ]),
+        spanish
+    );
+    assert_eq!(
+        error(&[("LC_MESSAGES", "de_DE.UTF-8"), ("LANG", "es_ES.UTF-8")]),
+        german
+    );
+    assert_eq!(error(&[("LANG", "es")]), spanish);
+    // An empty variable counts as unset
+    assert_eq!(error(&[("LC_ALL", ""), ("LANG", "de_DE.UTF-8")]), german);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_parallel_functions() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Each side waits for the other, which only finishes when both run at once
+    std::fs::write(
+        temp_dir.path().join("wait.sh"),
+        "touch $1.ready\nfor i in $(seq 100); do [ -f $2.ready ] && break; sleep 0.05; done\n[ -f $2.ready ] && echo $1\n",
+    )
+    .unwrap();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+setup() echo setup
+ping() sh wait.sh ping pong
+pong() needs(setup) sh wait.sh pong ping
+fail() exit 3
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["-j", "3", "--parallel", "ping", "pong"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+    assert_eq!(lines, ["ping", "pong", "setup"]);
+
+    // Every function runs even when one fails, and the run fails like it
+    std::fs::remove_file(temp_dir.path().join("ping.ready")).unwrap();
+    std::fs::remove_file(temp_dir.path().join("pong.ready")).unwrap();
+    let output = binary_command(&binary)
+        .args(["--parallel", "ping", "fail", "pong"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ping") && stdout.contains("pong"), "got: {}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 of 4 functions failed:"), "got: {}", stderr);
+    assert!(stderr.contains("  fail: 'exit 3' failed"), "got: {}", stderr);
+
+    let output = binary_command(&binary)
+        .args(["--parallel", "ping", "missing"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Function 'missing' not found"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_parallel_output_is_complete() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let events_path = temp_dir.path().join("events.ndjson");
+
+    // Each pauses halfway, so all of them are writing at once
+    std::fs::write(
+        temp_dir.path().join("count.sh"),
+        "for i in $(seq 300); do echo $1-$i; [ $i != 150 ] || sleep 0.3; done\n",
+    )
+    .unwrap();
+    create_runfile(
+        temp_dir.path(),
+        "a() sh count.sh a\nb() sh count.sh b\nc() sh count.sh c\n",
+    );
+
+    let output = binary_command(&binary)
+        .args(["--events", "ndjson", "--events-file"])
+        .arg(&events_path)
+        .args(["--parallel", "a", "b", "c"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    // No line is lost, and each function's come in order
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for name in ["a", "b", "c"] {
+        let lines: Vec<&str> = stdout
+            .lines()
+            .filter(|line| line.starts_with(&format!("{}-", name)))
+            .collect();
+        let expected: Vec<String> = (1..=300).map(|i| format!("{}-{}", name, i)).collect();
+        assert_eq!(lines, expected);
+    }
+    // and the functions' lines are interleaved, as they were written
+    let events = fs::read_to_string(&events_path).unwrap();
+    let position = |needle: &str| events.find(needle).unwrap();
+    assert!(position(r#""line":"c-1""#) < position(r#""line":"a-300""#));
+    assert!(position(r#""line":"a-1""#) < position(r#""line":"c-300""#));
+}
+
+#[test]
+fn test_check_unused() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+setup() echo setup
+version() echo 1.0
+db:shell() echo db
+legacy() echo legacy
+build() needs(setup) echo $version
+release() {
+    run build
+    run db shell
+}
+version = capture(version)
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["check", "--unused"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.ends_with("refers to:\n  legacy\n  release\n"), "got: {}", stdout);
+
+    // Functions only ~/.runfile defines aren't this project's to remove
+    let home = temp_dir.path().join("home");
+    fs::create_dir(&home).unwrap();
+    fs::write(home.join(".runfile"), "gl() echo global\nlegacy() echo global\n").unwrap();
+    let output = binary_command(&get_binary_path())
+        .args(["check", "--unused"])
+        .current_dir(temp_dir.path())
+        .env("HOME", &home)
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.ends_with("refers to:\n  legacy\n  release\n"), "got: {}", stdout);
+
+    // The history comes from the run logs
+    let output = run(&["check", "--unused", "--history"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("set logs.enabled = true"));
+
+    let runfile = fs::read_to_string(temp_dir.path().join("Runfile")).unwrap();
+    create_runfile(temp_dir.path(), &format!("logs.enabled = true\n{}", runfile));
+    assert!(run(&["legacy"]).status.success());
+    let output = run(&["check", "--unused", "--history"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("  legacy   last run 20"), "got: {}", stdout);
+    assert!(stdout.contains("  release  never run"), "got: {}", stdout);
+
+    let output = run(&["check"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage: run check --unused"));
+}
+
+#[test]
+fn test_env_blocks() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+env { RUST_LOG=info GREETING="hello world" }
+show() printenv RUST_LOG GREETING
+db() {
+    env {
+        DATABASE_URL=postgres://localhost/app
+        RUST_LOG=debug
+    }
+    printenv RUST_LOG DATABASE_URL GREETING
+}
+other() printenv DATABASE_URL || echo unset
+profile dev { RUST_LOG=trace }
+"#,
+    );
+    let run = |args: &[&str]| {
+        let output = binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("RUST_LOG")
+            .output()
+            .expect("Failed to execute command");
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    assert_eq!(run(&["show"]), "info\nhello world\n");
+    // A function's own block overrides the top-level one and stays its own
+    assert_eq!(
+        run(&["db"]),
+        "debug\npostgres://localhost/app\nhello world\n"
+    );
+    assert_eq!(run(&["other"]), "unset\n");
+    // The profile chosen on the command line wins
+    assert!(run(&["--profile", "dev", "db"]).starts_with("trace\n"));
+}
+
+#[test]
+fn test_export_package_manifests() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "build() echo build\n");
+    // Empty archives, laid out flat or as the release workflow downloads them
+    let artifacts = temp_dir.path().join("artifacts");
+    fs::create_dir_all(artifacts.join("devrun-x86_64-pc-windows-msvc")).unwrap();
+    for archive in [
+        "devrun-aarch64-apple-darwin.tar.gz",
+        "devrun-x86_64-apple-darwin.tar.gz",
+        "devrun-x86_64-unknown-linux-musl.tar.gz",
+        "devrun-x86_64-pc-windows-msvc/devrun-x86_64-pc-windows-msvc.zip",
+    ] {
+        fs::write(artifacts.join(archive), "").unwrap();
+    }
+    let empty_sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    let version = env!("CARGO_PKG_VERSION");
+    let export = |format: &str| {
+        binary_command(&binary)
+            .args(["export", "--format", format, "--artifacts", "artifacts"])
+            .current_dir(temp_dir.path())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = export("brew-formula");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let formula = String::from_utf8_lossy(&output.stdout);
+    assert!(formula.starts_with("class Devrun < Formula\n"));
+    assert!(formula.contains(&format!("  version \"{}\"\n", version)));
+    assert!(formula.contains(&format!(
+        "url \"https://github.com/nihilok/devrun/releases/download/v{}/devrun-aarch64-apple-darwin.tar.gz\"\n      sha256 \"{}\"",
+        version, empty_sha256
+    )));
+    assert!(formula.contains("bin.install \"run\""));
+
+    let output = export("scoop-manifest");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let manifest = String::from_utf8_lossy(&output.stdout);
+    assert!(manifest.contains(&format!("\"version\": \"{}\",", version)));
+    assert!(manifest.contains(&format!("\"hash\": \"{}\"", empty_sha256)));
+    assert!(manifest.contains("/download/v$version/devrun-x86_64-pc-windows-msvc.zip"));
+
+    let output = export("deb");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown format 'deb'"));
+}
+
+#[test]
+fn test_which_shell() {
+    let binary = get_binary_path();
+
+    let output = binary_command(&binary)
+        .arg("--which-shell")
+        .env("RUN_SHELL", "bash")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "bash (set by RUN_SHELL)\n"
+    );
+
+    if cfg!(unix) {
+        let output = binary_command(&binary)
+            .arg("--which-shell")
+            .env_remove("RUN_SHELL")
+            .output()
+            .expect("Failed to execute command");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "sh (platform default)\n"
+        );
+    }
+}
+
+#[test]
+fn test_function_substitution_and_return() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+patch = 3
+version() {
+    echo "checking the version"
+    return "1.2.$patch"
+}
+git:sha() echo abc123
+tag = $(version())
+show() echo "tag=$tag sha=$(git:sha()) shell=$(echo hi)"
+early() {
+    echo before
+    return
+    echo after
+}
+loop() echo $(loop())
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["show"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "tag=1.2.3 sha=abc123 shell=hi\n"
+    );
+
+    // Called directly, the function prints as it goes and then its value
+    let output = run(&["version"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "checking the version\n1.2.3\n"
+    );
+    assert_eq!(String::from_utf8_lossy(&run(&["early"]).stdout), "before\n");
+
+    let output = run(&["loop"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("'$(loop())' is captured from a function that uses it")
+    );
+}
+
+#[test]
+fn test_list_json() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+## Open a shell in a "container"
+docker:shell() docker exec -it ${1:-app} sh
+
+test() cargo test $@
+
+@matrix target=[x86_64,aarch64]
+build() cargo build --target $target $1
+
+@prod
+deploy() {
+    echo "deploying $2 to $1"
+    echo $3..
+}
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .args(["--list", "--format", "json"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        concat!(
+            r#"{"functions":["#,
+            r#"{"name":"docker:shell","namespace":["docker"],"command":"shell","description":"Open a shell in a \"container\"","params":[{"position":1,"default":"app"}],"rest":false,"matrix":{},"danger":null},"#,
+            r#"{"name":"test","namespace":[],"command":"test","description":null,"params":[],"rest":true,"matrix":{},"danger":null},"#,
+            r#"{"name":"build","namespace":[],"command":"build","description":null,"params":[{"position":1,"default":null}],"rest":false,"matrix":{"target":["x86_64","aarch64"]},"danger":null},"#,
+            r#"{"name":"deploy","namespace":[],"command":"deploy","description":null,"params":[{"position":1,"default":null},{"position":2,"default":null}],"rest":true,"matrix":{},"danger":"prod"}"#,
+            "]}\n"
+        )
+    );
+
+    // --format only applies to --list
+    let output = binary_command(&binary)
+        .args(["--format", "json"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+
+    let output = binary_command(&binary)
+        .args(["--list", "--format", "yaml"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("unknown --list format 'yaml'"),
+        "got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_output_limit() {
+    let temp_dir = create_temp_dir();
+    let runfile = |limit: &str| {
+        create_runfile(
+            temp_dir.path(),
+            &format!(
+                r#"
+output.limit = {}
+logs.enabled = true
+
+lines = capture(gen)
+gen() sh -c 'for i in 1 2 3 4 5 6 7 8 9 10; do echo "line $i"; done'
+show() echo "$lines"
+"#,
+                limit
+            ),
+        )
+    };
+    let run = |name: &str| run_in(temp_dir.path(), &[name]);
+
+    // The first and last halves of the limit are kept, in whole lines
+    runfile("40");
+    let output = run("show");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "line 1\nline 2\n[... 42 bytes truncated ...]\nline 9\nline 10\n"
+    );
+
+    // Logs have the end of the output written when the function finishes
+    let output = run("gen");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 10);
+    let log = fs::read_dir(temp_dir.path().join(".run/logs/gen"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .unwrap();
+    let log = fs::read_to_string(log).unwrap();
+    assert!(log.starts_with("line 1\nline 2\n"), "got: {}", log);
+    assert!(log.contains("bytes truncated ...]\n"), "got: {}", log);
+    assert!(log.ends_with("\nline 9\nline 10\n"), "got: {}", log);
+
+    runfile("none");
+    let output = run("show");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 10);
+
+    runfile("lots");
+    let output = run("show");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Invalid output.limit 'lots'"),
+        "got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_cancel_grace_period() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    // `stubborn` ignores SIGTERM and would otherwise run for 20 seconds
+    create_runfile(
+        temp_dir.path(),
+        r#"
+commands.grace_period = 300ms
+stubborn() sh -c 'trap "echo term >> term.txt" TERM; i=0; while [ $i -lt 200 ]; do sleep 0.1; i=$((i+1)); done'
+fail() sh -c 'sleep 0.5; exit 1'
+ci() needs(stubborn, fail) echo ci
+"#,
+    );
+
+    let started = std::time::Instant::now();
+    let output = binary_command(&binary)
+        .args(["-j", "2", "ci"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    // Asked to stop first, then killed once the grace period passed
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("term.txt")).unwrap(),
+        "term\n"
+    );
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(10),
+        "took {:?}",
+        started.elapsed()
+    );
+
+    create_runfile(
+        temp_dir.path(),
+        "commands.grace_period = soon\na() echo a\nb() needs(a) echo b\n",
+    );
+    let output = binary_command(&binary)
+        .arg("b")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Invalid commands.grace_period 'soon'"),
+        "got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn test_fail_fast_terminates_the_whole_job() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    // `slow` starts a process outside its console process group, which
+    // Ctrl-Break doesn't reach, and which would write `orphan.txt` later
+    create_runfile(
+        temp_dir.path(),
+        r#"
+commands.grace_period = 300ms
+slow() Start-Process powershell -WindowStyle Hidden -ArgumentList '-Command "Start-Sleep 3; Set-Content orphan.txt late"'; Start-Sleep 10
+fail() Start-Sleep 1; exit 1
+ci() needs(slow, fail) echo ci
+"#,
+    );
+
+    let started = std::time::Instant::now();
+    let output = binary_command(&binary)
+        .args(["-j", "2", "ci"])
+        .current_dir(temp_dir.path())
+        .env("RUN_SHELL", "powershell")
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(8),
+        "took {:?}",
+        started.elapsed()
+    );
+    // Killing the job killed what `slow` started, too
+    std::thread::sleep(std::time::Duration::from_secs(4));
+    assert!(!temp_dir.path().join("orphan.txt").exists());
+}
+
+#[test]
+fn test_confirm() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "@confirm(\"Really deploy to prod?\")\ndeploy() echo deployed\n",
+    );
+
+    // Without a terminal to ask on, nothing runs
+    let output = binary_command(&binary)
+        .arg("deploy")
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Function 'deploy' needs confirmation: Really deploy to prod?")
+            && stderr.contains("--yes"),
+        "got: {}",
+        stderr
+    );
+
+    let output = binary_command(&binary)
+        .args(["--yes", "deploy"])
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::null())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "deployed\n");
+}
+
+#[test]
+fn test_danger_guard() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "## Drop the database\n@danger\ndb:drop() echo dropped\n@prod\ndeploy() echo deployed\nbuild() echo built\n",
+    );
+    let run = |args: &[&str], allow: Option<&str>| {
+        let mut command = binary_command(&binary);
+        command
+            .args(args)
+            .current_dir(temp_dir.path())
+            .stdin(std::process::Stdio::null())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("RUN_ALLOW_DANGER")
+            .env_remove("FORCE_COLOR");
+        if let Some(value) = allow {
+            command.env("RUN_ALLOW_DANGER", value);
+        }
+        command.output().expect("Failed to execute command")
+    };
+
+    // Flagged in the list
+    assert_eq!(
+        String::from_utf8_lossy(&run(&["--list"], None).stdout),
+        "Available functions:\n  db:drop  [danger] Drop the database\n  deploy   [prod]\n  build\n"
+    );
+
+    // Not even --yes runs them without a terminal to type the name on
+    for args in [&["db", "drop"][..], &["--yes", "deploy"]] {
+        let output = run(args, None);
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--allow-danger"), "got: {}", stderr);
+    }
+    assert!(!run(&["deploy"], Some("0")).status.success());
+
+    let output = run(&["--allow-danger", "db:drop"], None);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "dropped\n");
+    let output = run(&["deploy"], Some("1"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "deployed\n");
+    assert_eq!(String::from_utf8_lossy(&run(&["build"], None).stdout), "built\n");
+}
+
+#[test]
+fn test_parameter_defaults() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+deploy(env=$DEPLOY_ENV, region=eu-west-1) echo "deploy $env to ${region} ($1 $2)"
+tag(version) {
+    echo tagging $version
+}
+"#,
+    );
+
+    let run = |args: &[&str], deploy_env: Option<&str>| {
+        let mut command = binary_command(&binary);
+        command
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("DEPLOY_ENV");
+        if let Some(value) = deploy_env {
+            command.env("DEPLOY_ENV", value);
+        }
+        command.output().expect("Failed to execute command")
+    };
+
+    let output = run(&["deploy"], Some("staging"));
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "deploy staging to eu-west-1 (staging eu-west-1)\n"
+    );
+
+    // Passed arguments win over the defaults
+    let output = run(&["deploy", "prod", "us-east-1"], Some("staging"));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "deploy prod to us-east-1 (prod us-east-1)\n"
+    );
+
+    let output = run(&["deploy"], None);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Function 'deploy' needs argument 'env' (or DEPLOY_ENV set in the environment)"),
+        "got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = run(&["tag", "1.2.0"], None);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "tagging 1.2.0\n");
+    let output = run(&["tag"], None);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Function 'tag' needs argument 'version'"));
+
+    let output = run(&["--list", "--format", "json"], None);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains(
+            r#""params":[{"position":1,"default":"$DEPLOY_ENV","name":"env"},{"position":2,"default":"eu-west-1","name":"region"}]"#
+        ),
+        "got: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_enum_variables() {
+    let temp_dir = create_temp_dir();
+    let run = |runfile: &str, args: &[&str]| {
+        create_runfile(temp_dir.path(), runfile);
+        run_in(temp_dir.path(), args)
+    };
+    let declared = "env: one_of(dev, staging, prod) = dev\nprofile live { env=prod }\nprofile typo { env=prodd }\ndeploy() echo deploying to $env\n";
+
+    let output = run(declared, &["deploy"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "deploying to dev\n");
+    let output = run(declared, &["--profile", "live", "deploy"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "deploying to prod\n");
+
+    for (runfile, args) in [
+        (declared, &["--profile", "typo", "deploy"][..]),
+        (&format!("{}env = prodd\n", declared), &["deploy"][..]),
+        ("env = prodd\nenv: one_of(dev, prod)\ndeploy() echo $env\n", &["deploy"][..]),
+    ] {
+        let output = run(runfile, args);
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Invalid value 'prodd' for 'env' (allowed: dev, "),
+            "got: {}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_watch() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    fs::create_dir(temp_dir.path().join("src")).unwrap();
+    fs::write(temp_dir.path().join("src/main.txt"), "one").unwrap();
+    fs::write(temp_dir.path().join("notes.md"), "one").unwrap();
+    create_runfile(temp_dir.path(), "build() echo built $1 >> out.txt\n");
+
+    let mut child = binary_command(&binary)
+        .args(["--watch", "src/*.txt", "build", "x"])
+        .current_dir(temp_dir.path())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to execute command");
+    let out = temp_dir.path().join("out.txt");
+    let wait_for_runs = |runs: usize| {
+        let started = std::time::Instant::now();
+        while started.elapsed() < std::time::Duration::from_secs(10) {
+            if fs::read_to_string(&out).is_ok_and(|text| text.lines().count() >= runs) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    };
+    wait_for_runs(1);
+
+    // Only matching files count, and a burst of changes runs it once
+    fs::write(temp_dir.path().join("notes.md"), "two").unwrap();
+    fs::write(temp_dir.path().join("src/main.txt"), "two").unwrap();
+    fs::write(temp_dir.path().join("src/new.txt"), "two").unwrap();
+    wait_for_runs(2);
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(fs::read_to_string(&out).unwrap(), "built x\nbuilt x\n");
+}
+
+#[test]
+fn test_which_runfile() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let root = temp_dir.path().canonicalize().unwrap();
+    let project = root.join("project");
+    let nested = project.join("src");
+    fs::create_dir_all(&nested).unwrap();
+    create_runfile(&project, "build() echo build\n");
+    fs::write(root.join(".runfile"), "global() echo global\n").unwrap();
+
+    let output = binary_command(&binary)
+        .arg("--which-runfile")
+        .current_dir(&nested)
+        .env("HOME", &root)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], format!("Searching from {}:", nested.display()));
+    assert!(lines[1].contains(&nested.join("Runfile").display().to_string()));
+    assert!(lines[1].ends_with("not found"));
+    assert!(lines[2].contains(&project.join("Runfile").display().to_string()));
+    assert!(lines[2].ends_with(" found"));
+    assert!(lines[3].contains(".runfile") && lines[3].ends_with("exists, read first"));
+    assert!(lines[4].starts_with(&format!("Using {}", project.join("Runfile").display())));
+
+    // Without a Runfile up to the home directory, ~/.runfile is used
+    let output = binary_command(&binary)
+        .arg("--which-runfile")
+        .current_dir(&root)
+        .env("HOME", &root)
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(stopped at the home directory)"), "got: {}", stdout);
+    assert!(
+        stdout.contains(&format!("Using {}", root.join(".runfile").display())),
+        "got: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_global_runfile_merged() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let root = temp_dir.path().canonicalize().unwrap();
+    let project = root.join("project");
+    fs::create_dir_all(&project).unwrap();
+    fs::write(
+        root.join(".runfile"),
+        "greeting = hello\nhelper() echo $greeting from global\nbuild() echo global build\n",
+    )
+    .unwrap();
+    create_runfile(&project, "greeting = hi\nbuild() echo project build\n");
+    let run = |args: &[&str]| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(&project)
+            .env("HOME", &root)
+            .env_remove("GITHUB_ACTIONS")
+            .output()
+            .expect("Failed to execute command")
+    };
+    let stdout = |args: &[&str]| String::from_utf8_lossy(&run(args).stdout).into_owned();
+
+    // Global helpers are available, and the project's definitions win
+    assert_eq!(stdout(&["helper"]), "hi from global\n");
+    assert_eq!(stdout(&["build"]), "project build\n");
+    assert_eq!(
+        stdout(&["--list"]),
+        "Available functions:\n  helper\n  build\n"
+    );
+    assert!(stdout(&["--explain", "helper"]).contains(".runfile:2"));
+
+    let output = run(&["--no-global", "helper"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Function 'helper' not found"));
+    assert!(stdout(&["--no-global", "--which-runfile"]).contains("exists, not used (--no-global)"));
+
+    // Errors in ~/.runfile name it
+    fs::write(root.join(".runfile"), "broken((\n").unwrap();
+    let output = run(&["build"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(".runfile:line 1"), "got: {}", stderr);
+    assert_eq!(stdout(&["--no-global", "build"]), "project build\n");
+}
+
+#[test]
+fn test_cwd_annotation() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let root = temp_dir.path().canonicalize().unwrap();
+    fs::create_dir_all(root.join("frontend/src")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    create_runfile(
+        &root,
+        "@cwd(./frontend)\nwhere() pwd\n@cwd docs\n@outputs index.html\nsite() touch index.html\n@cwd missing\nbroken() pwd\n",
+    );
+
+    // Relative to the Runfile, wherever run is invoked from
+    let output = binary_command(&binary)
+        .arg("where")
+        .current_dir(root.join("frontend/src"))
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        root.join("frontend").display().to_string()
+    );
+
+    // @outputs are looked for there too
+    let output = binary_command(&binary)
+        .arg("site")
+        .current_dir(&root)
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(root.join("docs/index.html").exists());
+
+    let output = binary_command(&binary)
+        .arg("broken")
+        .current_dir(&root)
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "Function 'broken' runs in {}, which is not a directory",
+        root.join("missing").display()
+    )));
+}
+
+#[test]
+fn test_from_makefile() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "NAME := app\nVERSION ?= 1.0\nexport GREETING = hello\nSRC = main.c \\\n  util.c\n\n\
+         .PHONY: all build test\n\n## Build and test\nall: build test\n\n\
+         build: $(SRC)\n\t@echo building $(NAME) $(VERSION) from $< and $^\n\t-@false\n\t@echo \"$$GREETING from $@\"\n\n\
+         test: build ## Run the tests\n\t@for i in 1 2; do echo test $$i; done\n\n\
+         %.o: %.c\n\tcc -c $<\n",
+    )
+    .unwrap();
+    // A target that is also a file still names the rule
+    fs::write(temp_dir.path().join("build"), "").unwrap();
+
+    let output = binary_command(&binary)
+        .args(["--from-makefile", "all"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env_remove("VERSION")
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "building app 1.0 from main.c and main.c util.c\nhello from build\ntest 1\ntest 2\n"
+    );
+
+    let output = binary_command(&binary)
+        .args(["--from-makefile", "--list"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Available functions:\n  all   Build and test\n  build\n  test  Run the tests\n"
+    );
+
+    // Without the flag, the missing Runfile error points to it
+    let output = binary_command(&binary)
+        .arg("all")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--from-makefile"), "got: {}", stderr);
+
+    fs::write(
+        temp_dir.path().join("Makefile"),
+        "ifeq ($(CC),cc)\nall:\n\techo cc\nendif\n",
+    )
+    .unwrap();
+    let output = binary_command(&binary)
+        .args(["--from-makefile", "all"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Makefile:1: 'ifeq' is not supported"), "got: {}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_package_json_scripts() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // A fake npm that shows how it was called
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let npm = bin_dir.join("npm");
+    fs::write(&npm, "#!/bin/sh\necho \"npm $*\"\n").unwrap();
+    fs::set_permissions(&npm, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+
+    fs::write(
+        temp_dir.path().join("package.json"),
+        r#"{
+  "name": "app",
+  "version": "1.0.0",
+  "scripts": {
+    "dev": "vite --port \"5173\"",
+    "build": "vite build",
+    "lint": "eslint ."
+  },
+  "devDependencies": { "vite": "^5.0.0" },
+  "files": ["dist"]
+}
+"#,
+    )
+    .unwrap();
+    create_runfile(
+        temp_dir.path(),
+        "commands.package_json = true\n\nbuild() echo runfile build\n",
+    );
+
+    let run = |args: &[&str]| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("PATH", &path)
+            .env_remove("GITHUB_ACTIONS")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run(&["dev", "--host"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "npm run dev -- --host\n");
+    // The Runfile's own functions come first
+    assert_eq!(String::from_utf8_lossy(&run(&["build"]).stdout), "runfile build\n");
+
+    assert_eq!(
+        String::from_utf8_lossy(&run(&["--list"]).stdout),
+        "Available functions:\n  build\n\npackage.json scripts (npm run):\n  dev   vite --port \"5173\"\n  lint  eslint .\n"
+    );
+
+    // The lockfile picks the package manager
+    fs::write(temp_dir.path().join("yarn.lock"), "").unwrap();
+    assert_eq!(String::from_utf8_lossy(&run(&["-n", "lint", "src"]).stdout), "yarn run lint src\n");
+
+    // Without the setting, scripts aren't called
+    create_runfile(temp_dir.path(), "build() echo runfile build\n");
+    assert!(!run(&["dev"]).status.success());
+}
+
+#[test]
+fn test_profiles() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"API_URL=http://localhost:8080
+
+profile staging { API_URL=https://staging.example.com REPLICAS=2 }
+
+profile prod {
+    API_URL="https://example.com"
+    REPLICAS=5
+}
+
+deploy() {
+    echo $API_URL
+    sh -c 'echo "replicas=$REPLICAS"'
+}
+"#,
+    );
+
+    let run = |args: &[&str]| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("REPLICAS")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run(&["deploy"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "http://localhost:8080\nreplicas=\n"
+    );
+
+    let output = run(&["--profile", "staging", "deploy"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "https://staging.example.com\nreplicas=2\n"
+    );
+
+    let output = run(&["--profile", "prod", "deploy"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "https://example.com\nreplicas=5\n"
+    );
+
+    let output = run(&["--profile", "qa", "deploy"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Profile 'qa' not found (available: prod, staging)")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_encrypted_secret_variables() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    // Fake `age` that "encrypts" by prefixing and needs an identity file to decrypt
+    let bin_dir = temp_dir.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let age = bin_dir.join("age");
+    fs::write(
+        &age,
+        "#!/bin/sh\ncase \"$1\" in\n  --encrypt) printf 'AGE:'; cat ;;\n  --decrypt) [ -f \"$3\" ] || { echo 'no identity' >&2; exit 1; }; sed 's/^AGE://' ;;\nesac\n",
+    )
+    .unwrap();
+    fs::set_permissions(&age, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap_or_default());
+    let identity = temp_dir.path().join("age.key");
+    fs::write(&identity, "AGE-SECRET-KEY-1FAKE\n").unwrap();
+
+    create_runfile(temp_dir.path(), "secrets.recipients = age1example\n");
+    let output = binary_command(&binary)
+        .args(["secrets", "encrypt", "API_TOKEN", "s3cret-value"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    assert!(line.starts_with("secretvar API_TOKEN = enc:"), "got: {}", line);
+
+    // The value may also come from stdin
+    let mut child = binary_command(&binary)
+        .args(["secrets", "encrypt", "DB_PASSWORD"])
+        .current_dir(temp_dir.path())
+        .env("PATH", &path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"hunter22\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdin_line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    create_runfile(
+        temp_dir.path(),
+        &format!(
+            "{}\n{}\n\nshow() echo token=$API_TOKEN password=$DB_PASSWORD\nother() echo fine\n",
+            line, stdin_line
+        ),
+    );
+    let run = |args: &[&str], identity: &std::path::Path| {
+        binary_command(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("PATH", &path)
+            .env("RUN_AGE_IDENTITY", identity)
+            .env_remove("GITHUB_ACTIONS")
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run(&["show"], &identity);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "token=s3cret-value password=hunter22\n"
+    );
+
+    // Traced commands show the secrets as their variables, not their values
+    let output = run(&["--verbose", "show"], &identity);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("token=$API_TOKEN password=$DB_PASSWORD"), "got: {}", stderr);
+    assert!(!stderr.contains("s3cret-value"), "got: {}", stderr);
+
+    let output = run(&["secrets", "decrypt", "API_TOKEN"], &identity);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "s3cret-value\n");
+
+    // Without the key, only functions using the secrets fail
+    let missing = temp_dir.path().join("missing.key");
+    let output = run(&["other"], &missing);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "fine\n");
+    let output = run(&["show"], &missing);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Cannot decrypt secret"),
+        "got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_allow_exit_and_map_exit() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+@allow-exit 1
+search() sh -c 'exit 1'
+
+check() {
+    @map-exit 2=warn
+    sh -c 'exit 2'
+    @allow-exit 3
+    sh -c 'exit 3'
+    sh -c 'exit 2'
+}
+
+@map-exit 2=warn
+lint() sh -c 'exit 2'
+"#,
+    );
+
+    let output = binary_command(&binary)
+        .arg("search")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let output = binary_command(&binary)
+        .arg("check")
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Only the annotated line is downgraded
+    assert_eq!(
+        stderr
+            .matches("Warning: 'sh -c 'exit 2'' exited with status 2")
+            .count(),
+        1,
+        "got: {}",
+        stderr
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr.contains("'sh -c 'exit 2'' failed with exit status: 2"),
+        "got: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("1 warning(s):\n  check: 'sh -c 'exit 2'' exited with status 2"),
+        "got: {}",
+        stderr
+    );
+
+    // Functions running concurrently report their warnings together, once
+    let output = binary_command(&binary)
+        .args(["--parallel", "lint", "search"])
+        .current_dir(temp_dir.path())
+        .env_remove("GITHUB_ACTIONS")
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "got: {}", stderr);
+    assert_eq!(stderr.matches("warning(s):").count(), 1, "got: {}", stderr);
+    assert!(
+        stderr.contains("1 warning(s):\n  lint: 'sh -c 'exit 2'' exited with status 2"),
+        "got: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_invalid_exit_annotations() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "@map-exit 2=ignore\nbuild() echo hi\n");
+
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid @map-exit: '2=ignore'"));
+
+    create_runfile(temp_dir.path(), "build() {\n    @once\n    echo hi\n}\n");
+    let output = binary_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Invalid annotation '@once' in block")
+    );
+}
+
+#[test]
+fn test_command_failure_stops_and_sets_exit_code() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+fail() {
+    echo before
+    sh -c 'exit 3'
+    echo after
+}
+
+dash() {
+    -sh -c 'exit 4'
+    echo after
+}
+
+lenient() {
+    echo before
+    set ignore_errors
+    false
+    echo after
+}
+
+ci() needs(fail) echo never
+"#,
+    );
+    let run = |function: &str| run_in(temp_dir.path(), &[function]);
+
+    let output = run("fail");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "before\n");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Error: 'sh -c 'exit 3'' failed with exit status: 3")
+    );
+
+    let output = run("ci");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("never"));
+
+    for function in ["dash", "lenient"] {
+        let output = run(function);
+        assert!(output.status.success(), "{} failed", function);
+        assert!(String::from_utf8_lossy(&output.stdout).ends_with("after\n"));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("(ignored)"));
+    }
+
+    // At the top of the Runfile, the directive applies to every function
+    create_runfile(
+        temp_dir.path(),
+        "set ignore_errors\n\nbuild() {\n    false\n    echo built\n}\n",
+    );
+    let output = run("build");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "built\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_last_exit_status() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+top() {
+    -sh -c 'exit 2'
+    if [ $? -eq 2 ] { echo top saw two } else { echo top saw other }
+}
+
+check() {
+    -sh -c 'exit 3'
+    echo "failed with $?"
+    echo now $?
+    false || echo inline $?
+}
+
+fresh() echo fresh $?
+
+quit() sh -c 'exit 5'
+"#,
+    );
+    let run = |args: &[&str]| run_in(temp_dir.path(), args);
+
+    let output = run(&["top"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "top saw two\n");
+
+    let output = run(&["check"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "failed with 3\nnow 0\ninline 1\n"
+    );
+
+    // Each function starts over, whatever ran before it
+    let output = run(&["fresh"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "fresh 0\n");
+
+    let output = run(&["quit"]);
+    assert_eq!(output.status.code(), Some(5));
+    let output = run(&["--parallel", "quit", "fresh"]);
+    assert_eq!(output.status.code(), Some(5));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_library_api() {
+    use devrun::ast::{Attribute, Expression, Statement};
+    use devrun::i18n::{self, Language};
+    use devrun::{Interpreter, parser};
+
+    // Messages in English, whatever the locale of the one running the tests
+    i18n::set_language(Language::English);
+    let temp_dir = create_temp_dir();
+    let log = temp_dir.path().join("log.txt");
+    let source = format!(
+        "name = world\ngreet() echo hello $1 >> '{0}'\nall() needs(greet) {{\n    echo all >> '{0}'\n}}\n",
+        log.display()
+    );
+
+    // The parser gives the statements of the Runfile, as the AST
+    let program = parser::parse_script(&source).expect("a valid Runfile");
+    assert_eq!(
+        program.statements[0],
+        Statement::Assignment {
+            name: "name".to_string(),
+            value: Expression::String("world".to_string()),
+        }
+    );
+    assert!(matches!(
+        &program.statements[1],
+        Statement::SimpleFunctionDef { name, .. } if name == "greet"
+    ));
+    let Statement::BlockFunctionDef {
+        name,
+        commands,
+        attributes,
+    } = &program.statements[2]
+    else {
+        panic!("Expected BlockFunctionDef");
+    };
+    assert_eq!(name, "all");
+    assert_eq!(commands, &[format!("echo all >> '{}'", log.display())]);
+    assert_eq!(attributes, &[Attribute::Needs(vec!["greet".to_string()])]);
+    assert!(parser::parse_script("greet( echo").is_err());
+
+    // and the interpreter loads them, to list and call the functions
+    let mut interpreter = Interpreter::new();
+    interpreter.execute(program).expect("definitions load");
+    assert_eq!(interpreter.function_names(), ["all", "greet"]);
+    assert!(interpreter.has_function("greet"));
+    interpreter
+        .call_function_with_args("greet", &["library".to_string()])
+        .expect("greet succeeds");
+    interpreter
+        .call_function_with_args("all", &[])
+        .expect("all succeeds");
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "hello library\nhello\nall\n"
+    );
+    assert_eq!(interpreter.eval("hello $name").unwrap(), "hello world");
+    let error = interpreter
+        .call_function_with_args("missing", &[])
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Function 'missing' not found");
+}